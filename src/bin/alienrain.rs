@@ -38,8 +38,8 @@ impl App for DemoApp {
     fn initialize(&mut self, _window: &Window) -> Result<()> {
         self.load_shaders();
 
-        let (_, data) = load_ktx!("../../assets/textures/aliens.ktx").unwrap();
-        self.alien_textures = prepare_texture(&data);
+        let data = load_ktx!("textures/aliens.ktx")?;
+        self.alien_textures = prepare_texture(&data)?;
 
         let mut vao = 0;
         unsafe {
@@ -5,8 +5,7 @@ use nalgebra_glm as glm;
 use std::{cmp, mem, ptr};
 use support::{
     app::{run_application, App},
-    load_object,
-    object::{render_all, Object},
+    object::{load_mesh_asset, prepare_object, render_all, Object},
     shader::ShaderProgram,
 };
 
@@ -63,7 +62,7 @@ impl App for DemoApp {
         self.update_aspect_ratio(width, height);
         self.load_shaders();
 
-        let (_, obj) = load_object!("../../assets/objects/dragon.sbm").unwrap();
+        let obj = prepare_object(&load_mesh_asset("objects/dragon.sbm")?);
         self.object = obj;
 
         unsafe {
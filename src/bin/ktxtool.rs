@@ -0,0 +1,680 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    env,
+    fmt::Display,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    path::Path,
+};
+use support::{
+    hdr::{FloatFormat, HdrImage},
+    image::{rows_run_up, texture_from_images, Image},
+    ktx::{
+        array::assemble_array,
+        dds::{parse_dds, DDS_MAGIC},
+        decode, determine_target,
+        envmap::{reproject, Projection},
+        ktx2::{parse_ktx2, KTX2_IDENTIFIER},
+        load_ktx_file,
+        mipmap::{generate_mipmaps, MipmapFilter, MipmapOptions},
+        parse_ktx,
+        rgtc::compress_rgtc,
+        writer::write_ktx,
+        KtxData, COMPRESSED_RGBA_S3TC_DXT1, COMPRESSED_RGBA_S3TC_DXT3, COMPRESSED_RGBA_S3TC_DXT5,
+        COMPRESSED_RGB_S3TC_DXT1, COMPRESSED_SRGB_ALPHA_S3TC_DXT1, COMPRESSED_SRGB_ALPHA_S3TC_DXT3,
+        COMPRESSED_SRGB_ALPHA_S3TC_DXT5, COMPRESSED_SRGB_S3TC_DXT1,
+    },
+    sdf::{distance_field_texture, DistanceFieldOptions},
+};
+
+const USAGE: &str = "usage:
+    ktxtool info <texture.ktx|texture.ktx2|texture.dds>...
+    ktxtool extract <texture> <image.png> [--level N] [--layer N] [--face N] [--slice N]
+    ktxtool decompress <texture> <texture.ktx>
+    ktxtool convert <image> <texture.ktx> [options]
+    ktxtool compress <image|texture.ktx> <texture.ktx> [--channels N] [options]
+    ktxtool assemble <texture.ktx> <image|texture.ktx>... [--array] [--cube] [options]
+    ktxtool reproject <texture.ktx> <texture.ktx> --to LAYOUT [--from LAYOUT]
+                       [--resolution N] [options]
+    ktxtool distance-field <texture.ktx> <image>... [--field-size WxH] [--spread N]
+                           [--threshold N] [options]
+
+images are PNG, PGM/PPM or raw pixels, which need --size WxH and --components N (default 4)
+array layers can also be single 2D KTX textures, all of the same format and size
+textures can be read from DDS files as well, legacy or with the DX10 header
+convert also takes Radiance .hdr and .pfm images, stored as RGB32F or with --half RGB16F
+distance-field makes an R8 texture, or a 2D array of one layer per image
+
+options:
+    --srgb                 mark 3 and 4 component images as sRGB
+    --flip                 flip images vertically before storing them
+    --half                 store HDR images as half floats
+    --mipmaps              generate the full mip chain
+    --filter NAME          mip filter: box (default), kaiser or lanczos
+    --alpha-coverage REF   keep the coverage of alpha tested at REF in every mip level
+    --channels N           compress the first 1 (RGTC1) or 2 (RGTC2) image channels
+    --from/--to LAYOUT     environment map layout: equirect, cube or sphere, with --from
+                           defaulting to cube for six faces
+    --resolution N         height of equirect maps, edge of cube faces and sphere maps
+                           (default: the source height)
+    --field-size WxH       distance field size, no larger than the images (default: theirs)
+    --spread N             distance in field texels from the edge to 0 or 1 (default 4)
+    --threshold N          alpha, or first channel without alpha, inside shapes (default 128)";
+
+// Options that take a value, everything else starting with -- is a flag
+const VALUE_OPTIONS: [&str; 15] = [
+    "level",
+    "layer",
+    "face",
+    "slice",
+    "size",
+    "components",
+    "filter",
+    "alpha-coverage",
+    "channels",
+    "from",
+    "to",
+    "resolution",
+    "field-size",
+    "spread",
+    "threshold",
+];
+
+struct Arguments {
+    command: String,
+    positional: Vec<String>,
+    options: Vec<(String, Option<String>)>,
+}
+
+impl Arguments {
+    fn parse<I: Iterator<Item = String>>(mut arguments: I) -> Result<Self> {
+        let command = arguments.next().unwrap_or_default();
+        let mut positional = Vec::new();
+        let mut options = Vec::new();
+        while let Some(argument) = arguments.next() {
+            match argument.strip_prefix("--") {
+                Some(name) if VALUE_OPTIONS.contains(&name) => {
+                    let value = arguments
+                        .next()
+                        .with_context(|| format!("--{} needs a value", name))?;
+                    options.push((name.to_string(), Some(value)));
+                }
+                Some(name) => options.push((name.to_string(), None)),
+                None => positional.push(argument),
+            }
+        }
+        Ok(Arguments {
+            command,
+            positional,
+            options,
+        })
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.options.iter().any(|(option, _)| option == name)
+    }
+
+    fn value(&self, name: &str) -> Option<&str> {
+        self.options
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .and_then(|(_, value)| value.as_deref())
+    }
+
+    fn number(&self, name: &str) -> Result<u32> {
+        self.value(name).map_or(Ok(0), |value| {
+            value
+                .parse()
+                .with_context(|| format!("--{} expects a number, not {:?}", name, value))
+        })
+    }
+
+    fn check_options(&self, allowed: &[&str]) -> Result<()> {
+        for (option, _) in self.options.iter() {
+            ensure!(
+                allowed.contains(&option.as_str()),
+                "{} does not take --{}",
+                self.command,
+                option
+            );
+        }
+        Ok(())
+    }
+}
+
+fn main() -> Result<()> {
+    let arguments = Arguments::parse(env::args().skip(1))?;
+    match arguments.command.as_str() {
+        "info" => info(&arguments),
+        "extract" => extract(&arguments),
+        "decompress" => decompress(&arguments),
+        "convert" => convert(&arguments),
+        "compress" => compress(&arguments),
+        "reproject" => reproject_texture(&arguments),
+        "assemble" => assemble(&arguments),
+        "distance-field" => distance_field(&arguments),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn info(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[])?;
+    ensure!(!arguments.positional.is_empty(), "{}", USAGE);
+    let stdout = std::io::stdout();
+    let mut out = stdout.lock();
+    for path in arguments.positional.iter() {
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path))?;
+        writeln!(out, "{}", path)?;
+        if bytes.starts_with(&KTX2_IDENTIFIER) {
+            let ktx2 = parse_ktx2(&bytes).with_context(|| format!("parsing {}", path))?;
+            let format = &ktx2.data_format;
+            field(&mut out, "container", "KTX 2.0")?;
+            field(&mut out, "vk_format", ktx2.vk_format)?;
+            field(&mut out, "supercompression", ktx2.supercompression_scheme)?;
+            field(
+                &mut out,
+                "data format",
+                format!(
+                    "model {}, primaries {}, transfer {}, {} samples",
+                    format.color_model,
+                    format.color_primaries,
+                    format.transfer_function,
+                    format.samples.len()
+                ),
+            )?;
+            print_texture(&mut out, &ktx2.texture)?;
+        } else if bytes.starts_with(&DDS_MAGIC) {
+            let texture = parse_dds(&bytes).with_context(|| format!("parsing {}", path))?;
+            field(&mut out, "container", "DDS")?;
+            print_texture(&mut out, &texture)?;
+        } else {
+            let texture = parse_ktx(&bytes).with_context(|| format!("parsing {}", path))?;
+            let big_endian = texture.header.endianess[0] == 0x04;
+            field(
+                &mut out,
+                "container",
+                if big_endian {
+                    "KTX 1.1, big endian"
+                } else {
+                    "KTX 1.1, little endian"
+                },
+            )?;
+            print_texture(&mut out, &texture)?;
+        }
+    }
+    Ok(())
+}
+
+fn field<W: Write, T: Display>(out: &mut W, name: &str, value: T) -> Result<()> {
+    writeln!(out, "  {:<24} {}", name, value)?;
+    Ok(())
+}
+
+fn print_texture<W: Write>(out: &mut W, texture: &KtxData) -> Result<()> {
+    let ktx = &texture.header;
+    let target = determine_target(texture)
+        .map(gl_name)
+        .unwrap_or_else(|error| error.to_string().into());
+    field(out, "target", target)?;
+    for (name, value) in [
+        ("gl_type", ktx.gl_type),
+        ("gl_format", ktx.gl_format),
+        ("gl_internal_format", ktx.gl_internal_format),
+        ("gl_base_internal_format", ktx.gl_base_internal_format),
+    ]
+    .iter()
+    {
+        field(out, name, format!("0x{:04X} {}", value, gl_name(*value)))?;
+    }
+    field(out, "gl_type_size", ktx.gl_type_size)?;
+    field(
+        out,
+        "pixel size",
+        format!(
+            "{} x {} x {}",
+            ktx.pixel_width, ktx.pixel_height, ktx.pixel_depth
+        ),
+    )?;
+    field(out, "array elements", ktx.array_elements)?;
+    field(out, "faces", ktx.faces)?;
+    field(out, "mip levels", ktx.mip_levels)?;
+    field(out, "row alignment", texture.unpack_alignment)?;
+
+    field(out, "key/value pairs", texture.key_values.len())?;
+    for key_value in texture.key_values.iter() {
+        writeln!(
+            out,
+            "    {} = {}",
+            key_value.key,
+            describe_value(&key_value.value)
+        )?;
+    }
+
+    writeln!(out, "  levels")?;
+    for (level, mip) in texture.levels.iter().enumerate() {
+        let bytes: usize = mip.images.iter().map(|image| image.len()).sum();
+        writeln!(
+            out,
+            "    {:>2}: {} x {} x {}, {} image{} of {} bytes, {} bytes",
+            level,
+            mip.width,
+            mip.height,
+            mip.depth,
+            mip.images.len(),
+            if mip.images.len() == 1 { "" } else { "s" },
+            mip.images.first().map_or(0, |image| image.len()),
+            bytes
+        )?;
+    }
+    Ok(())
+}
+
+// Values are usually NUL terminated text, anything else is shown as hex
+fn describe_value(value: &[u8]) -> String {
+    let text = value.strip_suffix(&[0]).unwrap_or(value);
+    match std::str::from_utf8(text) {
+        Ok(text) if !text.chars().any(char::is_control) => format!("{:?}", text),
+        _ => value
+            .iter()
+            .map(|byte| format!("{:02x}", byte))
+            .collect::<Vec<_>>()
+            .join(" "),
+    }
+}
+
+fn extract(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&["level", "layer", "face", "slice"])?;
+    let (input, output) = match &arguments.positional[..] {
+        [input, output] => (input, output),
+        _ => bail!("{}", USAGE),
+    };
+    let texture = load_ktx_file(input)?;
+    let mut image = Image::from_texture(
+        &texture,
+        arguments.number("level")?,
+        arguments.number("layer")?,
+        arguments.number("face")?,
+        arguments.number("slice")?,
+    )?;
+    if rows_run_up(&texture) {
+        image.flip_vertically();
+    }
+    image.save_png(output)
+}
+
+// Decodes block compressed textures to RGBA8, or RGBA32F for the signed and 11 bit formats
+fn decompress(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[])?;
+    let (input, output) = match &arguments.positional[..] {
+        [input, output] => (input, output),
+        _ => bail!("{}", USAGE),
+    };
+    let texture = load_ktx_file(input)?;
+    save_texture(arguments, decode::decompress(&texture)?, output)
+}
+
+fn convert(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[
+        "size",
+        "components",
+        "srgb",
+        "flip",
+        "mipmaps",
+        "filter",
+        "alpha-coverage",
+        "half",
+    ])?;
+    let (input, output) = match &arguments.positional[..] {
+        [input, output] => (input, output),
+        _ => bail!("{}", USAGE),
+    };
+    let is_hdr = Path::new(input).extension().is_some_and(|extension| {
+        ["hdr", "pic", "pfm"]
+            .iter()
+            .any(|hdr| extension.eq_ignore_ascii_case(hdr))
+    });
+    let texture = if is_hdr {
+        let mut image = HdrImage::load(input)?;
+        if arguments.flag("flip") {
+            image.flip_vertically();
+        }
+        let format = if arguments.flag("half") {
+            FloatFormat::Half
+        } else {
+            FloatFormat::Float
+        };
+        image.to_texture(format)
+    } else {
+        ensure!(!arguments.flag("half"), "--half is only for HDR images");
+        let image = load_image(arguments, input)?;
+        texture_from_images(&[image], 0, 1, arguments.flag("srgb"))?
+    };
+    save_texture(arguments, texture, output)
+}
+
+// Compresses 8 bit RED or RG textures, or images with --channels picking which channels
+// to keep, to RGTC with the full mip chain
+fn compress(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[
+        "size",
+        "components",
+        "flip",
+        "filter",
+        "alpha-coverage",
+        "channels",
+    ])?;
+    let (input, output) = match &arguments.positional[..] {
+        [input, output] => (input, output),
+        _ => bail!("{}", USAGE),
+    };
+    let texture = if is_ktx(input) {
+        load_ktx_file(input)?
+    } else {
+        let image = load_image(arguments, input)?;
+        let channels = match arguments.value("channels") {
+            Some(_) => arguments.number("channels")?,
+            None => image.components,
+        };
+        ensure!(
+            (1..=2).contains(&channels) && channels <= image.components,
+            "{} has {} components, pass --channels 1 or 2 to pick which to compress",
+            input,
+            image.components
+        );
+        let data = image
+            .data
+            .chunks_exact(image.components as usize)
+            .flat_map(|texel| texel[..channels as usize].iter().copied())
+            .collect();
+        let image = Image::new(image.width, image.height, channels, data)?;
+        texture_from_images(&[image], 0, 1, false)?
+    };
+
+    let compressed = compress_rgtc(&texture, &mipmap_options(arguments)?)?;
+    for (level, psnr) in compressed.level_psnr.iter().enumerate() {
+        println!("level {:>2}: {:.2} dB", level, psnr);
+    }
+    println!("PSNR: {:.2} dB", compressed.psnr);
+
+    write_texture(&compressed.texture, output)
+}
+
+// Plain images and 2D textures become array layers. --cube takes six faces, or several
+// cubes with --array.
+fn assemble(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[
+        "array",
+        "cube",
+        "size",
+        "components",
+        "srgb",
+        "flip",
+        "mipmaps",
+        "filter",
+        "alpha-coverage",
+    ])?;
+    let (output, inputs) = match arguments.positional.split_first() {
+        Some((output, inputs)) if !inputs.is_empty() => (output, inputs),
+        _ => bail!("{}", USAGE),
+    };
+    if !arguments.flag("cube") {
+        let layers = inputs
+            .iter()
+            .map(|input| {
+                if is_ktx(input) {
+                    Ok(load_ktx_file(input)?)
+                } else {
+                    let image = load_image(arguments, input)?;
+                    texture_from_images(&[image], 0, 1, arguments.flag("srgb"))
+                }
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mipmaps = if arguments.flag("mipmaps") {
+            Some(mipmap_options(arguments)?)
+        } else {
+            None
+        };
+        return write_texture(&assemble_array(&layers, mipmaps.as_ref())?, output);
+    }
+
+    let images = inputs
+        .iter()
+        .map(|input| load_image(arguments, input))
+        .collect::<Result<Vec<_>>>()?;
+    let count = images.len() as u32;
+    let array_elements = if arguments.flag("array") {
+        ensure!(
+            count.is_multiple_of(6),
+            "a cube map array needs a multiple of six faces, not {}",
+            count
+        );
+        count / 6
+    } else {
+        ensure!(count == 6, "a cube map needs six faces, not {}", count);
+        0
+    };
+    let texture = texture_from_images(&images, array_elements, 6, arguments.flag("srgb"))?;
+    save_texture(arguments, texture, output)
+}
+
+// Textures rather than images, which load_ktx_file reads whichever container they are in
+fn is_ktx(path: &str) -> bool {
+    Path::new(path).extension().is_some_and(|extension| {
+        extension.eq_ignore_ascii_case("ktx") || extension.eq_ignore_ascii_case("dds")
+    })
+}
+
+fn load_image(arguments: &Arguments, path: &str) -> Result<Image> {
+    let mut image = if Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("raw"))
+    {
+        let (width, height) = dimensions(arguments, "size")?
+            .with_context(|| format!("{} is raw, so --size WxH is needed", path))?;
+        let components = match arguments.value("components") {
+            Some(_) => arguments.number("components")?,
+            None => 4,
+        };
+        Image::load_raw(path, width, height, components)?
+    } else {
+        Image::load(path)?
+    };
+    if arguments.flag("flip") {
+        image.flip_vertically();
+    }
+    Ok(image)
+}
+
+fn dimensions(arguments: &Arguments, name: &str) -> Result<Option<(u32, u32)>> {
+    arguments
+        .value(name)
+        .map(|size| {
+            size.split_once('x')
+                .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                .with_context(|| format!("--{} expects WxH, not {:?}", name, size))
+        })
+        .transpose()
+}
+
+fn distance_field(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[
+        "field-size",
+        "spread",
+        "threshold",
+        "size",
+        "components",
+        "flip",
+        "mipmaps",
+        "filter",
+    ])?;
+    let (output, inputs) = match arguments.positional.split_first() {
+        Some((output, inputs)) if !inputs.is_empty() => (output, inputs),
+        _ => bail!("{}", USAGE),
+    };
+    let mut options = DistanceFieldOptions {
+        size: dimensions(arguments, "field-size")?,
+        ..Default::default()
+    };
+    if let Some(spread) = arguments.value("spread") {
+        options.spread = spread
+            .parse()
+            .with_context(|| format!("--spread expects a number, not {:?}", spread))?;
+    }
+    if let Some(threshold) = arguments.value("threshold") {
+        options.threshold = threshold
+            .parse()
+            .with_context(|| format!("--threshold expects 0 to 255, not {:?}", threshold))?;
+    }
+    let images = inputs
+        .iter()
+        .map(|input| load_image(arguments, input))
+        .collect::<Result<Vec<_>>>()?;
+    save_texture(
+        arguments,
+        distance_field_texture(&images, &options)?,
+        output,
+    )
+}
+
+fn save_texture(arguments: &Arguments, texture: KtxData, output: &str) -> Result<()> {
+    let texture = if arguments.flag("mipmaps") {
+        generate_mipmaps(&texture, &mipmap_options(arguments)?)?
+    } else {
+        texture
+    };
+    write_texture(&texture, output)
+}
+
+// Converts environment maps between equirectangular, cube map and sphere map layouts
+fn reproject_texture(arguments: &Arguments) -> Result<()> {
+    arguments.check_options(&[
+        "from",
+        "to",
+        "resolution",
+        "mipmaps",
+        "filter",
+        "alpha-coverage",
+    ])?;
+    let (input, output) = match &arguments.positional[..] {
+        [input, output] => (input, output),
+        _ => bail!("{}", USAGE),
+    };
+    let texture = load_ktx_file(input)?;
+    let projection = |name: &str| match name {
+        "equirect" => Ok(Projection::Equirectangular),
+        "cube" => Ok(Projection::CubeMap),
+        "sphere" => Ok(Projection::SphereMap),
+        name => bail!(
+            "unknown layout {:?}, expected equirect, cube or sphere",
+            name
+        ),
+    };
+    let from = match arguments.value("from") {
+        Some(name) => projection(name)?,
+        None if texture.header.faces == 6 => Projection::CubeMap,
+        None => bail!("--from is needed for textures that aren't cube maps"),
+    };
+    let to = projection(arguments.value("to").context("--to is needed")?)?;
+    let resolution = match arguments.value("resolution") {
+        Some(_) => arguments.number("resolution")?,
+        None => texture.header.pixel_height,
+    };
+    save_texture(
+        arguments,
+        reproject(&texture, from, to, resolution)?,
+        output,
+    )
+}
+
+fn write_texture(texture: &KtxData, output: &str) -> Result<()> {
+    let file = File::create(output).with_context(|| format!("creating {}", output))?;
+    let mut writer = BufWriter::new(file);
+    write_ktx(texture, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+fn mipmap_options(arguments: &Arguments) -> Result<MipmapOptions> {
+    let filter = match arguments.value("filter").unwrap_or("box") {
+        "box" => MipmapFilter::Box,
+        "kaiser" => MipmapFilter::Kaiser,
+        "lanczos" => MipmapFilter::Lanczos,
+        filter => bail!("unknown filter {:?}", filter),
+    };
+    let alpha_coverage =
+        match arguments.value("alpha-coverage") {
+            Some(reference) => Some(reference.parse().with_context(|| {
+                format!("--alpha-coverage expects a number, not {:?}", reference)
+            })?),
+            None => None,
+        };
+    Ok(MipmapOptions {
+        filter,
+        srgb: None,
+        alpha_coverage,
+    })
+}
+
+fn gl_name(value: u32) -> std::borrow::Cow<'static, str> {
+    let name = match value {
+        gl::TEXTURE_1D => "GL_TEXTURE_1D",
+        gl::TEXTURE_1D_ARRAY => "GL_TEXTURE_1D_ARRAY",
+        gl::TEXTURE_2D => "GL_TEXTURE_2D",
+        gl::TEXTURE_2D_ARRAY => "GL_TEXTURE_2D_ARRAY",
+        gl::TEXTURE_3D => "GL_TEXTURE_3D",
+        gl::TEXTURE_CUBE_MAP => "GL_TEXTURE_CUBE_MAP",
+        gl::TEXTURE_CUBE_MAP_ARRAY => "GL_TEXTURE_CUBE_MAP_ARRAY",
+        0 => "",
+        gl::BYTE => "GL_BYTE",
+        gl::UNSIGNED_BYTE => "GL_UNSIGNED_BYTE",
+        gl::SHORT => "GL_SHORT",
+        gl::UNSIGNED_SHORT => "GL_UNSIGNED_SHORT",
+        gl::INT => "GL_INT",
+        gl::UNSIGNED_INT => "GL_UNSIGNED_INT",
+        gl::HALF_FLOAT => "GL_HALF_FLOAT",
+        gl::FLOAT => "GL_FLOAT",
+        gl::RED => "GL_RED",
+        gl::RG => "GL_RG",
+        gl::RGB => "GL_RGB",
+        gl::BGR => "GL_BGR",
+        gl::RGBA => "GL_RGBA",
+        gl::BGRA => "GL_BGRA",
+        gl::R8 => "GL_R8",
+        gl::RG8 => "GL_RG8",
+        gl::RGB8 => "GL_RGB8",
+        gl::RGBA8 => "GL_RGBA8",
+        gl::SRGB8 => "GL_SRGB8",
+        gl::SRGB8_ALPHA8 => "GL_SRGB8_ALPHA8",
+        gl::R16F => "GL_R16F",
+        gl::RGB16F => "GL_RGB16F",
+        gl::RGBA16F => "GL_RGBA16F",
+        gl::R32F => "GL_R32F",
+        gl::RGB32F => "GL_RGB32F",
+        gl::RGBA32F => "GL_RGBA32F",
+        gl::COMPRESSED_RED_RGTC1 => "GL_COMPRESSED_RED_RGTC1",
+        gl::COMPRESSED_SIGNED_RED_RGTC1 => "GL_COMPRESSED_SIGNED_RED_RGTC1",
+        gl::COMPRESSED_RG_RGTC2 => "GL_COMPRESSED_RG_RGTC2",
+        gl::COMPRESSED_SIGNED_RG_RGTC2 => "GL_COMPRESSED_SIGNED_RG_RGTC2",
+        gl::COMPRESSED_RGBA_BPTC_UNORM => "GL_COMPRESSED_RGBA_BPTC_UNORM",
+        gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM => "GL_COMPRESSED_SRGB_ALPHA_BPTC_UNORM",
+        gl::COMPRESSED_RGB8_ETC2 => "GL_COMPRESSED_RGB8_ETC2",
+        gl::COMPRESSED_SRGB8_ETC2 => "GL_COMPRESSED_SRGB8_ETC2",
+        gl::COMPRESSED_RGBA8_ETC2_EAC => "GL_COMPRESSED_RGBA8_ETC2_EAC",
+        gl::COMPRESSED_R11_EAC => "GL_COMPRESSED_R11_EAC",
+        gl::COMPRESSED_RG11_EAC => "GL_COMPRESSED_RG11_EAC",
+        COMPRESSED_RGB_S3TC_DXT1 => "GL_COMPRESSED_RGB_S3TC_DXT1_EXT",
+        COMPRESSED_RGBA_S3TC_DXT1 => "GL_COMPRESSED_RGBA_S3TC_DXT1_EXT",
+        COMPRESSED_RGBA_S3TC_DXT3 => "GL_COMPRESSED_RGBA_S3TC_DXT3_EXT",
+        COMPRESSED_RGBA_S3TC_DXT5 => "GL_COMPRESSED_RGBA_S3TC_DXT5_EXT",
+        COMPRESSED_SRGB_S3TC_DXT1 => "GL_COMPRESSED_SRGB_S3TC_DXT1_EXT",
+        COMPRESSED_SRGB_ALPHA_S3TC_DXT1 => "GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT",
+        COMPRESSED_SRGB_ALPHA_S3TC_DXT3 => "GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT3_EXT",
+        COMPRESSED_SRGB_ALPHA_S3TC_DXT5 => "GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT5_EXT",
+        _ => return "(unknown)".into(),
+    };
+    name.into()
+}
@@ -1,9 +1,10 @@
 use anyhow::Result;
 use gl::types::*;
 use glutin::window::Window;
+use std::{env, path::PathBuf};
 use support::{
     app::{run_application, App},
-    ktx::prepare_texture,
+    ktx::{load_ktx_file, prepare_texture},
     load_ktx,
     shader::ShaderProgram,
 };
@@ -12,6 +13,7 @@ static GREEN: &[GLfloat; 4] = &[0.0, 0.25, 0.0, 1.0];
 
 #[derive(Default)]
 struct DemoApp {
+    path: Option<PathBuf>,
     shader_program: ShaderProgram,
     vao: u32,
     texture: u32,
@@ -30,8 +32,11 @@ impl DemoApp {
 impl App for DemoApp {
     fn initialize(&mut self, _window: &Window) -> Result<()> {
         self.load_shaders();
-        let (_, data) = load_ktx!("../../assets/textures/tree.ktx").unwrap();
-        self.texture = prepare_texture(&data);
+        let data = match &self.path {
+            Some(path) => load_ktx_file(path)?,
+            None => load_ktx!("textures/tree.ktx")?,
+        };
+        self.texture = prepare_texture(&data)?;
         unsafe {
             gl::GenVertexArrays(1, &mut self.vao);
             gl::BindVertexArray(self.vao);
@@ -58,6 +63,10 @@ impl App for DemoApp {
 }
 
 fn main() -> Result<()> {
-    let app = DemoApp::default();
+    // Any KTX or KTX2 file can be given on the command line
+    let app = DemoApp {
+        path: env::args_os().nth(1).map(PathBuf::from),
+        ..Default::default()
+    };
     run_application(app, "KTX Viewer")
 }
@@ -4,8 +4,7 @@ use glutin::{event::ElementState, event::VirtualKeyCode, window::Window};
 use nalgebra_glm as glm;
 use support::{
     app::{run_application, App},
-    load_object,
-    object::Object,
+    object::{load_mesh_asset, prepare_object, Object},
     shader::ShaderProgram,
 };
 
@@ -40,8 +39,7 @@ impl App for DemoApp {
     fn initialize(&mut self, _window: &Window) -> Result<()> {
         self.multidraw_active = true;
         self.load_shaders();
-        let (_, object) = load_object!("../../assets/objects/asteroids.sbm")?;
-        self.asteroids = object;
+        self.asteroids = prepare_object(&load_mesh_asset("objects/asteroids.sbm")?);
 
         unsafe {
             let mut indirect_draw_buffer = 0;
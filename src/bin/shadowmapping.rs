@@ -2,11 +2,11 @@ use anyhow::Result;
 use gl::types::*;
 use glutin::{event::ElementState, event::VirtualKeyCode, window::Window};
 use nalgebra_glm as glm;
-use std::cmp;
+use std::{cmp, fs::File, io::BufWriter};
 use support::{
     app::{run_application, App},
-    load_object,
-    object::{render_all, Object},
+    ktx::{read_texture, writer::write_ktx},
+    object::{load_mesh_asset, prepare_object, render_all, Object},
     shader::ShaderProgram,
 };
 
@@ -15,6 +15,7 @@ const ONES: &[GLfloat; 1] = &[1.0];
 const ZERO: &[GLfloat; 1] = &[0.0];
 
 const DEPTH_TEXTURE_SIZE: GLsizei = 4096;
+const DEPTH_CAPTURE_PATH: &str = "shadowmapping-depth.ktx";
 
 #[derive(Default)]
 struct Uniforms {
@@ -116,25 +117,25 @@ impl DemoApp {
     }
 
     fn load_objects(&mut self) {
-        let (_, object) = load_object!("../../assets/objects/dragon.sbm").unwrap();
+        let object = prepare_object(&load_mesh_asset("objects/dragon.sbm").unwrap());
         self.dragon = Model {
             object,
             model_matrix: glm::Mat4::identity(),
         };
 
-        let (_, object) = load_object!("../../assets/objects/torus.sbm").unwrap();
+        let object = prepare_object(&load_mesh_asset("objects/torus.sbm").unwrap());
         self.torus = Model {
             object,
             model_matrix: glm::Mat4::identity(),
         };
 
-        let (_, object) = load_object!("../../assets/objects/sphere.sbm").unwrap();
+        let object = prepare_object(&load_mesh_asset("objects/sphere.sbm").unwrap());
         self.sphere = Model {
             object,
             model_matrix: glm::Mat4::identity(),
         };
 
-        let (_, object) = load_object!("../../assets/objects/cube.sbm").unwrap();
+        let object = prepare_object(&load_mesh_asset("objects/cube.sbm").unwrap());
         self.cube = Model {
             object,
             model_matrix: glm::Mat4::identity(),
@@ -253,6 +254,14 @@ impl App for DemoApp {
             (VirtualKeyCode::R, ElementState::Pressed) => {
                 self.load_shaders();
             }
+            (VirtualKeyCode::C, ElementState::Pressed) => {
+                let depth = read_texture(self.depth_debug_texture, gl::TEXTURE_2D)?;
+                write_ktx(
+                    &depth,
+                    &mut BufWriter::new(File::create(DEPTH_CAPTURE_PATH)?),
+                )?;
+                println!("Wrote the light's depth to {}", DEPTH_CAPTURE_PATH);
+            }
             _ => (),
         }
 
@@ -6,8 +6,8 @@ use std::cmp;
 use support::{
     app::{run_application, App},
     ktx::prepare_texture,
-    load_ktx, load_object,
-    object::{render_all, Object},
+    load_ktx,
+    object::{load_mesh_asset, prepare_object, render_all, Object},
     shader::ShaderProgram,
 };
 
@@ -120,13 +120,12 @@ impl App for DemoApp {
         self.update_aspect_ratio(width, height);
         self.load_shaders();
 
-        let (_, data) = load_ktx!("../../assets/textures/pattern1.ktx").unwrap();
-        self.texture_1 = prepare_texture(&data);
+        let data = load_ktx!("textures/pattern1.ktx")?;
+        self.texture_1 = prepare_texture(&data)?;
         self.texture_2 = create_procedural_texture();
         self.bind_texture(self.texture_1);
 
-        let (_, obj) = load_object!("../../assets/objects/torus_nrms_tc.sbm").unwrap();
-        self.object = obj;
+        self.object = prepare_object(&load_mesh_asset("objects/torus_nrms_tc.sbm")?);
 
         unsafe {
             gl::Enable(gl::DEPTH_TEST);
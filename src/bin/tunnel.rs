@@ -51,16 +51,16 @@ impl App for DemoApp {
         self.uniform_loc_mvp = self.shader_program.uniform_location("mvp");
         self.uniform_loc_offset = self.shader_program.uniform_location("offset");
 
-        let (_, brick) = load_ktx!("../../assets/textures/brick.ktx").unwrap();
-        let (_, ceiling) = load_ktx!("../../assets/textures/ceiling.ktx").unwrap();
-        let (_, floor) = load_ktx!("../../assets/textures/floor.ktx").unwrap();
+        let brick = load_ktx!("textures/brick.ktx")?;
+        let ceiling = load_ktx!("textures/ceiling.ktx")?;
+        let floor = load_ktx!("textures/floor.ktx")?;
 
-        let wall_texture = prepare_texture(&brick);
+        let wall_texture = prepare_texture(&brick)?;
         self.textures = vec![
             wall_texture,
-            prepare_texture(&floor),
+            prepare_texture(&floor)?,
             wall_texture,
-            prepare_texture(&ceiling),
+            prepare_texture(&ceiling)?,
         ];
 
         for texture in self.textures.iter() {
@@ -36,9 +36,9 @@ impl DemoApp {
 impl App for DemoApp {
     fn initialize(&mut self, _window: &Window) -> Result<()> {
         self.load_shaders();
-        let (_, data) = load_ktx!("../../assets/textures/rightarrows.ktx").unwrap();
+        let data = load_ktx!("textures/rightarrows.ktx")?;
         let mut vao = 0;
-        let texture = prepare_texture(&data);
+        let texture = prepare_texture(&data)?;
         unsafe {
             gl::GenVertexArrays(1, &mut vao);
             gl::BindVertexArray(vao);
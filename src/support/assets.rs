@@ -0,0 +1,32 @@
+use std::{
+    env,
+    path::{Path, PathBuf},
+};
+
+pub const ASSET_ROOT_VARIABLE: &str = "SUPERBIBLE_ASSETS";
+
+// Demos are started from the workspace, from target/<profile> or from a copied build,
+// so the asset directory is searched for rather than assumed to be under the working directory.
+// The environment variable wins, then the nearest `assets` directory above the executable,
+// then the directory the crate was built from.
+pub fn asset_root() -> PathBuf {
+    if let Some(root) = env::var_os(ASSET_ROOT_VARIABLE) {
+        return PathBuf::from(root);
+    }
+    if let Ok(executable) = env::current_exe() {
+        let found = executable
+            .ancestors()
+            .skip(1)
+            .map(|directory| directory.join("assets"))
+            .find(|candidate| candidate.is_dir());
+        if let Some(root) = found {
+            return root;
+        }
+    }
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("assets")
+}
+
+// Absolute paths are returned unchanged
+pub fn asset_path<P: AsRef<Path>>(path: P) -> PathBuf {
+    asset_root().join(path)
+}
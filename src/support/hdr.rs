@@ -0,0 +1,334 @@
+use crate::ktx::{f32_to_half, Header, KeyValue, KtxData, MipLevel};
+use anyhow::{bail, ensure, Context, Result};
+use std::{borrow::Cow, fs, path::Path};
+
+// The type each channel is stored as in a texture made from an HdrImage
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloatFormat {
+    Half,
+    Float,
+}
+
+// A floating point image with tightly packed rows, top row first. Radiance files are
+// always RGB, PFM files can also be grayscale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HdrImage {
+    pub width: u32,
+    pub height: u32,
+    pub components: u32,
+    pub data: Vec<f32>,
+}
+
+impl HdrImage {
+    pub fn new(width: u32, height: u32, components: u32, data: Vec<f32>) -> Result<Self> {
+        ensure!(
+            components == 1 || components == 3,
+            "HDR images have 1 or 3 components, not {}",
+            components
+        );
+        let expected = width as usize * height as usize * components as usize;
+        ensure!(
+            data.len() == expected,
+            "a {}x{} image with {} components needs {} values but has {}",
+            width,
+            height,
+            components,
+            expected,
+            data.len()
+        );
+        Ok(HdrImage {
+            width,
+            height,
+            components,
+            data,
+        })
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        match extension.as_deref() {
+            Some("hdr") | Some("pic") => Self::decode_rgbe(&bytes),
+            Some("pfm") => Self::decode_pfm(&bytes),
+            _ => bail!("{} is not a Radiance HDR or PFM image", path.display()),
+        }
+        .with_context(|| format!("decoding {}", path.display()))
+    }
+
+    // Radiance RGBE with flat, old style run length or adaptive run length scanlines.
+    // EXPOSURE and the other header variables are ignored.
+    pub fn decode_rgbe(bytes: &[u8]) -> Result<Self> {
+        let mut lines = bytes.split(|byte| *byte == b'\n');
+        let magic = lines.next().unwrap_or_default();
+        ensure!(
+            magic == b"#?RADIANCE" || magic == b"#?RGBE",
+            "missing #?RADIANCE signature"
+        );
+        let mut offset = magic.len() + 1;
+        loop {
+            let line = lines.next().context("unexpected end of header")?;
+            offset += line.len() + 1;
+            if line.is_empty() {
+                break;
+            }
+            if let Some(format) = line.strip_prefix(b"FORMAT=") {
+                ensure!(
+                    format == b"32-bit_rle_rgbe",
+                    "unsupported format {}",
+                    String::from_utf8_lossy(format)
+                );
+            }
+        }
+
+        let resolution = lines.next().context("missing resolution line")?;
+        offset += resolution.len() + 1;
+        let resolution = std::str::from_utf8(resolution)?;
+        let (rows_run_up, height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..]
+        {
+            [y, height, "+X", width] if y == "-Y" || y == "+Y" => {
+                (y == "+Y", height.parse::<u32>()?, width.parse::<u32>()?)
+            }
+            _ => bail!("unsupported resolution {:?}", resolution),
+        };
+
+        let row_texels = width as usize;
+        ensure!(
+            row_texels
+                .checked_mul(height as usize)
+                .and_then(|texels| texels.checked_mul(3))
+                .is_some(),
+            "image dimensions are too large"
+        );
+        // Rows are added as they are read, so a header claiming more than the file holds
+        // fails at the first missing row instead of allocating for all of them
+        let rows = if row_texels == 0 { 0 } else { height };
+        let mut rgbe = Vec::new();
+        let mut data = &bytes[offset.min(bytes.len())..];
+        for _ in 0..rows {
+            let start = rgbe.len();
+            rgbe.try_reserve(row_texels).context("image is too large")?;
+            rgbe.resize(start + row_texels, [0u8; 4]);
+            data = read_scanline(data, &mut rgbe[start..]).context("pixel data is truncated")?;
+        }
+
+        let mut image = Self::new(
+            width,
+            height,
+            3,
+            rgbe.iter().flat_map(|texel| rgbe_to_rgb(*texel)).collect(),
+        )?;
+        if rows_run_up {
+            image.flip_vertically();
+        }
+        Ok(image)
+    }
+
+    // PF for RGB, Pf for grayscale. A negative scale marks little endian samples, and rows
+    // are stored bottom first.
+    pub fn decode_pfm(bytes: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut token = || -> Result<&[u8]> {
+            while bytes
+                .get(offset)
+                .is_some_and(|byte| byte.is_ascii_whitespace())
+            {
+                offset += 1;
+            }
+            let start = offset;
+            while bytes
+                .get(offset)
+                .is_some_and(|byte| !byte.is_ascii_whitespace())
+            {
+                offset += 1;
+            }
+            ensure!(offset > start, "unexpected end of header");
+            Ok(&bytes[start..offset])
+        };
+        let components = match token()? {
+            b"PF" => 3,
+            b"Pf" => 1,
+            _ => bail!("missing PF or Pf signature"),
+        };
+        let mut number = || -> Result<&str> { Ok(std::str::from_utf8(token()?)?) };
+        let width: u32 = number()?.parse()?;
+        let height: u32 = number()?.parse()?;
+        let scale = number()?;
+        let scale: f32 = scale
+            .parse()
+            .with_context(|| format!("{:?} is not a scale", scale))?;
+        ensure!(scale != 0.0 && scale.is_finite(), "invalid scale {}", scale);
+
+        // A single whitespace byte separates the header from the samples
+        let start = offset + 1;
+        let data = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|texels| texels.checked_mul(components as usize))
+            .and_then(|samples| samples.checked_mul(4))
+            .and_then(|length| start.checked_add(length))
+            .and_then(|end| bytes.get(start..end))
+            .context("pixel data is truncated")?;
+        let data = data
+            .chunks_exact(4)
+            .map(|sample| {
+                let sample = [sample[0], sample[1], sample[2], sample[3]];
+                if scale < 0.0 {
+                    f32::from_le_bytes(sample)
+                } else {
+                    f32::from_be_bytes(sample)
+                }
+            })
+            .collect();
+        let mut image = Self::new(width, height, components, data)?;
+        image.flip_vertically();
+        Ok(image)
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let row = (self.width as usize * self.components as usize).max(1);
+        let rows: Vec<_> = self.data.chunks(row).rev().flatten().copied().collect();
+        self.data = rows;
+    }
+
+    // A single level 2D texture, RGB16F/RGB32F or R16F/R32F, that prepare_texture can
+    // upload and write_ktx can save
+    pub fn to_texture(&self, format: FloatFormat) -> KtxData<'static> {
+        let (gl_format, gl_internal_format) = match (self.components, format) {
+            (1, FloatFormat::Half) => (gl::RED, gl::R16F),
+            (1, FloatFormat::Float) => (gl::RED, gl::R32F),
+            (_, FloatFormat::Half) => (gl::RGB, gl::RGB16F),
+            (_, FloatFormat::Float) => (gl::RGB, gl::RGB32F),
+        };
+        let (gl_type, gl_type_size, pixels) = match format {
+            FloatFormat::Half => (
+                gl::HALF_FLOAT,
+                2,
+                self.data
+                    .iter()
+                    .flat_map(|value| f32_to_half(*value).to_ne_bytes())
+                    .collect::<Vec<_>>(),
+            ),
+            FloatFormat::Float => (
+                gl::FLOAT,
+                4,
+                self.data
+                    .iter()
+                    .flat_map(|value| value.to_ne_bytes())
+                    .collect(),
+            ),
+        };
+
+        let image = 0..pixels.len();
+        KtxData {
+            header: Header {
+                endianess: 0x0403_0201u32.to_ne_bytes(),
+                gl_type,
+                gl_type_size,
+                gl_format,
+                gl_internal_format,
+                gl_base_internal_format: gl_format,
+                pixel_width: self.width,
+                pixel_height: self.height,
+                pixel_depth: 0,
+                array_elements: 0,
+                faces: 1,
+                mip_levels: 1,
+                key_pair_bytes: 0,
+            },
+            key_values: vec![KeyValue {
+                key: "KTXorient".to_string(),
+                value: b"S=r,T=d\0".to_vec(),
+            }],
+            levels: vec![MipLevel {
+                width: self.width,
+                height: self.height,
+                depth: 1,
+                images: vec![image],
+            }],
+            unpack_alignment: 1,
+            pixels: Cow::Owned(pixels),
+        }
+    }
+}
+
+fn rgbe_to_rgb([red, green, blue, exponent]: [u8; 4]) -> [f32; 3] {
+    if exponent == 0 {
+        return [0.0; 3];
+    }
+    let scale = 2f32.powi(exponent as i32 - (128 + 8));
+    [
+        red as f32 * scale,
+        green as f32 * scale,
+        blue as f32 * scale,
+    ]
+}
+
+// Reads one scanline, returning the data after it
+fn read_scanline<'a>(data: &'a [u8], row: &mut [[u8; 4]]) -> Option<&'a [u8]> {
+    let width = row.len();
+    // Adaptive run length scanlines start with 2 2 and the width, each channel stored
+    // separately as runs and literals
+    if (8..0x8000).contains(&width) && data.get(..2) == Some(&[2, 2]) && data.get(2)? & 0x80 == 0 {
+        let encoded_width = (*data.get(2)? as usize) << 8 | *data.get(3)? as usize;
+        if encoded_width != width {
+            return None;
+        }
+        let mut data = &data[4..];
+        for channel in 0..4 {
+            let mut texel = 0;
+            while texel < width {
+                let count = *data.first()? as usize;
+                if count > 128 {
+                    let count = count - 128;
+                    let value = *data.get(1)?;
+                    for rgbe in row.get_mut(texel..texel + count)? {
+                        rgbe[channel] = value;
+                    }
+                    data = &data[2..];
+                    texel += count;
+                } else {
+                    if count == 0 {
+                        return None;
+                    }
+                    let values = data.get(1..1 + count)?;
+                    for (rgbe, value) in row.get_mut(texel..texel + count)?.iter_mut().zip(values) {
+                        rgbe[channel] = *value;
+                    }
+                    data = &data[1 + count..];
+                    texel += count;
+                }
+            }
+        }
+        return Some(data);
+    }
+
+    // Flat texels, where 1 1 1 n repeats the previous texel n times, shifted left by 8
+    // bits for each repeat marker in a row
+    let mut data = data;
+    let mut texel = 0;
+    let mut shift = 0;
+    while texel < width {
+        let rgbe = [*data.first()?, *data.get(1)?, *data.get(2)?, *data.get(3)?];
+        data = &data[4..];
+        if rgbe[..3] == [1, 1, 1] {
+            let previous = *row.get(texel.checked_sub(1)?)?;
+            let count = (rgbe[3] as usize).checked_shl(shift)?;
+            if count > width - texel {
+                return None;
+            }
+            for repeated in &mut row[texel..texel + count] {
+                *repeated = previous;
+            }
+            texel += count;
+            shift += 8;
+        } else {
+            row[texel] = rgbe;
+            texel += 1;
+            shift = 0;
+        }
+    }
+    Some(data)
+}
@@ -0,0 +1,393 @@
+use crate::ktx::{
+    decode::{decode_image, Texels},
+    Header, KeyValue, KtxData, MipLevel, LUMINANCE, LUMINANCE_ALPHA,
+};
+use anyhow::{bail, ensure, Context, Result};
+use std::{
+    borrow::Cow,
+    fs::{self, File},
+    io::{BufWriter, Cursor},
+    path::Path,
+};
+
+// An 8 bit image with tightly packed rows, top row first
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub components: u32,
+    pub data: Vec<u8>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32, components: u32, data: Vec<u8>) -> Result<Self> {
+        ensure!(
+            (1..=4).contains(&components),
+            "images have 1 to 4 components, not {}",
+            components
+        );
+        let expected = width as usize * height as usize * components as usize;
+        ensure!(
+            data.len() == expected,
+            "a {}x{} image with {} components needs {} bytes but has {}",
+            width,
+            height,
+            components,
+            expected,
+            data.len()
+        );
+        Ok(Image {
+            width,
+            height,
+            components,
+            data,
+        })
+    }
+
+    // Picks the decoder from the file extension. Raw files have no header, see load_raw.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        let bytes = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        match extension.as_deref() {
+            Some("png") => Self::decode_png(&bytes),
+            Some("ppm") | Some("pgm") | Some("pnm") => Self::decode_pnm(&bytes),
+            _ => bail!("{} is not a PNG or PPM/PGM image", path.display()),
+        }
+        .with_context(|| format!("decoding {}", path.display()))
+    }
+
+    // Raw files are just pixels. Anything after them is ignored, smiley.raw for one
+    // carries a TGA footer.
+    pub fn load_raw<P: AsRef<Path>>(
+        path: P,
+        width: u32,
+        height: u32,
+        components: u32,
+    ) -> Result<Self> {
+        let path = path.as_ref();
+        let mut data = fs::read(path).with_context(|| format!("reading {}", path.display()))?;
+        let expected = width as usize * height as usize * components as usize;
+        ensure!(
+            data.len() >= expected,
+            "{} holds {} bytes but a {}x{} image with {} components needs {}",
+            path.display(),
+            data.len(),
+            width,
+            height,
+            components,
+            expected
+        );
+        data.truncate(expected);
+        Self::new(width, height, components, data)
+    }
+
+    pub fn decode_png(bytes: &[u8]) -> Result<Self> {
+        let mut decoder = png::Decoder::new(Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let mut reader = decoder.read_info()?;
+        let mut data = vec![0; reader.output_buffer_size()];
+        let info = reader.next_frame(&mut data)?;
+        data.truncate(info.buffer_size());
+        let components = match info.color_type {
+            png::ColorType::Grayscale => 1,
+            png::ColorType::GrayscaleAlpha => 2,
+            png::ColorType::Rgb => 3,
+            png::ColorType::Rgba => 4,
+            png::ColorType::Indexed => bail!("palette was not expanded"),
+        };
+        Self::new(info.width, info.height, components, data)
+    }
+
+    // Binary and ASCII PGM/PPM, with samples rescaled to 8 bits
+    pub fn decode_pnm(bytes: &[u8]) -> Result<Self> {
+        let mut offset = 0;
+        let mut token = || -> Result<&[u8]> {
+            loop {
+                match bytes.get(offset) {
+                    Some(b'#') => {
+                        while bytes.get(offset).is_some_and(|byte| *byte != b'\n') {
+                            offset += 1;
+                        }
+                    }
+                    Some(byte) if byte.is_ascii_whitespace() => offset += 1,
+                    Some(_) => break,
+                    None => bail!("unexpected end of header"),
+                }
+            }
+            let start = offset;
+            while bytes
+                .get(offset)
+                .is_some_and(|byte| !byte.is_ascii_whitespace())
+            {
+                offset += 1;
+            }
+            Ok(&bytes[start..offset])
+        };
+        let magic = token()?.to_vec();
+        let mut number = || -> Result<u32> {
+            let text = std::str::from_utf8(token()?)?;
+            text.parse()
+                .with_context(|| format!("{:?} is not a number", text))
+        };
+        let (components, ascii) = match &magic[..] {
+            b"P2" => (1, true),
+            b"P3" => (3, true),
+            b"P5" => (1, false),
+            b"P6" => (3, false),
+            _ => bail!("only P2, P3, P5 and P6 images are supported"),
+        };
+        let (width, height, max_value) = (number()?, number()?, number()?);
+        ensure!(
+            (1..=65535).contains(&max_value),
+            "invalid maximum value {}",
+            max_value
+        );
+        let samples = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|texels| texels.checked_mul(components as usize))
+            .context("image dimensions are too large")?;
+
+        let values: Vec<u32> = if ascii {
+            // Samples are at least a digit and a separator each, so the file bounds how many
+            // there can be whatever the header claims
+            let mut values = Vec::with_capacity(samples.min(bytes.len() / 2 + 1));
+            for _ in 0..samples {
+                values.push(number()?);
+            }
+            values
+        } else {
+            // A single whitespace byte separates the header from the samples
+            let start = offset + 1;
+            let wide = max_value > 255;
+            let sample_bytes = if wide { 2 } else { 1 };
+            let data = samples
+                .checked_mul(sample_bytes)
+                .and_then(|length| start.checked_add(length))
+                .and_then(|end| bytes.get(start..end))
+                .context("pixel data is truncated")?;
+            if wide {
+                data.chunks_exact(2)
+                    .map(|sample| u16::from_be_bytes([sample[0], sample[1]]) as u32)
+                    .collect()
+            } else {
+                data.iter().map(|sample| *sample as u32).collect()
+            }
+        };
+        let data = values
+            .into_iter()
+            .map(|value| ((value.min(max_value) * 255 + max_value / 2) / max_value) as u8)
+            .collect();
+        Self::new(width, height, components, data)
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        let file = File::create(path).with_context(|| format!("creating {}", path.display()))?;
+        let mut encoder = png::Encoder::new(BufWriter::new(file), self.width, self.height);
+        encoder.set_color(match self.components {
+            1 => png::ColorType::Grayscale,
+            2 => png::ColorType::GrayscaleAlpha,
+            3 => png::ColorType::Rgb,
+            _ => png::ColorType::Rgba,
+        });
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub fn flip_vertically(&mut self) {
+        let row = self.width as usize * self.components as usize;
+        let rows: Vec<_> = self.data.chunks(row).rev().flatten().copied().collect();
+        self.data = rows;
+    }
+
+    // Copies one 2D image out of a texture of unsigned bytes or block compressed texels.
+    // BGR(A) is swizzled to RGB(A) and RG gets an empty blue channel, since PNG has no two
+    // channel color type.
+    pub fn from_texture(
+        ktx_texture: &KtxData,
+        level: u32,
+        layer: u32,
+        face: u32,
+        slice: u32,
+    ) -> Result<Self> {
+        let ktx = &ktx_texture.header;
+        if ktx.is_compressed() {
+            return Self::from_compressed(ktx_texture, level, layer, face, slice);
+        }
+        ensure!(
+            ktx.gl_type == gl::UNSIGNED_BYTE,
+            "only textures of unsigned bytes can be extracted, not gl_type 0x{:X}",
+            ktx.gl_type
+        );
+        let (source_components, order): (usize, &[usize]) = match ktx.gl_format {
+            gl::RED | gl::GREEN | gl::BLUE | gl::ALPHA | LUMINANCE | gl::RED_INTEGER => (1, &[0]),
+            LUMINANCE_ALPHA => (2, &[0, 1]),
+            gl::RG | gl::RG_INTEGER => (2, &[0, 1, 2]),
+            gl::RGB | gl::RGB_INTEGER => (3, &[0, 1, 2]),
+            gl::BGR | gl::BGR_INTEGER => (3, &[2, 1, 0]),
+            gl::RGBA | gl::RGBA_INTEGER => (4, &[0, 1, 2, 3]),
+            gl::BGRA | gl::BGRA_INTEGER => (4, &[2, 1, 0, 3]),
+            format => bail!("gl_format 0x{:X} cannot be extracted", format),
+        };
+
+        let image = ktx_texture.image(level, layer, face).with_context(|| {
+            format!("no image at level {} layer {} face {}", level, layer, face)
+        })?;
+        let mip = &ktx_texture.levels[level as usize];
+        ensure!(
+            slice < mip.depth,
+            "level {} has {} slices",
+            level,
+            mip.depth
+        );
+
+        let row_bytes = mip.width as usize * source_components;
+        let alignment = ktx_texture.unpack_alignment as usize;
+        let stride = row_bytes.div_ceil(alignment) * alignment;
+        let mut data = Vec::with_capacity(mip.width as usize * mip.height as usize * order.len());
+        for row in 0..mip.height as usize {
+            let start = (slice as usize * mip.height as usize + row) * stride;
+            for texel in image[start..start + row_bytes].chunks_exact(source_components) {
+                data.extend(
+                    order
+                        .iter()
+                        .map(|component| texel.get(*component).copied().unwrap_or(0)),
+                );
+            }
+        }
+        Self::new(mip.width, mip.height, order.len() as u32, data)
+    }
+
+    // Decodes compressed images to RGBA, clamping the float formats to [0, 1]
+    fn from_compressed(
+        ktx_texture: &KtxData,
+        level: u32,
+        layer: u32,
+        face: u32,
+        slice: u32,
+    ) -> Result<Self> {
+        let decoded = decode_image(ktx_texture, level, layer, face)
+            .with_context(|| format!("decoding level {} layer {} face {}", level, layer, face))?;
+        ensure!(
+            slice < decoded.depth,
+            "level {} has {} slices",
+            level,
+            decoded.depth
+        );
+        let texels = match decoded.texels {
+            Texels::Rgba8(texels) => texels,
+            Texels::Rgba32F(texels) => texels
+                .iter()
+                .map(|value| (value.clamp(0.0, 1.0) * 255.0).round() as u8)
+                .collect(),
+        };
+        let slice_bytes = decoded.width as usize * decoded.height as usize * 4;
+        let start = slice as usize * slice_bytes;
+        Self::new(
+            decoded.width,
+            decoded.height,
+            4,
+            texels[start..start + slice_bytes].to_vec(),
+        )
+    }
+}
+
+// Whether the KTXorient key says the first row is the bottom one. The book's textures
+// have no orientation key and store the top row first, like images do.
+pub fn rows_run_up(ktx_texture: &KtxData) -> bool {
+    ktx_texture.key_values.iter().any(|key_value| {
+        key_value.key == "KTXorient" && String::from_utf8_lossy(&key_value.value).contains("T=u")
+    })
+}
+
+// Builds a single level texture of unsigned bytes from equally sized images, ordered
+// layer by layer with the faces of each layer adjacent. A layer count of zero makes a
+// texture that isn't an array.
+pub fn texture_from_images(
+    images: &[Image],
+    array_elements: u32,
+    faces: u32,
+    srgb: bool,
+) -> Result<KtxData<'static>> {
+    let first = images.first().context("no images were given")?;
+    let expected = array_elements.max(1) as usize * faces as usize;
+    ensure!(
+        images.len() == expected,
+        "{} layers of {} faces need {} images but {} were given",
+        array_elements.max(1),
+        faces,
+        expected,
+        images.len()
+    );
+    for (index, image) in images.iter().enumerate() {
+        ensure!(
+            (image.width, image.height, image.components)
+                == (first.width, first.height, first.components),
+            "image {} is {}x{} with {} components but image 0 is {}x{} with {}",
+            index,
+            image.width,
+            image.height,
+            image.components,
+            first.width,
+            first.height,
+            first.components
+        );
+    }
+    ensure!(
+        faces != 6 || first.width == first.height,
+        "cube map faces must be square"
+    );
+
+    let (gl_format, gl_internal_format) = match (first.components, srgb) {
+        (1, false) => (gl::RED, gl::R8),
+        (2, false) => (gl::RG, gl::RG8),
+        (3, false) => (gl::RGB, gl::RGB8),
+        (3, true) => (gl::RGB, gl::SRGB8),
+        (4, false) => (gl::RGBA, gl::RGBA8),
+        (4, true) => (gl::RGBA, gl::SRGB8_ALPHA8),
+        (components, _) => bail!("sRGB needs 3 or 4 components, not {}", components),
+    };
+
+    let mut pixels = Vec::new();
+    let mut ranges = Vec::new();
+    for image in images {
+        ranges.push(pixels.len()..pixels.len() + image.data.len());
+        pixels.extend_from_slice(&image.data);
+    }
+    Ok(KtxData {
+        header: Header {
+            endianess: 0x0403_0201u32.to_ne_bytes(),
+            gl_type: gl::UNSIGNED_BYTE,
+            gl_type_size: 1,
+            gl_format,
+            gl_internal_format,
+            gl_base_internal_format: gl_format,
+            pixel_width: first.width,
+            pixel_height: first.height,
+            pixel_depth: 0,
+            array_elements,
+            faces,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: vec![KeyValue {
+            key: "KTXorient".to_string(),
+            value: b"S=r,T=d\0".to_vec(),
+        }],
+        levels: vec![MipLevel {
+            width: first.width,
+            height: first.height,
+            depth: 1,
+            images: ranges,
+        }],
+        unpack_alignment: 1,
+        pixels: Cow::Owned(pixels),
+    })
+}
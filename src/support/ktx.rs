@@ -1,19 +1,201 @@
+use crate::assets::asset_path;
 use gl::types::{GLenum, GLvoid};
-use nom::{
-    bytes::complete::{tag, take},
-    combinator::rest,
-    multi::count,
-    number::complete::{be_u32, le_u32},
-    IResult,
+use std::{
+    borrow::Cow,
+    cmp,
+    error::Error,
+    fmt, fs,
+    io::{self, Read},
+    ops::Range,
+    path::Path,
 };
 
+pub mod array;
+pub mod dds;
+pub mod decode;
+pub mod envmap;
+pub mod ktx2;
+pub mod mipmap;
+pub mod rgtc;
+pub mod stream;
+pub mod writer;
+
 pub const KTX_IDENTIFIER: [u8; 12] = [
     0xAB, 0x4B, 0x54, 0x58, 0x20, 0x31, 0x31, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
 ];
 
-#[derive(Debug)]
-pub struct Header<'a> {
-    pub endianess: &'a [u8],
+const KTX_ENDIANNESS: u32 = 0x0403_0201;
+const KTX_HEADER_BYTES: usize = 64;
+
+// The spec pads rows, cube faces and mip levels to four bytes
+const KTX_ALIGNMENT: usize = 4;
+
+pub(crate) const LUMINANCE: GLenum = 0x1909;
+pub(crate) const LUMINANCE_ALPHA: GLenum = 0x190A;
+
+pub const COMPRESSED_RGB_S3TC_DXT1: GLenum = 0x83F0;
+pub const COMPRESSED_RGBA_S3TC_DXT1: GLenum = 0x83F1;
+pub const COMPRESSED_RGBA_S3TC_DXT3: GLenum = 0x83F2;
+pub const COMPRESSED_RGBA_S3TC_DXT5: GLenum = 0x83F3;
+pub const COMPRESSED_SRGB_S3TC_DXT1: GLenum = 0x8C4C;
+pub const COMPRESSED_SRGB_ALPHA_S3TC_DXT1: GLenum = 0x8C4D;
+pub const COMPRESSED_SRGB_ALPHA_S3TC_DXT3: GLenum = 0x8C4E;
+pub const COMPRESSED_SRGB_ALPHA_S3TC_DXT5: GLenum = 0x8C4F;
+pub const ETC1_RGB8: GLenum = 0x8D64;
+
+const HEADER_FIELDS: [&str; 12] = [
+    "gl_type",
+    "gl_type_size",
+    "gl_format",
+    "gl_internal_format",
+    "gl_base_internal_format",
+    "pixel_width",
+    "pixel_height",
+    "pixel_depth",
+    "array_elements",
+    "faces",
+    "mip_levels",
+    "key_pair_bytes",
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KtxError {
+    InvalidIdentifier,
+    InvalidEndianness(u32),
+    Truncated {
+        section: &'static str,
+        offset: usize,
+        needed: usize,
+        available: usize,
+    },
+    InvalidHeaderField {
+        field: &'static str,
+        offset: usize,
+        value: u32,
+        reason: &'static str,
+    },
+    InvalidKeyValue {
+        offset: usize,
+        reason: &'static str,
+    },
+    InvalidDataFormat {
+        offset: usize,
+        reason: &'static str,
+    },
+    ImageSizeMismatch {
+        level: u32,
+        offset: usize,
+        expected: usize,
+        found: usize,
+    },
+    InvalidLayer {
+        layer: usize,
+        reason: &'static str,
+    },
+    UnsupportedLayout(&'static str),
+    UnsupportedFormat(&'static str),
+    UnsupportedSupercompression(u32),
+    InvalidArgument(&'static str),
+    Readback(&'static str),
+    GlFailure(&'static str),
+    StreamCancelled,
+    NoSuchImage {
+        level: u32,
+        layer: u32,
+        face: u32,
+    },
+    Decompression {
+        level: u32,
+        reason: String,
+    },
+    Io {
+        kind: io::ErrorKind,
+        reason: String,
+    },
+}
+
+impl fmt::Display for KtxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            KtxError::InvalidIdentifier => write!(f, "missing KTX identifier at offset 0"),
+            KtxError::InvalidEndianness(value) => {
+                write!(f, "invalid endianness word 0x{:08X} at offset 12", value)
+            }
+            KtxError::Truncated {
+                section,
+                offset,
+                needed,
+                available,
+            } => write!(
+                f,
+                "truncated {} at offset {}: needed {} bytes but only {} remain",
+                section, offset, needed, available
+            ),
+            KtxError::InvalidHeaderField {
+                field,
+                offset,
+                value,
+                reason,
+            } => write!(
+                f,
+                "invalid header field {} = {} at offset {}: {}",
+                field, value, offset, reason
+            ),
+            KtxError::InvalidKeyValue { offset, reason } => {
+                write!(f, "invalid key/value pair at offset {}: {}", offset, reason)
+            }
+            KtxError::InvalidDataFormat { offset, reason } => write!(
+                f,
+                "invalid data format descriptor at offset {}: {}",
+                offset, reason
+            ),
+            KtxError::ImageSizeMismatch {
+                level,
+                offset,
+                expected,
+                found,
+            } => write!(
+                f,
+                "imageSize of mip level {} at offset {} is {} but the header implies {}",
+                level, offset, found, expected
+            ),
+            KtxError::InvalidLayer { layer, reason } => {
+                write!(f, "array layer {} cannot be used: {}", layer, reason)
+            }
+            KtxError::UnsupportedLayout(layout) => {
+                write!(f, "unsupported texture layout: {}", layout)
+            }
+            KtxError::UnsupportedFormat(reason) => {
+                write!(f, "unsupported pixel format: {}", reason)
+            }
+            KtxError::UnsupportedSupercompression(scheme) => {
+                write!(f, "unsupported supercompression scheme {}", scheme)
+            }
+            KtxError::InvalidArgument(reason) => write!(f, "invalid argument: {}", reason),
+            KtxError::Readback(reason) => write!(f, "cannot read back texture: {}", reason),
+            KtxError::GlFailure(operation) => write!(f, "GL failed to {}", operation),
+            KtxError::StreamCancelled => write!(
+                f,
+                "the texture streamer was dropped before the texture was uploaded"
+            ),
+            KtxError::NoSuchImage { level, layer, face } => write!(
+                f,
+                "no image at mip level {}, layer {}, face {}",
+                level, layer, face
+            ),
+            KtxError::Decompression { level, reason } => {
+                write!(f, "failed to decompress mip level {}: {}", level, reason)
+            }
+            KtxError::Io { reason, .. } => write!(f, "failed to read texture: {}", reason),
+        }
+    }
+}
+
+impl Error for KtxError {}
+
+#[derive(Debug, Clone)]
+pub struct Header {
+    pub endianess: [u8; 4],
     pub gl_type: u32,
     pub gl_type_size: u32,
     pub gl_format: u32,
@@ -28,289 +210,1201 @@ pub struct Header<'a> {
     pub key_pair_bytes: u32,
 }
 
+impl Header {
+    pub fn layer_count(&self) -> u32 {
+        cmp::max(self.array_elements, 1)
+    }
+
+    pub fn face_count(&self) -> u32 {
+        if self.faces == 6 {
+            6
+        } else {
+            1
+        }
+    }
+
+    pub fn level_count(&self) -> u32 {
+        cmp::max(self.mip_levels, 1)
+    }
+
+    // The number of levels down to 1x1x1
+    pub fn full_level_count(&self) -> u32 {
+        let largest = cmp::max(
+            self.pixel_width,
+            cmp::max(self.pixel_height, self.pixel_depth),
+        );
+        32 - largest.leading_zeros()
+    }
+
+    pub fn is_compressed(&self) -> bool {
+        self.gl_type == 0
+    }
+
+    pub fn level_dimensions(&self, level: u32) -> (u32, u32, u32) {
+        let dimension = |size: u32| cmp::max(size.checked_shr(level).unwrap_or(0), 1);
+        (
+            dimension(self.pixel_width),
+            dimension(self.pixel_height),
+            dimension(self.pixel_depth),
+        )
+    }
+
+    // Bytes per texel of uncompressed data, before any row padding
+    pub fn pixel_size(&self) -> Option<u32> {
+        match self.gl_type {
+            gl::UNSIGNED_BYTE_3_3_2 | gl::UNSIGNED_BYTE_2_3_3_REV => Some(1),
+            gl::UNSIGNED_SHORT_5_6_5
+            | gl::UNSIGNED_SHORT_5_6_5_REV
+            | gl::UNSIGNED_SHORT_4_4_4_4
+            | gl::UNSIGNED_SHORT_4_4_4_4_REV
+            | gl::UNSIGNED_SHORT_5_5_5_1
+            | gl::UNSIGNED_SHORT_1_5_5_5_REV => Some(2),
+            gl::UNSIGNED_INT_8_8_8_8
+            | gl::UNSIGNED_INT_8_8_8_8_REV
+            | gl::UNSIGNED_INT_10_10_10_2
+            | gl::UNSIGNED_INT_2_10_10_10_REV
+            | gl::UNSIGNED_INT_10F_11F_11F_REV
+            | gl::UNSIGNED_INT_5_9_9_9_REV
+            | gl::UNSIGNED_INT_24_8 => Some(4),
+            gl::FLOAT_32_UNSIGNED_INT_24_8_REV => Some(8),
+            gl_type => Some(format_components(self.gl_format)? * type_size(gl_type)?),
+        }
+    }
+
+    fn images_per_level(&self) -> usize {
+        self.layer_count() as usize * self.face_count() as usize
+    }
+
+    fn validate(&self) -> Result<(), KtxError> {
+        let invalid = |index: usize, value: u32, reason: &'static str| {
+            Err(KtxError::InvalidHeaderField {
+                field: HEADER_FIELDS[index],
+                offset: 16 + index * 4,
+                value,
+                reason,
+            })
+        };
+
+        match type_swap_size(self.gl_type) {
+            None => return invalid(0, self.gl_type, "unknown pixel type"),
+            Some(size) if size != self.gl_type_size => {
+                return invalid(1, self.gl_type_size, "does not match gl_type")
+            }
+            _ => {}
+        }
+        if self.gl_type == 0 && self.gl_format != 0 {
+            return invalid(2, self.gl_format, "compressed data must use a format of 0");
+        }
+        if self.gl_type == 0 && block_format(self.gl_internal_format).is_none() {
+            return invalid(
+                3,
+                self.gl_internal_format,
+                "unknown compressed internal format",
+            );
+        }
+        if self.gl_type != 0 && format_components(self.gl_format).is_none() {
+            return invalid(2, self.gl_format, "unknown pixel format");
+        }
+        if self.gl_internal_format == 0 {
+            return invalid(3, self.gl_internal_format, "must not be zero");
+        }
+        if self.pixel_width == 0 {
+            return invalid(5, self.pixel_width, "must not be zero");
+        }
+        if self.pixel_height == 0 && self.pixel_depth != 0 {
+            return invalid(7, self.pixel_depth, "3D textures need a height");
+        }
+        if self.pixel_depth != 0 && self.array_elements != 0 {
+            return invalid(8, self.array_elements, "3D textures cannot be arrays");
+        }
+        match self.faces {
+            0 | 1 => {}
+            6 => {
+                if self.pixel_height != self.pixel_width {
+                    return invalid(6, self.pixel_height, "cube map faces must be square");
+                }
+                if self.pixel_depth != 0 {
+                    return invalid(7, self.pixel_depth, "cube maps cannot be 3D");
+                }
+            }
+            faces => return invalid(9, faces, "must be 1 or 6"),
+        }
+        if self.mip_levels > self.full_level_count() {
+            return invalid(10, self.mip_levels, "more levels than the dimensions allow");
+        }
+        if !self.key_pair_bytes.is_multiple_of(4) {
+            return invalid(11, self.key_pair_bytes, "must be a multiple of four");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyValue {
+    pub key: String,
+    pub value: Vec<u8>,
+}
+
+// Byte ranges into `KtxData::pixels` for every layer and face of one mip level,
+// ordered layer by layer with the faces of each layer adjacent
+#[derive(Debug, Clone)]
+pub struct MipLevel {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub images: Vec<Range<usize>>,
+}
+
 pub struct KtxData<'a> {
-    pub header: Header<'a>,
-    pub pixels: &'a [u8],
+    pub header: Header,
+    pub key_values: Vec<KeyValue>,
+    pub levels: Vec<MipLevel>,
+    pub unpack_alignment: i32,
+    pub pixels: Cow<'a, [u8]>,
+}
+
+impl<'a> KtxData<'a> {
+    pub fn image(&self, level: u32, layer: u32, face: u32) -> Option<&[u8]> {
+        if layer >= self.header.layer_count() || face >= self.header.face_count() {
+            return None;
+        }
+        let index = layer as usize * self.header.face_count() as usize + face as usize;
+        let range = self.levels.get(level as usize)?.images.get(index)?;
+        Some(&self.pixels[range.clone()])
+    }
+
+    pub fn level_data(&self, level: u32) -> Option<&[u8]> {
+        let images = &self.levels.get(level as usize)?.images;
+        Some(&self.pixels[images.first()?.start..images.last()?.end])
+    }
+
+    pub fn into_owned(self) -> KtxData<'static> {
+        KtxData {
+            header: self.header,
+            key_values: self.key_values,
+            levels: self.levels,
+            unpack_alignment: self.unpack_alignment,
+            pixels: Cow::Owned(self.pixels.into_owned()),
+        }
+    }
 }
 
+// Loads a texture from a path relative to the asset root, e.g. load_ktx!("textures/brick.ktx")
 #[macro_export]
 macro_rules! load_ktx {
-    ($path:tt) => {
-        $crate::ktx::parse_ktx(include_bytes!($path))
+    ($path:expr) => {
+        $crate::ktx::load_ktx_asset($path)
     };
 }
 
-pub fn parse_ktx<'a>(input: &'a [u8]) -> IResult<&'a [u8], KtxData> {
-    let (input, _) = tag(KTX_IDENTIFIER)(input)?;
-    let (input, endianess) = take(4usize)(input)?;
-    let big_endian = endianess[0] == 0x04;
-    let take_endian = |input| -> IResult<&'a [u8], u32> {
-        if big_endian {
-            be_u32(input)
-        } else {
-            le_u32(input)
-        }
-    };
-    let (input, members) = count(take_endian, 12usize)(input)?;
-    let mut member_iter = members.iter();
+// Accepts KTX 1.1, KTX 2.0 or DDS data, telling them apart by their identifier
+pub fn parse_texture(input: &[u8]) -> Result<KtxData<'_>, KtxError> {
+    if input.starts_with(&ktx2::KTX2_IDENTIFIER) {
+        ktx2::parse_ktx2(input).map(|ktx2| ktx2.texture)
+    } else if input.starts_with(&dds::DDS_MAGIC) {
+        dds::parse_dds(input)
+    } else {
+        parse_ktx(input)
+    }
+}
 
-    let (input, pixels) = rest(input)?;
+pub fn read_ktx<R: Read>(mut reader: R) -> Result<KtxData<'static>, KtxError> {
+    let mut input = Vec::new();
+    reader
+        .read_to_end(&mut input)
+        .map_err(|error| KtxError::Io {
+            kind: error.kind(),
+            reason: error.to_string(),
+        })?;
+    parse_texture(&input).map(KtxData::into_owned)
+}
 
-    Ok((
-        input,
-        KtxData {
-            header: Header {
-                endianess,
-                gl_type: *member_iter.next().unwrap(),
-                gl_type_size: *member_iter.next().unwrap(),
-                gl_format: *member_iter.next().unwrap(),
-                gl_internal_format: *member_iter.next().unwrap(),
-                gl_base_internal_format: *member_iter.next().unwrap(),
-                pixel_width: *member_iter.next().unwrap(),
-                pixel_height: *member_iter.next().unwrap(),
-                pixel_depth: *member_iter.next().unwrap(),
-                array_elements: *member_iter.next().unwrap(),
-                faces: *member_iter.next().unwrap(),
-                mip_levels: *member_iter.next().unwrap(),
-                key_pair_bytes: *member_iter.next().unwrap(),
-            },
-            pixels,
-        },
-    ))
+pub fn load_ktx_file<P: AsRef<Path>>(path: P) -> Result<KtxData<'static>, KtxError> {
+    let path = path.as_ref();
+    let input = fs::read(path).map_err(|error| KtxError::Io {
+        kind: error.kind(),
+        reason: format!("{}: {}", path.display(), error),
+    })?;
+    parse_texture(&input).map(KtxData::into_owned)
 }
 
-pub fn prepare_texture(ktx_texture: &KtxData) -> u32 {
-    let target = determine_target(&ktx_texture);
-    let mut texture = 0;
-    unsafe {
-        gl::GenTextures(1, &mut texture);
-        gl::BindTexture(target, texture);
+pub fn load_ktx_asset<P: AsRef<Path>>(path: P) -> Result<KtxData<'static>, KtxError> {
+    load_ktx_file(asset_path(path))
+}
+
+// Reads fixed size fields from the file, reporting the absolute offset on failure
+struct Reader<'a> {
+    input: &'a [u8],
+    base: usize,
+    big_endian: bool,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(
+        &self,
+        offset: usize,
+        length: usize,
+        section: &'static str,
+    ) -> Result<&'a [u8], KtxError> {
+        offset
+            .checked_add(length)
+            .and_then(|end| self.input.get(offset..end))
+            .ok_or(KtxError::Truncated {
+                section,
+                offset: self.base + offset,
+                needed: length,
+                available: self.input.len().saturating_sub(offset),
+            })
     }
 
-    match target {
-        gl::TEXTURE_1D => prepare_texture_1d(ktx_texture),
-        gl::TEXTURE_1D_ARRAY => prepare_texture_1d_array(ktx_texture),
-        gl::TEXTURE_2D => prepare_texture_2d(ktx_texture),
-        gl::TEXTURE_2D_ARRAY => prepare_texture_2d_array(ktx_texture),
-        gl::TEXTURE_3D => prepare_texture_3d(ktx_texture),
-        gl::TEXTURE_CUBE_MAP => prepare_texture_cube_map(ktx_texture),
-        gl::TEXTURE_CUBE_MAP_ARRAY => prepare_texture_cube_map_array(ktx_texture),
-        _ => {} // TODO: This should be an error
+    fn u32(&self, offset: usize, section: &'static str) -> Result<u32, KtxError> {
+        let bytes = self.bytes(offset, 4, section)?;
+        let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+        Ok(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
     }
 
-    let ktx = &ktx_texture.header;
-    if ktx.mip_levels == 1 {
-        unsafe {
-            gl::GenerateMipmap(target);
+    fn u64(&self, offset: usize, section: &'static str) -> Result<u64, KtxError> {
+        let low = self.u32(offset, section)? as u64;
+        let high = self.u32(offset + 4, section)? as u64;
+        Ok(if self.big_endian {
+            (low << 32) | high
+        } else {
+            (high << 32) | low
+        })
+    }
+}
+
+pub fn parse_ktx(input: &[u8]) -> Result<KtxData<'_>, KtxError> {
+    if !input.starts_with(&KTX_IDENTIFIER) {
+        return if KTX_IDENTIFIER.starts_with(input) {
+            Err(KtxError::Truncated {
+                section: "identifier",
+                offset: 0,
+                needed: KTX_IDENTIFIER.len(),
+                available: input.len(),
+            })
+        } else {
+            Err(KtxError::InvalidIdentifier)
+        };
+    }
+
+    let mut reader = Reader {
+        input,
+        base: 0,
+        big_endian: false,
+    };
+    let endianess = reader.bytes(12, 4, "header")?;
+    let endianess = [endianess[0], endianess[1], endianess[2], endianess[3]];
+    reader.big_endian = match u32::from_le_bytes(endianess) {
+        KTX_ENDIANNESS => false,
+        value if value.swap_bytes() == KTX_ENDIANNESS => true,
+        value => return Err(KtxError::InvalidEndianness(value)),
+    };
+
+    let mut members = [0; 12];
+    for (index, member) in members.iter_mut().enumerate() {
+        *member = reader.u32(16 + index * 4, "header")?;
+    }
+    let header = Header {
+        endianess,
+        gl_type: members[0],
+        gl_type_size: members[1],
+        gl_format: members[2],
+        gl_internal_format: members[3],
+        gl_base_internal_format: members[4],
+        pixel_width: members[5],
+        pixel_height: members[6],
+        pixel_depth: members[7],
+        array_elements: members[8],
+        faces: members[9],
+        mip_levels: members[10],
+        key_pair_bytes: members[11],
+    };
+    header.validate()?;
+
+    let key_value_data = reader.bytes(
+        KTX_HEADER_BYTES,
+        header.key_pair_bytes as usize,
+        "key/value data",
+    )?;
+    let key_values = parse_key_values(&Reader {
+        input: key_value_data,
+        base: KTX_HEADER_BYTES,
+        big_endian: reader.big_endian,
+    })?;
+
+    let pixel_offset = KTX_HEADER_BYTES + key_value_data.len();
+    let pixels = &input[pixel_offset..];
+    let (unpack_alignment, levels) = parse_levels(
+        &header,
+        &Reader {
+            input: pixels,
+            base: pixel_offset,
+            big_endian: reader.big_endian,
+        },
+    )?;
+
+    // Pixel data is stored in units of gl_type_size, which must be put in the machine's byte order
+    let pixels = if reader.big_endian != cfg!(target_endian = "big") && header.gl_type_size > 1 {
+        Cow::Owned(swap_bytes(pixels, &levels, header.gl_type_size as usize))
+    } else {
+        Cow::Borrowed(pixels)
+    };
+
+    Ok(KtxData {
+        header,
+        key_values,
+        levels,
+        unpack_alignment,
+        pixels,
+    })
+}
+
+fn swap_bytes(pixels: &[u8], levels: &[MipLevel], unit: usize) -> Vec<u8> {
+    let mut swapped = pixels.to_vec();
+    for image in levels.iter().flat_map(|level| level.images.iter()) {
+        for value in swapped[image.clone()].chunks_exact_mut(unit) {
+            value.reverse();
         }
     }
+    swapped
+}
 
-    texture
+fn pad(offset: usize, alignment: usize) -> usize {
+    (offset + (alignment - 1)) & !(alignment - 1)
 }
 
-fn prepare_texture_1d(ktx_texture: &KtxData) {
-    let ktx = &ktx_texture.header;
-    let image = &ktx_texture.pixels;
-    let target = gl::TEXTURE_1D;
+// Copies rows of row_bytes from one row alignment to another
+fn repack_rows(image: &[u8], row_bytes: usize, rows: usize, from: usize, to: usize) -> Vec<u8> {
+    if from == to {
+        return image.to_vec();
+    }
+    let (source_stride, stride) = (pad(row_bytes, from), pad(row_bytes, to));
+    let mut repacked = vec![0; stride * rows];
+    for row in 0..rows {
+        let source = &image[row * source_stride..row * source_stride + row_bytes];
+        repacked[row * stride..row * stride + row_bytes].copy_from_slice(source);
+    }
+    repacked
+}
 
-    unsafe {
-        gl::TexStorage1D(
-            target,
-            ktx.mip_levels as i32,
-            ktx.gl_internal_format,
-            ktx.pixel_width as i32,
-        );
-        gl::TexSubImage1D(
-            target,
-            0,
-            0,
-            ktx.pixel_width as i32,
-            ktx.gl_format,
-            ktx.gl_internal_format,
-            image.as_ptr() as *const GLvoid,
-        );
+fn parse_key_values(reader: &Reader) -> Result<Vec<KeyValue>, KtxError> {
+    let mut key_values = Vec::new();
+    let mut offset = 0;
+    while offset < reader.input.len() {
+        let size = reader.u32(offset, "key/value data")? as usize;
+        let pair = reader.bytes(offset + 4, size, "key/value data")?;
+
+        // Keys are NUL terminated UTF-8, the value is whatever follows the terminator
+        let invalid = |reason| KtxError::InvalidKeyValue {
+            offset: reader.base + offset,
+            reason,
+        };
+        let terminator = pair
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| invalid("key is not NUL terminated"))?;
+        let key = std::str::from_utf8(&pair[..terminator])
+            .map_err(|_| invalid("key is not valid UTF-8"))?;
+        key_values.push(KeyValue {
+            key: key.to_string(),
+            value: pair[terminator + 1..].to_vec(),
+        });
+
+        offset = pad(offset + 4 + size, KTX_ALIGNMENT);
     }
+    Ok(key_values)
 }
 
-fn prepare_texture_2d(ktx_texture: &KtxData) {
-    let ktx = &ktx_texture.header;
-    let image = &ktx_texture.pixels;
-    let target = gl::TEXTURE_2D;
-    let mut image_ptr = image.as_ptr() as *const GLvoid;
-    let mut width = ktx.pixel_width as i32;
-    let mut height = ktx.pixel_height as i32;
-    unsafe {
-        gl::TexStorage2D(
-            target,
-            ktx.mip_levels as i32,
-            ktx.gl_internal_format,
-            ktx.pixel_width as i32,
-            ktx.pixel_height as i32,
-        );
+// Files written to the spec prefix every level with its imageSize and pad rows to four bytes.
+// The textures shipped with the book are tightly packed with no imageSize words,
+// so those are accepted as well when the first imageSize doesn't match the header.
+fn parse_levels(header: &Header, reader: &Reader) -> Result<(i32, Vec<MipLevel>), KtxError> {
+    let spec_error = match spec_layout(header, reader) {
+        Ok(levels) => return Ok((KTX_ALIGNMENT as i32, levels)),
+        Err(error) => error,
+    };
+    let first_image_size = match spec_error {
+        KtxError::ImageSizeMismatch { level: 0, .. } => true,
+        KtxError::Truncated {
+            section: "imageSize",
+            offset,
+            ..
+        } => offset == reader.base,
+        _ => false,
+    };
+    if !first_image_size {
+        return Err(spec_error);
+    }
 
-        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 1);
+    let mut packed_error = None;
+    for alignment in [KTX_ALIGNMENT, 1].iter() {
+        match packed_layout(header, reader, *alignment) {
+            Ok(levels) => return Ok((*alignment as i32, levels)),
+            Err(error) => packed_error = packed_error.or(Some(error)),
+        }
+    }
+    match packed_error {
+        Some(error @ KtxError::Truncated { .. }) => Err(error),
+        _ => Err(spec_error),
     }
+}
 
-    for level in 0..ktx.mip_levels {
-        unsafe {
-            gl::TexSubImage2D(
-                target,
-                level as i32,
-                0,
-                0,
-                width,
-                height,
-                ktx.gl_format,
-                ktx.gl_type,
-                image_ptr,
-            );
+fn spec_layout(header: &Header, reader: &Reader) -> Result<Vec<MipLevel>, KtxError> {
+    let images_per_level = header.images_per_level();
+
+    // Non-array cube maps store the size of a single face and pad every face
+    let non_array_cube = header.face_count() == 6 && header.array_elements == 0;
+
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    for level in 0..header.level_count() {
+        let (width, height, depth) = header.level_dimensions(level);
+        let image_size = reader.u32(offset, "imageSize")? as usize;
+        let mismatch = |expected| KtxError::ImageSizeMismatch {
+            level,
+            offset: reader.base + offset,
+            expected,
+            found: image_size,
+        };
+
+        let image_bytes = match image_bytes_for(header, width, height, depth, KTX_ALIGNMENT) {
+            Some(expected) => {
+                let expected_size = if non_array_cube {
+                    expected
+                } else {
+                    expected.saturating_mul(images_per_level)
+                };
+                if image_size != expected_size {
+                    return Err(mismatch(expected_size));
+                }
+                expected
+            }
+            // Without a known pixel size the level can only be split evenly between its images
+            None if non_array_cube => image_size,
+            None if image_size.is_multiple_of(images_per_level) => image_size / images_per_level,
+            None => return Err(mismatch(image_size - image_size % images_per_level)),
+        };
+        if image_bytes == 0 {
+            return Err(mismatch(images_per_level));
         }
+        offset += 4;
 
-        let stride = calculate_stride(&ktx_texture, width, 1);
-        // TODO: Find a safe way to do this.
-        unsafe {
-            image_ptr = image_ptr.offset(height as isize * stride);
+        let mut images = Vec::new();
+        for _ in 0..images_per_level {
+            reader.bytes(offset, image_bytes, "image data")?;
+            images.push(offset..offset + image_bytes);
+            offset += image_bytes;
+            if non_array_cube {
+                offset = pad(offset, KTX_ALIGNMENT);
+            }
         }
-        height >>= 1;
-        width >>= 1;
-        if height == 0 {
-            height = 1;
+        offset = pad(offset, KTX_ALIGNMENT);
+
+        levels.push(MipLevel {
+            width,
+            height,
+            depth,
+            images,
+        });
+    }
+    Ok(levels)
+}
+
+fn packed_layout(
+    header: &Header,
+    reader: &Reader,
+    alignment: usize,
+) -> Result<Vec<MipLevel>, KtxError> {
+    let mut levels = Vec::new();
+    let mut offset = 0;
+    for level in 0..header.level_count() {
+        let (width, height, depth) = header.level_dimensions(level);
+        let image_bytes = image_bytes_for(header, width, height, depth, alignment).ok_or(
+            KtxError::InvalidHeaderField {
+                field: "gl_format",
+                offset: 24,
+                value: header.gl_format,
+                reason: "image sizes cannot be derived without imageSize fields",
+            },
+        )?;
+        let mut images = Vec::new();
+        for _ in 0..header.images_per_level() {
+            reader.bytes(offset, image_bytes, "image data")?;
+            images.push(offset..offset + image_bytes);
+            offset += image_bytes;
         }
-        if width == 0 {
-            width = 1;
+        levels.push(MipLevel {
+            width,
+            height,
+            depth,
+            images,
+        });
+    }
+    Ok(levels)
+}
+
+fn image_bytes_for(
+    header: &Header,
+    width: u32,
+    height: u32,
+    depth: u32,
+    alignment: usize,
+) -> Option<usize> {
+    if header.is_compressed() {
+        let format = block_format(header.gl_internal_format)?;
+        return format.image_bytes(width, height, depth);
+    }
+    let row = pad(
+        (width as usize).checked_mul(header.pixel_size()? as usize)?,
+        alignment,
+    );
+    row.checked_mul(height as usize)?
+        .checked_mul(depth as usize)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockFormat {
+    pub block_width: u32,
+    pub block_height: u32,
+    pub block_bytes: u32,
+}
+
+impl BlockFormat {
+    pub fn image_bytes(&self, width: u32, height: u32, depth: u32) -> Option<usize> {
+        let blocks_wide = width.div_ceil(self.block_width) as usize;
+        let blocks_high = height.div_ceil(self.block_height) as usize;
+        blocks_wide
+            .checked_mul(blocks_high)?
+            .checked_mul(depth as usize)?
+            .checked_mul(self.block_bytes as usize)
+    }
+}
+
+pub fn block_format(internal_format: GLenum) -> Option<BlockFormat> {
+    let block_bytes = match internal_format {
+        COMPRESSED_RGB_S3TC_DXT1
+        | COMPRESSED_RGBA_S3TC_DXT1
+        | COMPRESSED_SRGB_S3TC_DXT1
+        | COMPRESSED_SRGB_ALPHA_S3TC_DXT1
+        | gl::COMPRESSED_RED_RGTC1
+        | gl::COMPRESSED_SIGNED_RED_RGTC1
+        | ETC1_RGB8
+        | gl::COMPRESSED_RGB8_ETC2
+        | gl::COMPRESSED_SRGB8_ETC2
+        | gl::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2
+        | gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2
+        | gl::COMPRESSED_R11_EAC
+        | gl::COMPRESSED_SIGNED_R11_EAC => 8,
+        COMPRESSED_RGBA_S3TC_DXT3
+        | COMPRESSED_RGBA_S3TC_DXT5
+        | COMPRESSED_SRGB_ALPHA_S3TC_DXT3
+        | COMPRESSED_SRGB_ALPHA_S3TC_DXT5
+        | gl::COMPRESSED_RG_RGTC2
+        | gl::COMPRESSED_SIGNED_RG_RGTC2
+        | gl::COMPRESSED_RGBA_BPTC_UNORM
+        | gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM
+        | gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT
+        | gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT
+        | gl::COMPRESSED_RGBA8_ETC2_EAC
+        | gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC
+        | gl::COMPRESSED_RG11_EAC
+        | gl::COMPRESSED_SIGNED_RG11_EAC => 16,
+        _ => return None,
+    };
+    Some(BlockFormat {
+        block_width: 4,
+        block_height: 4,
+        block_bytes,
+    })
+}
+
+fn type_size(gl_type: GLenum) -> Option<u32> {
+    match gl_type {
+        gl::BYTE | gl::UNSIGNED_BYTE => Some(1),
+        gl::SHORT | gl::UNSIGNED_SHORT | gl::HALF_FLOAT => Some(2),
+        gl::INT | gl::UNSIGNED_INT | gl::FLOAT => Some(4),
+        _ => None,
+    }
+}
+
+// The size of the units gl_type_size describes, which is what endianness conversion swaps
+fn type_swap_size(gl_type: GLenum) -> Option<u32> {
+    match gl_type {
+        0 | gl::UNSIGNED_BYTE_3_3_2 | gl::UNSIGNED_BYTE_2_3_3_REV => Some(1),
+        gl::UNSIGNED_SHORT_5_6_5
+        | gl::UNSIGNED_SHORT_5_6_5_REV
+        | gl::UNSIGNED_SHORT_4_4_4_4
+        | gl::UNSIGNED_SHORT_4_4_4_4_REV
+        | gl::UNSIGNED_SHORT_5_5_5_1
+        | gl::UNSIGNED_SHORT_1_5_5_5_REV => Some(2),
+        gl::UNSIGNED_INT_8_8_8_8
+        | gl::UNSIGNED_INT_8_8_8_8_REV
+        | gl::UNSIGNED_INT_10_10_10_2
+        | gl::UNSIGNED_INT_2_10_10_10_REV
+        | gl::UNSIGNED_INT_10F_11F_11F_REV
+        | gl::UNSIGNED_INT_5_9_9_9_REV
+        | gl::UNSIGNED_INT_24_8
+        | gl::FLOAT_32_UNSIGNED_INT_24_8_REV => Some(4),
+        gl_type => type_size(gl_type),
+    }
+}
+
+pub fn half_to_f32(half: u16) -> f32 {
+    let sign = if half & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((half >> 10) & 0x1F) as i32;
+    let mantissa = (half & 0x3FF) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        31 if mantissa == 0.0 => sign * f32::INFINITY,
+        31 => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
+
+// Rounds to the nearest half, saturating to infinity
+pub fn f32_to_half(value: f32) -> u16 {
+    let bits = value.to_bits();
+    let sign = ((bits >> 16) & 0x8000) as u16;
+    let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+    let mantissa = bits & 0x007F_FFFF;
+    if value.is_nan() {
+        return sign | 0x7E00;
+    }
+    if exponent >= 31 {
+        return sign | 0x7C00;
+    }
+    if exponent <= 0 {
+        if exponent < -10 {
+            return sign;
         }
+        let mantissa = mantissa | 0x0080_0000;
+        let shift = (14 - exponent) as u32;
+        let rounded = (mantissa + (1 << (shift - 1))) >> shift;
+        return sign | rounded as u16;
     }
+    // A carry out of the mantissa correctly rounds up into the exponent
+    let half = ((exponent as u32) << 10) | (mantissa >> 13);
+    sign | (half + ((mantissa >> 12) & 1)) as u16
 }
 
-fn prepare_texture_3d(ktx_texture: &KtxData) {
-    let ktx = &ktx_texture.header;
-    let image = &ktx_texture.pixels;
-    let target = gl::TEXTURE_3D;
+fn format_components(gl_format: GLenum) -> Option<u32> {
+    match gl_format {
+        gl::RED
+        | gl::GREEN
+        | gl::BLUE
+        | gl::ALPHA
+        | LUMINANCE
+        | gl::RED_INTEGER
+        | gl::GREEN_INTEGER
+        | gl::BLUE_INTEGER
+        | gl::STENCIL_INDEX
+        | gl::DEPTH_COMPONENT => Some(1),
+        gl::RG | gl::RG_INTEGER | LUMINANCE_ALPHA | gl::DEPTH_STENCIL => Some(2),
+        gl::RGB | gl::BGR | gl::RGB_INTEGER | gl::BGR_INTEGER => Some(3),
+        gl::RGBA | gl::BGRA | gl::RGBA_INTEGER | gl::BGRA_INTEGER => Some(4),
+        _ => None,
+    }
+}
 
+pub fn prepare_texture(ktx_texture: &KtxData) -> Result<u32, KtxError> {
+    if let Some(mipmapped) = generate_requested_mipmaps(ktx_texture)? {
+        return prepare_texture(&mipmapped);
+    }
+    let target = determine_target(ktx_texture)?;
+    if let Some(decompressed) = decompress_unsupported(ktx_texture, target)? {
+        return prepare_texture(&decompressed);
+    }
+    let texture = create_texture(ktx_texture, target)?;
+
+    // Rows are unpacked at the alignment they were stored with, and the caller's
+    // alignment is put back once every level is uploaded
+    let mut previous_alignment = 0;
     unsafe {
-        gl::TexStorage3D(
-            target,
-            ktx.mip_levels as i32,
-            ktx.gl_internal_format,
-            ktx.pixel_width as i32,
-            ktx.pixel_height as i32,
-            ktx.pixel_depth as i32,
-        );
-        gl::TexSubImage3D(
-            target,
-            0,
-            0,
-            0,
-            0,
-            ktx.pixel_width as i32,
-            ktx.pixel_height as i32,
-            ktx.pixel_depth as i32,
-            ktx.gl_format,
-            ktx.gl_type,
+        gl::GetIntegerv(gl::UNPACK_ALIGNMENT, &mut previous_alignment);
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, ktx_texture.unpack_alignment);
+    }
+    for (region, range) in image_uploads(ktx_texture, target) {
+        let image = &ktx_texture.pixels[range];
+        upload_region(
+            &ktx_texture.header,
+            &region,
             image.as_ptr() as *const GLvoid,
+            image.len(),
         );
     }
+    unsafe {
+        gl::PixelStorei(gl::UNPACK_ALIGNMENT, previous_alignment);
+    }
+    Ok(texture)
 }
 
-fn prepare_texture_1d_array(ktx_texture: &KtxData) {
+// Zero mip levels asks the loader for a full chain. Formats that can't be filtered
+// on the CPU are uploaded with just their base level.
+fn generate_requested_mipmaps(ktx_texture: &KtxData) -> Result<Option<KtxData<'static>>, KtxError> {
     let ktx = &ktx_texture.header;
-    let image = &ktx_texture.pixels;
-    let target = gl::TEXTURE_1D_ARRAY;
+    if ktx.mip_levels == 0 && ktx.full_level_count() > 1 && mipmap::can_filter(ktx_texture) {
+        mipmap::generate_mipmaps(ktx_texture, &Default::default()).map(Some)
+    } else {
+        Ok(None)
+    }
+}
 
-    unsafe {
-        gl::TexStorage2D(
-            target,
-            ktx.mip_levels as i32,
-            ktx.gl_internal_format,
-            ktx.pixel_width as i32,
-            ktx.array_elements as i32,
-        );
-        gl::TexSubImage2D(
-            target,
-            0,
-            0,
-            0,
-            ktx.pixel_width as i32,
-            ktx.array_elements as i32,
-            ktx.gl_format,
-            ktx.gl_type,
-            image.as_ptr() as *const GLvoid,
-        );
+fn decompress_unsupported(
+    ktx_texture: &KtxData,
+    target: GLenum,
+) -> Result<Option<KtxData<'static>>, KtxError> {
+    let ktx = &ktx_texture.header;
+    if ktx.is_compressed()
+        && decode::can_decode(ktx.gl_internal_format)
+        && !format_supported(target, ktx.gl_internal_format)
+    {
+        decode::decompress(ktx_texture).map(Some)
+    } else {
+        Ok(None)
     }
 }
 
-fn prepare_texture_2d_array(ktx_texture: &KtxData) {
+// Makes a texture with immutable storage for every level, left bound to its target.
+// Cube map arrays are addressed by layer-face, and each layer of a 1D array is a row.
+fn create_texture(ktx_texture: &KtxData, target: GLenum) -> Result<u32, KtxError> {
     let ktx = &ktx_texture.header;
-    let image = &ktx_texture.pixels;
-    let target = gl::TEXTURE_2D_ARRAY;
+    if ktx.is_compressed() && (target == gl::TEXTURE_1D || target == gl::TEXTURE_1D_ARRAY) {
+        return Err(KtxError::UnsupportedLayout("compressed 1D texture"));
+    }
 
+    let levels = ktx_texture.levels.len() as i32;
+    let (width, height) = (ktx.pixel_width as i32, ktx.pixel_height as i32);
+    let mut texture = 0;
     unsafe {
-        gl::PixelStorei(gl::UNPACK_ALIGNMENT, 4);
-        gl::TexStorage3D(
-            target,
-            ktx.mip_levels as i32,
-            ktx.gl_internal_format,
-            ktx.pixel_width as i32,
-            ktx.pixel_height as i32,
-            ktx.array_elements as i32,
-        );
-        gl::TexSubImage3D(
-            target,
-            0,
-            0,
-            0,
-            0,
-            ktx.pixel_width as i32,
-            ktx.pixel_height as i32,
-            ktx.array_elements as i32,
-            ktx.gl_format,
-            ktx.gl_type,
-            image.as_ptr() as *const GLvoid,
-        );
+        gl::GenTextures(1, &mut texture);
+        gl::BindTexture(target, texture);
+        match target {
+            gl::TEXTURE_1D => gl::TexStorage1D(target, levels, ktx.gl_internal_format, width),
+            gl::TEXTURE_1D_ARRAY => gl::TexStorage2D(
+                target,
+                levels,
+                ktx.gl_internal_format,
+                width,
+                ktx.array_elements as i32,
+            ),
+            gl::TEXTURE_2D | gl::TEXTURE_CUBE_MAP => {
+                gl::TexStorage2D(target, levels, ktx.gl_internal_format, width, height)
+            }
+            gl::TEXTURE_2D_ARRAY => gl::TexStorage3D(
+                target,
+                levels,
+                ktx.gl_internal_format,
+                width,
+                height,
+                ktx.array_elements as i32,
+            ),
+            gl::TEXTURE_3D => gl::TexStorage3D(
+                target,
+                levels,
+                ktx.gl_internal_format,
+                width,
+                height,
+                ktx.pixel_depth as i32,
+            ),
+            gl::TEXTURE_CUBE_MAP_ARRAY => gl::TexStorage3D(
+                target,
+                levels,
+                ktx.gl_internal_format,
+                width,
+                height,
+                ktx.array_elements as i32 * 6,
+            ),
+            _ => unreachable!(),
+        }
     }
+    Ok(texture)
 }
 
-fn prepare_texture_cube_map(_ktx_texture: &KtxData) {
-    unimplemented!()
+// The texels one TexSubImage call writes to, in one level of a texture or cube map face
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Region {
+    target: GLenum,
+    level: i32,
+    offset: [u32; 3],
+    size: [u32; 3],
 }
 
-fn prepare_texture_cube_map_array(_ktx_texture: &KtxData) {
-    unimplemented!()
+// Every level uploads as a single region, except cube maps which upload a face at a time,
+// along with the pixels each region is read from
+fn image_uploads(ktx_texture: &KtxData, target: GLenum) -> Vec<(Region, Range<usize>)> {
+    let ktx = &ktx_texture.header;
+    let mut uploads = Vec::new();
+    for (level, mip) in ktx_texture.levels.iter().enumerate() {
+        let size = match target {
+            gl::TEXTURE_1D => [mip.width, 1, 1],
+            gl::TEXTURE_1D_ARRAY => [mip.width, ktx.array_elements, 1],
+            gl::TEXTURE_2D_ARRAY => [mip.width, mip.height, ktx.array_elements],
+            gl::TEXTURE_CUBE_MAP_ARRAY => [mip.width, mip.height, ktx.array_elements * 6],
+            _ => [mip.width, mip.height, mip.depth],
+        };
+        let region = |target| Region {
+            target,
+            level: level as i32,
+            offset: [0; 3],
+            size,
+        };
+        if target == gl::TEXTURE_CUBE_MAP {
+            for (face, range) in mip.images.iter().enumerate() {
+                let face = gl::TEXTURE_CUBE_MAP_POSITIVE_X + face as GLenum;
+                uploads.push((region(face), range.clone()));
+            }
+        } else if let (Some(first), Some(last)) = (mip.images.first(), mip.images.last()) {
+            uploads.push((region(target), first.start..last.end));
+        }
+    }
+    uploads
 }
 
-fn calculate_stride(ktx_texture: &KtxData, width: i32, pad: usize) -> isize {
-    let ktx = &ktx_texture.header;
-    let channels = match ktx.gl_base_internal_format {
-        gl::RED => 1,
-        gl::RG => 2,
-        gl::BGR | gl::RGB => 3,
-        gl::BGRA | gl::RGBA => 4,
-        _ => 0,
-    };
-    (((ktx.gl_type_size * channels * width as u32) as usize + (pad - 1)) & !(pad - 1)) as isize
+// Uploads a region from client memory, or from an offset into the bound pixel unpack buffer
+fn upload_region(ktx: &Header, region: &Region, pixels: *const GLvoid, bytes: usize) {
+    let Region {
+        target,
+        level,
+        offset: [x, y, z],
+        size: [width, height, depth],
+    } = *region;
+    let compressed = ktx.is_compressed();
+    unsafe {
+        match target {
+            gl::TEXTURE_1D => gl::TexSubImage1D(
+                target,
+                level,
+                x as i32,
+                width as i32,
+                ktx.gl_format,
+                ktx.gl_type,
+                pixels,
+            ),
+            gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D | gl::TEXTURE_CUBE_MAP_ARRAY if compressed => {
+                gl::CompressedTexSubImage3D(
+                    target,
+                    level,
+                    x as i32,
+                    y as i32,
+                    z as i32,
+                    width as i32,
+                    height as i32,
+                    depth as i32,
+                    ktx.gl_internal_format,
+                    bytes as i32,
+                    pixels,
+                )
+            }
+            gl::TEXTURE_2D_ARRAY | gl::TEXTURE_3D | gl::TEXTURE_CUBE_MAP_ARRAY => {
+                gl::TexSubImage3D(
+                    target,
+                    level,
+                    x as i32,
+                    y as i32,
+                    z as i32,
+                    width as i32,
+                    height as i32,
+                    depth as i32,
+                    ktx.gl_format,
+                    ktx.gl_type,
+                    pixels,
+                )
+            }
+            _ if compressed => gl::CompressedTexSubImage2D(
+                target,
+                level,
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                ktx.gl_internal_format,
+                bytes as i32,
+                pixels,
+            ),
+            _ => gl::TexSubImage2D(
+                target,
+                level,
+                x as i32,
+                y as i32,
+                width as i32,
+                height as i32,
+                ktx.gl_format,
+                ktx.gl_type,
+                pixels,
+            ),
+        }
+    }
 }
 
-fn determine_target(ktx_texture: &KtxData) -> GLenum {
-    let ktx = &ktx_texture.header;
-    if ktx.pixel_height == 0 {
-        if ktx.array_elements == 0 {
-            gl::TEXTURE_1D
-        } else {
-            gl::TEXTURE_1D_ARRAY
+// Reads every level of a GL texture back into the layout parse_ktx produces, rows padded
+// to four bytes, so render targets can be written out with write_ktx. The size, levels
+// and internal format are queried from GL, and the texture is left bound to its target.
+pub fn read_texture(texture: u32, target: GLenum) -> Result<KtxData<'static>, KtxError> {
+    let image_targets: Vec<GLenum> = match target {
+        gl::TEXTURE_CUBE_MAP => (0..6)
+            .map(|face| gl::TEXTURE_CUBE_MAP_POSITIVE_X + face)
+            .collect(),
+        gl::TEXTURE_1D
+        | gl::TEXTURE_1D_ARRAY
+        | gl::TEXTURE_2D
+        | gl::TEXTURE_2D_ARRAY
+        | gl::TEXTURE_3D
+        | gl::TEXTURE_CUBE_MAP_ARRAY => vec![target],
+        _ => return Err(KtxError::Readback("target has no mip levels")),
+    };
+    let level_parameter = |level: u32, parameter: GLenum| {
+        let mut value = 0;
+        unsafe {
+            gl::GetTexLevelParameteriv(image_targets[0], level as i32, parameter, &mut value);
+        }
+        value.max(0) as u32
+    };
+
+    unsafe {
+        gl::BindTexture(target, texture);
+    }
+    let internal_format = level_parameter(0, gl::TEXTURE_INTERNAL_FORMAT);
+    let width = level_parameter(0, gl::TEXTURE_WIDTH);
+    let height = level_parameter(0, gl::TEXTURE_HEIGHT);
+    let depth = level_parameter(0, gl::TEXTURE_DEPTH);
+    if width == 0 {
+        return Err(KtxError::Readback("texture has no storage"));
+    }
+    let compressed = level_parameter(0, gl::TEXTURE_COMPRESSED) != 0;
+
+    let format = match ktx2::GlFormat::for_internal_format(internal_format) {
+        Some(format) => format,
+        None if compressed => {
+            return Err(KtxError::UnsupportedFormat(
+                "compressed format without a KTX 2.0 equivalent",
+            ))
         }
-    } else if ktx.pixel_depth == 0 {
-        if ktx.array_elements == 0 {
-            if ktx.faces == 0 {
-                gl::TEXTURE_2D
-            } else {
-                gl::TEXTURE_CUBE_MAP
+        None => {
+            let (mut format, mut gl_type) = (0, 0);
+            unsafe {
+                gl::GetInternalformativ(
+                    target,
+                    internal_format,
+                    gl::TEXTURE_IMAGE_FORMAT,
+                    1,
+                    &mut format,
+                );
+                gl::GetInternalformativ(
+                    target,
+                    internal_format,
+                    gl::TEXTURE_IMAGE_TYPE,
+                    1,
+                    &mut gl_type,
+                );
             }
-        } else if ktx.faces == 0 {
-            gl::TEXTURE_2D_ARRAY
-        } else {
-            gl::TEXTURE_CUBE_MAP_ARRAY
+            ktx2::GlFormat::uncompressed(internal_format, format as GLenum, gl_type as GLenum)
+        }
+    };
+    let gl_type_size = type_swap_size(format.gl_type).ok_or(KtxError::UnsupportedFormat(
+        "pixel type without a known size",
+    ))?;
+
+    // Array layers are the height of 1D arrays and the depth of everything else
+    let (pixel_height, pixel_depth, array_elements, faces) = match target {
+        gl::TEXTURE_1D => (0, 0, 0, 1),
+        gl::TEXTURE_1D_ARRAY => (0, 0, height, 1),
+        gl::TEXTURE_2D => (height, 0, 0, 1),
+        gl::TEXTURE_2D_ARRAY => (height, 0, depth, 1),
+        gl::TEXTURE_3D => (height, depth, 0, 1),
+        gl::TEXTURE_CUBE_MAP => (height, 0, 0, 6),
+        _ => (height, 0, depth / 6, 6),
+    };
+    let mut header = Header {
+        endianess: KTX_ENDIANNESS.to_ne_bytes(),
+        gl_type: format.gl_type,
+        gl_type_size,
+        gl_format: format.format,
+        gl_internal_format: internal_format,
+        gl_base_internal_format: format.base_internal_format,
+        pixel_width: width,
+        pixel_height,
+        pixel_depth,
+        array_elements,
+        faces,
+        mip_levels: 1,
+        key_pair_bytes: 0,
+    };
+
+    // Immutable storage knows its level count, otherwise levels run until one is missing
+    let mut immutable = 0;
+    unsafe {
+        gl::GetTexParameteriv(target, gl::TEXTURE_IMMUTABLE_FORMAT, &mut immutable);
+    }
+    header.mip_levels = if immutable != 0 {
+        let mut levels = 0;
+        unsafe {
+            gl::GetTexParameteriv(target, gl::TEXTURE_IMMUTABLE_LEVELS, &mut levels);
         }
+        levels as u32
     } else {
-        gl::TEXTURE_3D
+        (0..header.full_level_count())
+            .take_while(|level| level_parameter(*level, gl::TEXTURE_WIDTH) != 0)
+            .count() as u32
+    };
+
+    // Levels are read straight into client memory as tightly packed images, and the
+    // caller's pack state and pixel pack buffer are put back afterwards
+    let mut previous_buffer = 0;
+    let previous_state: Vec<i32> = unsafe {
+        gl::GetIntegerv(gl::PIXEL_PACK_BUFFER_BINDING, &mut previous_buffer);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        READBACK_PACK_STATE
+            .iter()
+            .map(|(parameter, value)| {
+                let mut previous = 0;
+                gl::GetIntegerv(*parameter, &mut previous);
+                gl::PixelStorei(*parameter, *value);
+                previous
+            })
+            .collect()
+    };
+    let pixels = read_levels(&header, &image_targets);
+    unsafe {
+        for ((parameter, _), previous) in READBACK_PACK_STATE.iter().zip(previous_state) {
+            gl::PixelStorei(*parameter, previous);
+        }
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, previous_buffer as u32);
+    }
+    let (pixels, levels) = pixels?;
+
+    Ok(KtxData {
+        header,
+        key_values: Vec::new(),
+        levels,
+        unpack_alignment: KTX_ALIGNMENT as i32,
+        pixels: Cow::Owned(pixels),
+    })
+}
+
+// Pixel store parameters that change where GetTexImage writes, set to tightly packed
+// rows at the spec's alignment
+const READBACK_PACK_STATE: [(GLenum, i32); 12] = [
+    (gl::PACK_ALIGNMENT, KTX_ALIGNMENT as i32),
+    (gl::PACK_ROW_LENGTH, 0),
+    (gl::PACK_IMAGE_HEIGHT, 0),
+    (gl::PACK_SKIP_PIXELS, 0),
+    (gl::PACK_SKIP_ROWS, 0),
+    (gl::PACK_SKIP_IMAGES, 0),
+    (gl::PACK_SWAP_BYTES, 0),
+    (gl::PACK_LSB_FIRST, 0),
+    (gl::PACK_COMPRESSED_BLOCK_WIDTH, 0),
+    (gl::PACK_COMPRESSED_BLOCK_HEIGHT, 0),
+    (gl::PACK_COMPRESSED_BLOCK_DEPTH, 0),
+    (gl::PACK_COMPRESSED_BLOCK_SIZE, 0),
+];
+
+// Cube map faces are read one at a time, every other target a whole level at once
+fn read_levels(
+    header: &Header,
+    image_targets: &[GLenum],
+) -> Result<(Vec<u8>, Vec<MipLevel>), KtxError> {
+    let images_per_read = header.images_per_level() / image_targets.len();
+    let mut pixels = Vec::new();
+    let mut levels = Vec::new();
+    for level in 0..header.level_count() {
+        let (width, height, depth) = header.level_dimensions(level);
+        let image_bytes = image_bytes_for(header, width, height, depth, KTX_ALIGNMENT).ok_or(
+            KtxError::UnsupportedFormat("image sizes cannot be derived from the format"),
+        )?;
+        let read_bytes = image_bytes * images_per_read;
+
+        let mut images = Vec::new();
+        for image_target in image_targets.iter() {
+            let start = pixels.len();
+            pixels.resize(start + read_bytes, 0);
+            let output = pixels[start..].as_mut_ptr() as *mut GLvoid;
+            unsafe {
+                // With the pack state reset, uncompressed reads are exactly the size worked out
+                // above. Compressed sizes come from the driver, so they are checked first.
+                if header.is_compressed() {
+                    let mut compressed_bytes = 0;
+                    gl::GetTexLevelParameteriv(
+                        *image_target,
+                        level as i32,
+                        gl::TEXTURE_COMPRESSED_IMAGE_SIZE,
+                        &mut compressed_bytes,
+                    );
+                    if compressed_bytes as usize != read_bytes {
+                        return Err(KtxError::Readback(
+                            "compressed image size doesn't match its dimensions",
+                        ));
+                    }
+                    gl::GetCompressedTexImage(*image_target, level as i32, output);
+                } else {
+                    gl::GetTexImage(
+                        *image_target,
+                        level as i32,
+                        header.gl_format,
+                        header.gl_type,
+                        output,
+                    );
+                }
+                if gl::GetError() != gl::NO_ERROR {
+                    return Err(KtxError::Readback("GL failed to read an image"));
+                }
+            }
+            for image in 0..images_per_read {
+                let offset = start + image * image_bytes;
+                images.push(offset..offset + image_bytes);
+            }
+        }
+        levels.push(MipLevel {
+            width,
+            height,
+            depth,
+            images,
+        });
+    }
+    Ok((pixels, levels))
+}
+
+// Asks the driver whether it can sample an internal format, which is how the decoders
+// step in for compressed formats it lacks. Without the query everything is assumed to work.
+fn format_supported(target: GLenum, internal_format: GLenum) -> bool {
+    if !gl::GetInternalformativ::is_loaded() {
+        return true;
+    }
+    let mut supported = gl::FALSE as i32;
+    unsafe {
+        gl::GetInternalformativ(
+            target,
+            internal_format,
+            gl::INTERNALFORMAT_SUPPORTED,
+            1,
+            &mut supported,
+        );
+    }
+    supported == gl::TRUE as i32
+}
+
+// Older files (including the book's) mark non-cube textures with zero faces instead of one
+pub fn determine_target(ktx_texture: &KtxData) -> Result<GLenum, KtxError> {
+    let ktx = &ktx_texture.header;
+    let cube = ktx.faces == 6;
+    let array = ktx.array_elements != 0;
+    match (ktx.pixel_height, ktx.pixel_depth) {
+        (0, 0) if cube => Err(KtxError::UnsupportedLayout("1D cube map")),
+        (0, 0) if array => Ok(gl::TEXTURE_1D_ARRAY),
+        (0, 0) => Ok(gl::TEXTURE_1D),
+        (0, _) => Err(KtxError::UnsupportedLayout("3D texture without a height")),
+        (_, 0) => Ok(match (cube, array) {
+            (false, false) => gl::TEXTURE_2D,
+            (false, true) => gl::TEXTURE_2D_ARRAY,
+            (true, false) => gl::TEXTURE_CUBE_MAP,
+            (true, true) => gl::TEXTURE_CUBE_MAP_ARRAY,
+        }),
+        _ if cube => Err(KtxError::UnsupportedLayout("3D cube map")),
+        _ if array => Err(KtxError::UnsupportedLayout("3D array")),
+        _ => Ok(gl::TEXTURE_3D),
     }
 }
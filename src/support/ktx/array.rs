@@ -0,0 +1,122 @@
+use super::{
+    block_format,
+    mipmap::{generate_mipmaps, MipmapOptions},
+    pad, repack_rows, KtxData, KtxError, MipLevel, KTX_ALIGNMENT, KTX_ENDIANNESS,
+};
+use std::borrow::Cow;
+
+// Stacks single 2D textures of the same format and size into a TEXTURE_2D_ARRAY, layer
+// i taken from layers[i]. Every level the layers share is kept, or with mipmaps the full
+// chain is generated from the base level. The result can be passed to prepare_texture or
+// written with write_ktx.
+pub fn assemble_array(
+    layers: &[KtxData],
+    mipmaps: Option<&MipmapOptions>,
+) -> Result<KtxData<'static>, KtxError> {
+    let first = layers
+        .first()
+        .ok_or(KtxError::InvalidArgument("no layers to assemble"))?;
+    let ktx = &first.header;
+    for (index, layer) in layers.iter().enumerate() {
+        let invalid = |reason| {
+            Err(KtxError::InvalidLayer {
+                layer: index,
+                reason,
+            })
+        };
+        let header = &layer.header;
+        if header.array_elements != 0
+            || header.face_count() != 1
+            || header.pixel_depth != 0
+            || header.pixel_height == 0
+        {
+            return invalid("only single 2D images can be layers");
+        }
+        if (
+            header.gl_type,
+            header.gl_format,
+            header.gl_internal_format,
+            header.gl_base_internal_format,
+        ) != (
+            ktx.gl_type,
+            ktx.gl_format,
+            ktx.gl_internal_format,
+            ktx.gl_base_internal_format,
+        ) {
+            return invalid("its format differs from layer 0");
+        }
+        if (header.pixel_width, header.pixel_height) != (ktx.pixel_width, ktx.pixel_height) {
+            return invalid("its size differs from layer 0");
+        }
+        if layer.levels.len() != first.levels.len() {
+            return invalid("its mip level count differs from layer 0");
+        }
+    }
+
+    let mut pixels = Vec::new();
+    let mut levels = Vec::with_capacity(first.levels.len());
+    for (level, mip) in first.levels.iter().enumerate() {
+        let (width, height) = (mip.width as usize, mip.height as usize);
+        let mut images = Vec::with_capacity(layers.len());
+        for (index, layer) in layers.iter().enumerate() {
+            let image = layer.image(level as u32, 0, 0).unwrap_or_default();
+            let start = pixels.len();
+            if ktx.is_compressed() {
+                let expected = block_format(ktx.gl_internal_format)
+                    .and_then(|format| format.image_bytes(mip.width, mip.height, 1));
+                if expected != Some(image.len()) {
+                    return Err(KtxError::InvalidLayer {
+                        layer: index,
+                        reason: "an image doesn't match its dimensions",
+                    });
+                }
+                pixels.extend_from_slice(image);
+            } else {
+                let row_bytes = width
+                    * ktx
+                        .pixel_size()
+                        .ok_or(KtxError::UnsupportedFormat("unknown pixel size"))?
+                        as usize;
+                let alignment = layer.unpack_alignment as usize;
+                if image.len() < pad(row_bytes, alignment) * height {
+                    return Err(KtxError::InvalidLayer {
+                        layer: index,
+                        reason: "an image is smaller than its dimensions",
+                    });
+                }
+                pixels.extend(repack_rows(
+                    image,
+                    row_bytes,
+                    height,
+                    alignment,
+                    KTX_ALIGNMENT,
+                ));
+            }
+            images.push(start..pixels.len());
+        }
+        levels.push(MipLevel {
+            width: mip.width,
+            height: mip.height,
+            depth: 1,
+            images,
+        });
+    }
+
+    let mut header = ktx.clone();
+    header.endianess = KTX_ENDIANNESS.to_ne_bytes();
+    header.array_elements = layers.len() as u32;
+    header.faces = 1;
+    header.mip_levels = levels.len() as u32;
+    let texture = KtxData {
+        header,
+        key_values: first.key_values.clone(),
+        levels,
+        unpack_alignment: KTX_ALIGNMENT as i32,
+        pixels: Cow::Owned(pixels),
+    };
+
+    match mipmaps {
+        Some(options) => generate_mipmaps(&texture, options),
+        None => Ok(texture),
+    }
+}
@@ -0,0 +1,391 @@
+use super::{ktx2::GlFormat, *};
+
+pub const DDS_MAGIC: [u8; 4] = *b"DDS ";
+
+const DDS_HEADER_BYTES: u32 = 124;
+const DDS_PIXEL_FORMAT_BYTES: u32 = 32;
+const DDS_DATA_OFFSET: usize = 128;
+const DX10_HEADER_BYTES: usize = 20;
+
+const DDSD_DEPTH: u32 = 0x80_0000;
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+const DDSCAPS2_CUBEMAP: u32 = 0x200;
+const DDSCAPS2_CUBEMAP_ALL_FACES: u32 = 0xFC00;
+const DDSCAPS2_VOLUME: u32 = 0x20_0000;
+
+const RESOURCE_DIMENSION_TEXTURE1D: u32 = 2;
+const RESOURCE_DIMENSION_TEXTURE2D: u32 = 3;
+const RESOURCE_DIMENSION_TEXTURE3D: u32 = 4;
+const RESOURCE_MISC_TEXTURECUBE: u32 = 0x4;
+
+// Reads a DirectDraw Surface, with or without the DX10 header, into the layout parse_ktx
+// produces so it can be handed to prepare_texture. DDS stores each image's whole mip chain
+// before the next image, so the pixels are copied into level order, with rows tightly packed.
+pub fn parse_dds(input: &[u8]) -> Result<KtxData<'static>, KtxError> {
+    if !input.starts_with(&DDS_MAGIC) {
+        return if DDS_MAGIC.starts_with(input) {
+            Err(KtxError::Truncated {
+                section: "identifier",
+                offset: 0,
+                needed: DDS_MAGIC.len(),
+                available: input.len(),
+            })
+        } else {
+            Err(KtxError::InvalidIdentifier)
+        };
+    }
+
+    let reader = Reader {
+        input,
+        base: 0,
+        big_endian: false,
+    };
+    let field = |offset| reader.u32(offset, "header");
+    let invalid = |field: &'static str, offset: usize, value: u32, reason: &'static str| {
+        Err(KtxError::InvalidHeaderField {
+            field,
+            offset,
+            value,
+            reason,
+        })
+    };
+
+    let size = field(4)?;
+    if size != DDS_HEADER_BYTES {
+        return invalid("size", 4, size, "must be 124");
+    }
+    let pixel_format_size = field(76)?;
+    if pixel_format_size != DDS_PIXEL_FORMAT_BYTES {
+        return invalid("pixel_format_size", 76, pixel_format_size, "must be 32");
+    }
+    let flags = field(8)?;
+    let width = field(16)?;
+    let height = field(12)?;
+    let mut one_dimensional = false;
+    let mut depth = 0;
+    let mut array_elements = 0;
+    let mut faces = 1;
+    let mip_map_count = field(28)?;
+    let four_cc = field(84)?;
+    let caps2 = field(112)?;
+
+    let dx10 = field(80)? & DDPF_FOURCC != 0 && &four_cc.to_le_bytes() == b"DX10";
+    let (format, data_offset) = if dx10 {
+        let dxgi_format = field(128)?;
+        let format = dxgi_format_to_gl(dxgi_format).ok_or(KtxError::InvalidHeaderField {
+            field: "dxgi_format",
+            offset: 128,
+            value: dxgi_format,
+            reason: "no matching GL format",
+        })?;
+        let misc_flags = field(136)?;
+        let array_size = field(140)?;
+        if array_size == 0 {
+            return invalid("array_size", 140, array_size, "must not be zero");
+        }
+        match field(132)? {
+            RESOURCE_DIMENSION_TEXTURE1D => one_dimensional = true,
+            RESOURCE_DIMENSION_TEXTURE2D if misc_flags & RESOURCE_MISC_TEXTURECUBE != 0 => {
+                faces = 6
+            }
+            RESOURCE_DIMENSION_TEXTURE2D => {}
+            RESOURCE_DIMENSION_TEXTURE3D => depth = field(24)?,
+            dimension => {
+                return invalid(
+                    "resource_dimension",
+                    132,
+                    dimension,
+                    "must be a 1D, 2D or 3D texture",
+                )
+            }
+        }
+        if array_size > 1 && depth != 0 {
+            return invalid(
+                "array_size",
+                140,
+                array_size,
+                "3D textures cannot be arrays",
+            );
+        }
+        if array_size > 1 {
+            array_elements = array_size;
+        }
+        (format, DDS_DATA_OFFSET + DX10_HEADER_BYTES)
+    } else {
+        let format = legacy_format(&reader)?;
+        if caps2 & DDSCAPS2_CUBEMAP != 0 {
+            if caps2 & DDSCAPS2_CUBEMAP_ALL_FACES != DDSCAPS2_CUBEMAP_ALL_FACES {
+                return Err(KtxError::UnsupportedLayout("cube map without every face"));
+            }
+            faces = 6;
+        }
+        if caps2 & DDSCAPS2_VOLUME != 0 && flags & DDSD_DEPTH != 0 {
+            depth = field(24)?;
+        }
+        (format, DDS_DATA_OFFSET)
+    };
+
+    let header = Header {
+        endianess: KTX_ENDIANNESS.to_le_bytes(),
+        gl_type: format.gl_type,
+        gl_type_size: type_swap_size(format.gl_type).unwrap_or(1),
+        gl_format: format.format,
+        gl_internal_format: format.internal_format,
+        gl_base_internal_format: format.base_internal_format,
+        pixel_width: width,
+        pixel_height: if one_dimensional { 0 } else { height },
+        pixel_depth: depth,
+        array_elements,
+        faces,
+        mip_levels: cmp::max(mip_map_count, 1),
+        key_pair_bytes: 0,
+    };
+    if width == 0 {
+        return invalid("width", 16, width, "must not be zero");
+    }
+    if height == 0 && !one_dimensional {
+        return invalid("height", 12, height, "must not be zero");
+    }
+    if faces == 6 && width != height {
+        return invalid("width", 16, width, "cube map faces must be square");
+    }
+    if header.mip_levels > header.full_level_count() {
+        return invalid(
+            "mip_map_count",
+            28,
+            mip_map_count,
+            "more levels than the dimensions allow",
+        );
+    }
+
+    let image_sizes = (0..header.level_count())
+        .map(|level| {
+            let (width, height, depth) = header.level_dimensions(level);
+            image_bytes_for(&header, width, height, depth, 1)
+        })
+        .collect::<Option<Vec<_>>>()
+        .ok_or(KtxError::UnsupportedFormat(
+            "image sizes cannot be derived from the format",
+        ))?;
+    let chain_bytes: usize = image_sizes.iter().sum();
+    let images_per_level = header.images_per_level();
+    let data = reader.bytes(
+        data_offset,
+        chain_bytes.saturating_mul(images_per_level),
+        "image data",
+    )?;
+
+    let mut pixels = Vec::with_capacity(data.len());
+    let mut levels = Vec::new();
+    let mut level_offset = 0;
+    for (level, image_bytes) in image_sizes.iter().enumerate() {
+        let (width, height, depth) = header.level_dimensions(level as u32);
+        let mut images = Vec::new();
+        for image in 0..images_per_level {
+            let start = image * chain_bytes + level_offset;
+            images.push(pixels.len()..pixels.len() + image_bytes);
+            pixels.extend_from_slice(&data[start..start + image_bytes]);
+        }
+        levels.push(MipLevel {
+            width,
+            height,
+            depth,
+            images,
+        });
+        level_offset += image_bytes;
+    }
+
+    Ok(KtxData {
+        header,
+        key_values: Vec::new(),
+        levels,
+        unpack_alignment: 1,
+        pixels: Cow::Owned(pixels),
+    })
+}
+
+// Pixel formats written before the DX10 header, named by a FourCC code or described by bit
+// masks. DXT1 is read as having punch through alpha, which changes nothing for opaque data,
+// and luminance is read as red since core profiles have no luminance formats.
+fn legacy_format(reader: &Reader) -> Result<GlFormat, KtxError> {
+    let field = |offset| reader.u32(offset, "pixel format");
+    let format = GlFormat::uncompressed;
+    let compressed = GlFormat::compressed;
+    // The alpha channel of X8R8G8B8 style formats is padding
+    let opaque = |internal_format, pixel_format| GlFormat {
+        base_internal_format: gl::RGB,
+        ..format(internal_format, pixel_format, gl::UNSIGNED_BYTE)
+    };
+
+    let flags = field(80)?;
+    let four_cc = field(84)?;
+    let alpha_mask = if flags & DDPF_ALPHAPIXELS != 0 {
+        field(104)?
+    } else {
+        0
+    };
+    let masks = (field(88)?, field(92)?, field(96)?, field(100)?, alpha_mask);
+
+    let gl_format = if flags & DDPF_FOURCC != 0 {
+        match &four_cc.to_le_bytes() {
+            b"DXT1" => Some(compressed(COMPRESSED_RGBA_S3TC_DXT1, gl::RGBA)),
+            b"DXT2" | b"DXT3" => Some(compressed(COMPRESSED_RGBA_S3TC_DXT3, gl::RGBA)),
+            b"DXT4" | b"DXT5" => Some(compressed(COMPRESSED_RGBA_S3TC_DXT5, gl::RGBA)),
+            b"ATI1" | b"BC4U" => Some(compressed(gl::COMPRESSED_RED_RGTC1, gl::RED)),
+            b"BC4S" => Some(compressed(gl::COMPRESSED_SIGNED_RED_RGTC1, gl::RED)),
+            b"ATI2" | b"BC5U" => Some(compressed(gl::COMPRESSED_RG_RGTC2, gl::RG)),
+            b"BC5S" => Some(compressed(gl::COMPRESSED_SIGNED_RG_RGTC2, gl::RG)),
+            // Direct3D format numbers stand in for codes in some files
+            _ => match four_cc {
+                36 => Some(format(gl::RGBA16, gl::RGBA, gl::UNSIGNED_SHORT)),
+                110 => Some(format(gl::RGBA16_SNORM, gl::RGBA, gl::SHORT)),
+                111 => Some(format(gl::R16F, gl::RED, gl::HALF_FLOAT)),
+                112 => Some(format(gl::RG16F, gl::RG, gl::HALF_FLOAT)),
+                113 => Some(format(gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT)),
+                114 => Some(format(gl::R32F, gl::RED, gl::FLOAT)),
+                115 => Some(format(gl::RG32F, gl::RG, gl::FLOAT)),
+                116 => Some(format(gl::RGBA32F, gl::RGBA, gl::FLOAT)),
+                _ => None,
+            },
+        }
+    } else if flags & DDPF_RGB != 0 {
+        match masks {
+            (32, 0xFF, 0xFF00, 0xFF_0000, 0xFF00_0000) => {
+                Some(format(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE))
+            }
+            (32, 0xFF_0000, 0xFF00, 0xFF, 0xFF00_0000) => {
+                Some(format(gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE))
+            }
+            (32, 0xFF, 0xFF00, 0xFF_0000, 0) => Some(opaque(gl::RGB8, gl::RGBA)),
+            (32, 0xFF_0000, 0xFF00, 0xFF, 0) => Some(opaque(gl::RGB8, gl::BGRA)),
+            (32, 0x3FF, 0xF_FC00, 0x3FF0_0000, 0xC000_0000) => Some(format(
+                gl::RGB10_A2,
+                gl::RGBA,
+                gl::UNSIGNED_INT_2_10_10_10_REV,
+            )),
+            (32, 0xFFFF, 0xFFFF_0000, 0, 0) => Some(format(gl::RG16, gl::RG, gl::UNSIGNED_SHORT)),
+            (24, 0xFF, 0xFF00, 0xFF_0000, 0) => Some(format(gl::RGB8, gl::RGB, gl::UNSIGNED_BYTE)),
+            (24, 0xFF_0000, 0xFF00, 0xFF, 0) => Some(format(gl::RGB8, gl::BGR, gl::UNSIGNED_BYTE)),
+            (16, 0xF800, 0x7E0, 0x1F, 0) => {
+                Some(format(gl::RGB565, gl::RGB, gl::UNSIGNED_SHORT_5_6_5))
+            }
+            (16, 0x7C00, 0x3E0, 0x1F, 0x8000) => Some(format(
+                gl::RGB5_A1,
+                gl::BGRA,
+                gl::UNSIGNED_SHORT_1_5_5_5_REV,
+            )),
+            (16, 0xF00, 0xF0, 0xF, 0xF000) => {
+                Some(format(gl::RGBA4, gl::BGRA, gl::UNSIGNED_SHORT_4_4_4_4_REV))
+            }
+            _ => None,
+        }
+    } else if flags & DDPF_LUMINANCE != 0 {
+        match masks {
+            (8, 0xFF, _, _, 0) => Some(format(gl::R8, gl::RED, gl::UNSIGNED_BYTE)),
+            (16, 0xFFFF, _, _, 0) => Some(format(gl::R16, gl::RED, gl::UNSIGNED_SHORT)),
+            (16, 0xFF, _, _, 0xFF00) => Some(format(gl::RG8, gl::RG, gl::UNSIGNED_BYTE)),
+            _ => None,
+        }
+    } else {
+        None
+    };
+    gl_format.ok_or(KtxError::UnsupportedFormat(
+        "DDS pixel format without a GL equivalent",
+    ))
+}
+
+// The typeless formats have no GL equivalent, as GL picks a type when storage is allocated
+fn dxgi_format_to_gl(dxgi_format: u32) -> Option<GlFormat> {
+    let format = GlFormat::uncompressed;
+    let compressed = GlFormat::compressed;
+    let opaque = |internal_format| GlFormat {
+        base_internal_format: gl::RGB,
+        ..format(internal_format, gl::BGRA, gl::UNSIGNED_BYTE)
+    };
+
+    Some(match dxgi_format {
+        2 => format(gl::RGBA32F, gl::RGBA, gl::FLOAT),
+        3 => format(gl::RGBA32UI, gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+        4 => format(gl::RGBA32I, gl::RGBA_INTEGER, gl::INT),
+        6 => format(gl::RGB32F, gl::RGB, gl::FLOAT),
+        7 => format(gl::RGB32UI, gl::RGB_INTEGER, gl::UNSIGNED_INT),
+        8 => format(gl::RGB32I, gl::RGB_INTEGER, gl::INT),
+        10 => format(gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+        11 => format(gl::RGBA16, gl::RGBA, gl::UNSIGNED_SHORT),
+        12 => format(gl::RGBA16UI, gl::RGBA_INTEGER, gl::UNSIGNED_SHORT),
+        13 => format(gl::RGBA16_SNORM, gl::RGBA, gl::SHORT),
+        14 => format(gl::RGBA16I, gl::RGBA_INTEGER, gl::SHORT),
+        16 => format(gl::RG32F, gl::RG, gl::FLOAT),
+        17 => format(gl::RG32UI, gl::RG_INTEGER, gl::UNSIGNED_INT),
+        18 => format(gl::RG32I, gl::RG_INTEGER, gl::INT),
+        24 => format(gl::RGB10_A2, gl::RGBA, gl::UNSIGNED_INT_2_10_10_10_REV),
+        25 => format(
+            gl::RGB10_A2UI,
+            gl::RGBA_INTEGER,
+            gl::UNSIGNED_INT_2_10_10_10_REV,
+        ),
+        26 => format(
+            gl::R11F_G11F_B10F,
+            gl::RGB,
+            gl::UNSIGNED_INT_10F_11F_11F_REV,
+        ),
+        28 => format(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        29 => format(gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        30 => format(gl::RGBA8UI, gl::RGBA_INTEGER, gl::UNSIGNED_BYTE),
+        31 => format(gl::RGBA8_SNORM, gl::RGBA, gl::BYTE),
+        32 => format(gl::RGBA8I, gl::RGBA_INTEGER, gl::BYTE),
+        34 => format(gl::RG16F, gl::RG, gl::HALF_FLOAT),
+        35 => format(gl::RG16, gl::RG, gl::UNSIGNED_SHORT),
+        36 => format(gl::RG16UI, gl::RG_INTEGER, gl::UNSIGNED_SHORT),
+        37 => format(gl::RG16_SNORM, gl::RG, gl::SHORT),
+        38 => format(gl::RG16I, gl::RG_INTEGER, gl::SHORT),
+        40 => format(gl::DEPTH_COMPONENT32F, gl::DEPTH_COMPONENT, gl::FLOAT),
+        41 => format(gl::R32F, gl::RED, gl::FLOAT),
+        42 => format(gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT),
+        43 => format(gl::R32I, gl::RED_INTEGER, gl::INT),
+        49 => format(gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+        50 => format(gl::RG8UI, gl::RG_INTEGER, gl::UNSIGNED_BYTE),
+        51 => format(gl::RG8_SNORM, gl::RG, gl::BYTE),
+        52 => format(gl::RG8I, gl::RG_INTEGER, gl::BYTE),
+        54 => format(gl::R16F, gl::RED, gl::HALF_FLOAT),
+        55 => format(
+            gl::DEPTH_COMPONENT16,
+            gl::DEPTH_COMPONENT,
+            gl::UNSIGNED_SHORT,
+        ),
+        56 => format(gl::R16, gl::RED, gl::UNSIGNED_SHORT),
+        57 => format(gl::R16UI, gl::RED_INTEGER, gl::UNSIGNED_SHORT),
+        58 => format(gl::R16_SNORM, gl::RED, gl::SHORT),
+        59 => format(gl::R16I, gl::RED_INTEGER, gl::SHORT),
+        61 => format(gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+        62 => format(gl::R8UI, gl::RED_INTEGER, gl::UNSIGNED_BYTE),
+        63 => format(gl::R8_SNORM, gl::RED, gl::BYTE),
+        64 => format(gl::R8I, gl::RED_INTEGER, gl::BYTE),
+        67 => format(gl::RGB9_E5, gl::RGB, gl::UNSIGNED_INT_5_9_9_9_REV),
+        71 => compressed(COMPRESSED_RGBA_S3TC_DXT1, gl::RGBA),
+        72 => compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT1, gl::RGBA),
+        74 => compressed(COMPRESSED_RGBA_S3TC_DXT3, gl::RGBA),
+        75 => compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT3, gl::RGBA),
+        77 => compressed(COMPRESSED_RGBA_S3TC_DXT5, gl::RGBA),
+        78 => compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT5, gl::RGBA),
+        80 => compressed(gl::COMPRESSED_RED_RGTC1, gl::RED),
+        81 => compressed(gl::COMPRESSED_SIGNED_RED_RGTC1, gl::RED),
+        83 => compressed(gl::COMPRESSED_RG_RGTC2, gl::RG),
+        84 => compressed(gl::COMPRESSED_SIGNED_RG_RGTC2, gl::RG),
+        85 => format(gl::RGB565, gl::RGB, gl::UNSIGNED_SHORT_5_6_5),
+        86 => format(gl::RGB5_A1, gl::BGRA, gl::UNSIGNED_SHORT_1_5_5_5_REV),
+        87 => format(gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE),
+        88 => opaque(gl::RGB8),
+        91 => format(gl::SRGB8_ALPHA8, gl::BGRA, gl::UNSIGNED_BYTE),
+        93 => opaque(gl::SRGB8),
+        95 => compressed(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, gl::RGB),
+        96 => compressed(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT, gl::RGB),
+        98 => compressed(gl::COMPRESSED_RGBA_BPTC_UNORM, gl::RGBA),
+        99 => compressed(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM, gl::RGBA),
+        115 => format(gl::RGBA4, gl::BGRA, gl::UNSIGNED_SHORT_4_4_4_4_REV),
+        _ => return None,
+    })
+}
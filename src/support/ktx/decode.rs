@@ -0,0 +1,910 @@
+use super::{
+    block_format, KtxData, KtxError, MipLevel, COMPRESSED_RGBA_S3TC_DXT1,
+    COMPRESSED_RGBA_S3TC_DXT3, COMPRESSED_RGBA_S3TC_DXT5, COMPRESSED_RGB_S3TC_DXT1,
+    COMPRESSED_SRGB_ALPHA_S3TC_DXT1, COMPRESSED_SRGB_ALPHA_S3TC_DXT3,
+    COMPRESSED_SRGB_ALPHA_S3TC_DXT5, COMPRESSED_SRGB_S3TC_DXT1, ETC1_RGB8, KTX_ENDIANNESS,
+};
+use gl::types::GLenum;
+use std::borrow::Cow;
+
+// Decoded RGBA texels. Formats whose values don't fit in a byte, the signed and 11 bit
+// ones, decode to floats.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Texels {
+    Rgba8(Vec<u8>),
+    Rgba32F(Vec<f32>),
+}
+
+// The texels of one image, rows in the order the blocks store them
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedImage {
+    pub width: u32,
+    pub height: u32,
+    pub depth: u32,
+    pub texels: Texels,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Codec {
+    Bc1 { transparent: bool },
+    Bc2,
+    Bc3,
+    Bc4 { signed: bool },
+    Bc5 { signed: bool },
+    Bc7,
+    Etc1,
+    Etc2 { punchthrough: bool },
+    Etc2Eac,
+    Eac { channels: usize, signed: bool },
+}
+
+impl Codec {
+    fn from_format(internal_format: GLenum) -> Result<Self, KtxError> {
+        Ok(match internal_format {
+            COMPRESSED_RGB_S3TC_DXT1 | COMPRESSED_SRGB_S3TC_DXT1 => {
+                Codec::Bc1 { transparent: false }
+            }
+            COMPRESSED_RGBA_S3TC_DXT1 | COMPRESSED_SRGB_ALPHA_S3TC_DXT1 => {
+                Codec::Bc1 { transparent: true }
+            }
+            COMPRESSED_RGBA_S3TC_DXT3 | COMPRESSED_SRGB_ALPHA_S3TC_DXT3 => Codec::Bc2,
+            COMPRESSED_RGBA_S3TC_DXT5 | COMPRESSED_SRGB_ALPHA_S3TC_DXT5 => Codec::Bc3,
+            gl::COMPRESSED_RED_RGTC1 => Codec::Bc4 { signed: false },
+            gl::COMPRESSED_SIGNED_RED_RGTC1 => Codec::Bc4 { signed: true },
+            gl::COMPRESSED_RG_RGTC2 => Codec::Bc5 { signed: false },
+            gl::COMPRESSED_SIGNED_RG_RGTC2 => Codec::Bc5 { signed: true },
+            gl::COMPRESSED_RGBA_BPTC_UNORM | gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM => Codec::Bc7,
+            ETC1_RGB8 => Codec::Etc1,
+            gl::COMPRESSED_RGB8_ETC2 | gl::COMPRESSED_SRGB8_ETC2 => Codec::Etc2 {
+                punchthrough: false,
+            },
+            gl::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2
+            | gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2 => Codec::Etc2 { punchthrough: true },
+            gl::COMPRESSED_RGBA8_ETC2_EAC | gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC => Codec::Etc2Eac,
+            gl::COMPRESSED_R11_EAC => Codec::Eac {
+                channels: 1,
+                signed: false,
+            },
+            gl::COMPRESSED_SIGNED_R11_EAC => Codec::Eac {
+                channels: 1,
+                signed: true,
+            },
+            gl::COMPRESSED_RG11_EAC => Codec::Eac {
+                channels: 2,
+                signed: false,
+            },
+            gl::COMPRESSED_SIGNED_RG11_EAC => Codec::Eac {
+                channels: 2,
+                signed: true,
+            },
+            _ => {
+                return Err(KtxError::UnsupportedFormat(
+                    "no CPU decoder for this format",
+                ))
+            }
+        })
+    }
+
+    fn is_float(self) -> bool {
+        matches!(
+            self,
+            Codec::Bc4 { signed: true } | Codec::Bc5 { signed: true } | Codec::Eac { .. }
+        )
+    }
+
+    fn decode_unorm(self, block: &[u8]) -> [[u8; 4]; 16] {
+        match self {
+            Codec::Bc1 { transparent } => decode_color_block(block, true, transparent),
+            Codec::Bc2 => {
+                let mut texels = decode_color_block(&block[8..], false, false);
+                let alpha = u64::from_le_bytes(block_bytes(block));
+                for (index, texel) in texels.iter_mut().enumerate() {
+                    texel[3] = ((alpha >> (4 * index)) & 0xF) as u8 * 17;
+                }
+                texels
+            }
+            Codec::Bc3 => {
+                let mut texels = decode_color_block(&block[8..], false, false);
+                for (texel, alpha) in texels.iter_mut().zip(decode_bc4_unorm(block).iter()) {
+                    texel[3] = *alpha;
+                }
+                texels
+            }
+            Codec::Bc4 { .. } => {
+                let mut texels = [[0, 0, 0, 255]; 16];
+                for (texel, red) in texels.iter_mut().zip(decode_bc4_unorm(block).iter()) {
+                    texel[0] = *red;
+                }
+                texels
+            }
+            Codec::Bc5 { .. } => {
+                let mut texels = [[0, 0, 0, 255]; 16];
+                let (red, green) = (decode_bc4_unorm(block), decode_bc4_unorm(&block[8..]));
+                for (index, texel) in texels.iter_mut().enumerate() {
+                    texel[0] = red[index];
+                    texel[1] = green[index];
+                }
+                texels
+            }
+            Codec::Bc7 => decode_bc7(block),
+            Codec::Etc1 | Codec::Etc2 { .. } | Codec::Etc2Eac => {
+                let (color, punchthrough) = match self {
+                    Codec::Etc2Eac => (&block[8..], false),
+                    Codec::Etc2 { punchthrough } => (block, punchthrough),
+                    _ => (block, false),
+                };
+                let mut texels = decode_etc(color, self != Codec::Etc1, punchthrough);
+                if self == Codec::Etc2Eac {
+                    let alpha = decode_eac(block, |base, modifier, multiplier| {
+                        (base as i32 + modifier * multiplier).clamp(0, 255)
+                    });
+                    for (texel, alpha) in texels.iter_mut().zip(alpha.iter()) {
+                        texel[3] = *alpha as u8;
+                    }
+                }
+                texels
+            }
+            Codec::Eac { .. } => unreachable!(),
+        }
+    }
+
+    fn decode_float(self, block: &[u8]) -> [[f32; 4]; 16] {
+        let mut texels = [[0.0, 0.0, 0.0, 1.0]; 16];
+        let channels = match self {
+            Codec::Bc4 { .. } => vec![decode_bc4_snorm(block)],
+            Codec::Bc5 { .. } => vec![decode_bc4_snorm(block), decode_bc4_snorm(&block[8..])],
+            Codec::Eac { channels, signed } => (0..channels)
+                .map(|channel| decode_r11(&block[channel * 8..], signed))
+                .collect(),
+            _ => unreachable!(),
+        };
+        for (channel, values) in channels.iter().enumerate() {
+            for (texel, value) in texels.iter_mut().zip(values.iter()) {
+                texel[channel] = *value;
+            }
+        }
+        texels
+    }
+}
+
+// Whether decode_image can handle textures of this internal format
+pub fn can_decode(internal_format: GLenum) -> bool {
+    Codec::from_format(internal_format).is_ok()
+}
+
+// Decodes tightly packed 4x4 blocks covering a width x height x depth image. Texels of
+// partial blocks past the edges are dropped.
+pub fn decode_blocks(
+    internal_format: GLenum,
+    width: u32,
+    height: u32,
+    depth: u32,
+    data: &[u8],
+) -> Result<DecodedImage, KtxError> {
+    let codec = Codec::from_format(internal_format)?;
+    let format = block_format(internal_format)
+        .ok_or(KtxError::UnsupportedFormat("no block size for this format"))?;
+    let expected = format
+        .image_bytes(width, height, depth)
+        .ok_or(KtxError::InvalidArgument("image too large to decode"))?;
+    if data.len() != expected {
+        return Err(KtxError::InvalidDataFormat {
+            offset: 0,
+            reason: "compressed image size doesn't match its dimensions",
+        });
+    }
+
+    let (width, height, depth) = (width as usize, height as usize, depth as usize);
+    let texel_count = width * height * depth;
+    let mut texels = if codec.is_float() {
+        Texels::Rgba32F(vec![0.0; texel_count * 4])
+    } else {
+        Texels::Rgba8(vec![0; texel_count * 4])
+    };
+    let (blocks_wide, blocks_high) = (width.div_ceil(4), height.div_ceil(4));
+    let blocks = data.chunks_exact(format.block_bytes as usize);
+    for (index, block) in blocks.enumerate() {
+        let row = index / blocks_wide;
+        let origin = (
+            index % blocks_wide * 4,
+            row % blocks_high * 4,
+            row / blocks_high,
+        );
+        match &mut texels {
+            Texels::Rgba8(output) => {
+                place_block(output, &codec.decode_unorm(block), origin, width, height)
+            }
+            Texels::Rgba32F(output) => {
+                place_block(output, &codec.decode_float(block), origin, width, height)
+            }
+        }
+    }
+
+    Ok(DecodedImage {
+        width: width as u32,
+        height: height as u32,
+        depth: depth as u32,
+        texels,
+    })
+}
+
+fn place_block<T: Copy>(
+    output: &mut [T],
+    block: &[[T; 4]; 16],
+    (block_x, block_y, slice): (usize, usize, usize),
+    width: usize,
+    height: usize,
+) {
+    for (texel, value) in block.iter().enumerate() {
+        let (x, y) = (block_x + texel % 4, block_y + texel / 4);
+        if x < width && y < height {
+            let offset = ((slice * height + y) * width + x) * 4;
+            output[offset..offset + 4].copy_from_slice(value);
+        }
+    }
+}
+
+pub fn decode_image(
+    ktx_texture: &KtxData,
+    level: u32,
+    layer: u32,
+    face: u32,
+) -> Result<DecodedImage, KtxError> {
+    let image = ktx_texture
+        .image(level, layer, face)
+        .ok_or(KtxError::NoSuchImage { level, layer, face })?;
+    let mip = &ktx_texture.levels[level as usize];
+    decode_blocks(
+        ktx_texture.header.gl_internal_format,
+        mip.width,
+        mip.height,
+        mip.depth,
+        image,
+    )
+}
+
+// Decodes every image of a compressed texture, giving RGBA8 (or SRGB8_ALPHA8) bytes or
+// RGBA32F floats that any GL implementation can sample
+pub fn decompress(ktx_texture: &KtxData) -> Result<KtxData<'static>, KtxError> {
+    let ktx = &ktx_texture.header;
+    let codec = Codec::from_format(ktx.gl_internal_format)?;
+
+    let mut header = ktx.clone();
+    header.endianess = KTX_ENDIANNESS.to_ne_bytes();
+    header.gl_format = gl::RGBA;
+    header.gl_base_internal_format = gl::RGBA;
+    if codec.is_float() {
+        header.gl_type = gl::FLOAT;
+        header.gl_type_size = 4;
+        header.gl_internal_format = gl::RGBA32F;
+    } else {
+        header.gl_type = gl::UNSIGNED_BYTE;
+        header.gl_type_size = 1;
+        header.gl_internal_format = if is_srgb(ktx.gl_internal_format) {
+            gl::SRGB8_ALPHA8
+        } else {
+            gl::RGBA8
+        };
+    }
+
+    let mut pixels = Vec::new();
+    let mut levels = Vec::with_capacity(ktx_texture.levels.len());
+    for (level, mip) in ktx_texture.levels.iter().enumerate() {
+        let mut images = Vec::with_capacity(mip.images.len());
+        for layer in 0..ktx.layer_count() {
+            for face in 0..ktx.face_count() {
+                let decoded = decode_image(ktx_texture, level as u32, layer, face)?;
+                let start = pixels.len();
+                match decoded.texels {
+                    Texels::Rgba8(texels) => pixels.extend(texels),
+                    Texels::Rgba32F(texels) => {
+                        pixels.extend(texels.iter().flat_map(|value| value.to_ne_bytes()))
+                    }
+                }
+                images.push(start..pixels.len());
+            }
+        }
+        levels.push(MipLevel {
+            width: mip.width,
+            height: mip.height,
+            depth: mip.depth,
+            images,
+        });
+    }
+
+    Ok(KtxData {
+        header,
+        key_values: ktx_texture.key_values.clone(),
+        levels,
+        unpack_alignment: 4,
+        pixels: Cow::Owned(pixels),
+    })
+}
+
+fn is_srgb(internal_format: GLenum) -> bool {
+    matches!(
+        internal_format,
+        COMPRESSED_SRGB_S3TC_DXT1
+            | COMPRESSED_SRGB_ALPHA_S3TC_DXT1
+            | COMPRESSED_SRGB_ALPHA_S3TC_DXT3
+            | COMPRESSED_SRGB_ALPHA_S3TC_DXT5
+            | gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM
+            | gl::COMPRESSED_SRGB8_ETC2
+            | gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2
+            | gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC
+    )
+}
+
+fn block_bytes(block: &[u8]) -> [u8; 8] {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&block[..8]);
+    bytes
+}
+
+fn rgb565(color: u16) -> [u8; 3] {
+    let (red, green, blue) = (
+        (color >> 11) as u8,
+        (color >> 5) as u8 & 0x3F,
+        color as u8 & 0x1F,
+    );
+    [
+        (red << 3) | (red >> 2),
+        (green << 2) | (green >> 4),
+        (blue << 3) | (blue >> 2),
+    ]
+}
+
+// The color half of the S3TC formats. Only BC1 switches to three colors and black, which
+// is transparent for the RGBA variants, when the first endpoint isn't the larger one.
+fn decode_color_block(block: &[u8], three_color: bool, transparent: bool) -> [[u8; 4]; 16] {
+    let color0 = u16::from_le_bytes([block[0], block[1]]);
+    let color1 = u16::from_le_bytes([block[2], block[3]]);
+    let (endpoint0, endpoint1) = (rgb565(color0), rgb565(color1));
+    let four_colors = !three_color || color0 > color1;
+
+    let mut palette = [[0, 0, 0, 255]; 4];
+    for channel in 0..3 {
+        let (first, second) = (endpoint0[channel] as u32, endpoint1[channel] as u32);
+        palette[0][channel] = first as u8;
+        palette[1][channel] = second as u8;
+        if four_colors {
+            palette[2][channel] = ((2 * first + second) / 3) as u8;
+            palette[3][channel] = ((first + 2 * second) / 3) as u8;
+        } else {
+            palette[2][channel] = ((first + second) / 2) as u8;
+        }
+    }
+    if !four_colors && transparent {
+        palette[3][3] = 0;
+    }
+
+    let indices = u32::from_le_bytes([block[4], block[5], block[6], block[7]]);
+    let mut texels = [[0; 4]; 16];
+    for (index, texel) in texels.iter_mut().enumerate() {
+        *texel = palette[(indices >> (2 * index)) as usize & 3];
+    }
+    texels
+}
+
+fn bc4_indices(block: &[u8]) -> [usize; 16] {
+    let bits = u64::from_le_bytes(block_bytes(block)) >> 16;
+    let mut indices = [0; 16];
+    for (texel, index) in indices.iter_mut().enumerate() {
+        *index = (bits >> (3 * texel)) as usize & 7;
+    }
+    indices
+}
+
+// Eight interpolated values when the first endpoint is larger, otherwise six plus 0 and 255
+pub(super) fn bc4_palette(red0: u8, red1: u8) -> [u8; 8] {
+    let (first, second) = (red0 as u32, red1 as u32);
+    let mut palette = [red0, red1, 0, 0, 0, 0, 0, 255];
+    if first > second {
+        for step in 1..7 {
+            palette[step as usize + 1] = ((first * (7 - step) + second * step) / 7) as u8;
+        }
+    } else {
+        for step in 1..5 {
+            palette[step as usize + 1] = ((first * (5 - step) + second * step) / 5) as u8;
+        }
+    }
+    palette
+}
+
+fn decode_bc4_unorm(block: &[u8]) -> [u8; 16] {
+    let palette = bc4_palette(block[0], block[1]);
+    let mut texels = [0; 16];
+    for (texel, index) in texels.iter_mut().zip(bc4_indices(block).iter()) {
+        *texel = palette[*index];
+    }
+    texels
+}
+
+// Signed endpoints compare as signed bytes, with -128 taken as -127 like in SNORM8
+fn decode_bc4_snorm(block: &[u8]) -> [f32; 16] {
+    let (red0, red1) = (block[0] as i8, block[1] as i8);
+    let (first, second) = (red0.max(-127) as f32 / 127.0, red1.max(-127) as f32 / 127.0);
+    let mut palette = [first, second, 0.0, 0.0, 0.0, 0.0, -1.0, 1.0];
+    if red0 > red1 {
+        for step in 1..7 {
+            palette[step + 1] = (first * (7 - step) as f32 + second * step as f32) / 7.0;
+        }
+    } else {
+        for step in 1..5 {
+            palette[step + 1] = (first * (5 - step) as f32 + second * step as f32) / 5.0;
+        }
+    }
+    let mut texels = [0.0; 16];
+    for (texel, index) in texels.iter_mut().zip(bc4_indices(block).iter()) {
+        *texel = palette[*index];
+    }
+    texels
+}
+
+struct Bc7Mode {
+    subsets: usize,
+    partition_bits: u32,
+    rotation_bits: u32,
+    selector_bits: u32,
+    color_bits: u32,
+    alpha_bits: u32,
+    endpoint_pbits: bool,
+    shared_pbits: bool,
+    index_bits: u32,
+    secondary_index_bits: u32,
+}
+
+const fn bc7_mode(
+    subsets: usize,
+    partition_bits: u32,
+    (rotation_bits, selector_bits): (u32, u32),
+    (color_bits, alpha_bits): (u32, u32),
+    (endpoint_pbits, shared_pbits): (bool, bool),
+    (index_bits, secondary_index_bits): (u32, u32),
+) -> Bc7Mode {
+    Bc7Mode {
+        subsets,
+        partition_bits,
+        rotation_bits,
+        selector_bits,
+        color_bits,
+        alpha_bits,
+        endpoint_pbits,
+        shared_pbits,
+        index_bits,
+        secondary_index_bits,
+    }
+}
+
+const BC7_MODES: [Bc7Mode; 8] = [
+    bc7_mode(3, 4, (0, 0), (4, 0), (true, false), (3, 0)),
+    bc7_mode(2, 6, (0, 0), (6, 0), (false, true), (3, 0)),
+    bc7_mode(3, 6, (0, 0), (5, 0), (false, false), (2, 0)),
+    bc7_mode(2, 6, (0, 0), (7, 0), (true, false), (2, 0)),
+    bc7_mode(1, 0, (2, 1), (5, 6), (false, false), (2, 3)),
+    bc7_mode(1, 0, (2, 0), (7, 8), (false, false), (2, 2)),
+    bc7_mode(1, 0, (0, 0), (7, 7), (true, false), (4, 0)),
+    bc7_mode(2, 6, (0, 0), (5, 5), (true, false), (2, 0)),
+];
+
+// Subset of each texel, one bit per texel for two subsets and two bits for three
+const BC7_PARTITIONS_2: [u32; 64] = [
+    0xCCCC, 0x8888, 0xEEEE, 0xECC8, 0xC880, 0xFEEC, 0xFEC8, 0xEC80, 0xC800, 0xFFEC, 0xFE80, 0xE800,
+    0xFFE8, 0xFF00, 0xFFF0, 0xF000, 0xF710, 0x008E, 0x7100, 0x08CE, 0x008C, 0x7310, 0x3100, 0x8CCE,
+    0x088C, 0x3110, 0x6666, 0x366C, 0x17E8, 0x0FF0, 0x718E, 0x399C, 0xAAAA, 0xF0F0, 0x5A5A, 0x33CC,
+    0x3C3C, 0x55AA, 0x9696, 0xA55A, 0x73CE, 0x13C8, 0x324C, 0x3BDC, 0x6996, 0xC33C, 0x9966, 0x0660,
+    0x0272, 0x04E4, 0x4E40, 0x2720, 0xC936, 0x936C, 0x39C6, 0x639C, 0x9336, 0x9CC6, 0x817E, 0xE718,
+    0xCCF0, 0x0FCC, 0x7744, 0xEE22,
+];
+
+const BC7_PARTITIONS_3: [u32; 64] = [
+    0xAA685050, 0x6A5A5040, 0x5A5A4200, 0x5450A0A8, 0xA5A50000, 0xA0A05050, 0x5555A0A0, 0x5A5A5050,
+    0xAA550000, 0xAA555500, 0xAAAA5500, 0x90909090, 0x94949494, 0xA4A4A4A4, 0xA9A59450, 0x2A0A4250,
+    0xA5945040, 0x0A425054, 0xA5A5A500, 0x55A0A0A0, 0xA8A85454, 0x6A6A4040, 0xA4A45000, 0x1A1A0500,
+    0x0050A4A4, 0xAAA59090, 0x14696914, 0x69691400, 0xA08585A0, 0xAA821414, 0x50A4A450, 0x6A5A0200,
+    0xA9A58000, 0x5090A0A8, 0xA8A09050, 0x24242424, 0x00AA5500, 0x24924924, 0x24499224, 0x50A50A50,
+    0x500AA550, 0xAAAA4444, 0x66660000, 0xA5A0A5A0, 0x50A050A0, 0x69286928, 0x44AAAA44, 0x66666600,
+    0xAA444444, 0x54A854A8, 0x95809580, 0x96969600, 0xA85454A8, 0x80959580, 0xAA141414, 0x96960000,
+    0xAAAA1414, 0xA05050A0, 0xA0A5A5A0, 0x96000000, 0x40804080, 0xA9A8A9A8, 0xAAAAAA44, 0x2A4A5254,
+];
+
+// The texel of the second subset whose index drops its top bit
+const BC7_ANCHORS_2: [u8; 64] = [
+    15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 15, 2, 8, 2, 2, 8, 8, 15, 2, 8,
+    2, 2, 8, 8, 2, 2, 15, 15, 6, 8, 2, 8, 15, 15, 2, 8, 2, 2, 2, 15, 15, 6, 6, 2, 6, 8, 15, 15, 2,
+    2, 15, 15, 15, 15, 15, 2, 2, 15,
+];
+
+// The anchors of the second and third subsets
+const BC7_ANCHORS_3: [[u8; 64]; 2] = [
+    [
+        3, 3, 15, 15, 8, 3, 15, 15, 8, 8, 6, 6, 6, 5, 3, 3, 3, 3, 8, 15, 3, 3, 6, 10, 5, 8, 8, 6,
+        8, 5, 15, 15, 8, 15, 3, 5, 6, 10, 8, 15, 15, 3, 15, 5, 15, 15, 15, 15, 3, 15, 5, 5, 5, 8,
+        5, 10, 5, 10, 8, 13, 15, 12, 3, 3,
+    ],
+    [
+        15, 8, 8, 3, 15, 15, 3, 8, 15, 15, 15, 15, 15, 15, 15, 8, 15, 8, 15, 3, 15, 8, 15, 8, 3,
+        15, 6, 10, 15, 15, 10, 8, 15, 3, 15, 10, 10, 8, 9, 10, 6, 15, 8, 15, 3, 6, 6, 8, 15, 3, 15,
+        15, 15, 15, 15, 15, 15, 15, 15, 15, 3, 15, 15, 8,
+    ],
+];
+
+const BC7_WEIGHTS_2: [u32; 4] = [0, 21, 43, 64];
+const BC7_WEIGHTS_3: [u32; 8] = [0, 9, 18, 27, 37, 46, 55, 64];
+const BC7_WEIGHTS_4: [u32; 16] = [0, 4, 9, 13, 17, 21, 26, 30, 34, 38, 43, 47, 51, 55, 60, 64];
+
+struct BitReader {
+    bits: u128,
+    position: u32,
+}
+
+impl BitReader {
+    fn read(&mut self, count: u32) -> u32 {
+        let value =
+            self.bits.checked_shr(self.position).unwrap_or(0) as u32 & ((1u64 << count) - 1) as u32;
+        self.position += count;
+        value
+    }
+}
+
+fn bc7_weights(index_bits: u32) -> &'static [u32] {
+    match index_bits {
+        2 => &BC7_WEIGHTS_2,
+        3 => &BC7_WEIGHTS_3,
+        _ => &BC7_WEIGHTS_4,
+    }
+}
+
+fn decode_bc7(block: &[u8]) -> [[u8; 4]; 16] {
+    let mut bytes = [0; 16];
+    bytes.copy_from_slice(&block[..16]);
+    let bits = u128::from_le_bytes(bytes);
+    // Reserved blocks without a mode bit decode to transparent black
+    if bits as u8 == 0 {
+        return [[0; 4]; 16];
+    }
+    let mode_index = bits.trailing_zeros();
+    let mode = &BC7_MODES[mode_index as usize];
+    let mut reader = BitReader {
+        bits,
+        position: mode_index + 1,
+    };
+
+    let partition = reader.read(mode.partition_bits) as usize;
+    let rotation = reader.read(mode.rotation_bits);
+    let selector = reader.read(mode.selector_bits);
+
+    // Endpoints are stored channel by channel, two for each subset
+    let endpoint_count = mode.subsets * 2;
+    let mut endpoints = [[0u32; 4]; 6];
+    for channel in 0..4 {
+        let bits = if channel < 3 {
+            mode.color_bits
+        } else {
+            mode.alpha_bits
+        };
+        for endpoint in endpoints.iter_mut().take(endpoint_count) {
+            endpoint[channel] = reader.read(bits);
+        }
+    }
+
+    let mut precision = [
+        mode.color_bits,
+        mode.color_bits,
+        mode.color_bits,
+        mode.alpha_bits,
+    ];
+    if mode.endpoint_pbits || mode.shared_pbits {
+        let pbits: Vec<u32> = if mode.endpoint_pbits {
+            (0..endpoint_count).map(|_| reader.read(1)).collect()
+        } else {
+            (0..mode.subsets)
+                .flat_map(|_| {
+                    let pbit = reader.read(1);
+                    [pbit, pbit]
+                })
+                .collect()
+        };
+        for (endpoint, pbit) in endpoints.iter_mut().zip(pbits) {
+            for channel in endpoint.iter_mut() {
+                *channel = (*channel << 1) | pbit;
+            }
+        }
+        for bits in precision.iter_mut() {
+            *bits += (*bits > 0) as u32;
+        }
+    }
+    for endpoint in endpoints.iter_mut().take(endpoint_count) {
+        for (channel, bits) in endpoint.iter_mut().zip(precision.iter()) {
+            *channel = match bits {
+                0 => 255,
+                bits => (*channel << (8 - bits)) | (*channel >> (2 * bits - 8)),
+            };
+        }
+    }
+
+    let subset_of = |texel: usize| match mode.subsets {
+        2 => (BC7_PARTITIONS_2[partition] >> texel) as usize & 1,
+        3 => (BC7_PARTITIONS_3[partition] >> (2 * texel)) as usize & 3,
+        _ => 0,
+    };
+    let is_anchor = |texel: usize| match (mode.subsets, subset_of(texel)) {
+        (_, 0) => texel == 0,
+        (2, _) => texel == BC7_ANCHORS_2[partition] as usize,
+        (_, subset) => texel == BC7_ANCHORS_3[subset - 1][partition] as usize,
+    };
+    let mut read_indices = |index_bits: u32| {
+        let mut indices = [0; 16];
+        for (texel, index) in indices.iter_mut().enumerate() {
+            *index = reader.read(index_bits - is_anchor(texel) as u32) as usize;
+        }
+        indices
+    };
+    let primary = read_indices(mode.index_bits);
+    let secondary = match mode.secondary_index_bits {
+        0 => primary,
+        bits => read_indices(bits),
+    };
+
+    // The selector swaps which indices the color and alpha use
+    let (mut color_indices, mut alpha_indices) = (&primary, &secondary);
+    let (mut color_bits, mut alpha_bits) = (mode.index_bits, mode.secondary_index_bits);
+    if alpha_bits == 0 {
+        alpha_bits = color_bits;
+    }
+    if selector == 1 {
+        std::mem::swap(&mut color_indices, &mut alpha_indices);
+        std::mem::swap(&mut color_bits, &mut alpha_bits);
+    }
+    let (color_weights, alpha_weights) = (bc7_weights(color_bits), bc7_weights(alpha_bits));
+
+    let mut texels = [[0; 4]; 16];
+    for (texel, output) in texels.iter_mut().enumerate() {
+        let subset = subset_of(texel);
+        let (first, second) = (endpoints[subset * 2], endpoints[subset * 2 + 1]);
+        for channel in 0..4 {
+            let weight = if channel < 3 {
+                color_weights[color_indices[texel]]
+            } else {
+                alpha_weights[alpha_indices[texel]]
+            };
+            output[channel] =
+                (((64 - weight) * first[channel] + weight * second[channel] + 32) >> 6) as u8;
+        }
+        if rotation > 0 {
+            output.swap(3, rotation as usize - 1);
+        }
+    }
+    texels
+}
+
+const ETC_MODIFIERS: [[i32; 2]; 8] = [
+    [2, 8],
+    [5, 17],
+    [9, 29],
+    [13, 42],
+    [18, 60],
+    [24, 80],
+    [33, 106],
+    [47, 183],
+];
+
+const ETC_DISTANCES: [i32; 8] = [3, 6, 11, 16, 23, 32, 41, 64];
+
+const EAC_MODIFIERS: [[i32; 8]; 16] = [
+    [-3, -6, -9, -15, 2, 5, 8, 14],
+    [-3, -7, -10, -13, 2, 6, 9, 12],
+    [-2, -5, -8, -13, 1, 4, 7, 12],
+    [-2, -4, -6, -13, 1, 3, 5, 12],
+    [-3, -6, -8, -12, 2, 5, 7, 11],
+    [-3, -7, -9, -11, 2, 6, 8, 10],
+    [-4, -7, -8, -11, 3, 6, 7, 10],
+    [-3, -5, -8, -11, 2, 4, 7, 10],
+    [-2, -6, -8, -10, 1, 5, 7, 9],
+    [-2, -5, -8, -10, 1, 4, 7, 9],
+    [-2, -4, -8, -10, 1, 3, 7, 9],
+    [-2, -5, -7, -10, 1, 4, 6, 9],
+    [-3, -4, -7, -10, 2, 3, 6, 9],
+    [-1, -2, -3, -10, 0, 1, 2, 9],
+    [-4, -6, -8, -9, 3, 5, 7, 8],
+    [-3, -5, -7, -9, 2, 4, 6, 8],
+];
+
+fn bits_at(bits: u64, high: u32, count: u32) -> u32 {
+    ((bits >> (high + 1 - count)) & ((1 << count) - 1)) as u32
+}
+
+fn extend(value: u32, bits: u32) -> i32 {
+    ((value << (8 - bits)) | (value >> (2 * bits - 8))) as i32
+}
+
+fn offset_color(color: [i32; 3], offset: i32) -> [u8; 4] {
+    [
+        (color[0] + offset).clamp(0, 255) as u8,
+        (color[1] + offset).clamp(0, 255) as u8,
+        (color[2] + offset).clamp(0, 255) as u8,
+        255,
+    ]
+}
+
+// ETC1 and the ETC2 color block, which reuses out of range differential colors to pick
+// its T, H and planar modes. Punchthrough blocks reuse the differential bit as an opaque
+// flag, and without it index 2 is transparent black. Texel indices run down the columns.
+fn decode_etc(block: &[u8], etc2: bool, punchthrough: bool) -> [[u8; 4]; 16] {
+    let bits = u64::from_be_bytes(block_bytes(block));
+    let differential = bits_at(bits, 33, 1) == 1 || punchthrough;
+    let opaque = !punchthrough || bits_at(bits, 33, 1) == 1;
+    let mut texels = [[0; 4]; 16];
+    let index_of = |texel: usize| {
+        let column_major = (texel % 4) * 4 + texel / 4;
+        (bits_at(bits, 16 + column_major as u32, 1) << 1 | bits_at(bits, column_major as u32, 1))
+            as usize
+    };
+    let transparent = |index: usize| !opaque && index == 2;
+
+    let base = |high: u32| bits_at(bits, high, 5) as i32;
+    let delta = |high: u32| ((bits_at(bits, high, 3) << 29) as i32) >> 29;
+    let sums = [
+        base(63) + delta(58),
+        base(55) + delta(50),
+        base(47) + delta(42),
+    ];
+    let overflow = |channel: usize| !(0..32).contains(&sums[channel]);
+
+    if etc2 && differential && overflow(0) {
+        // T mode
+        let color0 = [
+            bits_at(bits, 60, 2) << 2 | bits_at(bits, 57, 2),
+            bits_at(bits, 55, 4),
+            bits_at(bits, 51, 4),
+        ]
+        .map(|channel| extend(channel, 4));
+        let color1 = [47, 43, 39].map(|high| extend(bits_at(bits, high, 4), 4));
+        let distance = ETC_DISTANCES[(bits_at(bits, 35, 2) << 1 | bits_at(bits, 32, 1)) as usize];
+        let palette = [
+            offset_color(color0, 0),
+            offset_color(color1, distance),
+            offset_color(color1, 0),
+            offset_color(color1, -distance),
+        ];
+        for (texel, output) in texels.iter_mut().enumerate() {
+            let index = index_of(texel);
+            *output = if transparent(index) {
+                [0; 4]
+            } else {
+                palette[index]
+            };
+        }
+    } else if etc2 && differential && overflow(1) {
+        // H mode
+        let color0 = [
+            bits_at(bits, 62, 4),
+            bits_at(bits, 58, 3) << 1 | bits_at(bits, 52, 1),
+            bits_at(bits, 51, 1) << 3 | bits_at(bits, 49, 3),
+        ];
+        let color1 = [
+            bits_at(bits, 46, 4),
+            bits_at(bits, 42, 4),
+            bits_at(bits, 38, 4),
+        ];
+        let value = |color: [u32; 3]| color[0] << 8 | color[1] << 4 | color[2];
+        let distance_index = bits_at(bits, 34, 1) << 2
+            | bits_at(bits, 32, 1) << 1
+            | (value(color0) >= value(color1)) as u32;
+        let distance = ETC_DISTANCES[distance_index as usize];
+        let (color0, color1) = (color0.map(|c| extend(c, 4)), color1.map(|c| extend(c, 4)));
+        let palette = [
+            offset_color(color0, distance),
+            offset_color(color0, -distance),
+            offset_color(color1, distance),
+            offset_color(color1, -distance),
+        ];
+        for (texel, output) in texels.iter_mut().enumerate() {
+            let index = index_of(texel);
+            *output = if transparent(index) {
+                [0; 4]
+            } else {
+                palette[index]
+            };
+        }
+    } else if etc2 && differential && overflow(2) {
+        // Planar mode interpolates between an origin and the colors at x = 4 and y = 4
+        let origin = [
+            extend(bits_at(bits, 62, 6), 6),
+            extend(bits_at(bits, 56, 1) << 6 | bits_at(bits, 54, 6), 7),
+            extend(
+                bits_at(bits, 48, 1) << 5 | bits_at(bits, 44, 2) << 3 | bits_at(bits, 41, 3),
+                6,
+            ),
+        ];
+        let horizontal = [
+            extend(bits_at(bits, 38, 5) << 1 | bits_at(bits, 32, 1), 6),
+            extend(bits_at(bits, 31, 7), 7),
+            extend(bits_at(bits, 24, 6), 6),
+        ];
+        let vertical = [
+            extend(bits_at(bits, 18, 6), 6),
+            extend(bits_at(bits, 12, 7), 7),
+            extend(bits_at(bits, 5, 6), 6),
+        ];
+        for (texel, output) in texels.iter_mut().enumerate() {
+            let (x, y) = ((texel % 4) as i32, (texel / 4) as i32);
+            for channel in 0..3 {
+                let value = (x * (horizontal[channel] - origin[channel])
+                    + y * (vertical[channel] - origin[channel])
+                    + 4 * origin[channel]
+                    + 2)
+                    >> 2;
+                output[channel] = value.clamp(0, 255) as u8;
+            }
+            output[3] = 255;
+        }
+    } else {
+        let (color0, color1) = if differential {
+            (
+                [base(63), base(55), base(47)].map(|channel| extend(channel as u32, 5)),
+                sums.map(|channel| extend(channel as u32 & 0x1F, 5)),
+            )
+        } else {
+            (
+                [63, 55, 47].map(|high| extend(bits_at(bits, high, 4), 4)),
+                [59, 51, 43].map(|high| extend(bits_at(bits, high, 4), 4)),
+            )
+        };
+        let tables = [bits_at(bits, 39, 3), bits_at(bits, 36, 3)];
+        let flipped = bits_at(bits, 32, 1) == 1;
+        for (texel, output) in texels.iter_mut().enumerate() {
+            let (x, y) = (texel % 4, texel / 4);
+            let subblock = if flipped { y >= 2 } else { x >= 2 } as usize;
+            let index = index_of(texel);
+            let mut modifier = ETC_MODIFIERS[tables[subblock] as usize][index & 1];
+            // Without the opaque bit the smaller modifiers are zero
+            if !opaque && index & 1 == 0 {
+                modifier = 0;
+            }
+            if index & 2 != 0 {
+                modifier = -modifier;
+            }
+            let color = if subblock == 0 { color0 } else { color1 };
+            *output = if transparent(index) {
+                [0; 4]
+            } else {
+                offset_color(color, modifier)
+            };
+        }
+    }
+    texels
+}
+
+// An EAC block gives each texel a base value plus a scaled modifier, resolved by
+// the caller since alpha, unsigned and signed R11 all do it differently
+fn decode_eac<F: Fn(u32, i32, i32) -> i32>(block: &[u8], resolve: F) -> [i32; 16] {
+    let bits = u64::from_be_bytes(block_bytes(block));
+    let base = bits_at(bits, 63, 8);
+    let multiplier = bits_at(bits, 55, 4) as i32;
+    let modifiers = EAC_MODIFIERS[bits_at(bits, 51, 4) as usize];
+    let mut texels = [0; 16];
+    for (texel, output) in texels.iter_mut().enumerate() {
+        let column_major = (texel % 4) * 4 + texel / 4;
+        let index = bits_at(bits, 47 - 3 * column_major as u32, 3) as usize;
+        *output = resolve(base, modifiers[index], multiplier);
+    }
+    texels
+}
+
+fn decode_r11(block: &[u8], signed: bool) -> [f32; 16] {
+    // A multiplier of zero scales the modifier by an eighth of the usual step
+    let scale = |modifier: i32, multiplier: i32| match multiplier {
+        0 => modifier,
+        multiplier => modifier * multiplier * 8,
+    };
+    let values = decode_eac(block, |base, modifier, multiplier| {
+        if signed {
+            let base = (base as u8 as i8).max(-127) as i32;
+            (base * 8 + scale(modifier, multiplier)).clamp(-1023, 1023)
+        } else {
+            (base as i32 * 8 + 4 + scale(modifier, multiplier)).clamp(0, 2047)
+        }
+    });
+    values.map(|value| value as f32 / if signed { 1023.0 } else { 2047.0 })
+}
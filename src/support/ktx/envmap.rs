@@ -0,0 +1,247 @@
+use super::{
+    decode,
+    mipmap::{is_srgb_format, PixelLayout},
+    KtxData, KtxError, MipLevel, KTX_ALIGNMENT, KTX_ENDIANNESS,
+};
+use std::{borrow::Cow, f32::consts::PI};
+
+// Layouts an environment map can be stored in. Texture coordinates follow GL sampling,
+// so t = 0 is the first row of an image.
+//
+// Equirectangular maps span longitude atan2(z, x) across s and latitude asin(y) up t,
+// twice as wide as they are tall. Cube maps pick faces as GL does, in KTX face order.
+// Sphere maps are looked up like the book's envmapsphere shader, reflections about a
+// viewer looking down -Z.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Projection {
+    Equirectangular,
+    CubeMap,
+    SphereMap,
+}
+
+impl Projection {
+    fn faces(self) -> usize {
+        if self == Projection::CubeMap {
+            6
+        } else {
+            1
+        }
+    }
+
+    // The direction seen through texture coordinates s, t of a face
+    fn direction(self, face: usize, s: f32, t: f32) -> [f32; 3] {
+        match self {
+            Projection::Equirectangular => {
+                let (longitude, latitude) = ((s - 0.5) * 2.0 * PI, (t - 0.5) * PI);
+                [
+                    latitude.cos() * longitude.cos(),
+                    latitude.sin(),
+                    latitude.cos() * longitude.sin(),
+                ]
+            }
+            Projection::CubeMap => {
+                let (sc, tc) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+                normalize(match face {
+                    0 => [1.0, -tc, -sc],
+                    1 => [-1.0, -tc, sc],
+                    2 => [sc, 1.0, tc],
+                    3 => [sc, -1.0, -tc],
+                    4 => [sc, -tc, 1.0],
+                    _ => [-sc, -tc, -1.0],
+                })
+            }
+            Projection::SphereMap => {
+                // Texels past the disc take the direction of its edge
+                let (mut x, mut y) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+                let length = (x * x + y * y).sqrt();
+                if length > 1.0 {
+                    x /= length;
+                    y /= length;
+                }
+                let z = (1.0 - x * x - y * y).max(0.0).sqrt();
+                [2.0 * z * x, 2.0 * z * y, 2.0 * z * z - 1.0]
+            }
+        }
+    }
+
+    // The face and texture coordinates a direction is looked up at
+    fn coordinates(self, direction: [f32; 3]) -> (usize, f32, f32) {
+        let [x, y, z] = direction;
+        match self {
+            Projection::Equirectangular => {
+                let latitude = (y / (x * x + y * y + z * z).sqrt()).clamp(-1.0, 1.0).asin();
+                (0, 0.5 + z.atan2(x) / (2.0 * PI), 0.5 + latitude / PI)
+            }
+            Projection::CubeMap => {
+                let (ax, ay, az) = (x.abs(), y.abs(), z.abs());
+                let (face, sc, tc, major) = if ax >= ay && ax >= az {
+                    if x > 0.0 {
+                        (0, -z, -y, ax)
+                    } else {
+                        (1, z, -y, ax)
+                    }
+                } else if ay >= az {
+                    if y > 0.0 {
+                        (2, x, z, ay)
+                    } else {
+                        (3, x, -z, ay)
+                    }
+                } else if z > 0.0 {
+                    (4, x, -y, az)
+                } else {
+                    (5, -x, -y, az)
+                };
+                (face, (sc / major + 1.0) / 2.0, (tc / major + 1.0) / 2.0)
+            }
+            Projection::SphereMap => {
+                let [x, y, z] = normalize(direction);
+                let length = (x * x + y * y + (z + 1.0) * (z + 1.0)).sqrt();
+                if length < 1e-6 {
+                    // Straight behind maps to the whole rim
+                    return (0, 1.0, 0.5);
+                }
+                (0, x / (2.0 * length) + 0.5, y / (2.0 * length) + 0.5)
+            }
+        }
+    }
+}
+
+fn normalize([x, y, z]: [f32; 3]) -> [f32; 3] {
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
+// The linear texels of every face of an environment map's base level
+struct Faces {
+    projection: Projection,
+    width: usize,
+    height: usize,
+    components: usize,
+    texels: Vec<Vec<f32>>,
+}
+
+impl Faces {
+    // Bilinear filtering, wrapping around the equirectangular seam and clamping elsewhere
+    fn sample(&self, direction: [f32; 3], output: &mut [f32]) {
+        let (face, s, t) = self.projection.coordinates(direction);
+        let texels = &self.texels[face];
+        let wraps = self.projection == Projection::Equirectangular;
+        let (x, y) = (s * self.width as f32 - 0.5, t * self.height as f32 - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = (x - x0, y - y0);
+        let column = |x: f32| {
+            let x = x as isize;
+            if wraps {
+                x.rem_euclid(self.width as isize) as usize
+            } else {
+                x.clamp(0, self.width as isize - 1) as usize
+            }
+        };
+        let row = |y: f32| (y as isize).clamp(0, self.height as isize - 1) as usize;
+
+        output.fill(0.0);
+        let corners = [
+            (column(x0), row(y0), (1.0 - fx) * (1.0 - fy)),
+            (column(x0 + 1.0), row(y0), fx * (1.0 - fy)),
+            (column(x0), row(y0 + 1.0), (1.0 - fx) * fy),
+            (column(x0 + 1.0), row(y0 + 1.0), fx * fy),
+        ];
+        for (x, y, weight) in corners.iter() {
+            let texel = (y * self.width + x) * self.components;
+            for (value, source) in output.iter_mut().zip(&texels[texel..]) {
+                *value += weight * source;
+            }
+        }
+    }
+}
+
+// Resamples the base level of an environment map from one projection to another.
+// Equirectangular results are 2 * size by size, cube faces and sphere maps size by size.
+// Compressed maps the CPU can decode come out as RGBA.
+pub fn reproject(
+    ktx_texture: &KtxData,
+    from: Projection,
+    to: Projection,
+    size: u32,
+) -> Result<KtxData<'static>, KtxError> {
+    let ktx = &ktx_texture.header;
+    if ktx.face_count() as usize != from.faces() || ktx.array_elements != 0 {
+        return Err(KtxError::UnsupportedLayout(
+            "environment map with the wrong number of faces or layers",
+        ));
+    }
+    if ktx.pixel_depth > 1 || ktx.pixel_height == 0 || size == 0 {
+        return Err(KtxError::UnsupportedLayout("3D or empty environment map"));
+    }
+    if ktx.is_compressed() && decode::can_decode(ktx.gl_internal_format) {
+        return reproject(&decode::decompress(ktx_texture)?, from, to, size);
+    }
+
+    let layout = PixelLayout::new(ktx_texture)?;
+    let srgb = is_srgb_format(ktx.gl_internal_format);
+    let base = ktx_texture
+        .levels
+        .first()
+        .ok_or(KtxError::InvalidArgument("texture has no mip levels"))?;
+    let source_dimensions = [base.width as usize, base.height as usize, 1];
+    let source = Faces {
+        projection: from,
+        width: base.width as usize,
+        height: base.height as usize,
+        components: layout.components,
+        texels: base
+            .images
+            .iter()
+            .map(|range| {
+                layout.decode(
+                    &ktx_texture.pixels[range.clone()],
+                    source_dimensions,
+                    ktx_texture.unpack_alignment as usize,
+                    srgb,
+                )
+            })
+            .collect(),
+    };
+
+    let (width, height) = match to {
+        Projection::Equirectangular => (2 * size, size),
+        _ => (size, size),
+    };
+    let mut pixels = Vec::new();
+    let mut images = Vec::with_capacity(to.faces());
+    let mut texels = vec![0.0; width as usize * height as usize * layout.components];
+    for face in 0..to.faces() {
+        for (index, texel) in texels.chunks_exact_mut(layout.components).enumerate() {
+            let (x, y) = (index % width as usize, index / width as usize);
+            let direction = to.direction(
+                face,
+                (x as f32 + 0.5) / width as f32,
+                (y as f32 + 0.5) / height as f32,
+            );
+            source.sample(direction, texel);
+        }
+        let start = pixels.len();
+        pixels.extend(layout.encode(&texels, [width as usize, height as usize, 1], srgb));
+        images.push(start..pixels.len());
+    }
+
+    let mut header = ktx.clone();
+    header.endianess = KTX_ENDIANNESS.to_ne_bytes();
+    header.pixel_width = width;
+    header.pixel_height = height;
+    header.pixel_depth = 0;
+    header.faces = to.faces() as u32;
+    header.mip_levels = 1;
+    Ok(KtxData {
+        header,
+        key_values: ktx_texture.key_values.clone(),
+        levels: vec![MipLevel {
+            width,
+            height,
+            depth: 1,
+            images,
+        }],
+        unpack_alignment: KTX_ALIGNMENT as i32,
+        pixels: Cow::Owned(pixels),
+    })
+}
@@ -0,0 +1,510 @@
+use super::*;
+use miniz_oxide::inflate::decompress_to_vec_zlib_with_limit;
+use ruzstd::decoding::StreamingDecoder;
+use std::io::Read;
+
+pub const KTX2_IDENTIFIER: [u8; 12] = [
+    0xAB, 0x4B, 0x54, 0x58, 0x20, 0x32, 0x30, 0xBB, 0x0D, 0x0A, 0x1A, 0x0A,
+];
+
+pub const SUPERCOMPRESSION_NONE: u32 = 0;
+pub const SUPERCOMPRESSION_BASIS_LZ: u32 = 1;
+pub const SUPERCOMPRESSION_ZSTD: u32 = 2;
+pub const SUPERCOMPRESSION_ZLIB: u32 = 3;
+
+const KTX2_LEVEL_INDEX_OFFSET: usize = 80;
+const KTX2_LEVEL_INDEX_BYTES: usize = 24;
+
+const KHR_DF_VENDORID_KHRONOS: u32 = 0;
+const KHR_DF_KHR_DESCRIPTORTYPE_BASICFORMAT: u32 = 0;
+const KHR_DF_BASIC_HEADER_BYTES: usize = 24;
+const KHR_DF_SAMPLE_BYTES: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFormatSample {
+    pub bit_offset: u16,
+    pub bit_length: u8,
+    pub channel_type: u8,
+    pub sample_position: [u8; 4],
+    pub sample_lower: u32,
+    pub sample_upper: u32,
+}
+
+// The basic descriptor block of the Khronos Data Format Descriptor
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataFormatDescriptor {
+    pub color_model: u8,
+    pub color_primaries: u8,
+    pub transfer_function: u8,
+    pub flags: u8,
+    pub texel_block_dimensions: [u8; 4],
+    pub bytes_planes: [u8; 8],
+    pub samples: Vec<DataFormatSample>,
+}
+
+pub struct Ktx2Data<'a> {
+    pub vk_format: u32,
+    pub supercompression_scheme: u32,
+    pub data_format: DataFormatDescriptor,
+    pub texture: KtxData<'a>,
+}
+
+pub(super) struct GlFormat {
+    pub(super) internal_format: GLenum,
+    pub(super) format: GLenum,
+    pub(super) gl_type: GLenum,
+    pub(super) base_internal_format: GLenum,
+}
+
+impl GlFormat {
+    pub(super) fn uncompressed(internal_format: GLenum, format: GLenum, gl_type: GLenum) -> Self {
+        GlFormat {
+            internal_format,
+            format,
+            gl_type,
+            base_internal_format: match format {
+                gl::RED_INTEGER => gl::RED,
+                gl::RG_INTEGER => gl::RG,
+                gl::RGB_INTEGER | gl::BGR => gl::RGB,
+                gl::RGBA_INTEGER | gl::BGRA => gl::RGBA,
+                format => format,
+            },
+        }
+    }
+
+    pub(super) fn compressed(internal_format: GLenum, base_internal_format: GLenum) -> Self {
+        GlFormat {
+            internal_format,
+            format: 0,
+            gl_type: 0,
+            base_internal_format,
+        }
+    }
+
+    // The first Vulkan format stored with an internal format, which prefers RGB order
+    // over BGR
+    pub(super) fn for_internal_format(internal_format: GLenum) -> Option<Self> {
+        (0..=156)
+            .filter_map(vk_format_to_gl)
+            .find(|format| format.internal_format == internal_format)
+    }
+}
+
+pub fn parse_ktx2(input: &[u8]) -> Result<Ktx2Data<'_>, KtxError> {
+    if !input.starts_with(&KTX2_IDENTIFIER) {
+        return if KTX2_IDENTIFIER.starts_with(input) {
+            Err(KtxError::Truncated {
+                section: "identifier",
+                offset: 0,
+                needed: KTX2_IDENTIFIER.len(),
+                available: input.len(),
+            })
+        } else {
+            Err(KtxError::InvalidIdentifier)
+        };
+    }
+
+    let reader = Reader {
+        input,
+        base: 0,
+        big_endian: false,
+    };
+    let field = |offset| reader.u32(offset, "header");
+    let vk_format = field(12)?;
+    let type_size = field(16)?;
+    let supercompression_scheme = field(44)?;
+    let format = vk_format_to_gl(vk_format).ok_or(KtxError::InvalidHeaderField {
+        field: "vk_format",
+        offset: 12,
+        value: vk_format,
+        reason: "no matching GL format",
+    })?;
+
+    let header = Header {
+        endianess: KTX_ENDIANNESS.to_le_bytes(),
+        gl_type: format.gl_type,
+        gl_type_size: type_size,
+        gl_format: format.format,
+        gl_internal_format: format.internal_format,
+        gl_base_internal_format: format.base_internal_format,
+        pixel_width: field(20)?,
+        pixel_height: field(24)?,
+        pixel_depth: field(28)?,
+        array_elements: field(32)?,
+        faces: field(36)?,
+        mip_levels: field(40)?,
+        key_pair_bytes: field(60)?,
+    };
+    validate(&header)?;
+
+    match supercompression_scheme {
+        SUPERCOMPRESSION_NONE | SUPERCOMPRESSION_ZSTD | SUPERCOMPRESSION_ZLIB => {}
+        scheme => return Err(KtxError::UnsupportedSupercompression(scheme)),
+    }
+
+    let dfd_offset = field(48)? as usize;
+    let dfd = reader.bytes(dfd_offset, field(52)? as usize, "data format descriptor")?;
+    let data_format = parse_data_format_descriptor(&Reader {
+        input: dfd,
+        base: dfd_offset,
+        big_endian: false,
+    })?;
+
+    let kvd_offset = field(56)? as usize;
+    let kvd = reader.bytes(kvd_offset, header.key_pair_bytes as usize, "key/value data")?;
+    let key_values = parse_key_values(&Reader {
+        input: kvd,
+        base: kvd_offset,
+        big_endian: false,
+    })?;
+
+    let (levels, pixels) = parse_levels(&header, &reader, supercompression_scheme)?;
+
+    Ok(Ktx2Data {
+        vk_format,
+        supercompression_scheme,
+        data_format,
+        texture: KtxData {
+            header,
+            key_values,
+            levels,
+            unpack_alignment: 1,
+            pixels,
+        },
+    })
+}
+
+fn validate(header: &Header) -> Result<(), KtxError> {
+    let invalid = |field: &'static str, offset: usize, value: u32, reason: &'static str| {
+        Err(KtxError::InvalidHeaderField {
+            field,
+            offset,
+            value,
+            reason,
+        })
+    };
+
+    if type_swap_size(header.gl_type) != Some(header.gl_type_size) {
+        return invalid(
+            "type_size",
+            16,
+            header.gl_type_size,
+            "does not match vk_format",
+        );
+    }
+    if header.pixel_width == 0 {
+        return invalid("pixel_width", 20, header.pixel_width, "must not be zero");
+    }
+    if header.pixel_height == 0 && header.pixel_depth != 0 {
+        return invalid(
+            "pixel_depth",
+            28,
+            header.pixel_depth,
+            "3D textures need a height",
+        );
+    }
+    if header.pixel_depth != 0 && header.array_elements != 0 {
+        return invalid(
+            "layer_count",
+            32,
+            header.array_elements,
+            "3D textures cannot be arrays",
+        );
+    }
+    match header.faces {
+        1 => {}
+        6 if header.pixel_width == header.pixel_height && header.pixel_depth == 0 => {}
+        6 => {
+            return invalid(
+                "face_count",
+                36,
+                header.faces,
+                "cube map faces must be square 2D images",
+            )
+        }
+        faces => return invalid("face_count", 36, faces, "must be 1 or 6"),
+    }
+    let largest = cmp::max(
+        header.pixel_width,
+        cmp::max(header.pixel_height, header.pixel_depth),
+    );
+    if header.mip_levels > 32 - largest.leading_zeros() {
+        return invalid(
+            "level_count",
+            40,
+            header.mip_levels,
+            "more levels than the dimensions allow",
+        );
+    }
+    Ok(())
+}
+
+fn parse_data_format_descriptor(reader: &Reader) -> Result<DataFormatDescriptor, KtxError> {
+    let invalid = |offset: usize, reason| KtxError::InvalidDataFormat {
+        offset: reader.base + offset,
+        reason,
+    };
+    let total_size = reader.u32(0, "data format descriptor")? as usize;
+    if total_size != reader.input.len() {
+        return Err(invalid(0, "total size does not match the index"));
+    }
+
+    let mut offset = 4;
+    while offset < total_size {
+        let descriptor = reader.u32(offset, "data format descriptor")?;
+        let block_size = (reader.u32(offset + 4, "data format descriptor")? >> 16) as usize;
+        if block_size < 8 {
+            return Err(invalid(offset, "descriptor block is too small"));
+        }
+        let block = reader.bytes(offset, block_size, "data format descriptor")?;
+
+        let vendor = descriptor & 0x1FFFF;
+        let descriptor_type = descriptor >> 17;
+        if vendor == KHR_DF_VENDORID_KHRONOS
+            && descriptor_type == KHR_DF_KHR_DESCRIPTORTYPE_BASICFORMAT
+        {
+            if block_size < KHR_DF_BASIC_HEADER_BYTES {
+                return Err(invalid(offset, "basic descriptor block is too small"));
+            }
+            let mut texel_block_dimensions = [0; 4];
+            texel_block_dimensions.copy_from_slice(&block[12..16]);
+            let mut bytes_planes = [0; 8];
+            bytes_planes.copy_from_slice(&block[16..24]);
+
+            let samples = block[KHR_DF_BASIC_HEADER_BYTES..]
+                .chunks_exact(KHR_DF_SAMPLE_BYTES)
+                .map(|sample| {
+                    let word = |index: usize| {
+                        u32::from_le_bytes([
+                            sample[index],
+                            sample[index + 1],
+                            sample[index + 2],
+                            sample[index + 3],
+                        ])
+                    };
+                    DataFormatSample {
+                        bit_offset: word(0) as u16,
+                        bit_length: sample[2],
+                        channel_type: sample[3],
+                        sample_position: [sample[4], sample[5], sample[6], sample[7]],
+                        sample_lower: word(8),
+                        sample_upper: word(12),
+                    }
+                })
+                .collect();
+
+            return Ok(DataFormatDescriptor {
+                color_model: block[8],
+                color_primaries: block[9],
+                transfer_function: block[10],
+                flags: block[11],
+                texel_block_dimensions,
+                bytes_planes,
+                samples,
+            });
+        }
+        offset += block_size;
+    }
+    Err(invalid(4, "no basic descriptor block"))
+}
+
+// Level data is tightly packed in KTX2, with every layer, face and slice of a level stored back to back
+fn parse_levels<'a>(
+    header: &Header,
+    reader: &Reader<'a>,
+    scheme: u32,
+) -> Result<(Vec<MipLevel>, Cow<'a, [u8]>), KtxError> {
+    let mut levels = Vec::new();
+    let mut decompressed = Vec::new();
+    for level in 0..header.level_count() {
+        let index_offset = KTX2_LEVEL_INDEX_OFFSET + level as usize * KTX2_LEVEL_INDEX_BYTES;
+        let byte_offset = reader.u64(index_offset, "level index")? as usize;
+        let byte_length = reader.u64(index_offset + 8, "level index")? as usize;
+        let uncompressed_length = reader.u64(index_offset + 16, "level index")? as usize;
+
+        let (width, height, depth) = header.level_dimensions(level);
+        let images_per_level = header.images_per_level();
+        let image_bytes = image_bytes_for(header, width, height, depth, 1)
+            .filter(|bytes| *bytes > 0)
+            .ok_or(KtxError::UnsupportedFormat(
+                "image sizes cannot be derived from the format",
+            ))?;
+        let expected = image_bytes.saturating_mul(images_per_level);
+        let found = if scheme == SUPERCOMPRESSION_NONE {
+            byte_length
+        } else {
+            uncompressed_length
+        };
+        if found != expected {
+            return Err(KtxError::ImageSizeMismatch {
+                level,
+                offset: index_offset,
+                expected,
+                found,
+            });
+        }
+
+        let data = reader.bytes(byte_offset, byte_length, "image data")?;
+        let start = if scheme == SUPERCOMPRESSION_NONE {
+            byte_offset
+        } else {
+            let start = decompressed.len();
+            decompressed.extend_from_slice(&supercompressed_level(scheme, level, data, expected)?);
+            start
+        };
+
+        levels.push(MipLevel {
+            width,
+            height,
+            depth,
+            images: (0..images_per_level)
+                .map(|image| start + image * image_bytes..start + (image + 1) * image_bytes)
+                .collect(),
+        });
+    }
+
+    let pixels = if scheme == SUPERCOMPRESSION_NONE {
+        Cow::Borrowed(reader.input)
+    } else {
+        Cow::Owned(decompressed)
+    };
+    Ok((levels, pixels))
+}
+
+fn supercompressed_level(
+    scheme: u32,
+    level: u32,
+    data: &[u8],
+    expected: usize,
+) -> Result<Vec<u8>, KtxError> {
+    let failed = |reason: String| KtxError::Decompression { level, reason };
+    let output = match scheme {
+        SUPERCOMPRESSION_ZSTD => {
+            let decoder = StreamingDecoder::new(data).map_err(|error| failed(error.to_string()))?;
+            let mut output = Vec::new();
+            decoder
+                .take((expected as u64).saturating_add(1))
+                .read_to_end(&mut output)
+                .map_err(|error| failed(error.to_string()))?;
+            output
+        }
+        SUPERCOMPRESSION_ZLIB => decompress_to_vec_zlib_with_limit(data, expected)
+            .map_err(|error| failed(format!("{:?}", error.status)))?,
+        scheme => return Err(KtxError::UnsupportedSupercompression(scheme)),
+    };
+    if output.len() != expected {
+        return Err(failed(format!(
+            "produced {} bytes instead of {}",
+            output.len(),
+            expected
+        )));
+    }
+    Ok(output)
+}
+
+fn vk_format_to_gl(vk_format: u32) -> Option<GlFormat> {
+    let format = GlFormat::uncompressed;
+    let compressed = GlFormat::compressed;
+
+    Some(match vk_format {
+        9 => format(gl::R8, gl::RED, gl::UNSIGNED_BYTE),
+        10 => format(gl::R8_SNORM, gl::RED, gl::BYTE),
+        13 => format(gl::R8UI, gl::RED_INTEGER, gl::UNSIGNED_BYTE),
+        14 => format(gl::R8I, gl::RED_INTEGER, gl::BYTE),
+        16 => format(gl::RG8, gl::RG, gl::UNSIGNED_BYTE),
+        17 => format(gl::RG8_SNORM, gl::RG, gl::BYTE),
+        20 => format(gl::RG8UI, gl::RG_INTEGER, gl::UNSIGNED_BYTE),
+        21 => format(gl::RG8I, gl::RG_INTEGER, gl::BYTE),
+        23 => format(gl::RGB8, gl::RGB, gl::UNSIGNED_BYTE),
+        24 => format(gl::RGB8_SNORM, gl::RGB, gl::BYTE),
+        27 => format(gl::RGB8UI, gl::RGB_INTEGER, gl::UNSIGNED_BYTE),
+        28 => format(gl::RGB8I, gl::RGB_INTEGER, gl::BYTE),
+        29 => format(gl::SRGB8, gl::RGB, gl::UNSIGNED_BYTE),
+        30 => format(gl::RGB8, gl::BGR, gl::UNSIGNED_BYTE),
+        36 => format(gl::SRGB8, gl::BGR, gl::UNSIGNED_BYTE),
+        37 => format(gl::RGBA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        38 => format(gl::RGBA8_SNORM, gl::RGBA, gl::BYTE),
+        41 => format(gl::RGBA8UI, gl::RGBA_INTEGER, gl::UNSIGNED_BYTE),
+        42 => format(gl::RGBA8I, gl::RGBA_INTEGER, gl::BYTE),
+        43 => format(gl::SRGB8_ALPHA8, gl::RGBA, gl::UNSIGNED_BYTE),
+        44 => format(gl::RGBA8, gl::BGRA, gl::UNSIGNED_BYTE),
+        50 => format(gl::SRGB8_ALPHA8, gl::BGRA, gl::UNSIGNED_BYTE),
+        64 => format(gl::RGB10_A2, gl::RGBA, gl::UNSIGNED_INT_2_10_10_10_REV),
+        68 => format(
+            gl::RGB10_A2UI,
+            gl::RGBA_INTEGER,
+            gl::UNSIGNED_INT_2_10_10_10_REV,
+        ),
+        70 => format(gl::R16, gl::RED, gl::UNSIGNED_SHORT),
+        71 => format(gl::R16_SNORM, gl::RED, gl::SHORT),
+        74 => format(gl::R16UI, gl::RED_INTEGER, gl::UNSIGNED_SHORT),
+        75 => format(gl::R16I, gl::RED_INTEGER, gl::SHORT),
+        76 => format(gl::R16F, gl::RED, gl::HALF_FLOAT),
+        77 => format(gl::RG16, gl::RG, gl::UNSIGNED_SHORT),
+        78 => format(gl::RG16_SNORM, gl::RG, gl::SHORT),
+        81 => format(gl::RG16UI, gl::RG_INTEGER, gl::UNSIGNED_SHORT),
+        82 => format(gl::RG16I, gl::RG_INTEGER, gl::SHORT),
+        83 => format(gl::RG16F, gl::RG, gl::HALF_FLOAT),
+        84 => format(gl::RGB16, gl::RGB, gl::UNSIGNED_SHORT),
+        85 => format(gl::RGB16_SNORM, gl::RGB, gl::SHORT),
+        88 => format(gl::RGB16UI, gl::RGB_INTEGER, gl::UNSIGNED_SHORT),
+        89 => format(gl::RGB16I, gl::RGB_INTEGER, gl::SHORT),
+        90 => format(gl::RGB16F, gl::RGB, gl::HALF_FLOAT),
+        91 => format(gl::RGBA16, gl::RGBA, gl::UNSIGNED_SHORT),
+        92 => format(gl::RGBA16_SNORM, gl::RGBA, gl::SHORT),
+        95 => format(gl::RGBA16UI, gl::RGBA_INTEGER, gl::UNSIGNED_SHORT),
+        96 => format(gl::RGBA16I, gl::RGBA_INTEGER, gl::SHORT),
+        97 => format(gl::RGBA16F, gl::RGBA, gl::HALF_FLOAT),
+        98 => format(gl::R32UI, gl::RED_INTEGER, gl::UNSIGNED_INT),
+        99 => format(gl::R32I, gl::RED_INTEGER, gl::INT),
+        100 => format(gl::R32F, gl::RED, gl::FLOAT),
+        101 => format(gl::RG32UI, gl::RG_INTEGER, gl::UNSIGNED_INT),
+        102 => format(gl::RG32I, gl::RG_INTEGER, gl::INT),
+        103 => format(gl::RG32F, gl::RG, gl::FLOAT),
+        104 => format(gl::RGB32UI, gl::RGB_INTEGER, gl::UNSIGNED_INT),
+        105 => format(gl::RGB32I, gl::RGB_INTEGER, gl::INT),
+        106 => format(gl::RGB32F, gl::RGB, gl::FLOAT),
+        107 => format(gl::RGBA32UI, gl::RGBA_INTEGER, gl::UNSIGNED_INT),
+        108 => format(gl::RGBA32I, gl::RGBA_INTEGER, gl::INT),
+        109 => format(gl::RGBA32F, gl::RGBA, gl::FLOAT),
+        122 => format(
+            gl::R11F_G11F_B10F,
+            gl::RGB,
+            gl::UNSIGNED_INT_10F_11F_11F_REV,
+        ),
+        123 => format(gl::RGB9_E5, gl::RGB, gl::UNSIGNED_INT_5_9_9_9_REV),
+        124 => format(
+            gl::DEPTH_COMPONENT16,
+            gl::DEPTH_COMPONENT,
+            gl::UNSIGNED_SHORT,
+        ),
+        126 => format(gl::DEPTH_COMPONENT32F, gl::DEPTH_COMPONENT, gl::FLOAT),
+        131 => compressed(COMPRESSED_RGB_S3TC_DXT1, gl::RGB),
+        132 => compressed(COMPRESSED_SRGB_S3TC_DXT1, gl::RGB),
+        133 => compressed(COMPRESSED_RGBA_S3TC_DXT1, gl::RGBA),
+        134 => compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT1, gl::RGBA),
+        135 => compressed(COMPRESSED_RGBA_S3TC_DXT3, gl::RGBA),
+        136 => compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT3, gl::RGBA),
+        137 => compressed(COMPRESSED_RGBA_S3TC_DXT5, gl::RGBA),
+        138 => compressed(COMPRESSED_SRGB_ALPHA_S3TC_DXT5, gl::RGBA),
+        139 => compressed(gl::COMPRESSED_RED_RGTC1, gl::RED),
+        140 => compressed(gl::COMPRESSED_SIGNED_RED_RGTC1, gl::RED),
+        141 => compressed(gl::COMPRESSED_RG_RGTC2, gl::RG),
+        142 => compressed(gl::COMPRESSED_SIGNED_RG_RGTC2, gl::RG),
+        143 => compressed(gl::COMPRESSED_RGB_BPTC_UNSIGNED_FLOAT, gl::RGB),
+        144 => compressed(gl::COMPRESSED_RGB_BPTC_SIGNED_FLOAT, gl::RGB),
+        145 => compressed(gl::COMPRESSED_RGBA_BPTC_UNORM, gl::RGBA),
+        146 => compressed(gl::COMPRESSED_SRGB_ALPHA_BPTC_UNORM, gl::RGBA),
+        147 => compressed(gl::COMPRESSED_RGB8_ETC2, gl::RGB),
+        148 => compressed(gl::COMPRESSED_SRGB8_ETC2, gl::RGB),
+        149 => compressed(gl::COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2, gl::RGBA),
+        150 => compressed(gl::COMPRESSED_SRGB8_PUNCHTHROUGH_ALPHA1_ETC2, gl::RGBA),
+        151 => compressed(gl::COMPRESSED_RGBA8_ETC2_EAC, gl::RGBA),
+        152 => compressed(gl::COMPRESSED_SRGB8_ALPHA8_ETC2_EAC, gl::RGBA),
+        153 => compressed(gl::COMPRESSED_R11_EAC, gl::RED),
+        154 => compressed(gl::COMPRESSED_SIGNED_R11_EAC, gl::RED),
+        155 => compressed(gl::COMPRESSED_RG11_EAC, gl::RG),
+        156 => compressed(gl::COMPRESSED_SIGNED_RG11_EAC, gl::RG),
+        _ => return None,
+    })
+}
@@ -0,0 +1,475 @@
+use super::{
+    f32_to_half, format_components, half_to_f32, pad, repack_rows, KtxData, KtxError, MipLevel,
+    KTX_ALIGNMENT, LUMINANCE, LUMINANCE_ALPHA,
+};
+use gl::types::GLenum;
+use std::{borrow::Cow, f32::consts::PI};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MipmapFilter {
+    Box,
+    Kaiser,
+    Lanczos,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MipmapOptions {
+    pub filter: MipmapFilter,
+    // Whether color channels are sRGB encoded, or None to decide from gl_internal_format
+    pub srgb: Option<bool>,
+    // The alpha test reference value whose coverage every level should keep
+    pub alpha_coverage: Option<f32>,
+}
+
+impl Default for MipmapOptions {
+    fn default() -> Self {
+        MipmapOptions {
+            filter: MipmapFilter::Box,
+            srgb: None,
+            alpha_coverage: None,
+        }
+    }
+}
+
+impl MipmapFilter {
+    // Half width of the kernel in destination texels
+    fn support(self) -> f32 {
+        match self {
+            MipmapFilter::Box => 0.5,
+            MipmapFilter::Kaiser | MipmapFilter::Lanczos => 3.0,
+        }
+    }
+
+    fn weight(self, x: f32) -> f32 {
+        match self {
+            MipmapFilter::Box => {
+                if (-0.5..0.5).contains(&x) {
+                    1.0
+                } else {
+                    0.0
+                }
+            }
+            MipmapFilter::Kaiser => {
+                let t = x / 3.0;
+                if t.abs() >= 1.0 {
+                    return 0.0;
+                }
+                const ALPHA: f32 = 4.0;
+                sinc(x) * bessel_i0(ALPHA * (1.0 - t * t).sqrt()) / bessel_i0(ALPHA)
+            }
+            MipmapFilter::Lanczos => {
+                if x.abs() >= 3.0 {
+                    0.0
+                } else {
+                    sinc(x) * sinc(x / 3.0)
+                }
+            }
+        }
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x.abs() < 1e-6 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn bessel_i0(x: f32) -> f32 {
+    let mut sum = 1.0;
+    let mut term = 1.0;
+    let quarter = x * x / 4.0;
+    for k in 1..32 {
+        term *= quarter / (k * k) as f32;
+        sum += term;
+        if term < sum * 1e-8 {
+            break;
+        }
+    }
+    sum
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Channel {
+    UnsignedByte,
+    UnsignedShort,
+    HalfFloat,
+    Float,
+}
+
+impl Channel {
+    fn size(self) -> usize {
+        match self {
+            Channel::UnsignedByte => 1,
+            Channel::UnsignedShort | Channel::HalfFloat => 2,
+            Channel::Float => 4,
+        }
+    }
+
+    fn is_normalized(self) -> bool {
+        matches!(self, Channel::UnsignedByte | Channel::UnsignedShort)
+    }
+}
+
+// How the texels of an uncompressed texture convert to and from linear floats, shared with
+// the environment map conversions
+pub(super) struct PixelLayout {
+    channel: Channel,
+    pub(super) components: usize,
+    alpha: Option<usize>,
+}
+
+impl PixelLayout {
+    pub(super) fn new(ktx_texture: &KtxData) -> Result<Self, KtxError> {
+        let ktx = &ktx_texture.header;
+        if ktx.is_compressed() {
+            return Err(KtxError::UnsupportedFormat(
+                "compressed data cannot be filtered",
+            ));
+        }
+        let channel = match ktx.gl_type {
+            gl::UNSIGNED_BYTE => Channel::UnsignedByte,
+            gl::UNSIGNED_SHORT => Channel::UnsignedShort,
+            gl::HALF_FLOAT => Channel::HalfFloat,
+            gl::FLOAT => Channel::Float,
+            _ => {
+                return Err(KtxError::UnsupportedFormat(
+                    "filtering needs 8 or 16 bit normalized or floating point channels",
+                ))
+            }
+        };
+        let alpha = match ktx.gl_format {
+            gl::RGBA | gl::BGRA => Some(3),
+            LUMINANCE_ALPHA => Some(1),
+            gl::ALPHA => Some(0),
+            gl::RED | gl::GREEN | gl::BLUE | gl::RG | gl::RGB | gl::BGR | LUMINANCE => None,
+            _ => {
+                return Err(KtxError::UnsupportedFormat(
+                    "integer, depth and stencil textures cannot be filtered",
+                ))
+            }
+        };
+        Ok(PixelLayout {
+            channel,
+            components: format_components(ktx.gl_format).unwrap_or(0) as usize,
+            alpha,
+        })
+    }
+
+    fn row_bytes(&self, width: usize) -> usize {
+        width * self.components * self.channel.size()
+    }
+
+    // Converts one image to linear floats, with rows read at the given alignment
+    pub(super) fn decode(
+        &self,
+        image: &[u8],
+        dimensions: [usize; 3],
+        alignment: usize,
+        srgb: bool,
+    ) -> Vec<f32> {
+        let [width, height, depth] = dimensions;
+        let row_bytes = self.row_bytes(width);
+        let stride = pad(row_bytes, alignment);
+        let size = self.channel.size();
+        let mut texels = Vec::with_capacity(width * height * depth * self.components);
+        for row in 0..height * depth {
+            let bytes = &image[row * stride..row * stride + row_bytes];
+            for (index, value) in bytes.chunks_exact(size).enumerate() {
+                let value = match self.channel {
+                    Channel::UnsignedByte => value[0] as f32 / 255.0,
+                    Channel::UnsignedShort => {
+                        u16::from_ne_bytes([value[0], value[1]]) as f32 / 65535.0
+                    }
+                    Channel::HalfFloat => half_to_f32(u16::from_ne_bytes([value[0], value[1]])),
+                    Channel::Float => f32::from_ne_bytes([value[0], value[1], value[2], value[3]]),
+                };
+                let component = index % self.components;
+                texels.push(if self.is_srgb_channel(component, srgb) {
+                    srgb_to_linear(value)
+                } else {
+                    value
+                });
+            }
+        }
+        texels
+    }
+
+    // Converts linear floats back to the texture's format, with rows padded for KTX
+    pub(super) fn encode(&self, texels: &[f32], dimensions: [usize; 3], srgb: bool) -> Vec<u8> {
+        let [width, height, depth] = dimensions;
+        let row_bytes = self.row_bytes(width);
+        let stride = pad(row_bytes, KTX_ALIGNMENT);
+        let mut image = vec![0; stride * height * depth];
+        let row_texels = width * self.components;
+        let size = self.channel.size();
+        for row in 0..height * depth {
+            let bytes = &mut image[row * stride..row * stride + row_bytes];
+            let values = &texels[row * row_texels..(row + 1) * row_texels];
+            for (index, (output, value)) in bytes.chunks_exact_mut(size).zip(values).enumerate() {
+                let mut value = *value;
+                if self.is_srgb_channel(index % self.components, srgb) {
+                    value = linear_to_srgb(value);
+                }
+                if self.channel.is_normalized() {
+                    value = value.clamp(0.0, 1.0);
+                }
+                match self.channel {
+                    Channel::UnsignedByte => output[0] = (value * 255.0).round() as u8,
+                    Channel::UnsignedShort => {
+                        output.copy_from_slice(&((value * 65535.0).round() as u16).to_ne_bytes())
+                    }
+                    Channel::HalfFloat => output.copy_from_slice(&f32_to_half(value).to_ne_bytes()),
+                    Channel::Float => output.copy_from_slice(&value.to_ne_bytes()),
+                }
+            }
+        }
+        image
+    }
+
+    // sRGB only applies to the color channels of normalized data
+    fn is_srgb_channel(&self, component: usize, srgb: bool) -> bool {
+        srgb && self.channel.is_normalized() && self.alpha != Some(component)
+    }
+}
+
+fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> f32 {
+    let value = value.max(0.0);
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+pub(super) fn is_srgb_format(internal_format: GLenum) -> bool {
+    matches!(
+        internal_format,
+        gl::SRGB | gl::SRGB8 | gl::SRGB_ALPHA | gl::SRGB8_ALPHA8
+    )
+}
+
+// The source texels and weights contributing to each destination texel along one axis,
+// with the kernel stretched by the reduction so it covers every source texel
+fn filter_taps(source: usize, destination: usize, filter: MipmapFilter) -> Vec<Vec<(usize, f32)>> {
+    let scale = source as f32 / destination as f32;
+    let support = filter.support() * scale;
+    (0..destination)
+        .map(|texel| {
+            let center = (texel as f32 + 0.5) * scale;
+            let start = (center - support).floor() as isize;
+            let end = (center + support).ceil() as isize;
+            let mut taps = Vec::new();
+            for position in start..end {
+                let weight = filter.weight((position as f32 + 0.5 - center) / scale);
+                if weight != 0.0 {
+                    // Texels past the edge repeat the edge texel
+                    let clamped = position.clamp(0, source as isize - 1) as usize;
+                    taps.push((clamped, weight));
+                }
+            }
+            let total: f32 = taps.iter().map(|(_, weight)| weight).sum();
+            for tap in taps.iter_mut() {
+                tap.1 /= total;
+            }
+            taps
+        })
+        .collect()
+}
+
+fn resample(
+    texels: &[f32],
+    dimensions: [usize; 3],
+    components: usize,
+    axis: usize,
+    size: usize,
+    filter: MipmapFilter,
+) -> Vec<f32> {
+    let taps = filter_taps(dimensions[axis], size, filter);
+    let stride = components * dimensions[..axis].iter().product::<usize>();
+    let mut resized = dimensions;
+    resized[axis] = size;
+
+    let mut output = vec![0.0; resized.iter().product::<usize>() * components];
+    let mut index = 0;
+    for z in 0..resized[2] {
+        for y in 0..resized[1] {
+            for x in 0..resized[0] {
+                let mut coordinates = [x, y, z];
+                let texel = coordinates[axis];
+                coordinates[axis] = 0;
+                let base = ((coordinates[2] * dimensions[1] + coordinates[1]) * dimensions[0]
+                    + coordinates[0])
+                    * components;
+                for (position, weight) in taps[texel].iter() {
+                    let source = base + position * stride;
+                    for component in 0..components {
+                        output[index + component] += weight * texels[source + component];
+                    }
+                }
+                index += components;
+            }
+        }
+    }
+    output
+}
+
+fn coverage(texels: &[f32], components: usize, alpha: usize, reference: f32, scale: f32) -> f32 {
+    let texel_count = texels.len() / components;
+    let covered = texels
+        .chunks_exact(components)
+        .filter(|texel| texel[alpha] * scale > reference)
+        .count();
+    covered as f32 / texel_count as f32
+}
+
+// Scales alpha so that as many texels pass the alpha test as did in the base level,
+// otherwise alpha tested foliage and fences thin out as they get further away
+fn preserve_coverage(
+    texels: &mut [f32],
+    components: usize,
+    alpha: usize,
+    reference: f32,
+    target: f32,
+) {
+    let (mut low, mut high) = (0.0f32, 4.0f32);
+    let mut best = (f32::MAX, 1.0);
+    for _ in 0..20 {
+        let scale = (low + high) / 2.0;
+        let current = coverage(texels, components, alpha, reference, scale);
+        let error = (current - target).abs();
+        if error < best.0 {
+            best = (error, scale);
+        }
+        if current < target {
+            low = scale;
+        } else if current > target {
+            high = scale;
+        } else {
+            break;
+        }
+    }
+    for texel in texels.chunks_exact_mut(components) {
+        texel[alpha] = (texel[alpha] * best.1).min(1.0);
+    }
+}
+
+// Whether generate_mipmaps can filter textures of this format
+pub fn can_filter(ktx_texture: &KtxData) -> bool {
+    PixelLayout::new(ktx_texture).is_ok()
+}
+
+// Builds the full mip chain from the base level of every layer and face. The result is
+// laid out like a KTX file, so it can be passed to prepare_texture or write_ktx.
+pub fn generate_mipmaps(
+    ktx_texture: &KtxData,
+    options: &MipmapOptions,
+) -> Result<KtxData<'static>, KtxError> {
+    let layout = PixelLayout::new(ktx_texture)?;
+    let ktx = &ktx_texture.header;
+    let base = ktx_texture
+        .levels
+        .first()
+        .ok_or(KtxError::InvalidArgument("texture has no mip levels"))?;
+    let srgb = options
+        .srgb
+        .unwrap_or_else(|| is_srgb_format(ktx.gl_internal_format));
+    let alignment = ktx_texture.unpack_alignment as usize;
+    let level_count = ktx.full_level_count();
+
+    let mut level_images = vec![Vec::new(); level_count as usize];
+    for range in base.images.iter() {
+        let image = &ktx_texture.pixels[range.clone()];
+        let mut dimensions = [
+            base.width as usize,
+            base.height as usize,
+            base.depth as usize,
+        ];
+
+        // The base level is kept exactly as it was given
+        level_images[0].push(repack_rows(
+            image,
+            layout.row_bytes(dimensions[0]),
+            dimensions[1] * dimensions[2],
+            alignment,
+            KTX_ALIGNMENT,
+        ));
+
+        let mut texels = layout.decode(image, dimensions, alignment, srgb);
+        let coverage_target = match (options.alpha_coverage, layout.alpha) {
+            (Some(reference), Some(alpha)) => Some((
+                alpha,
+                reference,
+                coverage(&texels, layout.components, alpha, reference, 1.0),
+            )),
+            _ => None,
+        };
+
+        for (level, images) in level_images.iter_mut().enumerate().skip(1) {
+            let (width, height, depth) = ktx.level_dimensions(level as u32);
+            for (axis, size) in [width, height, depth].iter().enumerate() {
+                let size = *size as usize;
+                if dimensions[axis] != size {
+                    texels = resample(
+                        &texels,
+                        dimensions,
+                        layout.components,
+                        axis,
+                        size,
+                        options.filter,
+                    );
+                    dimensions[axis] = size;
+                }
+            }
+
+            let mut filtered = Cow::Borrowed(&texels[..]);
+            if let Some((alpha, reference, target)) = coverage_target {
+                // Scaling is applied to a copy so later levels are filtered from unscaled alpha
+                preserve_coverage(
+                    filtered.to_mut(),
+                    layout.components,
+                    alpha,
+                    reference,
+                    target,
+                );
+            }
+            images.push(layout.encode(&filtered, dimensions, srgb));
+        }
+    }
+
+    let mut header = ktx.clone();
+    header.mip_levels = level_count;
+    let mut pixels = Vec::new();
+    let mut levels = Vec::new();
+    for (level, images) in level_images.into_iter().enumerate() {
+        let (width, height, depth) = ktx.level_dimensions(level as u32);
+        let mut ranges = Vec::new();
+        for image in images {
+            ranges.push(pixels.len()..pixels.len() + image.len());
+            pixels.extend_from_slice(&image);
+        }
+        levels.push(MipLevel {
+            width,
+            height,
+            depth,
+            images: ranges,
+        });
+    }
+
+    Ok(KtxData {
+        header,
+        key_values: ktx_texture.key_values.clone(),
+        levels,
+        unpack_alignment: KTX_ALIGNMENT as i32,
+        pixels: Cow::Owned(pixels),
+    })
+}
@@ -0,0 +1,269 @@
+use super::{
+    decode::{bc4_palette, decode_blocks, Texels},
+    mipmap::{generate_mipmaps, MipmapOptions},
+    pad, KtxData, KtxError, MipLevel, KTX_ENDIANNESS,
+};
+use std::{borrow::Cow, cmp};
+
+// How many times endpoints are solved for again after the indices change
+const REFINE_PASSES: usize = 4;
+
+// A compressed texture along with the peak signal to noise ratio, in dB, of its decoded
+// texels against the uncompressed ones. Lossless levels are infinite.
+pub struct RgtcTexture {
+    pub texture: KtxData<'static>,
+    pub psnr: f64,
+    pub level_psnr: Vec<f64>,
+}
+
+// Compresses an 8 bit RED or RG texture to COMPRESSED_RED_RGTC1 or COMPRESSED_RG_RGTC2.
+// Missing mip levels are generated from the base level first, so the result always has
+// the full chain.
+pub fn compress_rgtc(
+    ktx_texture: &KtxData,
+    options: &MipmapOptions,
+) -> Result<RgtcTexture, KtxError> {
+    let ktx = &ktx_texture.header;
+    let (channels, gl_internal_format) = match (ktx.gl_type, ktx.gl_format) {
+        (gl::UNSIGNED_BYTE, gl::RED) => (1, gl::COMPRESSED_RED_RGTC1),
+        (gl::UNSIGNED_BYTE, gl::RG) => (2, gl::COMPRESSED_RG_RGTC2),
+        _ => {
+            return Err(KtxError::UnsupportedFormat(
+                "RGTC compression needs unsigned byte RED or RG texels",
+            ))
+        }
+    };
+
+    // Checked up front since the mip generator expects whole images
+    let alignment = ktx_texture.unpack_alignment as usize;
+    for mip in ktx_texture.levels.iter() {
+        let stride = pad(mip.width as usize * channels, alignment);
+        for range in mip.images.iter() {
+            if range.len() < stride * (mip.height * mip.depth) as usize {
+                return Err(KtxError::InvalidDataFormat {
+                    offset: range.start,
+                    reason: "image is smaller than its dimensions",
+                });
+            }
+        }
+    }
+
+    let generated;
+    let source = if ktx_texture.levels.len() < ktx.full_level_count() as usize {
+        generated = generate_mipmaps(ktx_texture, options)?;
+        &generated
+    } else {
+        ktx_texture
+    };
+    let alignment = source.unpack_alignment as usize;
+
+    let mut pixels = Vec::new();
+    let mut levels = Vec::with_capacity(source.levels.len());
+    let mut level_psnr = Vec::with_capacity(source.levels.len());
+    let (mut total_error, mut total_samples) = (0, 0);
+    for mip in source.levels.iter() {
+        let (width, height, depth) = (mip.width as usize, mip.height as usize, mip.depth as usize);
+        let stride = pad(width * channels, alignment);
+        let (mut level_error, mut level_samples) = (0, 0);
+        let mut images = Vec::with_capacity(mip.images.len());
+        for range in mip.images.iter() {
+            let image = &source.pixels[range.clone()];
+            let start = pixels.len();
+            for slice in 0..depth {
+                let slice = &image[slice * stride * height..];
+                for block_y in (0..height).step_by(4) {
+                    for block_x in (0..width).step_by(4) {
+                        for channel in 0..channels {
+                            let mut texels = [0; 16];
+                            for (index, texel) in texels.iter_mut().enumerate() {
+                                // Partial blocks repeat the edge texels
+                                let x = cmp::min(block_x + index % 4, width - 1);
+                                let y = cmp::min(block_y + index / 4, height - 1);
+                                *texel = slice[y * stride + x * channels + channel];
+                            }
+                            pixels.extend_from_slice(&encode_bc4_block(&texels));
+                        }
+                    }
+                }
+            }
+
+            let decoded = decode_blocks(
+                gl_internal_format,
+                mip.width,
+                mip.height,
+                mip.depth,
+                &pixels[start..],
+            )?;
+            let texels = match decoded.texels {
+                Texels::Rgba8(texels) => texels,
+                Texels::Rgba32F(_) => unreachable!(),
+            };
+            for (index, texel) in texels.chunks_exact(4).enumerate() {
+                let row = index / width;
+                let source_texel = &image[row * stride + index % width * channels..];
+                for channel in 0..channels {
+                    let difference = texel[channel] as i64 - source_texel[channel] as i64;
+                    level_error += difference * difference;
+                }
+            }
+            level_samples += texels.len() / 4 * channels;
+            images.push(start..pixels.len());
+        }
+        level_psnr.push(psnr(level_error, level_samples));
+        total_error += level_error;
+        total_samples += level_samples;
+        levels.push(MipLevel {
+            width: mip.width,
+            height: mip.height,
+            depth: mip.depth,
+            images,
+        });
+    }
+
+    let mut header = source.header.clone();
+    header.endianess = KTX_ENDIANNESS.to_ne_bytes();
+    header.gl_type = 0;
+    header.gl_type_size = 1;
+    header.gl_format = 0;
+    header.gl_internal_format = gl_internal_format;
+    header.gl_base_internal_format = if channels == 1 { gl::RED } else { gl::RG };
+    header.mip_levels = levels.len() as u32;
+
+    Ok(RgtcTexture {
+        texture: KtxData {
+            header,
+            key_values: ktx_texture.key_values.clone(),
+            levels,
+            unpack_alignment: 4,
+            pixels: Cow::Owned(pixels),
+        },
+        psnr: psnr(total_error, total_samples),
+        level_psnr,
+    })
+}
+
+fn psnr(squared_error: i64, samples: usize) -> f64 {
+    if squared_error == 0 {
+        return f64::INFINITY;
+    }
+    let mean = squared_error as f64 / samples as f64;
+    10.0 * (255.0 * 255.0 / mean).log10()
+}
+
+// Encodes 16 texels, in row order, as one BC4 block. Both the eight value mode and the
+// six value mode with exact 0 and 255 are tried with the block's extremes at a few
+// palette positions near the ends, then refined, keeping whichever decodes closest.
+pub fn encode_bc4_block(texels: &[u8; 16]) -> [u8; 8] {
+    let (low, high) = extremes(texels.iter());
+    let mut eight = fit_bc4(texels, high, low);
+    for high_position in 0..=2 {
+        for low_position in 5..=7 {
+            let (red0, red1) = extrapolate((high, high_position), (low, low_position), 7);
+            if red0 > red1 {
+                eight = cmp::min(eight, fit_bc4(texels, red0, red1));
+            }
+        }
+    }
+
+    // 0 and 255 come free in six value mode, so the endpoints only cover the rest
+    let inner = texels.iter().filter(|texel| **texel != 0 && **texel != 255);
+    let (low, high) = extremes(inner);
+    let mut six = fit_bc4(texels, 0, 0);
+    if low <= high {
+        for low_position in 0..=1 {
+            for high_position in 4..=5 {
+                let (red0, red1) = extrapolate((low, low_position), (high, high_position), 5);
+                if red0 <= red1 {
+                    six = cmp::min(six, fit_bc4(texels, red0, red1));
+                }
+            }
+        }
+    }
+
+    let (_, red0, red1, indices) = cmp::min(refine_bc4(texels, eight), refine_bc4(texels, six));
+    let mut bits = red0 as u64 | (red1 as u64) << 8;
+    for (texel, index) in indices.iter().enumerate() {
+        bits |= (*index as u64) << (16 + 3 * texel);
+    }
+    bits.to_le_bytes()
+}
+
+// The endpoints of a palette of steps + 1 evenly spaced values that puts two values at
+// the given positions
+fn extrapolate(first: (u8, usize), second: (u8, usize), steps: usize) -> (u8, u8) {
+    let slope = (second.0 as f32 - first.0 as f32) / (second.1 - first.1) as f32;
+    let start = first.0 as f32 - slope * first.1 as f32;
+    let end = start + slope * steps as f32;
+    (
+        start.round().clamp(0.0, 255.0) as u8,
+        end.round().clamp(0.0, 255.0) as u8,
+    )
+}
+
+fn extremes<'a, I: Iterator<Item = &'a u8>>(texels: I) -> (u8, u8) {
+    texels.fold((255, 0), |(low, high), texel| {
+        (cmp::min(low, *texel), cmp::max(high, *texel))
+    })
+}
+
+// The squared error, endpoints and nearest palette indices of one endpoint pair
+type Fit = (u32, u8, u8, [u8; 16]);
+
+fn fit_bc4(texels: &[u8; 16], red0: u8, red1: u8) -> Fit {
+    let palette = bc4_palette(red0, red1);
+    let mut indices = [0; 16];
+    let mut error = 0;
+    for (texel, index) in texels.iter().zip(indices.iter_mut()) {
+        let (nearest, difference) = palette
+            .iter()
+            .map(|value| (*value as i32 - *texel as i32).unsigned_abs())
+            .enumerate()
+            .min_by_key(|(_, difference)| *difference)
+            .unwrap();
+        *index = nearest as u8;
+        error += difference * difference;
+    }
+    (error, red0, red1, indices)
+}
+
+// Solves for the endpoints that best fit the texels at their current indices, which may
+// lie past the block's extremes, and repeats while rounding them lowers the error
+fn refine_bc4(texels: &[u8; 16], mut best: Fit) -> Fit {
+    for _ in 0..REFINE_PASSES {
+        let (_, red0, red1, indices) = best;
+        let steps = if red0 > red1 { 7.0 } else { 5.0 };
+        let (mut aa, mut ab, mut bb, mut av, mut bv) = (0.0, 0.0, 0.0, 0.0, 0.0);
+        for (texel, index) in texels.iter().zip(indices.iter()) {
+            let weight = match *index {
+                0 => 0.0,
+                1 => 1.0,
+                6 | 7 if red0 <= red1 => continue,
+                index => (index - 1) as f32 / steps,
+            };
+            let (a, b, value) = (1.0 - weight, weight, *texel as f32);
+            aa += a * a;
+            ab += a * b;
+            bb += b * b;
+            av += a * value;
+            bv += b * value;
+        }
+        let determinant = aa * bb - ab * ab;
+        if determinant.abs() < 1e-6 {
+            break;
+        }
+        let first = (av * bb - bv * ab) / determinant;
+        let second = (bv * aa - av * ab) / determinant;
+
+        let previous = best.0;
+        for red0 in [first.floor(), first.ceil()] {
+            for red1 in [second.floor(), second.ceil()] {
+                let (red0, red1) = (red0.clamp(0.0, 255.0) as u8, red1.clamp(0.0, 255.0) as u8);
+                best = cmp::min(best, fit_bc4(texels, red0, red1));
+            }
+        }
+        if best.0 == previous {
+            break;
+        }
+    }
+    best
+}
@@ -0,0 +1,368 @@
+use super::{
+    block_format, create_texture, decompress_unsupported, determine_target,
+    generate_requested_mipmaps, image_uploads, load_ktx_file, upload_region, KtxData, KtxError,
+    Region,
+};
+use crate::assets::asset_path;
+use gl::types::{GLenum, GLsync, GLvoid};
+use std::{
+    cell::RefCell,
+    collections::{HashMap, VecDeque},
+    io,
+    ops::Range,
+    path::{Path, PathBuf},
+    ptr,
+    rc::Rc,
+    sync::mpsc::{self, Receiver, Sender},
+    thread::{self, JoinHandle},
+};
+
+// Frames whose uploads may still be reading from the buffer, each given its own part of it
+const FRAMES_IN_FLIGHT: usize = 3;
+
+// Buffer offsets passed to TexSubImage must be a multiple of the pixel type's size
+const OFFSET_ALIGNMENT: usize = 16;
+
+type Loaded = (usize, Result<KtxData<'static>, KtxError>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamStatus {
+    // Waiting for the worker thread to read the file
+    Loading,
+    Uploading { uploaded: usize, total: usize },
+    Ready(u32),
+    Failed(KtxError),
+}
+
+// Polled by the app to find out when a streamed texture can be used
+#[derive(Debug, Clone)]
+pub struct StreamHandle {
+    status: Rc<RefCell<StreamStatus>>,
+}
+
+impl StreamHandle {
+    pub fn status(&self) -> StreamStatus {
+        self.status.borrow().clone()
+    }
+
+    // The texture, once every level has been uploaded
+    pub fn texture(&self) -> Option<u32> {
+        match *self.status.borrow() {
+            StreamStatus::Ready(texture) => Some(texture),
+            _ => None,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        matches!(
+            *self.status.borrow(),
+            StreamStatus::Ready(_) | StreamStatus::Failed(_)
+        )
+    }
+}
+
+// A texture whose storage exists and whose regions are being uploaded, a band of rows at a
+// time. Compressed rows are rows of blocks.
+struct Job {
+    status: Rc<RefCell<StreamStatus>>,
+    ktx_texture: KtxData<'static>,
+    target: GLenum,
+    texture: u32,
+    uploads: Vec<(Region, Range<usize>)>,
+    // The region, slice and row the next band starts at
+    upload: usize,
+    slice: u32,
+    row: u32,
+    uploaded: usize,
+    total: usize,
+}
+
+impl Job {
+    fn rows_per_band(&self) -> u32 {
+        match block_format(self.ktx_texture.header.gl_internal_format) {
+            Some(block) if self.ktx_texture.header.is_compressed() => block.block_height,
+            _ => 1,
+        }
+    }
+
+    // The next band of at most max_bytes, or of a single row when one doesn't fit, without
+    // crossing slices. Each slice of a region holds the same number of equally sized rows.
+    fn next_band(&self, max_bytes: usize) -> Option<(Region, Range<usize>)> {
+        let (region, range) = self.uploads.get(self.upload)?;
+        let rows_per_band = self.rows_per_band();
+        let rows = region.size[1].div_ceil(rows_per_band);
+        let slice_bytes = range.len() / region.size[2] as usize;
+        let row_bytes = slice_bytes / rows as usize;
+
+        let count = (max_bytes / row_bytes.max(1)).clamp(1, (rows - self.row) as usize) as u32;
+        let start = range.start + self.slice as usize * slice_bytes + self.row as usize * row_bytes;
+        let first = self.row * rows_per_band;
+        let last = ((self.row + count) * rows_per_band).min(region.size[1]);
+        let band = Region {
+            target: region.target,
+            level: region.level,
+            offset: [0, first, region.offset[2] + self.slice],
+            size: [region.size[0], last - first, 1],
+        };
+        Some((band, start..start + count as usize * row_bytes))
+    }
+
+    fn advance(&mut self, band: &Region, bytes: usize) {
+        let region = &self.uploads[self.upload].0;
+        self.row += band.size[1].div_ceil(self.rows_per_band());
+        if band.offset[1] + band.size[1] >= region.size[1] {
+            self.row = 0;
+            self.slice += 1;
+            if self.slice == region.size[2] {
+                self.slice = 0;
+                self.upload += 1;
+            }
+        }
+        self.uploaded += bytes;
+        *self.status.borrow_mut() = StreamStatus::Uploading {
+            uploaded: self.uploaded,
+            total: self.total,
+        };
+    }
+}
+
+// Streams KTX files into textures without stalling the render thread. Files are read and
+// mipmapped on a worker thread, then copied into a persistently mapped pixel unpack buffer
+// and uploaded from it a few rows at a time, at most frame_budget bytes each frame. Fences
+// keep a frame's part of the buffer from being overwritten while GL may still read it.
+//
+// Needs GL 4.4 for BufferStorage, and the context current whenever it is used or dropped.
+pub struct TextureStreamer {
+    requests: Option<Sender<(usize, PathBuf)>>,
+    loaded: Receiver<Loaded>,
+    worker: Option<JoinHandle<()>>,
+    next_request: usize,
+    loading: HashMap<usize, Rc<RefCell<StreamStatus>>>,
+    jobs: VecDeque<Job>,
+    frame_budget: usize,
+    slot_size: usize,
+    buffer: u32,
+    mapping: *mut u8,
+    fences: [GLsync; FRAMES_IN_FLIGHT],
+    frame: usize,
+}
+
+impl TextureStreamer {
+    pub fn new(frame_budget: usize) -> Result<Self, KtxError> {
+        if frame_budget == 0 {
+            return Err(KtxError::InvalidArgument("frame budget of zero bytes"));
+        }
+        // Each slot starts on an aligned offset, whatever the budget
+        let slot_size = frame_budget.div_ceil(OFFSET_ALIGNMENT) * OFFSET_ALIGNMENT;
+        let size = (slot_size * FRAMES_IN_FLIGHT) as isize;
+        let flags = gl::MAP_WRITE_BIT | gl::MAP_PERSISTENT_BIT | gl::MAP_COHERENT_BIT;
+        let mut buffer = 0;
+        let mapping = unsafe {
+            gl::GenBuffers(1, &mut buffer);
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, buffer);
+            gl::BufferStorage(gl::PIXEL_UNPACK_BUFFER, size, ptr::null(), flags);
+            let mapping = gl::MapBufferRange(gl::PIXEL_UNPACK_BUFFER, 0, size, flags);
+            gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+            mapping as *mut u8
+        };
+        if mapping.is_null() {
+            unsafe {
+                gl::DeleteBuffers(1, &buffer);
+            }
+            return Err(KtxError::GlFailure("map the pixel unpack buffer"));
+        }
+
+        let (requests, received) = mpsc::channel();
+        let (sender, loaded) = mpsc::channel();
+        let worker = thread::spawn(move || load_requests(received, sender));
+        Ok(TextureStreamer {
+            requests: Some(requests),
+            loaded,
+            worker: Some(worker),
+            next_request: 0,
+            loading: HashMap::new(),
+            jobs: VecDeque::new(),
+            frame_budget,
+            slot_size,
+            buffer,
+            mapping,
+            fences: [ptr::null(); FRAMES_IN_FLIGHT],
+            frame: 0,
+        })
+    }
+
+    pub fn load_file<P: AsRef<Path>>(&mut self, path: P) -> StreamHandle {
+        let status = Rc::new(RefCell::new(StreamStatus::Loading));
+        let request = (self.next_request, path.as_ref().to_path_buf());
+        let sent = self
+            .requests
+            .as_ref()
+            .is_some_and(|requests| requests.send(request).is_ok());
+        if sent {
+            self.loading.insert(self.next_request, status.clone());
+            self.next_request += 1;
+        } else {
+            *status.borrow_mut() = StreamStatus::Failed(KtxError::Io {
+                kind: io::ErrorKind::BrokenPipe,
+                reason: "the loader thread has stopped".to_string(),
+            });
+        }
+        StreamHandle { status }
+    }
+
+    pub fn load_asset<P: AsRef<Path>>(&mut self, path: P) -> StreamHandle {
+        self.load_file(asset_path(path))
+    }
+
+    // True while any texture is still loading or uploading
+    pub fn is_busy(&self) -> bool {
+        !self.loading.is_empty() || !self.jobs.is_empty()
+    }
+
+    // Call once a frame. Starts textures the worker has finished reading and uploads the
+    // next frame_budget bytes, skipping the frame if GL is still reading this frame's part of
+    // the buffer. Rows too wide for the budget are uploaded one at a time from client memory.
+    // The last texture uploaded to is left bound.
+    pub fn update(&mut self) {
+        while let Ok((request, result)) = self.loaded.try_recv() {
+            if let Some(status) = self.loading.remove(&request) {
+                match result.and_then(|ktx_texture| start_job(ktx_texture, status.clone())) {
+                    Ok(job) => self.jobs.push_back(job),
+                    Err(error) => *status.borrow_mut() = StreamStatus::Failed(error),
+                }
+            }
+        }
+        if self.jobs.is_empty() {
+            return;
+        }
+
+        let slot = self.frame % FRAMES_IN_FLIGHT;
+        let fence = self.fences[slot];
+        if !fence.is_null() {
+            let result = unsafe { gl::ClientWaitSync(fence, gl::SYNC_FLUSH_COMMANDS_BIT, 0) };
+            if result == gl::TIMEOUT_EXPIRED {
+                return;
+            }
+            unsafe {
+                gl::DeleteSync(fence);
+            }
+            self.fences[slot] = ptr::null();
+        }
+
+        let mut previous_alignment = 0;
+        unsafe {
+            gl::GetIntegerv(gl::UNPACK_ALIGNMENT, &mut previous_alignment);
+        }
+        let slot_start = slot * self.slot_size;
+        let mut used: usize = 0;
+        let mut buffered = false;
+        'jobs: while let Some(job) = self.jobs.front_mut() {
+            unsafe {
+                gl::BindTexture(job.target, job.texture);
+                gl::PixelStorei(gl::UNPACK_ALIGNMENT, job.ktx_texture.unpack_alignment);
+            }
+            loop {
+                let offset = used.div_ceil(OFFSET_ALIGNMENT) * OFFSET_ALIGNMENT;
+                let available = self.frame_budget.saturating_sub(offset);
+                let (band, range) = match job.next_band(available) {
+                    Some(band) => band,
+                    None => break,
+                };
+                let bytes = range.len();
+                let header = &job.ktx_texture.header;
+                if bytes <= available {
+                    unsafe {
+                        let source = &job.ktx_texture.pixels[range];
+                        ptr::copy_nonoverlapping(
+                            source.as_ptr(),
+                            self.mapping.add(slot_start + offset),
+                            bytes,
+                        );
+                        gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, self.buffer);
+                        upload_region(header, &band, (slot_start + offset) as *const GLvoid, bytes);
+                        gl::BindBuffer(gl::PIXEL_UNPACK_BUFFER, 0);
+                    }
+                    used = offset + bytes;
+                    buffered = true;
+                } else if used == 0 && bytes > self.frame_budget {
+                    let source = &job.ktx_texture.pixels[range];
+                    upload_region(header, &band, source.as_ptr() as *const GLvoid, bytes);
+                    used = self.frame_budget;
+                } else {
+                    break 'jobs;
+                }
+                job.advance(&band, bytes);
+            }
+            *job.status.borrow_mut() = StreamStatus::Ready(job.texture);
+            self.jobs.pop_front();
+        }
+
+        unsafe {
+            gl::PixelStorei(gl::UNPACK_ALIGNMENT, previous_alignment);
+            if buffered {
+                self.fences[slot] = gl::FenceSync(gl::SYNC_GPU_COMMANDS_COMPLETE, 0);
+            }
+        }
+        self.frame += 1;
+    }
+}
+
+impl Drop for TextureStreamer {
+    fn drop(&mut self) {
+        // Closing the channel ends the worker once it finishes the file it is reading
+        self.requests = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+        for status in self.loading.values() {
+            *status.borrow_mut() = StreamStatus::Failed(KtxError::StreamCancelled);
+        }
+        unsafe {
+            for job in self.jobs.iter() {
+                gl::DeleteTextures(1, &job.texture);
+                *job.status.borrow_mut() = StreamStatus::Failed(KtxError::StreamCancelled);
+            }
+            for fence in self.fences.iter().filter(|fence| !fence.is_null()) {
+                gl::DeleteSync(*fence);
+            }
+            gl::DeleteBuffers(1, &self.buffer);
+        }
+    }
+}
+
+fn load_requests(requests: Receiver<(usize, PathBuf)>, loaded: Sender<Loaded>) {
+    for (request, path) in requests {
+        let result = load_ktx_file(path).and_then(|ktx_texture| {
+            Ok(generate_requested_mipmaps(&ktx_texture)?.unwrap_or(ktx_texture))
+        });
+        if loaded.send((request, result)).is_err() {
+            break;
+        }
+    }
+}
+
+// Allocates the texture's storage so its levels can be filled in over the following frames.
+// Compressed formats the driver lacks are decoded here, as the check needs the context.
+fn start_job(
+    ktx_texture: KtxData<'static>,
+    status: Rc<RefCell<StreamStatus>>,
+) -> Result<Job, KtxError> {
+    let target = determine_target(&ktx_texture)?;
+    let ktx_texture = decompress_unsupported(&ktx_texture, target)?.unwrap_or(ktx_texture);
+    let texture = create_texture(&ktx_texture, target)?;
+    let uploads = image_uploads(&ktx_texture, target);
+    let total = uploads.iter().map(|(_, range)| range.len()).sum();
+    *status.borrow_mut() = StreamStatus::Uploading { uploaded: 0, total };
+    Ok(Job {
+        status,
+        ktx_texture,
+        target,
+        texture,
+        uploads,
+        upload: 0,
+        slice: 0,
+        row: 0,
+        uploaded: 0,
+        total,
+    })
+}
@@ -0,0 +1,163 @@
+use super::{
+    image_bytes_for, pad, repack_rows, KtxData, KTX_ALIGNMENT, KTX_ENDIANNESS, KTX_IDENTIFIER,
+};
+use std::{
+    borrow::Cow,
+    convert::TryFrom,
+    io::{self, Write},
+};
+
+// Writes a KTX 1.1 file in the layout the spec describes: imageSize before every level and rows,
+// cube faces and levels padded to four bytes, whatever the source used. Everything is written
+// in the machine's byte order, which is what the pixel data is held in after parsing.
+pub fn write_ktx<W: Write>(ktx_texture: &KtxData, output: &mut W) -> io::Result<()> {
+    let ktx = &ktx_texture.header;
+    ktx.validate().map_err(invalid_input)?;
+    if ktx_texture.levels.len() != ktx.level_count() as usize {
+        return Err(invalid_input(format!(
+            "header describes {} mip levels but {} were given",
+            ktx.level_count(),
+            ktx_texture.levels.len()
+        )));
+    }
+
+    let key_values = key_value_data(ktx_texture)?;
+    let key_value_bytes = u32::try_from(key_values.len())
+        .map_err(|_| invalid_input("key/value data is too large for bytesOfKeyValueData"))?;
+    let members = [
+        ktx.gl_type,
+        ktx.gl_type_size,
+        ktx.gl_format,
+        ktx.gl_internal_format,
+        ktx.gl_base_internal_format,
+        ktx.pixel_width,
+        ktx.pixel_height,
+        ktx.pixel_depth,
+        ktx.array_elements,
+        ktx.face_count(),
+        ktx.mip_levels,
+        key_value_bytes,
+    ];
+    output.write_all(&KTX_IDENTIFIER)?;
+    output.write_all(&KTX_ENDIANNESS.to_ne_bytes())?;
+    for member in members.iter() {
+        output.write_all(&member.to_ne_bytes())?;
+    }
+    output.write_all(&key_values)?;
+
+    let non_array_cube = ktx.face_count() == 6 && ktx.array_elements == 0;
+    let images_per_level = ktx.images_per_level();
+    for (level, mip) in ktx_texture.levels.iter().enumerate() {
+        if mip.images.len() != images_per_level {
+            return Err(invalid_input(format!(
+                "mip level {} has {} images but the header describes {}",
+                level,
+                mip.images.len(),
+                images_per_level
+            )));
+        }
+
+        let mut images = Vec::with_capacity(images_per_level);
+        for (index, range) in mip.images.iter().enumerate() {
+            let image = ktx_texture.pixels.get(range.clone()).ok_or_else(|| {
+                invalid_input(format!(
+                    "image {} of mip level {} lies outside the pixel data",
+                    index, level
+                ))
+            })?;
+            images.push(repack_image(ktx_texture, level, image)?);
+        }
+
+        let image_bytes = images[0].len();
+        let image_size = if non_array_cube {
+            Some(image_bytes)
+        } else {
+            image_bytes.checked_mul(images_per_level)
+        };
+        let image_size = image_size
+            .and_then(|size| u32::try_from(size).ok())
+            .ok_or_else(|| {
+                invalid_input(format!(
+                    "mip level {} is too large for its imageSize field",
+                    level
+                ))
+            })?;
+        output.write_all(&image_size.to_ne_bytes())?;
+
+        let mut written = 0;
+        for image in images.iter() {
+            output.write_all(image)?;
+            written += image.len();
+            if non_array_cube {
+                written += write_padding(output, written)?;
+            }
+        }
+        write_padding(output, written)?;
+    }
+    Ok(())
+}
+
+fn key_value_data(ktx_texture: &KtxData) -> io::Result<Vec<u8>> {
+    let mut data = Vec::new();
+    for key_value in ktx_texture.key_values.iter() {
+        let size =
+            u32::try_from(key_value.key.len() + 1 + key_value.value.len()).map_err(|_| {
+                invalid_input(format!(
+                    "value of {} is too large for its keyAndValueByteSize",
+                    key_value.key
+                ))
+            })?;
+        data.extend_from_slice(&size.to_ne_bytes());
+        data.extend_from_slice(key_value.key.as_bytes());
+        data.push(0);
+        data.extend_from_slice(&key_value.value);
+        data.resize(pad(data.len(), KTX_ALIGNMENT), 0);
+    }
+    Ok(data)
+}
+
+// Rows are stored at the alignment the texture was loaded with and have to be re-padded
+// to four bytes. Compressed images are made of whole blocks and are copied as they are.
+fn repack_image<'a>(
+    ktx_texture: &KtxData,
+    level: usize,
+    image: &'a [u8],
+) -> io::Result<Cow<'a, [u8]>> {
+    let ktx = &ktx_texture.header;
+    let mip = &ktx_texture.levels[level];
+    let source_alignment = ktx_texture.unpack_alignment as usize;
+    if !source_alignment.is_power_of_two() {
+        return Err(invalid_input(format!(
+            "unpack alignment {} is not a power of two",
+            source_alignment
+        )));
+    }
+
+    let expected = image_bytes_for(ktx, mip.width, mip.height, mip.depth, source_alignment);
+    if expected != Some(image.len()) {
+        return Err(invalid_input(format!(
+            "image of mip level {} is {} bytes but the header implies {:?}",
+            level,
+            image.len(),
+            expected
+        )));
+    }
+    if ktx.is_compressed() || source_alignment == KTX_ALIGNMENT {
+        return Ok(Cow::Borrowed(image));
+    }
+
+    let row_bytes = mip.width as usize * ktx.pixel_size().unwrap_or(0) as usize;
+    let rows = mip.height as usize * mip.depth as usize;
+    let repacked = repack_rows(image, row_bytes, rows, source_alignment, KTX_ALIGNMENT);
+    Ok(Cow::Owned(repacked))
+}
+
+fn write_padding<W: Write>(output: &mut W, offset: usize) -> io::Result<usize> {
+    let padding = pad(offset, KTX_ALIGNMENT) - offset;
+    output.write_all(&[0; KTX_ALIGNMENT][..padding])?;
+    Ok(padding)
+}
+
+fn invalid_input<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
@@ -3,6 +3,10 @@ extern crate nom;
 #[macro_use]
 pub mod ktx;
 pub mod app;
+pub mod assets;
+pub mod hdr;
+pub mod image;
 pub mod object;
+pub mod sdf;
 pub mod shader;
 pub mod text;
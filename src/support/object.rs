@@ -1,15 +1,9 @@
-use gl::types::{GLsizeiptr, GLuint};
-use nom::{
-    branch::alt,
-    bytes::complete::{tag, take},
-    combinator::{cut, map, peek},
-    error::{context, ParseError},
-    multi::many_m_n,
-    number::complete::le_u32,
-    sequence::{preceded, tuple},
-    IResult,
-};
-use std::{mem, ptr, str};
+use crate::assets::asset_path;
+use gl::types::{GLenum, GLsizeiptr, GLuint};
+use nom::{bytes::complete::take, number::complete::le_u32, sequence::tuple, IResult, Offset};
+use std::{convert::TryInto, error::Error, fmt, fs, io, path::Path, ptr, str};
+
+pub mod writer;
 
 const HEADER_TAG: &str = "SB6M";
 const INDEX_DATA_TAG: &str = "INDX";
@@ -19,16 +13,78 @@ const SUB_OBJECT_LIST_TAG: &str = "OLST";
 const COMMENT_TAG: &str = "CMNT";
 const DATA_TAG: &str = "DATA";
 
+const HEADER_BYTES: usize = 16;
+const CHUNK_BYTES: usize = 8;
 const CHUNK_HEADER_BYTES: u32 = 4;
 const VERTEX_ATTRIBUTE_NAME_BYTES: u32 = 64;
 
+// Raw bytes are the only encoding the format defines for data
+const DATA_ENCODING_RAW: u32 = 0;
+
 const VERTEX_ATTRIB_FLAG_NORMALIZED: u32 = 0x0000_0001;
-// const VERTEX_ATTRIB_FLAG_INTEGER: u32 = 0x0000_0002;
+const VERTEX_ATTRIB_FLAG_INTEGER: u32 = 0x0000_0002;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SbmError {
+    InvalidIdentifier,
+    Truncated {
+        chunk: String,
+        offset: usize,
+    },
+    InvalidChunk {
+        chunk: String,
+        offset: usize,
+        reason: &'static str,
+    },
+    InvalidAttribute {
+        name: String,
+        offset: usize,
+        reason: &'static str,
+    },
+    MissingChunk(&'static str),
+    Io {
+        kind: io::ErrorKind,
+        reason: String,
+    },
+}
+
+impl fmt::Display for SbmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SbmError::InvalidIdentifier => write!(f, "missing SB6M identifier at offset 0"),
+            SbmError::Truncated { chunk, offset } => {
+                write!(f, "truncated {} chunk at offset {}", chunk, offset)
+            }
+            SbmError::InvalidChunk {
+                chunk,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "invalid {} chunk at offset {}: {}",
+                chunk, offset, reason
+            ),
+            SbmError::InvalidAttribute {
+                name,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "invalid vertex attribute {} at offset {}: {}",
+                name, offset, reason
+            ),
+            SbmError::MissingChunk(chunk) => write!(f, "missing {} chunk", chunk),
+            SbmError::Io { reason, .. } => write!(f, "failed to read object: {}", reason),
+        }
+    }
+}
+
+impl Error for SbmError {}
 
 #[derive(Debug)]
-pub enum ChunkType<'a> {
+pub enum ChunkType {
     IndexData(IndexData),
-    VertexData(VertexData<'a>),
+    VertexData(VertexData),
     VertexAttributes(Vec<VertexAttribute>),
     SubObjects(Vec<SubObject>),
     Comment(String),
@@ -36,11 +92,10 @@ pub enum ChunkType<'a> {
 }
 
 #[derive(Debug)]
-pub struct VertexData<'a> {
-    data_size: isize,
+pub struct VertexData {
+    data_size: u32,
     data_offset: u32,
     total_vertices: u32,
-    vertices: &'a [u8],
 }
 
 #[derive(Debug)]
@@ -50,12 +105,6 @@ pub struct Data {
     data_length: u32,
 }
 
-#[derive(Debug)]
-pub struct ChunkHeader {
-    name: String,
-    chunk_size: u32,
-}
-
 #[derive(Debug)]
 pub struct IndexData {
     index_type: u32,
@@ -73,295 +122,766 @@ pub struct VertexAttribute {
     data_offset: u32,
 }
 
-impl VertexAttribute {
-    fn is_normalized(&self) -> u8 {
-        if self.flags & VERTEX_ATTRIB_FLAG_NORMALIZED == 1 {
-            gl::TRUE
-        } else {
-            gl::FALSE
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SubObject {
+    pub first: u32,
+    pub count: u32,
+}
+
+// The values of one attribute for every vertex, in the type they are stored as. Packed
+// types hold a single value per vertex.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VertexStream {
+    Byte(Vec<i8>),
+    UnsignedByte(Vec<u8>),
+    Short(Vec<i16>),
+    UnsignedShort(Vec<u16>),
+    HalfFloat(Vec<u16>),
+    Int(Vec<i32>),
+    UnsignedInt(Vec<u32>),
+    Fixed(Vec<i32>),
+    Float(Vec<f32>),
+    Double(Vec<f64>),
+    Int2_10_10_10Rev(Vec<u32>),
+    UnsignedInt2_10_10_10Rev(Vec<u32>),
+    UnsignedInt10F11F11FRev(Vec<u32>),
+}
+
+fn le_values<T, const N: usize>(bytes: &[u8], convert: fn([u8; N]) -> T) -> Vec<T> {
+    bytes
+        .chunks_exact(N)
+        .map(|value| convert(value.try_into().unwrap_or([0; N])))
+        .collect()
+}
+
+impl VertexStream {
+    pub fn from_le_bytes(gl_type: GLenum, bytes: &[u8]) -> Option<Self> {
+        let stream = match gl_type {
+            gl::BYTE => VertexStream::Byte(bytes.iter().map(|value| *value as i8).collect()),
+            gl::UNSIGNED_BYTE => VertexStream::UnsignedByte(bytes.to_vec()),
+            gl::SHORT => VertexStream::Short(le_values(bytes, i16::from_le_bytes)),
+            gl::UNSIGNED_SHORT => VertexStream::UnsignedShort(le_values(bytes, u16::from_le_bytes)),
+            gl::HALF_FLOAT => VertexStream::HalfFloat(le_values(bytes, u16::from_le_bytes)),
+            gl::INT => VertexStream::Int(le_values(bytes, i32::from_le_bytes)),
+            gl::UNSIGNED_INT => VertexStream::UnsignedInt(le_values(bytes, u32::from_le_bytes)),
+            gl::FIXED => VertexStream::Fixed(le_values(bytes, i32::from_le_bytes)),
+            gl::FLOAT => VertexStream::Float(le_values(bytes, f32::from_le_bytes)),
+            gl::DOUBLE => VertexStream::Double(le_values(bytes, f64::from_le_bytes)),
+            gl::INT_2_10_10_10_REV => {
+                VertexStream::Int2_10_10_10Rev(le_values(bytes, u32::from_le_bytes))
+            }
+            gl::UNSIGNED_INT_2_10_10_10_REV => {
+                VertexStream::UnsignedInt2_10_10_10Rev(le_values(bytes, u32::from_le_bytes))
+            }
+            gl::UNSIGNED_INT_10F_11F_11F_REV => {
+                VertexStream::UnsignedInt10F11F11FRev(le_values(bytes, u32::from_le_bytes))
+            }
+            _ => return None,
+        };
+        Some(stream)
+    }
+
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        match self {
+            VertexStream::Byte(values) => values.iter().map(|value| *value as u8).collect(),
+            VertexStream::UnsignedByte(values) => values.clone(),
+            VertexStream::Short(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            VertexStream::UnsignedShort(values) | VertexStream::HalfFloat(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            VertexStream::Int(values) | VertexStream::Fixed(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            VertexStream::UnsignedInt(values)
+            | VertexStream::Int2_10_10_10Rev(values)
+            | VertexStream::UnsignedInt2_10_10_10Rev(values)
+            | VertexStream::UnsignedInt10F11F11FRev(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            VertexStream::Float(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+            VertexStream::Double(values) => values
+                .iter()
+                .flat_map(|value| value.to_le_bytes())
+                .collect(),
+        }
+    }
+
+    // The number of values, which is one per vertex for packed types
+    pub fn len(&self) -> usize {
+        match self {
+            VertexStream::Byte(values) => values.len(),
+            VertexStream::UnsignedByte(values) => values.len(),
+            VertexStream::Short(values) => values.len(),
+            VertexStream::UnsignedShort(values) | VertexStream::HalfFloat(values) => values.len(),
+            VertexStream::Int(values) | VertexStream::Fixed(values) => values.len(),
+            VertexStream::UnsignedInt(values)
+            | VertexStream::Int2_10_10_10Rev(values)
+            | VertexStream::UnsignedInt2_10_10_10Rev(values)
+            | VertexStream::UnsignedInt10F11F11FRev(values) => values.len(),
+            VertexStream::Float(values) => values.len(),
+            VertexStream::Double(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_integer(&self) -> bool {
+        is_integer_type(self.gl_type())
+    }
+
+    pub fn gl_type(&self) -> GLenum {
+        match self {
+            VertexStream::Byte(_) => gl::BYTE,
+            VertexStream::UnsignedByte(_) => gl::UNSIGNED_BYTE,
+            VertexStream::Short(_) => gl::SHORT,
+            VertexStream::UnsignedShort(_) => gl::UNSIGNED_SHORT,
+            VertexStream::HalfFloat(_) => gl::HALF_FLOAT,
+            VertexStream::Int(_) => gl::INT,
+            VertexStream::UnsignedInt(_) => gl::UNSIGNED_INT,
+            VertexStream::Fixed(_) => gl::FIXED,
+            VertexStream::Float(_) => gl::FLOAT,
+            VertexStream::Double(_) => gl::DOUBLE,
+            VertexStream::Int2_10_10_10Rev(_) => gl::INT_2_10_10_10_REV,
+            VertexStream::UnsignedInt2_10_10_10Rev(_) => gl::UNSIGNED_INT_2_10_10_10_REV,
+            VertexStream::UnsignedInt10F11F11FRev(_) => gl::UNSIGNED_INT_10F_11F_11F_REV,
         }
     }
 }
 
-#[derive(Debug, Default)]
-pub struct SubObject {
-    pub first: u32,
-    pub count: u32,
+#[derive(Debug, Clone, PartialEq)]
+pub struct MeshAttribute {
+    pub name: String,
+    pub components: u32,
+    pub normalized: bool,
+    // Read by shaders as integers rather than converted to floats
+    pub integer: bool,
+    pub stream: VertexStream,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Indices {
+    UnsignedByte(Vec<u8>),
+    UnsignedShort(Vec<u16>),
+    UnsignedInt(Vec<u32>),
+}
+
+impl Indices {
+    pub fn gl_type(&self) -> GLenum {
+        match self {
+            Indices::UnsignedByte(_) => gl::UNSIGNED_BYTE,
+            Indices::UnsignedShort(_) => gl::UNSIGNED_SHORT,
+            Indices::UnsignedInt(_) => gl::UNSIGNED_INT,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Indices::UnsignedByte(indices) => indices.len(),
+            Indices::UnsignedShort(indices) => indices.len(),
+            Indices::UnsignedInt(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn values(&self) -> Vec<u32> {
+        match self {
+            Indices::UnsignedByte(indices) => indices.iter().map(|index| *index as u32).collect(),
+            Indices::UnsignedShort(indices) => indices.iter().map(|index| *index as u32).collect(),
+            Indices::UnsignedInt(indices) => indices.clone(),
+        }
+    }
+
+    pub fn to_le_bytes(&self) -> Vec<u8> {
+        match self {
+            Indices::UnsignedByte(indices) => indices.clone(),
+            Indices::UnsignedShort(indices) => indices
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect(),
+            Indices::UnsignedInt(indices) => indices
+                .iter()
+                .flat_map(|index| index.to_le_bytes())
+                .collect(),
+        }
+    }
+}
+
+// A model as it is stored, without anything uploaded to GL. Sub-objects are ranges of
+// indices when the mesh is indexed, and of vertices otherwise.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mesh {
+    pub attributes: Vec<MeshAttribute>,
+    pub vertex_count: u32,
+    pub indices: Option<Indices>,
+    pub sub_objects: Vec<SubObject>,
+    pub comment: Option<String>,
+}
+
+impl Mesh {
+    pub fn attribute(&self, name: &str) -> Option<&MeshAttribute> {
+        self.attributes
+            .iter()
+            .find(|attribute| attribute.name == name)
+    }
 }
 
 #[derive(Debug, Default)]
 pub struct Object {
     vbo: GLuint,
+    ebo: GLuint,
     pub vao: GLuint,
+    pub index_type: Option<GLenum>,
     pub sub_objects: Vec<SubObject>,
+    pub comment: Option<String>,
 }
 
-#[macro_export]
-macro_rules! load_object {
-    ($path:tt) => {
-        $crate::object::parse_object(include_bytes!($path))
-    };
+impl Drop for Object {
+    fn drop(&mut self) {
+        // A default object never made any GL calls, so it has nothing to delete
+        if self.vao == 0 {
+            return;
+        }
+        unsafe {
+            gl::DeleteVertexArrays(1, &self.vao);
+            gl::DeleteBuffers(1, &self.vbo);
+            if self.ebo != 0 {
+                gl::DeleteBuffers(1, &self.ebo);
+            }
+        }
+    }
 }
 
-fn bytes_to_string(bytes: &[u8]) -> String {
-    str::from_utf8(&bytes)
-        .unwrap()
-        .trim_end_matches(char::from(0))
-        .to_string()
-}
-
-#[rustfmt::skip]
-fn chunk<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(input: &'static [u8]) -> IResult<&'static [u8], ChunkType, E> {
-    context(
-        "Chunk",
-        cut(alt((
-            preceded(
-                peek(tag(INDEX_DATA_TAG)),
-                map(index_data, ChunkType::IndexData)),
-            preceded(
-                peek(tag(COMMENT_TAG)),
-                map(comment, ChunkType::Comment)),
-            preceded(
-                peek(tag(DATA_TAG)),
-                map(data, ChunkType::Data)),
-            preceded(
-                peek(tag(VERTEX_DATA_TAG)),
-                map(vertex_data, ChunkType::VertexData),
-            ),
-            preceded(
-                peek(tag(VERTEX_ATTRIBUTES_TAG)),
-                map(vertex_attributes, ChunkType::VertexAttributes),
-            ),
-            preceded(
-                peek(tag(SUB_OBJECT_LIST_TAG)),
-                map(sub_objects, ChunkType::SubObjects),
-            ),
-        ))),
-    )(input)
-}
-
-fn chunk_header<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], ChunkHeader, E> {
-    context(
-        "Chunk Header",
-        cut(map(
-            tuple((take(CHUNK_HEADER_BYTES), le_u32)),
-            |(name_bytes, chunk_size)| ChunkHeader {
-                name: bytes_to_string(name_bytes),
-                chunk_size,
-            },
-        )),
-    )(input)
-}
-
-fn index_data<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], IndexData, E> {
-    let (input, _) = chunk_header(input)?;
-    context(
-        "IndexData",
-        cut(map(
-            tuple((le_u32, le_u32, le_u32)),
-            |(index_type, index_count, index_data_offset)| IndexData {
-                index_type,
-                index_count,
-                index_data_offset,
-            },
-        )),
-    )(input)
-}
-
-fn comment<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], String, E> {
-    let (input, header) = chunk_header(input)?;
-    context(
-        "Comment",
-        cut(map(
-            take((header.chunk_size - 8_u32) as usize),
-            bytes_to_string,
-        )),
-    )(input)
-}
-
-fn data<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], Data, E> {
-    let (input, _) = chunk_header(input)?;
-    context(
-        "Data",
-        cut(map(
-            tuple((le_u32, le_u32, le_u32)),
-            |(encoding, data_offset, data_length)| Data {
-                encoding,
-                data_offset,
-                data_length,
-            },
-        )),
-    )(input)
-}
-
-fn vertex_data<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], VertexData, E> {
-    let (input, _) = chunk_header(input)?;
-    let (input, data_size) = le_u32(input)?;
-    context(
-        "Vertex Data",
-        cut(map(
-            tuple((le_u32, le_u32, take(data_size as usize))),
-            |(data_offset, total_vertices, vertices): (u32, u32, &[u8])| VertexData {
-                data_size: (vertices.len() * mem::size_of::<u8>()) as GLsizeiptr,
-                data_offset,
-                total_vertices,
-                vertices,
+// Names and comments are padded with nulls, which sb6mtool also puts in front of comments
+fn bytes_to_string(bytes: &[u8]) -> Option<String> {
+    str::from_utf8(bytes)
+        .ok()
+        .map(|text| text.trim_matches(char::from(0)).to_string())
+}
+
+// Every chunk is parsed from its own bytes, with offsets reported from the start of the file
+struct ChunkReader<'a> {
+    file: &'a [u8],
+    name: String,
+    offset: usize,
+}
+
+impl<'a> ChunkReader<'a> {
+    fn parse<T, F>(&self, input: &'a [u8], mut parser: F) -> Result<(&'a [u8], T), SbmError>
+    where
+        F: FnMut(&'a [u8]) -> IResult<&'a [u8], T>,
+    {
+        parser(input).map_err(|error| SbmError::Truncated {
+            chunk: self.name.clone(),
+            offset: match error {
+                nom::Err::Error(error) | nom::Err::Failure(error) => self.file.offset(error.input),
+                nom::Err::Incomplete(_) => self.file.len(),
             },
-        )),
-    )(input)
-}
-
-fn vertex_attributes<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], Vec<VertexAttribute>, E> {
-    let (input, _) = chunk_header(input)?;
-    let (input, num_attributes) = le_u32(input)?;
-    context(
-        "Vertex Attributes",
-        cut(many_m_n(
-            num_attributes as usize,
-            num_attributes as usize,
-            map(
-                tuple((
-                    take(VERTEX_ATTRIBUTE_NAME_BYTES),
-                    le_u32,
-                    le_u32,
-                    le_u32,
-                    le_u32,
-                    le_u32,
-                )),
-                |(name_bytes, size, attribute_type, stride, flags, data_offset)| VertexAttribute {
-                    name: bytes_to_string(name_bytes),
-                    size,
-                    attribute_type,
-                    stride,
-                    flags,
-                    data_offset,
-                },
-            ),
-        )),
-    )(input)
-}
-
-fn sub_objects<E: ParseError<&'static [u8]> + nom::error::ContextError<&'static [u8]>>(
-    input: &'static [u8],
-) -> IResult<&'static [u8], Vec<SubObject>, E> {
-    let (input, _) = chunk_header(input)?;
-    let (input, num_objects) = le_u32(input)?;
-    context(
-        "Sub Objects",
-        cut(many_m_n(
-            num_objects as usize,
-            num_objects as usize,
-            map(tuple((le_u32, le_u32)), |(first, count)| SubObject {
-                first,
-                count,
-            }),
-        )),
-    )(input)
-}
-
-pub fn parse_object(input: &'static [u8]) -> IResult<&'static [u8], Object> {
-    let (input, _) = alt((
-        tag(HEADER_TAG),                                              // Little Endian
-        tag(HEADER_TAG.chars().rev().collect::<String>().as_bytes()), // Big Endian
-    ))(input)?;
+        })
+    }
+
+    fn invalid(&self, offset: usize, reason: &'static str) -> SbmError {
+        SbmError::InvalidChunk {
+            chunk: self.name.clone(),
+            offset,
+            reason,
+        }
+    }
+
+    fn string(&self, bytes: &[u8]) -> Result<String, SbmError> {
+        bytes_to_string(bytes)
+            .ok_or_else(|| self.invalid(self.file.offset(bytes), "text is not UTF-8"))
+    }
+}
+
+// Chunks are found by walking their sizes, and each is parsed from its own bytes
+fn parse_chunks(file: &[u8]) -> Result<Vec<(usize, ChunkType)>, SbmError> {
+    let reader = ChunkReader {
+        file,
+        name: HEADER_TAG.to_string(),
+        offset: 0,
+    };
+    let (_, identifier) = reader.parse(file, take(4usize))?;
+    let big_endian: String = HEADER_TAG.chars().rev().collect();
+    if identifier != HEADER_TAG.as_bytes() && identifier != big_endian.as_bytes() {
+        return Err(SbmError::InvalidIdentifier);
+    }
 
     // No header flags are defined for the format currently so they are ignored
-    let (input, (size, num_chunks, _)) = tuple((le_u32, le_u32, le_u32))(input)?;
+    let (_, (size, num_chunks, _)) = reader.parse(&file[4..], tuple((le_u32, le_u32, le_u32)))?;
+
+    // Data can be stored between header and first chunk, so the first chunk is found by
+    // advancing the size of the header
+    let mut offset = size as usize;
+    if offset < HEADER_BYTES || offset > file.len() {
+        return Err(reader.invalid(4, "header size is outside the file"));
+    }
+
+    let mut chunks = Vec::new();
+    for _ in 0..num_chunks {
+        let (_, (name, chunk_size)) =
+            reader.parse(&file[offset..], tuple((take(CHUNK_HEADER_BYTES), le_u32)))?;
+        let reader = ChunkReader {
+            file,
+            name: String::from_utf8_lossy(name).into_owned(),
+            offset,
+        };
+        let end = offset.saturating_add(chunk_size as usize);
+        if (chunk_size as usize) < CHUNK_BYTES {
+            return Err(reader.invalid(offset + 4, "chunk is smaller than its header"));
+        }
+        if end > file.len() {
+            return Err(SbmError::Truncated {
+                chunk: reader.name,
+                offset,
+            });
+        }
+        let body = &file[offset + CHUNK_BYTES..end];
+
+        let chunk = match reader.name.as_str() {
+            INDEX_DATA_TAG => ChunkType::IndexData(index_data(&reader, body)?),
+            COMMENT_TAG => ChunkType::Comment(reader.string(body)?),
+            DATA_TAG => ChunkType::Data(data(&reader, body)?),
+            VERTEX_DATA_TAG => ChunkType::VertexData(vertex_data(&reader, body)?),
+            VERTEX_ATTRIBUTES_TAG => ChunkType::VertexAttributes(vertex_attributes(&reader, body)?),
+            SUB_OBJECT_LIST_TAG => ChunkType::SubObjects(sub_objects(&reader, body)?),
+            _ => return Err(reader.invalid(offset, "unknown chunk type")),
+        };
+        chunks.push((offset, chunk));
+        offset = end;
+    }
+    Ok(chunks)
+}
+
+fn index_bytes(index_type: GLenum) -> Option<usize> {
+    match index_type {
+        gl::UNSIGNED_BYTE => Some(1),
+        gl::UNSIGNED_SHORT => Some(2),
+        gl::UNSIGNED_INT => Some(4),
+        _ => None,
+    }
+}
+
+fn index_data(reader: &ChunkReader, body: &[u8]) -> Result<IndexData, SbmError> {
+    let (_, (index_type, index_count, index_data_offset)) =
+        reader.parse(body, tuple((le_u32, le_u32, le_u32)))?;
+    if index_bytes(index_type).is_none() {
+        return Err(reader.invalid(reader.offset + CHUNK_BYTES, "unknown index type"));
+    }
+    Ok(IndexData {
+        index_type,
+        index_count,
+        index_data_offset,
+    })
+}
+
+fn data(reader: &ChunkReader, body: &[u8]) -> Result<Data, SbmError> {
+    let (_, (encoding, data_offset, data_length)) =
+        reader.parse(body, tuple((le_u32, le_u32, le_u32)))?;
+    if data_offset as u64 + data_length as u64 > reader.file.len() as u64 {
+        return Err(SbmError::Truncated {
+            chunk: reader.name.clone(),
+            offset: reader.file.len().min(data_offset as usize),
+        });
+    }
+    Ok(Data {
+        encoding,
+        data_offset,
+        data_length,
+    })
+}
 
-    // Data can be stored between header and first chunk,
-    // so advance to first chunk by advancing the size
-    // of the header (16 bytes) minus the current position in the file
-    let (input, _) = take((size - 16) as usize)(input)?;
+fn vertex_data(reader: &ChunkReader, body: &[u8]) -> Result<VertexData, SbmError> {
+    let (_, (data_size, data_offset, total_vertices)) =
+        reader.parse(body, tuple((le_u32, le_u32, le_u32)))?;
+    Ok(VertexData {
+        data_size,
+        data_offset,
+        total_vertices,
+    })
+}
 
-    let (input, chunks) = many_m_n(num_chunks as usize, num_chunks as usize, chunk)(input)?;
+fn vertex_attributes(reader: &ChunkReader, body: &[u8]) -> Result<Vec<VertexAttribute>, SbmError> {
+    let (mut input, num_attributes) = reader.parse(body, le_u32)?;
+    let mut attributes = Vec::new();
+    for _ in 0..num_attributes {
+        let (rest, (name_bytes, size, attribute_type, stride, flags, data_offset)) = reader.parse(
+            input,
+            tuple((
+                take(VERTEX_ATTRIBUTE_NAME_BYTES),
+                le_u32,
+                le_u32,
+                le_u32,
+                le_u32,
+                le_u32,
+            )),
+        )?;
+        attributes.push(VertexAttribute {
+            name: reader.string(name_bytes)?,
+            size,
+            attribute_type,
+            stride,
+            flags,
+            data_offset,
+        });
+        input = rest;
+    }
+    Ok(attributes)
+}
 
-    let object = prepare_object(chunks);
+fn sub_objects(reader: &ChunkReader, body: &[u8]) -> Result<Vec<SubObject>, SbmError> {
+    let (mut input, num_objects) = reader.parse(body, le_u32)?;
+    let mut sub_objects = Vec::new();
+    for _ in 0..num_objects {
+        let (rest, (first, count)) = reader.parse(input, tuple((le_u32, le_u32)))?;
+        sub_objects.push(SubObject { first, count });
+        input = rest;
+    }
+    Ok(sub_objects)
+}
+
+// Types that shaders can read as integers
+fn is_integer_type(gl_type: GLenum) -> bool {
+    matches!(
+        gl_type,
+        gl::BYTE | gl::UNSIGNED_BYTE | gl::SHORT | gl::UNSIGNED_SHORT | gl::INT | gl::UNSIGNED_INT
+    )
+}
 
-    Ok((input, object))
+// The bytes one vertex's value of an attribute takes up
+fn attribute_bytes(attribute: &VertexAttribute) -> Option<u64> {
+    let component_bytes = match attribute.attribute_type {
+        gl::INT_2_10_10_10_REV | gl::UNSIGNED_INT_2_10_10_10_REV if attribute.size == 4 => {
+            return Some(4)
+        }
+        gl::UNSIGNED_INT_10F_11F_11F_REV if attribute.size == 3 => return Some(4),
+        gl::BYTE | gl::UNSIGNED_BYTE => 1,
+        gl::SHORT | gl::UNSIGNED_SHORT | gl::HALF_FLOAT => 2,
+        gl::INT | gl::UNSIGNED_INT | gl::FLOAT | gl::FIXED => 4,
+        gl::DOUBLE => 8,
+        _ => return None,
+    };
+    Some(component_bytes * attribute.size as u64)
 }
 
-fn prepare_object(chunks: Vec<ChunkType>) -> Object {
-    // TODO:
-    // Only one of the book's models (asteroids.sbm) use chunks besides the VertexData and VertexAttributes chunks.
-    // So this doesn't use any info from the chunks other than that.
-    // If a .sbm file comes up that actually has IndexData in it, this will have to be updated
-    // according to the source from the book's github repo.
-    // The IndexData and Data chunks will need to have data read in as well.
+// Payloads are found by their offset from the start of the file. Those inside a DATA
+// chunk's range are stored in its encoding and can't run past the end of it.
+fn payload<'a>(
+    file: &'a [u8],
+    data_chunks: &[(usize, Data)],
+    chunk: &str,
+    offset: u32,
+    length: u64,
+) -> Result<&'a [u8], SbmError> {
+    let (start, end) = (offset as u64, offset as u64 + length);
+    if end > file.len() as u64 {
+        return Err(SbmError::Truncated {
+            chunk: chunk.to_string(),
+            offset: file.len().min(start as usize),
+        });
+    }
+    let containing = data_chunks.iter().find(|(_, data)| {
+        let data_start = data.data_offset as u64;
+        (data_start..data_start + data.data_length as u64).contains(&start)
+    });
+    match containing {
+        Some((chunk_offset, data)) => {
+            if end > data.data_offset as u64 + data.data_length as u64 {
+                return Err(SbmError::InvalidChunk {
+                    chunk: DATA_TAG.to_string(),
+                    offset: *chunk_offset,
+                    reason: "payload runs past the end of its data chunk",
+                });
+            }
+            match data.encoding {
+                DATA_ENCODING_RAW => Ok(&file[start as usize..end as usize]),
+                _ => Err(SbmError::InvalidChunk {
+                    chunk: DATA_TAG.to_string(),
+                    offset: *chunk_offset,
+                    reason: "unknown data encoding",
+                }),
+            }
+        }
+        None => Ok(&file[start as usize..end as usize]),
+    }
+}
+
+fn indices_from_le_bytes(index_type: GLenum, bytes: &[u8]) -> Indices {
+    match index_type {
+        gl::UNSIGNED_BYTE => Indices::UnsignedByte(bytes.to_vec()),
+        gl::UNSIGNED_SHORT => Indices::UnsignedShort(le_values(bytes, u16::from_le_bytes)),
+        _ => Indices::UnsignedInt(le_values(bytes, u32::from_le_bytes)),
+    }
+}
+
+// Every vertex of every attribute has to be read from inside the vertex data
+fn validate_attributes(
+    attributes: &[VertexAttribute],
+    vertex_data: &VertexData,
+    vertices: &[u8],
+) -> Result<(), SbmError> {
+    for attribute in attributes.iter() {
+        let invalid = |reason| SbmError::InvalidAttribute {
+            name: attribute.name.clone(),
+            offset: attribute.data_offset as usize,
+            reason,
+        };
+        if !(1..=4).contains(&attribute.size) {
+            return Err(invalid("size must be 1 to 4 components"));
+        }
+        let bytes = attribute_bytes(attribute).ok_or_else(|| invalid("unknown component type"))?;
+        if attribute.flags & VERTEX_ATTRIB_FLAG_INTEGER != 0
+            && !is_integer_type(attribute.attribute_type)
+        {
+            return Err(invalid("only integer types can be read as integers"));
+        }
+        let stride = if attribute.stride == 0 {
+            bytes
+        } else {
+            attribute.stride as u64
+        };
+        let last = (vertex_data.total_vertices as u64).saturating_sub(1) * stride;
+        let end = attribute.data_offset as u64 + last + bytes;
+        if vertex_data.total_vertices > 0 && end > vertices.len() as u64 {
+            return Err(invalid("reads past the end of the vertex data"));
+        }
+    }
+    Ok(())
+}
 
-    let mut _comment_chunk: Option<String> = None;
-    let mut _data_chunk: Option<Data> = None;
-    let mut _index_data_chunk: Option<IndexData> = None;
-    let mut sub_objects_chunk: Option<Vec<SubObject>> = None;
+pub fn parse_mesh(input: &[u8]) -> Result<Mesh, SbmError> {
+    let mut sub_objects_chunk: Option<(usize, Vec<SubObject>)> = None;
     let mut vertex_attributes_chunk: Option<Vec<VertexAttribute>> = None;
     let mut vertex_data_chunk: Option<VertexData> = None;
+    let mut index_data_chunk: Option<(usize, IndexData)> = None;
+    let mut data_chunks: Vec<(usize, Data)> = Vec::new();
+    let mut comments: Vec<String> = Vec::new();
 
-    for chunk in chunks {
+    for (offset, chunk) in parse_chunks(input)? {
         match chunk {
-            ChunkType::Comment(comment) => _comment_chunk = Some(comment),
-            ChunkType::Data(data) => _data_chunk = Some(data),
-            ChunkType::IndexData(index_data) => _index_data_chunk = Some(index_data),
-            ChunkType::SubObjects(sub_objects) => sub_objects_chunk = Some(sub_objects),
+            ChunkType::Comment(comment) => comments.push(comment),
+            ChunkType::Data(data) => data_chunks.push((offset, data)),
+            ChunkType::IndexData(index_data) => index_data_chunk = Some((offset, index_data)),
+            ChunkType::SubObjects(sub_objects) => sub_objects_chunk = Some((offset, sub_objects)),
             ChunkType::VertexAttributes(attributes) => vertex_attributes_chunk = Some(attributes),
             ChunkType::VertexData(vertex_data) => vertex_data_chunk = Some(vertex_data),
         }
     }
 
+    let vertex_data = vertex_data_chunk.ok_or(SbmError::MissingChunk(VERTEX_DATA_TAG))?;
+    let vertex_attributes =
+        vertex_attributes_chunk.ok_or(SbmError::MissingChunk(VERTEX_ATTRIBUTES_TAG))?;
+    let vertices = payload(
+        input,
+        &data_chunks,
+        VERTEX_DATA_TAG,
+        vertex_data.data_offset,
+        vertex_data.data_size as u64,
+    )?;
+    validate_attributes(&vertex_attributes, &vertex_data, vertices)?;
+
+    let indices = match index_data_chunk.as_ref() {
+        Some((offset, index_data)) => {
+            let index_bytes = index_bytes(index_data.index_type).unwrap_or(1) as u64;
+            let bytes = payload(
+                input,
+                &data_chunks,
+                INDEX_DATA_TAG,
+                index_data.index_data_offset,
+                index_data.index_count as u64 * index_bytes,
+            )?;
+            let indices = indices_from_le_bytes(index_data.index_type, bytes);
+            if indices
+                .values()
+                .iter()
+                .any(|index| *index >= vertex_data.total_vertices)
+            {
+                return Err(SbmError::InvalidChunk {
+                    chunk: INDEX_DATA_TAG.to_string(),
+                    offset: *offset,
+                    reason: "index refers past the last vertex",
+                });
+            }
+            Some(indices)
+        }
+        None => None,
+    };
+
+    let total_elements = index_data_chunk
+        .as_ref()
+        .map_or(vertex_data.total_vertices, |(_, index_data)| {
+            index_data.index_count
+        });
+    let sub_objects = match sub_objects_chunk {
+        Some((offset, sub_objects)) => {
+            if sub_objects
+                .iter()
+                .any(|object| object.first as u64 + object.count as u64 > total_elements as u64)
+            {
+                return Err(SbmError::InvalidChunk {
+                    chunk: SUB_OBJECT_LIST_TAG.to_string(),
+                    offset,
+                    reason: "sub-object draws past the end of the vertex or index data",
+                });
+            }
+            sub_objects
+        }
+        None => vec![SubObject {
+            first: 0,
+            count: total_elements,
+        }],
+    };
+
+    // Attributes are copied out of the vertex data into a stream each
+    let attributes = vertex_attributes
+        .iter()
+        .map(|attribute| {
+            let bytes = attribute_bytes(attribute).unwrap_or(0) as usize;
+            let stride = match attribute.stride {
+                0 => bytes,
+                stride => stride as usize,
+            };
+            let mut values = Vec::with_capacity(bytes * vertex_data.total_vertices as usize);
+            for vertex in 0..vertex_data.total_vertices as usize {
+                let start = attribute.data_offset as usize + vertex * stride;
+                values.extend_from_slice(&vertices[start..start + bytes]);
+            }
+            MeshAttribute {
+                name: attribute.name.clone(),
+                components: attribute.size,
+                normalized: attribute.flags & VERTEX_ATTRIB_FLAG_NORMALIZED != 0,
+                integer: attribute.flags & VERTEX_ATTRIB_FLAG_INTEGER != 0,
+                stream: VertexStream::from_le_bytes(attribute.attribute_type, &values)
+                    .unwrap_or(VertexStream::UnsignedByte(values)),
+            }
+        })
+        .collect();
+
+    Ok(Mesh {
+        attributes,
+        vertex_count: vertex_data.total_vertices,
+        indices,
+        sub_objects,
+        comment: if comments.is_empty() {
+            None
+        } else {
+            Some(comments.join("\n"))
+        },
+    })
+}
+
+pub fn load_mesh_file<P: AsRef<Path>>(path: P) -> Result<Mesh, SbmError> {
+    let path = path.as_ref();
+    let input = fs::read(path).map_err(|error| SbmError::Io {
+        kind: error.kind(),
+        reason: format!("{}: {}", path.display(), error),
+    })?;
+    parse_mesh(&input)
+}
+
+pub fn load_mesh_asset<P: AsRef<Path>>(path: P) -> Result<Mesh, SbmError> {
+    load_mesh_file(asset_path(path))
+}
+
+pub fn parse_object(input: &[u8]) -> Result<Object, SbmError> {
+    parse_mesh(input).map(|mesh| prepare_object(&mesh))
+}
+
+// Each attribute's stream follows the last, aligned to its component size or four bytes
+fn pack_streams(mesh: &Mesh) -> (Vec<u8>, Vec<usize>) {
+    let mut vertices = Vec::new();
+    let mut offsets = Vec::new();
+    for attribute in mesh.attributes.iter() {
+        let alignment = if attribute.stream.gl_type() == gl::DOUBLE {
+            8
+        } else {
+            4
+        };
+        vertices.resize(vertices.len().div_ceil(alignment) * alignment, 0);
+        offsets.push(vertices.len());
+        vertices.extend(attribute.stream.to_le_bytes());
+    }
+    (vertices, offsets)
+}
+
+pub fn prepare_object(mesh: &Mesh) -> Object {
+    let (vertices, offsets) = pack_streams(mesh);
+
     let mut vao = 0;
     let mut vbo = 0;
+    let mut ebo = 0;
 
     unsafe {
         gl::GenVertexArrays(1, &mut vao);
         gl::BindVertexArray(vao);
     }
 
-    let mut data_size = 0;
-
-    if vertex_data_chunk.is_some() {
-        let vertex_data = vertex_data_chunk.as_ref().unwrap();
-        data_size += vertex_data.data_size;
-    }
-
     unsafe {
         gl::GenBuffers(1, &mut vbo);
         gl::BindBuffer(gl::ARRAY_BUFFER, vbo);
-        gl::BufferData(gl::ARRAY_BUFFER, data_size, ptr::null(), gl::STATIC_DRAW);
+        gl::BufferData(
+            gl::ARRAY_BUFFER,
+            vertices.len() as GLsizeiptr,
+            ptr::null(),
+            gl::STATIC_DRAW,
+        );
+        gl::BufferSubData(
+            gl::ARRAY_BUFFER,
+            0,
+            vertices.len() as GLsizeiptr,
+            vertices.as_ptr() as *const gl::types::GLvoid,
+        );
     }
 
-    if vertex_data_chunk.is_some() {
-        let vertex_data = vertex_data_chunk.as_ref().unwrap();
+    // The element buffer stays attached to the vertex array
+    if let Some(indices) = mesh.indices.as_ref() {
+        let indices = indices.to_le_bytes();
         unsafe {
-            gl::BufferSubData(
-                gl::ARRAY_BUFFER,
-                0,
-                vertex_data.data_size,
-                vertex_data.vertices.as_ptr() as *const gl::types::GLvoid,
+            gl::GenBuffers(1, &mut ebo);
+            gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, ebo);
+            gl::BufferData(
+                gl::ELEMENT_ARRAY_BUFFER,
+                indices.len() as GLsizeiptr,
+                indices.as_ptr() as *const gl::types::GLvoid,
+                gl::STATIC_DRAW,
             );
         }
     }
 
-    let vertex_attributes = vertex_attributes_chunk.as_ref().unwrap();
-    for (index, attribute) in vertex_attributes.iter().enumerate() {
+    for (index, (attribute, offset)) in mesh.attributes.iter().zip(offsets).enumerate() {
+        let offset = offset as *const gl::types::GLvoid;
         unsafe {
-            gl::VertexAttribPointer(
-                index as u32,
-                attribute.size as i32,
-                attribute.attribute_type,
-                attribute.is_normalized(),
-                attribute.stride as i32,
-                attribute.data_offset as *const gl::types::GLvoid,
-            );
+            if attribute.integer {
+                gl::VertexAttribIPointer(
+                    index as u32,
+                    attribute.components as i32,
+                    attribute.stream.gl_type(),
+                    0,
+                    offset,
+                );
+            } else {
+                gl::VertexAttribPointer(
+                    index as u32,
+                    attribute.components as i32,
+                    attribute.stream.gl_type(),
+                    attribute.normalized as u8,
+                    0,
+                    offset,
+                );
+            }
             gl::EnableVertexAttribArray(index as u32);
         }
     }
@@ -371,22 +891,13 @@ fn prepare_object(chunks: Vec<ChunkType>) -> Object {
         gl::BindBuffer(gl::ELEMENT_ARRAY_BUFFER, 0);
     }
 
-    let sub_objects = if sub_objects_chunk.is_none() {
-        let mut sub_object_list = Vec::new();
-        let vertex_data = vertex_data_chunk.as_ref().unwrap();
-        sub_object_list.push(SubObject {
-            first: 0,
-            count: vertex_data.total_vertices,
-        });
-        sub_object_list
-    } else {
-        sub_objects_chunk.unwrap()
-    };
-
     Object {
         vbo,
+        ebo,
         vao,
-        sub_objects,
+        index_type: mesh.indices.as_ref().map(Indices::gl_type),
+        sub_objects: mesh.sub_objects.clone(),
+        comment: mesh.comment.clone(),
     }
 }
 
@@ -397,14 +908,29 @@ pub fn render_all(object: &Object) {
 }
 
 pub fn render_object(object: &Object, index: u32, instance_count: u32, base_instance: u32) {
+    let sub_object = &object.sub_objects[index as usize];
     unsafe {
         gl::BindVertexArray(object.vao);
-        gl::DrawArraysInstancedBaseInstance(
-            gl::TRIANGLES,
-            object.sub_objects[index as usize].first as i32,
-            object.sub_objects[index as usize].count as i32,
-            instance_count as i32,
-            base_instance,
-        );
+        match object.index_type {
+            Some(index_type) => {
+                let first = sub_object.first as usize * index_bytes(index_type).unwrap_or(1);
+                gl::DrawElementsInstancedBaseVertexBaseInstance(
+                    gl::TRIANGLES,
+                    sub_object.count as i32,
+                    index_type,
+                    first as *const gl::types::GLvoid,
+                    instance_count as i32,
+                    0,
+                    base_instance,
+                );
+            }
+            None => gl::DrawArraysInstancedBaseInstance(
+                gl::TRIANGLES,
+                sub_object.first as i32,
+                sub_object.count as i32,
+                instance_count as i32,
+                base_instance,
+            ),
+        }
     }
 }
@@ -0,0 +1,186 @@
+use super::{
+    pack_streams, Mesh, MeshAttribute, VertexStream, CHUNK_BYTES, COMMENT_TAG, HEADER_BYTES,
+    HEADER_TAG, INDEX_DATA_TAG, SUB_OBJECT_LIST_TAG, VERTEX_ATTRIBUTES_TAG,
+    VERTEX_ATTRIBUTE_NAME_BYTES, VERTEX_ATTRIB_FLAG_INTEGER, VERTEX_ATTRIB_FLAG_NORMALIZED,
+    VERTEX_DATA_TAG,
+};
+use std::io::{self, Write};
+
+// Writes the chunks the loader reads, with the vertex data and then the indices after the
+// last chunk. Streams are written one after another rather than interleaved, and sub-objects
+// are always listed so that an empty list survives being read back.
+pub fn write_sbm<W: Write>(mesh: &Mesh, output: &mut W) -> io::Result<()> {
+    validate(mesh)?;
+
+    let (vertices, offsets) = pack_streams(mesh);
+    let indices = mesh.indices.as_ref().map(|indices| indices.to_le_bytes());
+
+    let mut chunks = Vec::new();
+    if let Some(comment) = mesh.comment.as_ref() {
+        // Null terminated and padded to four bytes
+        let mut text = comment.as_bytes().to_vec();
+        text.resize((text.len() + 1).div_ceil(4) * 4, 0);
+        chunks.push((COMMENT_TAG, text));
+    }
+
+    let mut sub_objects = words(&[mesh.sub_objects.len() as u32]);
+    for sub_object in mesh.sub_objects.iter() {
+        sub_objects.extend(words(&[sub_object.first, sub_object.count]));
+    }
+    chunks.push((SUB_OBJECT_LIST_TAG, sub_objects));
+
+    let mut attributes = words(&[mesh.attributes.len() as u32]);
+    for (attribute, offset) in mesh.attributes.iter().zip(offsets) {
+        let mut name = attribute.name.as_bytes().to_vec();
+        name.resize(VERTEX_ATTRIBUTE_NAME_BYTES as usize, 0);
+        attributes.extend(name);
+        let mut flags = 0;
+        if attribute.normalized {
+            flags |= VERTEX_ATTRIB_FLAG_NORMALIZED;
+        }
+        if attribute.integer {
+            flags |= VERTEX_ATTRIB_FLAG_INTEGER;
+        }
+        attributes.extend(words(&[
+            attribute.components,
+            attribute.stream.gl_type(),
+            0,
+            flags,
+            offset as u32,
+        ]));
+    }
+    chunks.push((VERTEX_ATTRIBUTES_TAG, attributes));
+
+    // Payload offsets are from the start of the file, so they're known once every chunk is
+    let chunk_bytes = |fields: usize| CHUNK_BYTES + fields * 4;
+    let vertex_offset = HEADER_BYTES
+        + chunks
+            .iter()
+            .map(|(_, body)| CHUNK_BYTES + body.len())
+            .sum::<usize>()
+        + chunk_bytes(3)
+        + if indices.is_some() { chunk_bytes(3) } else { 0 };
+    let index_offset = vertex_offset + vertices.len();
+    chunks.push((
+        VERTEX_DATA_TAG,
+        words(&[
+            vertices.len() as u32,
+            vertex_offset as u32,
+            mesh.vertex_count,
+        ]),
+    ));
+    if let Some(mesh_indices) = mesh.indices.as_ref() {
+        chunks.push((
+            INDEX_DATA_TAG,
+            words(&[
+                mesh_indices.gl_type(),
+                mesh_indices.len() as u32,
+                index_offset as u32,
+            ]),
+        ));
+    }
+
+    if index_offset + indices.as_ref().map_or(0, Vec::len) > u32::MAX as usize {
+        return Err(invalid_input(
+            "the mesh is too large for the offsets of an SBM file",
+        ));
+    }
+
+    output.write_all(HEADER_TAG.as_bytes())?;
+    output.write_all(&words(&[HEADER_BYTES as u32, chunks.len() as u32, 0]))?;
+    for (tag, body) in chunks.iter() {
+        output.write_all(tag.as_bytes())?;
+        output.write_all(&((CHUNK_BYTES + body.len()) as u32).to_le_bytes())?;
+        output.write_all(body)?;
+    }
+    output.write_all(&vertices)?;
+    if let Some(indices) = indices {
+        output.write_all(&indices)?;
+    }
+    Ok(())
+}
+
+fn words(values: &[u32]) -> Vec<u8> {
+    values
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+// The number of values each vertex has in the attribute's stream
+fn values_per_vertex(attribute: &MeshAttribute) -> Option<usize> {
+    let components = attribute.components as usize;
+    match attribute.stream {
+        VertexStream::Int2_10_10_10Rev(_) | VertexStream::UnsignedInt2_10_10_10Rev(_) => {
+            Some(1).filter(|_| components == 4)
+        }
+        VertexStream::UnsignedInt10F11F11FRev(_) => Some(1).filter(|_| components == 3),
+        _ => Some(components).filter(|components| (1..=4).contains(components)),
+    }
+}
+
+// Meshes are checked against what the loader accepts, so written files can be read back
+fn validate(mesh: &Mesh) -> io::Result<()> {
+    for attribute in mesh.attributes.iter() {
+        let name = &attribute.name;
+        if name.len() > VERTEX_ATTRIBUTE_NAME_BYTES as usize {
+            return Err(invalid_input(format!(
+                "attribute name {} is longer than {} bytes",
+                name, VERTEX_ATTRIBUTE_NAME_BYTES
+            )));
+        }
+        let per_vertex = values_per_vertex(attribute).ok_or_else(|| {
+            invalid_input(format!(
+                "attribute {} can't have {} components of its type",
+                name, attribute.components
+            ))
+        })?;
+        let values = attribute.stream.len();
+        if values != per_vertex * mesh.vertex_count as usize {
+            return Err(invalid_input(format!(
+                "attribute {} has {} values but {} vertices need {}",
+                name,
+                values,
+                mesh.vertex_count,
+                per_vertex * mesh.vertex_count as usize
+            )));
+        }
+        if attribute.integer && !attribute.stream.is_integer() {
+            return Err(invalid_input(format!(
+                "attribute {} can't be read as integers",
+                name
+            )));
+        }
+    }
+
+    if let Some(indices) = mesh.indices.as_ref() {
+        if let Some(index) = indices
+            .values()
+            .into_iter()
+            .find(|index| *index >= mesh.vertex_count)
+        {
+            return Err(invalid_input(format!(
+                "index {} refers past the last of {} vertices",
+                index, mesh.vertex_count
+            )));
+        }
+    }
+
+    let elements = mesh
+        .indices
+        .as_ref()
+        .map_or(mesh.vertex_count as usize, |indices| indices.len());
+    for (index, sub_object) in mesh.sub_objects.iter().enumerate() {
+        if sub_object.first as usize + sub_object.count as usize > elements {
+            return Err(invalid_input(format!(
+                "sub-object {} draws past the end of the {} vertices or indices",
+                index, elements
+            )));
+        }
+    }
+    Ok(())
+}
+
+fn invalid_input<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, error)
+}
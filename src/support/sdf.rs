@@ -0,0 +1,183 @@
+use crate::{
+    image::{texture_from_images, Image},
+    ktx::KtxData,
+};
+use anyhow::{ensure, Result};
+
+// Squared distance standing in for "no feature", finite so the envelope math stays exact
+const FAR: f64 = 1e20;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DistanceFieldOptions {
+    // The size of the field, no larger than the source, or None to keep the source size
+    pub size: Option<(u32, u32)>,
+    // The distance in field texels at which values reach 0 inside the shape and 1 outside
+    pub spread: f32,
+    // Source values at or above this are inside the shape
+    pub threshold: u8,
+}
+
+impl Default for DistanceFieldOptions {
+    fn default() -> Self {
+        DistanceFieldOptions {
+            size: None,
+            spread: 4.0,
+            threshold: 128,
+        }
+    }
+}
+
+// A single channel distance field in the book's convention, 0.5 on the edge, darker
+// inside the shape and brighter outside. Images with alpha are thresholded on it,
+// anything else on its first channel.
+pub fn generate_distance_field(image: &Image, options: &DistanceFieldOptions) -> Result<Image> {
+    let (width, height) = options.size.unwrap_or((image.width, image.height));
+    ensure!(
+        width > 0 && height > 0,
+        "distance fields need at least one texel"
+    );
+    ensure!(
+        width <= image.width && height <= image.height,
+        "a {}x{} distance field cannot be made from a smaller {}x{} image",
+        width,
+        height,
+        image.width,
+        image.height
+    );
+    ensure!(
+        options.spread > 0.0,
+        "the spread must be positive, not {}",
+        options.spread
+    );
+
+    let components = image.components as usize;
+    let channel = if components.is_multiple_of(2) {
+        components - 1
+    } else {
+        0
+    };
+    let inside: Vec<bool> = image
+        .data
+        .chunks_exact(components)
+        .map(|texel| texel[channel] >= options.threshold)
+        .collect();
+    let distances = signed_distances(&inside, image.width as usize, image.height as usize);
+
+    // Each field texel averages the source texels whose centers fall in its footprint
+    let scale_x = image.width as f64 / width as f64;
+    let scale_y = image.height as f64 / height as f64;
+    let texel_size = (scale_x + scale_y) / 2.0;
+    let footprint = |index: u32, scale: f64| {
+        let first = (index as f64 * scale - 0.5).ceil() as usize;
+        let last = ((index + 1) as f64 * scale - 0.5).ceil() as usize;
+        first..last.max(first + 1)
+    };
+    let mut data = Vec::with_capacity(width as usize * height as usize);
+    for y in 0..height {
+        for x in 0..width {
+            let (rows, columns) = (footprint(y, scale_y), footprint(x, scale_x));
+            let count = (rows.len() * columns.len()) as f64;
+            let sum: f64 = rows
+                .flat_map(|row| {
+                    let start = row * image.width as usize;
+                    distances[start + columns.start..start + columns.end].iter()
+                })
+                .sum();
+            let distance = sum / count / texel_size;
+            let value = 0.5 + distance / (2.0 * options.spread as f64);
+            data.push((value.clamp(0.0, 1.0) * 255.0).round() as u8);
+        }
+    }
+    Image::new(width, height, 1, data)
+}
+
+// An R8 texture of the fields of every image, as a 2D array when there is more than one,
+// such as for a set of glyphs
+pub fn distance_field_texture(
+    images: &[Image],
+    options: &DistanceFieldOptions,
+) -> Result<KtxData<'static>> {
+    let fields = images
+        .iter()
+        .map(|image| generate_distance_field(image, options))
+        .collect::<Result<Vec<_>>>()?;
+    let array_elements = if fields.len() > 1 { fields.len() } else { 0 };
+    texture_from_images(&fields, array_elements as u32, 1, false)
+}
+
+// Exact Euclidean distance in texels from each texel center to the edge, taken as halfway
+// between neighboring inside and outside texels. Negative inside the shape.
+fn signed_distances(inside: &[bool], width: usize, height: usize) -> Vec<f64> {
+    let to_inside = squared_distances(inside, true, width, height);
+    let to_outside = squared_distances(inside, false, width, height);
+    inside
+        .iter()
+        .zip(to_inside.iter().zip(to_outside.iter()))
+        .map(|(inside, (to_inside, to_outside))| {
+            if *inside {
+                0.5 - to_outside.sqrt()
+            } else {
+                to_inside.sqrt() - 0.5
+            }
+        })
+        .collect()
+}
+
+// Squared distance to the nearest texel matching feature, transforming columns then rows
+// with the lower envelope of parabolas from Felzenszwalb and Huttenlocher
+fn squared_distances(inside: &[bool], feature: bool, width: usize, height: usize) -> Vec<f64> {
+    let mut grid: Vec<f64> = inside
+        .iter()
+        .map(|inside| if *inside == feature { 0.0 } else { FAR })
+        .collect();
+    let mut line = vec![0.0; width.max(height)];
+    let mut output = vec![0.0; width.max(height)];
+
+    for x in 0..width {
+        for y in 0..height {
+            line[y] = grid[y * width + x];
+        }
+        transform_line(&line[..height], &mut output[..height]);
+        for y in 0..height {
+            grid[y * width + x] = output[y];
+        }
+    }
+    for row in grid.chunks_exact_mut(width) {
+        transform_line(row, &mut output[..width]);
+        row.copy_from_slice(&output[..width]);
+    }
+    grid
+}
+
+fn transform_line(input: &[f64], output: &mut [f64]) {
+    let length = input.len();
+    let mut vertices = vec![0usize; length];
+    let mut boundaries = vec![0.0; length + 1];
+    let intersection = |q: usize, v: usize| {
+        ((input[q] + (q * q) as f64) - (input[v] + (v * v) as f64)) / (2.0 * (q as f64 - v as f64))
+    };
+
+    let mut k = 0;
+    boundaries[0] = f64::NEG_INFINITY;
+    boundaries[1] = f64::INFINITY;
+    for q in 1..length {
+        let mut s = intersection(q, vertices[k]);
+        while s <= boundaries[k] {
+            k -= 1;
+            s = intersection(q, vertices[k]);
+        }
+        k += 1;
+        vertices[k] = q;
+        boundaries[k] = s;
+        boundaries[k + 1] = f64::INFINITY;
+    }
+
+    k = 0;
+    for (q, value) in output.iter_mut().enumerate() {
+        while boundaries[k + 1] < q as f64 {
+            k += 1;
+        }
+        let offset = q as f64 - vertices[k] as f64;
+        *value = offset * offset + input[vertices[k]];
+    }
+}
@@ -1,4 +1,5 @@
 use crate::{ktx::*, shader::*};
+use anyhow::Result;
 
 #[derive(Default)]
 pub struct TextOverlay {
@@ -13,7 +14,7 @@ pub struct TextOverlay {
 }
 
 impl TextOverlay {
-    pub fn initialize(&mut self, width: i32, height: i32) {
+    pub fn initialize(&mut self, width: i32, height: i32) -> Result<()> {
         self.shader_program = ShaderProgram::new();
         self.shader_program
             .vertex_shader("assets/shaders/text-overlay/textoverlay.vs.glsl")
@@ -29,8 +30,8 @@ impl TextOverlay {
             gl::TexStorage2D(gl::TEXTURE_2D, 1, gl::R8UI, width, height);
         }
 
-        let (_, data) = load_ktx!("../../assets/textures/cp437_9x16.ktx").unwrap();
-        self.font_texture = prepare_texture(&data);
+        let data = load_ktx!("textures/cp437_9x16.ktx")?;
+        self.font_texture = prepare_texture(&data)?;
 
         self.buffer_width = width;
         self.buffer_height = height;
@@ -38,6 +39,8 @@ impl TextOverlay {
         let buffer_size = width as usize * height as usize;
         self.screen_buffer = Vec::with_capacity(buffer_size);
         self.screen_buffer.resize(buffer_size, ' ');
+
+        Ok(())
     }
 
     pub fn render(&mut self) {
@@ -0,0 +1,146 @@
+use std::borrow::Cow;
+use support::ktx::{
+    array::assemble_array, determine_target, load_ktx_file, mipmap::MipmapOptions, parse_ktx,
+    rgtc::compress_rgtc, writer::write_ktx, Header, KtxData, KtxError, MipLevel,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_RED: u32 = 0x1903;
+const GL_RGB: u32 = 0x1907;
+const GL_R8: u32 = 0x8229;
+const GL_RGB8: u32 = 0x8051;
+const GL_TEXTURE_2D_ARRAY: u32 = 0x8C1A;
+
+// A single level 2D texture of one image with rows at the given alignment
+fn layer(
+    gl_format: u32,
+    gl_internal_format: u32,
+    width: u32,
+    height: u32,
+    unpack_alignment: i32,
+    data: Vec<u8>,
+) -> KtxData<'static> {
+    let image = 0..data.len();
+    KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type: GL_UNSIGNED_BYTE,
+            gl_type_size: 1,
+            gl_format,
+            gl_internal_format,
+            gl_base_internal_format: gl_format,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: 0,
+            array_elements: 0,
+            faces: 1,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: vec![MipLevel {
+            width,
+            height,
+            depth: 1,
+            images: vec![image],
+        }],
+        unpack_alignment,
+        pixels: Cow::Owned(data),
+    }
+}
+
+fn rgb_layer(value: u8) -> KtxData<'static> {
+    layer(GL_RGB, GL_RGB8, 3, 2, 1, vec![value; 18])
+}
+
+#[test]
+fn assembles_layers() {
+    let layers: Vec<_> = (0..3).map(|index| rgb_layer(index * 50)).collect();
+    let array = assemble_array(&layers, None).unwrap();
+    assert_eq!(determine_target(&array), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(array.header.array_elements, 3);
+    assert_eq!(array.levels.len(), 1);
+    // Rows of 9 bytes are padded to 12
+    let mut expected = [100; 24];
+    expected[9..12].fill(0);
+    expected[21..].fill(0);
+    assert_eq!(array.image(0, 2, 0).unwrap(), &expected[..]);
+
+    let mut file = Vec::new();
+    write_ktx(&array, &mut file).unwrap();
+    let parsed = parse_ktx(&file).unwrap();
+    assert_eq!(parsed.header.array_elements, 3);
+    assert_eq!(parsed.image(0, 1, 0), array.image(0, 1, 0));
+
+    let mipmapped = assemble_array(&layers, Some(&MipmapOptions::default())).unwrap();
+    assert_eq!(mipmapped.header.mip_levels, 2);
+    assert_eq!(mipmapped.levels[1].images.len(), 3);
+    assert_eq!(&mipmapped.image(1, 1, 0).unwrap()[..3], &[50; 3]);
+}
+
+#[test]
+fn keeps_shared_levels() {
+    // Compressed layers that already have their mips are stacked as they are
+    let layers: Vec<_> = (0..2)
+        .map(|index| {
+            let data = (0..64).map(|texel| (texel * 4 + index) as u8).collect();
+            let texture = layer(GL_RED, GL_R8, 8, 8, 1, data);
+            compress_rgtc(&texture, &MipmapOptions::default())
+                .unwrap()
+                .texture
+        })
+        .collect();
+    let array = assemble_array(&layers, None).unwrap();
+    assert_eq!(array.header.mip_levels, 4);
+    assert_eq!(array.header.gl_type, 0);
+    for level in 0..4 {
+        for (index, layer) in layers.iter().enumerate() {
+            assert_eq!(
+                array.image(level, index as u32, 0),
+                layer.image(level, 0, 0)
+            );
+        }
+    }
+}
+
+#[test]
+fn rejects_mismatched_layers() {
+    let invalid = |layers: &[KtxData], layer: usize| match assemble_array(layers, None) {
+        Err(KtxError::InvalidLayer { layer: index, .. }) => assert_eq!(index, layer),
+        result => panic!("{:?}", result.map(|texture| texture.header)),
+    };
+    invalid(
+        &[rgb_layer(0), layer(GL_RED, GL_R8, 3, 2, 1, vec![0; 6])],
+        1,
+    );
+    invalid(
+        &[
+            rgb_layer(0),
+            rgb_layer(0),
+            layer(GL_RGB, GL_RGB8, 2, 3, 1, vec![0; 18]),
+        ],
+        2,
+    );
+    invalid(
+        &[rgb_layer(0), layer(GL_RGB, GL_RGB8, 3, 2, 4, vec![0; 18])],
+        1,
+    );
+
+    let mut mipmapped = rgb_layer(0);
+    let image = 0..3;
+    mipmapped.levels.push(MipLevel {
+        width: 1,
+        height: 1,
+        depth: 1,
+        images: vec![image],
+    });
+    invalid(&[rgb_layer(0), mipmapped], 1);
+
+    let fonts = load_ktx_file("assets/textures/cp437_9x16.ktx").unwrap();
+    invalid(&[fonts], 0);
+
+    assert_eq!(
+        assemble_array(&[], None).err(),
+        Some(KtxError::InvalidArgument("no layers to assemble"))
+    );
+}
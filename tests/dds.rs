@@ -0,0 +1,382 @@
+use std::{env, fs};
+use support::ktx::{
+    dds::parse_dds, determine_target, load_ktx_file, parse_ktx, writer::write_ktx, KtxError,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_RED: u32 = 0x1903;
+const GL_RGB: u32 = 0x1907;
+const GL_RGBA: u32 = 0x1908;
+const GL_BGR: u32 = 0x80E0;
+const GL_BGRA: u32 = 0x80E1;
+const GL_RGB8: u32 = 0x8051;
+const GL_RGBA8: u32 = 0x8058;
+const GL_R8: u32 = 0x8229;
+const GL_RGB565: u32 = 0x8D62;
+const GL_UNSIGNED_SHORT_5_6_5: u32 = 0x8363;
+const GL_RGBA16F: u32 = 0x881A;
+const GL_COMPRESSED_RGBA_S3TC_DXT1: u32 = 0x83F1;
+const GL_COMPRESSED_SRGB_ALPHA_BPTC_UNORM: u32 = 0x8E8D;
+const GL_TEXTURE_1D_ARRAY: u32 = 0x8C18;
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+const GL_TEXTURE_2D_ARRAY: u32 = 0x8C1A;
+const GL_TEXTURE_3D: u32 = 0x806F;
+const GL_TEXTURE_CUBE_MAP: u32 = 0x8513;
+const GL_TEXTURE_CUBE_MAP_ARRAY: u32 = 0x9009;
+
+// Offsets of the header fields the tests set
+const FLAGS: usize = 8;
+const HEIGHT: usize = 12;
+const WIDTH: usize = 16;
+const DEPTH: usize = 24;
+const MIP_MAP_COUNT: usize = 28;
+const PIXEL_FLAGS: usize = 80;
+const FOUR_CC: usize = 84;
+const BIT_COUNT: usize = 88;
+const RED_MASK: usize = 92;
+const GREEN_MASK: usize = 96;
+const BLUE_MASK: usize = 100;
+const ALPHA_MASK: usize = 104;
+const CAPS2: usize = 112;
+
+const DDPF_ALPHAPIXELS: u32 = 0x1;
+const DDPF_FOURCC: u32 = 0x4;
+const DDPF_RGB: u32 = 0x40;
+const DDPF_LUMINANCE: u32 = 0x2_0000;
+const DDSD_DEPTH: u32 = 0x80_0000;
+const CUBE_MAP_ALL_FACES: u32 = 0xFE00;
+const VOLUME: u32 = 0x20_0000;
+
+fn four_cc(code: &[u8; 4]) -> u32 {
+    u32::from_le_bytes(*code)
+}
+
+// A file with the given header fields, an optional DX10 header of format, dimension, misc
+// flags and array size, and its data
+fn dds(fields: &[(usize, u32)], dx10: Option<[u32; 4]>, data: &[u8]) -> Vec<u8> {
+    let mut file = vec![0; 128];
+    file[..4].copy_from_slice(b"DDS ");
+    let mut set = |offset: usize, value: u32| {
+        file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    };
+    set(4, 124);
+    set(76, 32);
+    for (offset, value) in fields.iter() {
+        set(*offset, *value);
+    }
+    if let Some([format, dimension, misc, array_size]) = dx10 {
+        set(PIXEL_FLAGS, DDPF_FOURCC);
+        set(FOUR_CC, four_cc(b"DX10"));
+        for value in [format, dimension, misc, array_size, 0].iter() {
+            file.extend_from_slice(&value.to_le_bytes());
+        }
+    }
+    file.extend_from_slice(data);
+    file
+}
+
+// Images filled with their index, each holding its whole mip chain as DDS stores them
+fn chains(images: usize, level_bytes: &[usize]) -> Vec<u8> {
+    let mut data = Vec::new();
+    for image in 0..images {
+        for (level, bytes) in level_bytes.iter().enumerate() {
+            data.extend(std::iter::repeat_n((image * 16 + level) as u8, *bytes));
+        }
+    }
+    data
+}
+
+#[test]
+fn reads_legacy_bit_mask_formats() {
+    let bgra = [
+        (PIXEL_FLAGS, DDPF_RGB | DDPF_ALPHAPIXELS),
+        (BIT_COUNT, 32),
+        (RED_MASK, 0xFF_0000),
+        (GREEN_MASK, 0xFF00),
+        (BLUE_MASK, 0xFF),
+        (ALPHA_MASK, 0xFF00_0000),
+    ];
+    let size = [(WIDTH, 4), (HEIGHT, 2), (MIP_MAP_COUNT, 3)];
+    let data: Vec<u8> = (0..44).collect();
+    let texture = parse_dds(&dds(&[&bgra[..], &size[..]].concat(), None, &data)).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_2D));
+    let ktx = &texture.header;
+    assert_eq!(
+        (ktx.gl_type, ktx.gl_format, ktx.gl_internal_format),
+        (GL_UNSIGNED_BYTE, GL_BGRA, GL_RGBA8)
+    );
+    assert_eq!(ktx.gl_base_internal_format, GL_RGBA);
+    assert_eq!(ktx.mip_levels, 3);
+    let dimensions: Vec<_> = texture
+        .levels
+        .iter()
+        .map(|mip| (mip.width, mip.height, mip.depth))
+        .collect();
+    assert_eq!(dimensions, [(4, 2, 1), (2, 1, 1), (1, 1, 1)]);
+    assert_eq!(texture.image(0, 0, 0), Some(&data[..32]));
+    assert_eq!(texture.image(2, 0, 0), Some(&data[40..]));
+
+    // Alpha masks count only with the alpha flag, making X8R8G8B8 opaque
+    let opaque = [
+        (PIXEL_FLAGS, DDPF_RGB),
+        (BIT_COUNT, 32),
+        (RED_MASK, 0xFF_0000),
+        (GREEN_MASK, 0xFF00),
+        (BLUE_MASK, 0xFF),
+        (ALPHA_MASK, 0xFF00_0000),
+        (WIDTH, 1),
+        (HEIGHT, 1),
+    ];
+    let texture = parse_dds(&dds(&opaque, None, &[0; 4])).unwrap();
+    assert_eq!(
+        (texture.header.gl_format, texture.header.gl_internal_format),
+        (GL_BGRA, GL_RGB8)
+    );
+    assert_eq!(texture.header.gl_base_internal_format, GL_RGB);
+
+    let rgb565 = [
+        (PIXEL_FLAGS, DDPF_RGB),
+        (BIT_COUNT, 16),
+        (RED_MASK, 0xF800),
+        (GREEN_MASK, 0x7E0),
+        (BLUE_MASK, 0x1F),
+        (WIDTH, 2),
+        (HEIGHT, 2),
+    ];
+    let texture = parse_dds(&dds(&rgb565, None, &[0; 8])).unwrap();
+    assert_eq!(
+        (texture.header.gl_type, texture.header.gl_internal_format),
+        (GL_UNSIGNED_SHORT_5_6_5, GL_RGB565)
+    );
+
+    let luminance = [
+        (PIXEL_FLAGS, DDPF_LUMINANCE),
+        (BIT_COUNT, 8),
+        (RED_MASK, 0xFF),
+        (WIDTH, 2),
+        (HEIGHT, 2),
+    ];
+    let texture = parse_dds(&dds(&luminance, None, &[1, 2, 3, 4])).unwrap();
+    assert_eq!(
+        (texture.header.gl_format, texture.header.gl_internal_format),
+        (GL_RED, GL_R8)
+    );
+}
+
+#[test]
+fn keeps_rows_tightly_packed() {
+    // Three BGR texels are nine bytes a row, which KTX files pad to twelve
+    let bgr = [
+        (PIXEL_FLAGS, DDPF_RGB),
+        (BIT_COUNT, 24),
+        (RED_MASK, 0xFF_0000),
+        (GREEN_MASK, 0xFF00),
+        (BLUE_MASK, 0xFF),
+        (WIDTH, 3),
+        (HEIGHT, 2),
+    ];
+    let data: Vec<u8> = (0..18).collect();
+    let texture = parse_dds(&dds(&bgr, None, &data)).unwrap();
+    assert_eq!(texture.header.gl_format, GL_BGR);
+    assert_eq!(texture.unpack_alignment, 1);
+    assert_eq!(texture.image(0, 0, 0), Some(&data[..]));
+
+    let mut file = Vec::new();
+    write_ktx(&texture, &mut file).unwrap();
+    let parsed = parse_ktx(&file).unwrap();
+    let rows: Vec<_> = parsed.image(0, 0, 0).unwrap().chunks(12).collect();
+    assert_eq!(rows[0][..9], data[..9]);
+    assert_eq!(rows[1][..9], data[9..]);
+}
+
+#[test]
+fn reorders_cube_maps_into_levels() {
+    // Each face holds an 8x8 and a 4x4 level, four blocks and one
+    let fields = [
+        (PIXEL_FLAGS, DDPF_FOURCC),
+        (FOUR_CC, four_cc(b"DXT1")),
+        (WIDTH, 8),
+        (HEIGHT, 8),
+        (MIP_MAP_COUNT, 2),
+        (CAPS2, CUBE_MAP_ALL_FACES),
+    ];
+    let texture = parse_dds(&dds(&fields, None, &chains(6, &[32, 8]))).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_CUBE_MAP));
+    assert_eq!(
+        texture.header.gl_internal_format,
+        GL_COMPRESSED_RGBA_S3TC_DXT1
+    );
+    for level in 0..2 {
+        for face in 0..6 {
+            let image = texture.image(level, 0, face).unwrap();
+            assert_eq!(image.len(), if level == 0 { 32 } else { 8 });
+            let expected = (face * 16 + level) as u8;
+            assert!(image.iter().all(|byte| *byte == expected));
+        }
+        // Every face of a level sits together, as prepare_texture uploads them
+        let level_data = texture.level_data(level).unwrap();
+        assert_eq!(
+            level_data.len(),
+            texture.image(level, 0, 0).unwrap().len() * 6
+        );
+    }
+}
+
+#[test]
+fn reads_dx10_arrays_volumes_and_1d_textures() {
+    let square = [(WIDTH, 8), (HEIGHT, 8), (MIP_MAP_COUNT, 2)];
+
+    let bc7 = parse_dds(&dds(&square, Some([99, 3, 0, 3]), &chains(3, &[64, 16]))).unwrap();
+    assert_eq!(determine_target(&bc7), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(
+        bc7.header.gl_internal_format,
+        GL_COMPRESSED_SRGB_ALPHA_BPTC_UNORM
+    );
+    assert_eq!(bc7.header.array_elements, 3);
+    assert_eq!(bc7.image(1, 2, 0), Some(&[2 * 16 + 1; 16][..]));
+
+    let cubes = parse_dds(&dds(&square, Some([71, 3, 4, 2]), &chains(12, &[32, 8]))).unwrap();
+    assert_eq!(determine_target(&cubes), Ok(GL_TEXTURE_CUBE_MAP_ARRAY));
+    assert_eq!(
+        (cubes.header.array_elements, cubes.header.face_count()),
+        (2, 6)
+    );
+    assert_eq!(cubes.image(1, 1, 3), Some(&[9 * 16 + 1; 8][..]));
+
+    let volume_fields = [(WIDTH, 4), (HEIGHT, 4), (DEPTH, 2), (MIP_MAP_COUNT, 2)];
+    let volume = parse_dds(&dds(
+        &volume_fields,
+        Some([10, 4, 0, 1]),
+        &chains(1, &[256, 32]),
+    ))
+    .unwrap();
+    assert_eq!(determine_target(&volume), Ok(GL_TEXTURE_3D));
+    assert_eq!(volume.header.gl_internal_format, GL_RGBA16F);
+    assert_eq!((volume.levels[1].width, volume.levels[1].depth), (2, 1));
+
+    let line = parse_dds(&dds(
+        &[(WIDTH, 5), (HEIGHT, 1)],
+        Some([61, 2, 0, 2]),
+        &[7; 10],
+    ))
+    .unwrap();
+    assert_eq!(determine_target(&line), Ok(GL_TEXTURE_1D_ARRAY));
+    assert_eq!(line.header.pixel_height, 0);
+    assert_eq!(line.image(0, 1, 0), Some(&[7; 5][..]));
+}
+
+#[test]
+fn loads_dds_wherever_ktx_is_loaded() {
+    let fields = [(WIDTH, 8), (HEIGHT, 8), (MIP_MAP_COUNT, 4)];
+    let file = dds(&fields, Some([71, 3, 0, 1]), &chains(1, &[32, 8, 8, 8]));
+    let path = env::temp_dir().join(format!("superbible-{}-load.dds", std::process::id()));
+    fs::write(&path, &file).unwrap();
+    let loaded = load_ktx_file(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    let parsed = parse_dds(&file).unwrap();
+    assert_eq!(
+        loaded.header.gl_internal_format,
+        parsed.header.gl_internal_format
+    );
+    assert_eq!(loaded.pixels, parsed.pixels);
+    assert_eq!(loaded.levels.len(), 4);
+}
+
+#[test]
+fn rejects_malformed_files() {
+    let bgra = [
+        (PIXEL_FLAGS, DDPF_RGB | DDPF_ALPHAPIXELS),
+        (BIT_COUNT, 32),
+        (RED_MASK, 0xFF_0000),
+        (GREEN_MASK, 0xFF00),
+        (BLUE_MASK, 0xFF),
+        (ALPHA_MASK, 0xFF00_0000),
+        (WIDTH, 2),
+        (HEIGHT, 2),
+    ];
+    assert!(parse_dds(&dds(&bgra, None, &[0; 16])).is_ok());
+
+    match parse_dds(&dds(&bgra, None, &[0; 15])) {
+        Err(KtxError::Truncated { section, .. }) => assert_eq!(section, "image data"),
+        result => panic!("{:?}", result.map(|texture| texture.header)),
+    }
+    assert!(matches!(
+        parse_dds(b"DD"),
+        Err(KtxError::Truncated { offset: 0, .. })
+    ));
+    assert_eq!(
+        parse_dds(b"KTX file").err(),
+        Some(KtxError::InvalidIdentifier)
+    );
+
+    let mut wrong_size = dds(&bgra, None, &[0; 16]);
+    wrong_size[4] = 100;
+    assert!(matches!(
+        parse_dds(&wrong_size),
+        Err(KtxError::InvalidHeaderField { field: "size", .. })
+    ));
+
+    let unknown = [(PIXEL_FLAGS, DDPF_FOURCC), (FOUR_CC, four_cc(b"ABCD"))];
+    let unknown = dds(&[&bgra[..], &unknown[..]].concat(), None, &[0; 16]);
+    assert_eq!(
+        parse_dds(&unknown).err(),
+        Some(KtxError::UnsupportedFormat(
+            "DDS pixel format without a GL equivalent"
+        ))
+    );
+
+    let partial_cube = dds(&[&bgra[..], &[(CAPS2, 0x0600)]].concat(), None, &[0; 96]);
+    assert_eq!(
+        parse_dds(&partial_cube).err(),
+        Some(KtxError::UnsupportedLayout("cube map without every face"))
+    );
+
+    let too_many_levels = dds(&[&bgra[..], &[(MIP_MAP_COUNT, 3)]].concat(), None, &[0; 24]);
+    assert!(matches!(
+        parse_dds(&too_many_levels),
+        Err(KtxError::InvalidHeaderField {
+            field: "mip_map_count",
+            ..
+        })
+    ));
+
+    // Typeless formats have no GL equivalent
+    let typeless = dds(&bgra[6..], Some([27, 3, 0, 1]), &[0; 16]);
+    assert!(matches!(
+        parse_dds(&typeless),
+        Err(KtxError::InvalidHeaderField {
+            field: "dxgi_format",
+            ..
+        })
+    ));
+    let volume_array = dds(
+        &[(WIDTH, 2), (HEIGHT, 2), (DEPTH, 2), (FLAGS, DDSD_DEPTH)],
+        Some([28, 4, 0, 2]),
+        &[0; 64],
+    );
+    assert!(matches!(
+        parse_dds(&volume_array),
+        Err(KtxError::InvalidHeaderField {
+            field: "array_size",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn reads_legacy_volumes() {
+    let fields = [
+        (PIXEL_FLAGS, DDPF_LUMINANCE),
+        (BIT_COUNT, 8),
+        (RED_MASK, 0xFF),
+        (WIDTH, 2),
+        (HEIGHT, 2),
+        (DEPTH, 4),
+        (FLAGS, DDSD_DEPTH),
+        (CAPS2, VOLUME),
+    ];
+    let texture = parse_dds(&dds(&fields, None, &[3; 16])).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_3D));
+    assert_eq!(texture.levels[0].depth, 4);
+}
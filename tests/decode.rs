@@ -0,0 +1,458 @@
+use std::borrow::Cow;
+use support::ktx::{
+    decode::{decode_blocks, decode_image, decompress, DecodedImage, Texels},
+    parse_ktx,
+    writer::write_ktx,
+    Header, KtxData, KtxError, MipLevel, COMPRESSED_RGBA_S3TC_DXT1, COMPRESSED_RGBA_S3TC_DXT3,
+    COMPRESSED_RGBA_S3TC_DXT5, COMPRESSED_RGB_S3TC_DXT1, COMPRESSED_SRGB_ALPHA_S3TC_DXT1,
+    ETC1_RGB8,
+};
+
+const GL_RGBA: u32 = 0x1908;
+const GL_FLOAT: u32 = 0x1406;
+const GL_RGBA8: u32 = 0x8058;
+const GL_RGBA32F: u32 = 0x8814;
+const GL_SRGB8_ALPHA8: u32 = 0x8C43;
+const GL_COMPRESSED_RED_RGTC1: u32 = 0x8DBB;
+const GL_COMPRESSED_SIGNED_RED_RGTC1: u32 = 0x8DBC;
+const GL_COMPRESSED_RG_RGTC2: u32 = 0x8DBD;
+const GL_COMPRESSED_SIGNED_RG_RGTC2: u32 = 0x8DBE;
+const GL_COMPRESSED_RGBA_BPTC_UNORM: u32 = 0x8E8C;
+const GL_COMPRESSED_RGB_BPTC_SIGNED_FLOAT: u32 = 0x8E8E;
+const GL_COMPRESSED_R11_EAC: u32 = 0x9270;
+const GL_COMPRESSED_SIGNED_R11_EAC: u32 = 0x9271;
+const GL_COMPRESSED_RG11_EAC: u32 = 0x9272;
+const GL_COMPRESSED_SIGNED_RG11_EAC: u32 = 0x9273;
+const GL_COMPRESSED_RGB8_ETC2: u32 = 0x9274;
+const GL_COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2: u32 = 0x9276;
+const GL_COMPRESSED_RGBA8_ETC2_EAC: u32 = 0x9278;
+
+fn unhex(text: &str) -> Vec<u8> {
+    (0..text.len())
+        .step_by(2)
+        .map(|index| u8::from_str_radix(&text[index..index + 2], 16).unwrap())
+        .collect()
+}
+
+fn rgba8(image: DecodedImage) -> Vec<u8> {
+    match image.texels {
+        Texels::Rgba8(texels) => texels,
+        Texels::Rgba32F(_) => panic!("expected bytes"),
+    }
+}
+
+fn rgba32f(image: DecodedImage) -> Vec<f32> {
+    match image.texels {
+        Texels::Rgba32F(texels) => texels,
+        Texels::Rgba8(_) => panic!("expected floats"),
+    }
+}
+
+fn block(format: u32, data: &[u8]) -> Vec<u8> {
+    rgba8(decode_blocks(format, 4, 4, 1, data).unwrap())
+}
+
+fn float_block(format: u32, data: &[u8]) -> Vec<f32> {
+    rgba32f(decode_blocks(format, 4, 4, 1, data).unwrap())
+}
+
+// Expected texels as hex, checked against an independent decoder
+fn assert_block(format: u32, data: &str, expected: &str) {
+    assert_eq!(
+        block(format, &unhex(data)),
+        unhex(expected),
+        "block {}",
+        data
+    );
+}
+
+// Packs (value, bits) fields from the least significant bit up, like BC7 stores them
+fn pack_bits(fields: &[(u32, u32)]) -> Vec<u8> {
+    let mut bits = 0u128;
+    let mut position = 0;
+    for (value, count) in fields {
+        bits |= (*value as u128) << position;
+        position += count;
+    }
+    assert_eq!(position, 128);
+    bits.to_le_bytes().to_vec()
+}
+
+// Compressed layers of a single face, each level given as the blocks of every layer
+fn compressed(
+    gl_internal_format: u32,
+    width: u32,
+    height: u32,
+    layers: u32,
+    levels: Vec<Vec<Vec<u8>>>,
+) -> KtxData<'static> {
+    let mut pixels = Vec::new();
+    let mut mips = Vec::new();
+    for (level, images) in levels.iter().enumerate() {
+        let mut ranges = Vec::new();
+        for image in images {
+            ranges.push(pixels.len()..pixels.len() + image.len());
+            pixels.extend_from_slice(image);
+        }
+        mips.push(MipLevel {
+            width: (width >> level).max(1),
+            height: (height >> level).max(1),
+            depth: 1,
+            images: ranges,
+        });
+    }
+    KtxData {
+        header: Header {
+            endianess: 0x0403_0201u32.to_ne_bytes(),
+            gl_type: 0,
+            gl_type_size: 1,
+            gl_format: 0,
+            gl_internal_format,
+            gl_base_internal_format: GL_RGBA,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: 0,
+            array_elements: layers,
+            faces: 1,
+            mip_levels: levels.len() as u32,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: mips,
+        unpack_alignment: 4,
+        pixels: Cow::Owned(pixels),
+    }
+}
+
+#[test]
+fn decodes_bc1_palettes() {
+    // Red and blue endpoints with each row stepping through all four indices
+    let four_colors = [0x00, 0xF8, 0x1F, 0x00, 0xE4, 0xE4, 0xE4, 0xE4];
+    let row = [
+        255, 0, 0, 255, 0, 0, 255, 255, 170, 0, 85, 255, 85, 0, 170, 255,
+    ];
+    for format in [COMPRESSED_RGB_S3TC_DXT1, COMPRESSED_RGBA_S3TC_DXT1].iter() {
+        assert_eq!(block(*format, &four_colors), row.repeat(4));
+    }
+
+    // Swapped endpoints pick three colors and black, transparent only with alpha
+    let three_colors = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+    let opaque = block(COMPRESSED_RGB_S3TC_DXT1, &three_colors);
+    assert_eq!(
+        &opaque[..16],
+        &[0, 0, 255, 255, 255, 0, 0, 255, 127, 0, 127, 255, 0, 0, 0, 255]
+    );
+    let transparent = block(COMPRESSED_RGBA_S3TC_DXT1, &three_colors);
+    assert_eq!(&transparent[12..16], &[0, 0, 0, 0]);
+    assert_eq!(&transparent[..12], &opaque[..12]);
+}
+
+#[test]
+fn decodes_explicit_and_interpolated_alpha() {
+    // BC2 and BC3 always use four colors, whatever the endpoint order
+    let color = [0x1F, 0x00, 0x00, 0xF8, 0xE4, 0xE4, 0xE4, 0xE4];
+    let explicit = [[0x10, 0x32, 0x54, 0x76, 0x98, 0xBA, 0xDC, 0xFE], color].concat();
+    let texels = block(COMPRESSED_RGBA_S3TC_DXT3, &explicit);
+    assert_eq!(&texels[8..12], &[85, 0, 170, 34]);
+    let alpha: Vec<u8> = texels.chunks(4).map(|texel| texel[3]).collect();
+    assert_eq!(alpha, (0..16).map(|step| step * 17).collect::<Vec<u8>>());
+
+    // Texels 0 to 7 take alpha indices 0 to 7, the rest index 0
+    let eight_values = [[255, 0, 0x88, 0xC6, 0xFA, 0, 0, 0], color].concat();
+    let texels = block(COMPRESSED_RGBA_S3TC_DXT5, &eight_values);
+    let alpha: Vec<u8> = texels.chunks(4).map(|texel| texel[3]).collect();
+    assert_eq!(
+        alpha,
+        [255, 0, 218, 182, 145, 109, 72, 36, 255, 255, 255, 255, 255, 255, 255, 255]
+    );
+    let six_values = [[0, 255, 0x88, 0xC6, 0xFA, 0, 0, 0], color].concat();
+    let texels = block(COMPRESSED_RGBA_S3TC_DXT5, &six_values);
+    let alpha: Vec<u8> = texels.chunks(4).take(8).map(|texel| texel[3]).collect();
+    assert_eq!(alpha, [0, 255, 51, 102, 153, 204, 0, 255]);
+}
+
+#[test]
+fn decodes_rgtc() {
+    let red = [0, 255, 0x88, 0xC6, 0xFA, 0, 0, 0];
+    let texels = block(GL_COMPRESSED_RED_RGTC1, &red);
+    assert_eq!(
+        &texels[..12],
+        &[0, 0, 0, 255, 255, 0, 0, 255, 51, 0, 0, 255]
+    );
+
+    let green = [255, 0, 0x88, 0xC6, 0xFA, 0, 0, 0];
+    let texels = block(GL_COMPRESSED_RG_RGTC2, &[red, green].concat());
+    assert_eq!(&texels[4..12], &[255, 0, 0, 255, 51, 218, 0, 255]);
+
+    // Signed endpoints 127 and -127 interpolate in sevenths, and -128 clamps to -1
+    let signed = [0x7F, 0x81, 0x88, 0xC6, 0xFA, 0, 0, 0];
+    let texels = float_block(GL_COMPRESSED_SIGNED_RED_RGTC1, &signed);
+    let reds: Vec<f32> = texels.chunks(4).take(8).map(|texel| texel[0]).collect();
+    let sevenths: Vec<f32> = [7.0, -7.0, 5.0, 3.0, 1.0, -1.0, -3.0, -5.0]
+        .iter()
+        .map(|value| value / 7.0)
+        .collect();
+    for (red, expected) in reds.iter().zip(sevenths.iter()) {
+        assert!((red - expected).abs() < 1.0e-6, "{} != {}", red, expected);
+    }
+    assert_eq!(&texels[..4], &[1.0, 0.0, 0.0, 1.0]);
+
+    let minimum = [0x80, 0x80, 0, 0, 0, 0, 0, 0];
+    let texels = float_block(GL_COMPRESSED_SIGNED_RG_RGTC2, &[minimum, signed].concat());
+    assert_eq!(&texels[..4], &[-1.0, 1.0, 0.0, 1.0]);
+}
+
+#[test]
+fn decodes_bc7_modes() {
+    // Mode 6 from black to white, the p-bit making the second endpoint 255, with each
+    // texel taking the next of the sixteen weights
+    let mut fields = vec![(0, 6), (1, 1)];
+    fields.extend((0..4).flat_map(|_| vec![(0, 7), (127, 7)]));
+    fields.extend(vec![(0, 1), (1, 1), (0, 3)]);
+    fields.extend((1..16).map(|index| (index, 4)));
+    let ramp = [
+        0, 16, 36, 52, 68, 84, 104, 120, 135, 151, 171, 187, 203, 219, 239, 255,
+    ];
+    let texels = block(GL_COMPRESSED_RGBA_BPTC_UNORM, &pack_bits(&fields));
+    let expected: Vec<u8> = ramp.iter().flat_map(|value| vec![*value; 4]).collect();
+    assert_eq!(texels, expected);
+
+    // Rotation and index selection
+    assert_block(
+        GL_COMPRESSED_RGBA_BPTC_UNORM,
+        "f0aae3c9611afddcb075baa3b893e730",
+        "68c086d9ef9c4584d9a2459268c086d97eba65cb68c045d9d9a26592c3a886a0\
+         94b4a6bd7eba65cbd9a2869294b445bdd9a2659268c065d9adae45ae68c0a6d9",
+    );
+    // Separate alpha indices
+    assert_block(
+        GL_COMPRESSED_RGBA_BPTC_UNORM,
+        "a0816134d43bef6f9348f096f47d57d7",
+        "02fb7aa35bf1b36202db7aa32edb968302f17aa32edb96835bdbb36202f17aa3\
+         02db7aa35bf1b36287f1cf422ef1968387dbcf425bf1b36202f17aa32edb9683",
+    );
+    // Two partitioned subsets with alpha
+    assert_block(
+        GL_COMPRESSED_RGBA_BPTC_UNORM,
+        "80e47b63d7421cf9261007886adb5bea",
+        "79aa20207a81693c593c240c593c240cdf750c249e5918187a81693c7d2cff75\
+         7d2cff757c55b659593c240c593c240c9e5918189e5918187c55b6597d2cff75",
+    );
+
+    // Blocks without a mode bit are reserved and decode to transparent black
+    assert_eq!(block(GL_COMPRESSED_RGBA_BPTC_UNORM, &[0; 16]), vec![0; 64]);
+}
+
+#[test]
+fn decodes_etc1_blocks() {
+    // Individual colors
+    assert_block(
+        ETC1_RGB8,
+        "0352df70564f8c29",
+        "002bb3ff0d62eaff4534ffff2110edff0048d0ff2a7fffff2110edff4534ffff\
+         0048d0ff0048d0ff0000c3ff2110edff002bb3ff0d62eaff6f5effff6f5effff",
+    );
+    // Differential colors decode the same in ETC1 and ETC2
+    let differential = "4eaee95eee4ecfc1";
+    let expected = "67caffff53b6f8fff0ffffff68cbffff41a4e6ff53b6f8ff000040ff0a6dc8ff\
+                    41a4e6ff2d90d2ff000040ff000040ff41a4e6ff67caffff000040ff000040ff";
+    assert_block(ETC1_RGB8, differential, expected);
+    assert_block(GL_COMPRESSED_RGB8_ETC2, differential, expected);
+}
+
+#[test]
+fn decodes_etc2_modes() {
+    // T mode
+    assert_block(
+        GL_COMPRESSED_RGB8_ETC2,
+        "0d3af1b29e5cf6ca",
+        "5533aaffff11bbff5533aafffc0eb8ffff14beff5533aafffc0eb8ffff14beff\
+         ff11bbfffc0eb8fffc0eb8ffff14befffc0eb8ffff14beffff11bbfffc0eb8ff",
+    );
+    // H mode
+    assert_block(
+        GL_COMPRESSED_RGB8_ETC2,
+        "26f2073e4b7b2c9d",
+        "00ce57ff00ce57ff20ff97ff64fd64ff20ff97ff20ff97ff20ff97ff24bd24ff\
+         24bd24ff20ff97ff24bd24ff20ff97ff00ce57ff24bd24ff00ce57ff64fd64ff",
+    );
+    // Planar mode
+    assert_block(
+        GL_COMPRESSED_RGB8_ETC2,
+        "019beb3601f3a4c9",
+        "009bbaff1a74caff354edbff4f27ebff1d7e95ff3857a5ff5230b5ff6c0ac5ff\
+         3b616fff553a7fff6f1390ff8900a0ff58434aff721d5aff8c006affa7007aff",
+    );
+}
+
+#[test]
+fn decodes_etc2_alpha() {
+    // Without the opaque bit, index 2 texels are transparent black
+    assert_block(
+        GL_COMPRESSED_RGB8_PUNCHTHROUGH_ALPHA1_ETC2,
+        "e2772d54738a0e18",
+        "e77329ffff9046ff000000000000000000000000e77329ffa71b00ff00000000\
+         e77329ffe77329ffffbb60ff00000000ca560cff00000000ffbb60fff76b10ff",
+    );
+    assert_block(
+        GL_COMPRESSED_RGBA8_ETC2_EAC,
+        "8d3fe83c1c09548c81c25a6f14357d02",
+        "77b94da577b94d9faef084845a9c3078aef0847877b94d8491d36778aef08478\
+         7fc95e8499e3787262ac4178b6ff957e99e3787299e37893b6ff959999e37893",
+    );
+}
+
+#[test]
+fn decodes_eac_channels() {
+    // Base 128 with modifiers -3 for the first texel and 14 for the rest
+    let unsigned = [0x80, 0x10, 0x1F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+    let texels = float_block(GL_COMPRESSED_R11_EAC, &unsigned);
+    assert_eq!(&texels[..4], &[1004.0 / 2047.0, 0.0, 0.0, 1.0]);
+    assert_eq!(texels[4], 1140.0 / 2047.0);
+
+    // A zero multiplier adds the bare modifier
+    let unscaled = [0x80, 0x00, 0x1F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+    let texels = float_block(GL_COMPRESSED_R11_EAC, &unscaled);
+    assert_eq!((texels[0], texels[4]), (1025.0 / 2047.0, 1042.0 / 2047.0));
+
+    // Signed bases of -128 act as -127 and results clamp to -1023
+    let texels = float_block(GL_COMPRESSED_SIGNED_R11_EAC, &unsigned);
+    assert_eq!((texels[0], texels[4]), (-1.0, -904.0 / 1023.0));
+
+    // Base 255, or -1 when signed, saturates with the largest multiplier
+    let saturated = [0xFF, 0xFD, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF];
+    let texels = float_block(GL_COMPRESSED_RG11_EAC, &[unsigned, saturated].concat());
+    assert_eq!(&texels[..4], &[1004.0 / 2047.0, 1.0, 0.0, 1.0]);
+    let texels = float_block(
+        GL_COMPRESSED_SIGNED_RG11_EAC,
+        &[saturated, unsigned].concat(),
+    );
+    assert_eq!((texels[0], texels[1]), (1.0, -1.0));
+}
+
+#[test]
+fn crops_partial_blocks() {
+    // Flat BC4 blocks of 10, 20, ... covering two slices of 6x5
+    let blocks: Vec<u8> = (1..=8)
+        .flat_map(|value| vec![value * 10, value * 10, 0, 0, 0, 0, 0, 0])
+        .collect();
+    let image = decode_blocks(GL_COMPRESSED_RED_RGTC1, 6, 5, 2, &blocks).unwrap();
+    assert_eq!((image.width, image.height, image.depth), (6, 5, 2));
+    let reds: Vec<u8> = rgba8(image).chunks(4).map(|texel| texel[0]).collect();
+    for slice in 0..2 {
+        for y in 0..5 {
+            for x in 0..6 {
+                let expected = (slice * 4 + y / 4 * 2 + x / 4 + 1) as u8 * 10;
+                assert_eq!(
+                    reds[(slice * 5 + y) * 6 + x],
+                    expected,
+                    "{} {} {}",
+                    x,
+                    y,
+                    slice
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn decompresses_textures() {
+    let red = [0x00, 0xF8, 0x00, 0xF8, 0, 0, 0, 0].to_vec();
+    let blue = [0x1F, 0x00, 0x1F, 0x00, 0, 0, 0, 0].to_vec();
+    let layers = |gl_internal_format| {
+        compressed(
+            gl_internal_format,
+            8,
+            4,
+            2,
+            vec![
+                vec![
+                    [red.clone(), blue.clone()].concat(),
+                    [blue.clone(), red.clone()].concat(),
+                ],
+                vec![red.clone(), blue.clone()],
+            ],
+        )
+    };
+    let texture = layers(COMPRESSED_SRGB_ALPHA_S3TC_DXT1);
+    let decompressed = decompress(&texture).unwrap();
+    let header = &decompressed.header;
+    assert_eq!(
+        (
+            header.gl_format,
+            header.gl_internal_format,
+            header.gl_type_size
+        ),
+        (GL_RGBA, GL_SRGB8_ALPHA8, 1)
+    );
+    assert_eq!(decompressed.levels.len(), 2);
+    let image = decompressed.image(0, 1, 0).unwrap();
+    assert_eq!(image.len(), 8 * 4 * 4);
+    assert_eq!(
+        (&image[..4], &image[16..20]),
+        (&[0, 0, 255, 255][..], &[255, 0, 0, 255][..])
+    );
+    assert_eq!(
+        decompressed.image(1, 1, 0).unwrap(),
+        &rgba8(decode_image(&texture, 1, 1, 0).unwrap())[..]
+    );
+
+    let mut file = Vec::new();
+    write_ktx(&decompressed, &mut file).unwrap();
+    let written = parse_ktx(&file).unwrap();
+    for (level, layer) in [(0, 0), (0, 1), (1, 0), (1, 1)].iter() {
+        assert_eq!(
+            written.image(*level, *layer, 0),
+            decompressed.image(*level, *layer, 0)
+        );
+    }
+
+    let plain = layers(COMPRESSED_RGBA_S3TC_DXT1);
+    assert_eq!(
+        decompress(&plain).unwrap().header.gl_internal_format,
+        GL_RGBA8
+    );
+
+    let eac = [0x80, 0x10, 0x1F, 0xFF, 0xFF, 0xFF, 0xFF, 0xFF].to_vec();
+    let floats = decompress(&compressed(GL_COMPRESSED_R11_EAC, 4, 4, 0, vec![vec![eac]])).unwrap();
+    assert_eq!(
+        (floats.header.gl_type, floats.header.gl_internal_format),
+        (GL_FLOAT, GL_RGBA32F)
+    );
+    let image = floats.image(0, 0, 0).unwrap();
+    assert_eq!(image.len(), 16 * 16);
+    assert_eq!(
+        f32::from_ne_bytes([image[0], image[1], image[2], image[3]]),
+        1004.0 / 2047.0
+    );
+}
+
+#[test]
+fn rejects_undecodable_data() {
+    assert!(matches!(
+        decode_blocks(GL_COMPRESSED_RGB_BPTC_SIGNED_FLOAT, 4, 4, 1, &[0; 16]),
+        Err(KtxError::UnsupportedFormat(_))
+    ));
+    assert!(matches!(
+        decode_blocks(GL_RGBA8, 4, 4, 1, &[0; 64]),
+        Err(KtxError::UnsupportedFormat(_))
+    ));
+    assert!(matches!(
+        decode_blocks(GL_COMPRESSED_RGBA_BPTC_UNORM, 8, 4, 1, &[0; 16]),
+        Err(KtxError::InvalidDataFormat { .. })
+    ));
+    let texture = compressed(COMPRESSED_RGB_S3TC_DXT1, 4, 4, 0, vec![vec![vec![0; 8]]]);
+    assert_eq!(
+        decode_image(&texture, 1, 0, 0).err(),
+        Some(KtxError::NoSuchImage {
+            level: 1,
+            layer: 0,
+            face: 0
+        })
+    );
+    assert!(matches!(
+        decode_blocks(COMPRESSED_RGB_S3TC_DXT1, u32::MAX, u32::MAX, u32::MAX, &[]),
+        Err(KtxError::InvalidArgument(_))
+    ));
+}
@@ -0,0 +1,303 @@
+use std::{borrow::Cow, f32::consts::PI};
+use support::ktx::{
+    determine_target,
+    envmap::{reproject, Projection},
+    load_ktx_file, parse_ktx,
+    writer::write_ktx,
+    Header, KtxData, KtxError, MipLevel,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_INT: u32 = 0x1404;
+const GL_FLOAT: u32 = 0x1406;
+const GL_RGB: u32 = 0x1907;
+const GL_BGR: u32 = 0x80E0;
+const GL_RGB32F: u32 = 0x8815;
+const GL_RGB32I: u32 = 0x8D83;
+const GL_RGB_INTEGER: u32 = 0x8D98;
+const GL_SRGB8: u32 = 0x8C41;
+const GL_TEXTURE_CUBE_MAP: u32 = 0x8513;
+
+// A single level texture of tightly packed texels
+fn texture(
+    gl_type: u32,
+    gl_format: u32,
+    gl_internal_format: u32,
+    size: (u32, u32),
+    images: Vec<Vec<u8>>,
+) -> KtxData<'static> {
+    let mut pixels = Vec::new();
+    let mut ranges = Vec::new();
+    for image in images.iter() {
+        ranges.push(pixels.len()..pixels.len() + image.len());
+        pixels.extend_from_slice(image);
+    }
+    KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type,
+            gl_type_size: if gl_type == GL_UNSIGNED_BYTE { 1 } else { 4 },
+            gl_format,
+            gl_internal_format,
+            gl_base_internal_format: gl_format,
+            pixel_width: size.0,
+            pixel_height: size.1,
+            pixel_depth: 0,
+            array_elements: 0,
+            faces: images.len() as u32,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: vec![MipLevel {
+            width: size.0,
+            height: size.1,
+            depth: 1,
+            images: ranges,
+        }],
+        unpack_alignment: 1,
+        pixels: Cow::Owned(pixels),
+    }
+}
+
+// The direction at the center of each texel, following GL's cube map face table
+fn cube_direction(face: usize, s: f32, t: f32) -> [f32; 3] {
+    let (sc, tc) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+    match face {
+        0 => [1.0, -tc, -sc],
+        1 => [-1.0, -tc, sc],
+        2 => [sc, 1.0, tc],
+        3 => [sc, -1.0, -tc],
+        4 => [sc, -tc, 1.0],
+        _ => [-sc, -tc, -1.0],
+    }
+}
+
+fn equirect_direction(s: f32, t: f32) -> [f32; 3] {
+    let (longitude, latitude) = ((s - 0.5) * 2.0 * PI, (t - 0.5) * PI);
+    [
+        latitude.cos() * longitude.cos(),
+        latitude.sin(),
+        latitude.cos() * longitude.sin(),
+    ]
+}
+
+fn normalize([x, y, z]: [f32; 3]) -> [f32; 3] {
+    let length = (x * x + y * y + z * z).sqrt();
+    [x / length, y / length, z / length]
+}
+
+// An RGB32F map whose texels hold the direction they are seen from
+fn direction_map<F: Fn(usize, f32, f32) -> [f32; 3]>(
+    width: u32,
+    height: u32,
+    faces: usize,
+    direction: F,
+) -> KtxData<'static> {
+    let images = (0..faces)
+        .map(|face| {
+            let mut image = Vec::new();
+            for y in 0..height {
+                for x in 0..width {
+                    let (s, t) = (
+                        (x as f32 + 0.5) / width as f32,
+                        (y as f32 + 0.5) / height as f32,
+                    );
+                    for value in normalize(direction(face, s, t)).iter() {
+                        image.extend(value.to_ne_bytes());
+                    }
+                }
+            }
+            image
+        })
+        .collect();
+    texture(GL_FLOAT, GL_RGB, GL_RGB32F, (width, height), images)
+}
+
+fn texels(texture: &KtxData, face: u32) -> Vec<[f32; 3]> {
+    texture
+        .image(0, 0, face)
+        .unwrap()
+        .chunks_exact(12)
+        .map(|texel| {
+            let value = |offset: usize| {
+                f32::from_ne_bytes([
+                    texel[offset],
+                    texel[offset + 1],
+                    texel[offset + 2],
+                    texel[offset + 3],
+                ])
+            };
+            [value(0), value(4), value(8)]
+        })
+        .collect()
+}
+
+// The largest angle, in degrees, between a map's texels and the directions they should hold
+fn largest_error<F: Fn(usize, f32, f32) -> Option<[f32; 3]>>(
+    texture: &KtxData,
+    expected: F,
+) -> f32 {
+    let (width, height) = (texture.header.pixel_width, texture.header.pixel_height);
+    let mut largest: f32 = 0.0;
+    for face in 0..texture.header.face_count() as usize {
+        for (index, texel) in texels(texture, face as u32).iter().enumerate() {
+            let (x, y) = (index as u32 % width, index as u32 / width);
+            let (s, t) = (
+                (x as f32 + 0.5) / width as f32,
+                (y as f32 + 0.5) / height as f32,
+            );
+            if let Some(direction) = expected(face, s, t) {
+                let [a, b, c] = normalize(*texel);
+                let [x, y, z] = normalize(direction);
+                let cosine = (a * x + b * y + c * z).clamp(-1.0, 1.0);
+                largest = largest.max(cosine.acos().to_degrees());
+            }
+        }
+    }
+    largest
+}
+
+#[test]
+fn converts_between_projections() {
+    let equirect = direction_map(128, 64, 1, |_, s, t| equirect_direction(s, t));
+
+    let cube = reproject(
+        &equirect,
+        Projection::Equirectangular,
+        Projection::CubeMap,
+        32,
+    )
+    .unwrap();
+    assert_eq!(determine_target(&cube), Ok(GL_TEXTURE_CUBE_MAP));
+    assert_eq!(
+        (cube.header.pixel_width, cube.header.pixel_height),
+        (32, 32)
+    );
+    assert!(largest_error(&cube, |face, s, t| Some(cube_direction(face, s, t))) < 2.0);
+
+    // Sphere maps hold reflections of -Z about the normal facing the viewer at each texel
+    let sphere = reproject(&cube, Projection::CubeMap, Projection::SphereMap, 64).unwrap();
+    let sphere_error = largest_error(&sphere, |_, s, t| {
+        let (x, y) = (2.0 * s - 1.0, 2.0 * t - 1.0);
+        let z = (1.0 - x * x - y * y).sqrt();
+        // The rim squeezes the whole back hemisphere, so only the middle is compared
+        (x * x + y * y < 0.5).then_some([2.0 * z * x, 2.0 * z * y, 2.0 * z * z - 1.0])
+    });
+    assert!(sphere_error < 3.0, "{}", sphere_error);
+    let center = texels(&sphere, 0)[32 * 64 + 32];
+    assert!(center[2] > 0.99, "{:?}", center);
+
+    let back = reproject(&cube, Projection::CubeMap, Projection::Equirectangular, 64).unwrap();
+    assert_eq!(
+        (back.header.pixel_width, back.header.pixel_height),
+        (128, 64)
+    );
+    assert_eq!(back.header.face_count(), 1);
+    // Away from the poles, where a texel spans every longitude
+    let back_error = largest_error(&back, |_, s, t| {
+        (t > 0.1 && t < 0.9).then(|| equirect_direction(s, t))
+    });
+    assert!(back_error < 3.0, "{}", back_error);
+}
+
+#[test]
+fn filters_bilinearly() {
+    // Halfway between two texels across the equirectangular seam
+    let image = [255u8, 0, 0, 0, 0, 255].to_vec();
+    let equirect = texture(GL_UNSIGNED_BYTE, GL_RGB, GL_SRGB8, (2, 1), vec![image]);
+    let cube = reproject(
+        &equirect,
+        Projection::Equirectangular,
+        Projection::CubeMap,
+        1,
+    )
+    .unwrap();
+    assert_eq!(cube.header.gl_internal_format, GL_SRGB8);
+    // -X looks along longitude 180 degrees, the seam between the last and first texel.
+    // Blended in linear space, each half is 188 once encoded as sRGB.
+    let seam = cube.image(0, 0, 1).unwrap();
+    assert!(
+        seam[..3] == [188, 0, 188] || seam[..3] == [187, 0, 187],
+        "{:?}",
+        &seam[..3]
+    );
+
+    // A constant map stays constant, whatever the layout
+    let flat = texture(
+        GL_UNSIGNED_BYTE,
+        GL_BGR,
+        GL_SRGB8,
+        (4, 4),
+        vec![[10, 120, 250].repeat(16)],
+    );
+    for (from, to) in [
+        (Projection::SphereMap, Projection::Equirectangular),
+        (Projection::Equirectangular, Projection::SphereMap),
+    ]
+    .iter()
+    {
+        let result = reproject(&flat, *from, *to, 8).unwrap();
+        assert!(result
+            .image(0, 0, 0)
+            .unwrap()
+            .chunks_exact(3)
+            .all(|texel| texel == [10, 120, 250]));
+    }
+}
+
+#[test]
+fn converts_sphere_maps() {
+    let sphere = load_ktx_file("assets/textures/envmaps/spheremap1.ktx").unwrap();
+    let cube = reproject(&sphere, Projection::SphereMap, Projection::CubeMap, 64).unwrap();
+    assert_eq!(cube.header.gl_format, GL_BGR);
+    assert_eq!(cube.levels[0].images.len(), 6);
+    assert_eq!(cube.image(0, 0, 5).unwrap().len(), 64 * 64 * 3);
+
+    let mut file = Vec::new();
+    write_ktx(&cube, &mut file).unwrap();
+    let parsed = parse_ktx(&file).unwrap();
+    assert_eq!(determine_target(&parsed), Ok(GL_TEXTURE_CUBE_MAP));
+    assert_eq!(parsed.image(0, 0, 3), cube.image(0, 0, 3));
+}
+
+#[test]
+fn rejects_unsuitable_textures() {
+    let sphere = load_ktx_file("assets/textures/envmaps/spheremap1.ktx").unwrap();
+    let unsupported = |result: Result<KtxData, KtxError>| match result {
+        Err(KtxError::UnsupportedLayout(_)) | Err(KtxError::UnsupportedFormat(_)) => {}
+        result => panic!("{:?}", result.map(|texture| texture.header)),
+    };
+    unsupported(reproject(
+        &sphere,
+        Projection::CubeMap,
+        Projection::SphereMap,
+        64,
+    ));
+    unsupported(reproject(
+        &sphere,
+        Projection::SphereMap,
+        Projection::CubeMap,
+        0,
+    ));
+    let volume = load_ktx_file("assets/textures/envmaps/mountains3d.ktx").unwrap();
+    unsupported(reproject(
+        &volume,
+        Projection::SphereMap,
+        Projection::CubeMap,
+        64,
+    ));
+    let integer = texture(GL_INT, GL_RGB_INTEGER, GL_RGB32I, (1, 1), vec![vec![0; 12]]);
+    unsupported(reproject(
+        &integer,
+        Projection::SphereMap,
+        Projection::CubeMap,
+        4,
+    ));
+    let mut sphere = sphere;
+    sphere.levels.clear();
+    assert_eq!(
+        reproject(&sphere, Projection::SphereMap, Projection::CubeMap, 4).err(),
+        Some(KtxError::InvalidArgument("texture has no mip levels"))
+    );
+}
@@ -0,0 +1,196 @@
+use support::{
+    hdr::{FloatFormat, HdrImage},
+    ktx::{
+        determine_target, half_to_f32,
+        mipmap::{generate_mipmaps, MipmapOptions},
+        parse_ktx,
+        writer::write_ktx,
+    },
+};
+
+const GL_HALF_FLOAT: u32 = 0x140B;
+const GL_FLOAT: u32 = 0x1406;
+const GL_RED: u32 = 0x1903;
+const GL_RGB: u32 = 0x1907;
+const GL_R32F: u32 = 0x822E;
+const GL_RGB16F: u32 = 0x881B;
+const GL_RGB32F: u32 = 0x8815;
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+
+// 1.0, 0.5 and 0.25 with a shared exponent
+const TEXEL: [u8; 4] = [128, 64, 32, 129];
+
+fn radiance(resolution: &str, scanlines: &[u8]) -> Vec<u8> {
+    let mut file = format!(
+        "#?RADIANCE\n# made by hand\nFORMAT=32-bit_rle_rgbe\nEXPOSURE=2.0\n\n{}\n",
+        resolution
+    )
+    .into_bytes();
+    file.extend_from_slice(scanlines);
+    file
+}
+
+fn pfm(header: &str, values: &[f32], little_endian: bool) -> Vec<u8> {
+    let mut file = header.as_bytes().to_vec();
+    for value in values {
+        if little_endian {
+            file.extend(value.to_le_bytes());
+        } else {
+            file.extend(value.to_be_bytes());
+        }
+    }
+    file
+}
+
+#[test]
+fn decodes_flat_rgbe() {
+    let mut scanlines = Vec::new();
+    for index in 0..6 {
+        let exponent = if index == 5 { 0 } else { 129 + index as u8 };
+        scanlines.extend([128, 64, 32, exponent]);
+    }
+    let image = HdrImage::decode_rgbe(&radiance("-Y 2 +X 3", &scanlines)).unwrap();
+    assert_eq!((image.width, image.height, image.components), (3, 2, 3));
+    assert_eq!(&image.data[..6], &[1.0, 0.5, 0.25, 2.0, 1.0, 0.5]);
+    assert_eq!(&image.data[12..], &[16.0, 8.0, 4.0, 0.0, 0.0, 0.0]);
+
+    // Rows that run up are flipped so the top row comes first
+    let flipped = HdrImage::decode_rgbe(&radiance("+Y 2 +X 3", &scanlines)).unwrap();
+    assert_eq!(&flipped.data[..9], &image.data[9..]);
+}
+
+#[test]
+fn decodes_run_length_rgbe() {
+    // Old style runs repeat the previous texel, consecutive markers scale the count
+    let mut scanlines = TEXEL.to_vec();
+    scanlines.extend([1, 1, 1, 2]);
+    scanlines.extend([0, 0, 0, 0]);
+    scanlines.extend([1, 1, 1, 1]);
+    scanlines.extend([1, 1, 1, 1]);
+    let image = HdrImage::decode_rgbe(&radiance("-Y 1 +X 261", &scanlines)).unwrap();
+    assert_eq!(&image.data[6..9], &[1.0, 0.5, 0.25]);
+    assert_eq!(image.data.len(), 261 * 3);
+    assert!(image.data[9..].iter().all(|value| *value == 0.0));
+
+    // Adaptive runs store each channel as runs over 128 and literals up to 128
+    let width = 10;
+    let mut scanlines = Vec::new();
+    for _ in 0..2 {
+        scanlines.extend([2, 2, 0, width]);
+        for channel in TEXEL.iter() {
+            scanlines.extend([128 + 8, *channel, 2, *channel, 0]);
+        }
+    }
+    let image = HdrImage::decode_rgbe(&radiance("-Y 2 +X 10", &scanlines)).unwrap();
+    assert_eq!(image.data.len(), 2 * 10 * 3);
+    for (index, texel) in image.data.chunks_exact(3).enumerate() {
+        let expected = if index % 10 == 9 {
+            [0.0; 3]
+        } else {
+            [1.0, 0.5, 0.25]
+        };
+        assert_eq!(texel, &expected, "texel {}", index);
+    }
+}
+
+#[test]
+fn decodes_pfm() {
+    // Rows are stored bottom first
+    let values = [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, -1.5, 0.25, 1e6, 7.0, 8.0, 9.0];
+    let little = HdrImage::decode_pfm(&pfm("PF\n2 2\n-1.0\n", &values, true)).unwrap();
+    assert_eq!((little.width, little.height, little.components), (2, 2, 3));
+    assert_eq!(&little.data[..6], &values[6..]);
+    assert_eq!(&little.data[6..], &values[..6]);
+    let big = HdrImage::decode_pfm(&pfm("PF 2 2 1.0\n", &values, false)).unwrap();
+    assert_eq!(big, little);
+
+    let gray = HdrImage::decode_pfm(&pfm("Pf\n3 1\n-0.5\n", &[0.5, 1.5, 2.5], true)).unwrap();
+    assert_eq!(gray.components, 1);
+    assert_eq!(gray.data, [0.5, 1.5, 2.5]);
+}
+
+#[test]
+fn converts_to_float_textures() {
+    let image = HdrImage::new(
+        3,
+        1,
+        3,
+        vec![1.0, 0.5, 0.25, 2.0, 65504.0, 0.0, -1.0, 3.0, 0.125],
+    )
+    .unwrap();
+
+    let float = image.to_texture(FloatFormat::Float);
+    assert_eq!(determine_target(&float), Ok(GL_TEXTURE_2D));
+    assert_eq!(
+        (
+            float.header.gl_type,
+            float.header.gl_format,
+            float.header.gl_internal_format
+        ),
+        (GL_FLOAT, GL_RGB, GL_RGB32F)
+    );
+    let values: Vec<f32> = float
+        .image(0, 0, 0)
+        .unwrap()
+        .chunks_exact(4)
+        .map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+        .collect();
+    assert_eq!(values, image.data);
+
+    let half = image.to_texture(FloatFormat::Half);
+    assert_eq!(
+        (half.header.gl_type, half.header.gl_internal_format),
+        (GL_HALF_FLOAT, GL_RGB16F)
+    );
+    let mut file = Vec::new();
+    write_ktx(&half, &mut file).unwrap();
+    let parsed = parse_ktx(&file).unwrap();
+    let values: Vec<f32> = parsed
+        .image(0, 0, 0)
+        .unwrap()
+        .chunks_exact(2)
+        .take(9)
+        .map(|value| half_to_f32(u16::from_ne_bytes([value[0], value[1]])))
+        .collect();
+    assert_eq!(values, image.data);
+
+    let mipmapped = generate_mipmaps(&float, &MipmapOptions::default()).unwrap();
+    assert_eq!(mipmapped.levels.len(), 2);
+
+    let gray = HdrImage::new(1, 1, 1, vec![4.0]).unwrap();
+    let texture = gray.to_texture(FloatFormat::Float);
+    assert_eq!(
+        (texture.header.gl_format, texture.header.gl_internal_format),
+        (GL_RED, GL_R32F)
+    );
+}
+
+#[test]
+fn rejects_malformed_images() {
+    let flat = [TEXEL; 6].concat();
+    assert!(HdrImage::decode_rgbe(&radiance("-Y 2 +X 3", &flat[..20])).is_err());
+    assert!(HdrImage::decode_rgbe(&radiance("+X 3 -Y 2", &flat)).is_err());
+    assert!(HdrImage::decode_rgbe(&flat).is_err());
+    let xyze = b"#?RADIANCE\nFORMAT=32-bit_rle_xyze\n\n-Y 2 +X 3\n".to_vec();
+    assert!(HdrImage::decode_rgbe(&[xyze, flat.clone()].concat()).is_err());
+    // A repeat with nothing before it, and one running past the end of the row
+    assert!(HdrImage::decode_rgbe(&radiance("-Y 1 +X 2", &[1, 1, 1, 1, 0, 0, 0, 0])).is_err());
+    let mut overlong = TEXEL.to_vec();
+    overlong.extend([1, 1, 1, 255, 1, 1, 1, 255, 1, 1, 1, 255]);
+    assert!(HdrImage::decode_rgbe(&radiance("-Y 1 +X 3", &overlong)).is_err());
+    // An adaptive run longer than the row
+    let mut scanline = vec![2, 2, 0, 8];
+    scanline.extend([128 + 9, 0]);
+    assert!(HdrImage::decode_rgbe(&radiance("-Y 1 +X 8", &scanline)).is_err());
+    // Huge dimensions over a truncated file fail without allocating the whole image
+    let huge = HdrImage::decode_rgbe(&radiance("-Y 200000 +X 200000", &flat[..8]));
+    assert!(huge.is_err());
+    let huge = HdrImage::decode_rgbe(&radiance("-Y 4294967295 +X 4294967295", &[]));
+    assert!(huge.is_err());
+
+    assert!(HdrImage::decode_pfm(&pfm("PF\n2 2\n-1.0\n", &[0.0; 11], true)).is_err());
+    assert!(HdrImage::decode_pfm(&pfm("PF\n2 2\n0\n", &[0.0; 12], true)).is_err());
+    assert!(HdrImage::decode_pfm(b"PF\n4294967295 4294967295\n-1.0\n").is_err());
+    assert!(HdrImage::decode_pfm(&pfm("P6\n2 2\n-1\n", &[0.0; 12], true)).is_err());
+    assert!(HdrImage::new(2, 2, 4, vec![0.0; 16]).is_err());
+}
@@ -0,0 +1,713 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{borrow::Cow, fs, io, iter, path::PathBuf};
+use support::{
+    assets::asset_path,
+    ktx::{
+        determine_target, load_ktx_file, parse_ktx, parse_texture, read_ktx, writer::write_ktx,
+        Header, KeyValue, KtxData, KtxError, MipLevel, COMPRESSED_RGB_S3TC_DXT1, KTX_IDENTIFIER,
+    },
+    load_ktx,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_UNSIGNED_SHORT: u32 = 0x1403;
+const GL_RGB: u32 = 0x1907;
+const GL_RGBA: u32 = 0x1908;
+const GL_RGB8: u32 = 0x8051;
+const GL_RGBA8: u32 = 0x8058;
+const GL_RGBA16: u32 = 0x805B;
+const GL_RGBA32F: u32 = 0x8814;
+const GL_FLOAT: u32 = 0x1406;
+const GL_UNSIGNED_INT_8_8_8_8: u32 = 0x8035;
+const GL_TEXTURE_CUBE_MAP: u32 = 0x8513;
+const GL_TEXTURE_CUBE_MAP_ARRAY: u32 = 0x9009;
+
+struct Fields {
+    gl_type: u32,
+    gl_type_size: u32,
+    gl_format: u32,
+    gl_internal_format: u32,
+    width: u32,
+    height: u32,
+    depth: u32,
+    array_elements: u32,
+    faces: u32,
+    mip_levels: u32,
+}
+
+fn rgba8(width: u32, height: u32) -> Fields {
+    Fields {
+        gl_type: GL_UNSIGNED_BYTE,
+        gl_type_size: 1,
+        gl_format: GL_RGBA,
+        gl_internal_format: GL_RGBA8,
+        width,
+        height,
+        depth: 0,
+        array_elements: 0,
+        faces: 1,
+        mip_levels: 1,
+    }
+}
+
+fn ktx_file(fields: &Fields, key_values: &[u8], levels: &[Vec<u8>]) -> Vec<u8> {
+    encode_file(fields, key_values, levels, false)
+}
+
+// Writes the header, imageSize words and pixel units of gl_type_size in either byte order.
+// Key/value data is passed already encoded.
+fn encode_file(
+    fields: &Fields,
+    key_values: &[u8],
+    levels: &[Vec<u8>],
+    big_endian: bool,
+) -> Vec<u8> {
+    let word = |value: u32| {
+        if big_endian {
+            value.to_be_bytes()
+        } else {
+            value.to_le_bytes()
+        }
+    };
+    let mut file = KTX_IDENTIFIER.to_vec();
+    for value in [
+        0x0403_0201,
+        fields.gl_type,
+        fields.gl_type_size,
+        fields.gl_format,
+        fields.gl_internal_format,
+        fields.gl_format,
+        fields.width,
+        fields.height,
+        fields.depth,
+        fields.array_elements,
+        fields.faces,
+        fields.mip_levels,
+        key_values.len() as u32,
+    ]
+    .iter()
+    {
+        file.extend_from_slice(&word(*value));
+    }
+    file.extend_from_slice(key_values);
+    for level in levels {
+        file.extend_from_slice(&word(level.len() as u32));
+        if big_endian {
+            for unit in level.chunks(fields.gl_type_size as usize) {
+                file.extend(unit.iter().rev());
+            }
+        } else {
+            file.extend_from_slice(level);
+        }
+    }
+    file
+}
+
+fn key_value_data(pairs: &[(&str, &[u8])], big_endian: bool) -> Vec<u8> {
+    let mut data = Vec::new();
+    for (key, value) in pairs.iter() {
+        let size = (key.len() + 1 + value.len()) as u32;
+        if big_endian {
+            data.extend_from_slice(&size.to_be_bytes());
+        } else {
+            data.extend_from_slice(&size.to_le_bytes());
+        }
+        data.extend_from_slice(key.as_bytes());
+        data.push(0);
+        data.extend_from_slice(value);
+        data.resize(data.len().div_ceil(4) * 4, 0);
+    }
+    data
+}
+
+fn valid_file() -> Vec<u8> {
+    let mut fields = rgba8(4, 4);
+    fields.mip_levels = 3;
+    let mut key_values = Vec::new();
+    key_values.extend_from_slice(&13u32.to_le_bytes());
+    key_values.extend_from_slice(b"KTXorient\0S=r");
+    key_values.resize(20, 0);
+    let levels = [vec![1; 64], vec![2; 16], vec![3; 4]];
+    ktx_file(&fields, &key_values, &levels)
+}
+
+fn set_field(file: &mut [u8], index: usize, value: u32) {
+    let offset = 16 + index * 4;
+    file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+// Every KTX file under assets/textures, with its contents
+fn assets() -> Vec<(PathBuf, Vec<u8>)> {
+    let mut assets = Vec::new();
+    for directory in ["assets/textures", "assets/textures/envmaps"].iter() {
+        for entry in fs::read_dir(directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().is_none_or(|extension| extension != "ktx") {
+                continue;
+            }
+            let bytes = fs::read(&path).unwrap();
+            if bytes.starts_with(&KTX_IDENTIFIER) {
+                assets.push((path, bytes));
+            }
+        }
+    }
+    assets
+}
+
+// The texels of one image with any row padding removed
+fn texels(data: &KtxData, level: u32, layer: u32, face: u32) -> Vec<u8> {
+    let image = data.image(level, layer, face).unwrap();
+    if data.header.is_compressed() {
+        return image.to_vec();
+    }
+    let mip = &data.levels[level as usize];
+    let row_bytes = (mip.width * data.header.pixel_size().unwrap()) as usize;
+    let alignment = data.unpack_alignment as usize;
+    let stride = row_bytes.div_ceil(alignment) * alignment;
+    image
+        .chunks(stride)
+        .flat_map(|row| row[..row_bytes].iter().copied())
+        .collect()
+}
+
+fn write(data: &KtxData) -> Vec<u8> {
+    let mut file = Vec::new();
+    write_ktx(data, &mut file).unwrap();
+    file
+}
+
+#[test]
+fn parses_every_asset() {
+    for (path, bytes) in assets() {
+        let data = parse_ktx(&bytes).unwrap_or_else(|error| panic!("{:?}: {}", path, error));
+        assert_eq!(
+            data.levels.len(),
+            data.header.level_count() as usize,
+            "{:?}",
+            path
+        );
+    }
+}
+
+#[test]
+fn loads_assets_from_the_asset_root() {
+    let path = asset_path("textures/brick.ktx");
+    assert!(path.is_absolute() && path.is_file(), "{:?}", path);
+
+    let loaded = load_ktx!("textures/brick.ktx").unwrap();
+    let bytes = fs::read(&path).unwrap();
+    let parsed = parse_ktx(&bytes).unwrap();
+    assert_eq!(loaded.levels.len(), parsed.levels.len());
+    assert_eq!(loaded.level_data(0), parsed.level_data(0));
+}
+
+#[test]
+fn reads_owned_textures() {
+    // The result outlives the buffer it was read from
+    let data: KtxData<'static> = read_ktx(io::Cursor::new(valid_file())).unwrap();
+    assert!(matches!(data.pixels, Cow::Owned(_)));
+    assert_eq!(data.image(1, 0, 0).unwrap(), &[2; 16][..]);
+    assert_eq!(
+        parse_texture(&valid_file()).unwrap().level_data(0),
+        data.level_data(0)
+    );
+
+    let mut file = valid_file();
+    file.truncate(100);
+    assert!(matches!(
+        read_ktx(&file[..]),
+        Err(KtxError::Truncated { .. })
+    ));
+}
+
+#[test]
+fn reports_missing_files() {
+    match load_ktx_file("textures/does-not-exist.ktx") {
+        Err(KtxError::Io { kind, reason }) => {
+            assert_eq!(kind, io::ErrorKind::NotFound);
+            assert!(reason.contains("does-not-exist.ktx"));
+        }
+        other => panic!("{:?}", other.err()),
+    }
+}
+
+#[test]
+fn round_trips_every_asset() {
+    for (path, bytes) in assets() {
+        let original = parse_ktx(&bytes).unwrap();
+        let file = write(&original);
+        let written = parse_ktx(&file).unwrap_or_else(|error| panic!("{:?}: {}", path, error));
+        assert_eq!(written.unpack_alignment, 4, "{:?}", path);
+
+        let (a, b) = (&original.header, &written.header);
+        assert_eq!(
+            (a.gl_type, a.gl_type_size, a.gl_format, a.gl_internal_format),
+            (b.gl_type, b.gl_type_size, b.gl_format, b.gl_internal_format),
+            "{:?}",
+            path
+        );
+        assert_eq!(a.gl_base_internal_format, b.gl_base_internal_format);
+        assert_eq!(
+            (a.pixel_width, a.pixel_height, a.pixel_depth),
+            (b.pixel_width, b.pixel_height, b.pixel_depth),
+            "{:?}",
+            path
+        );
+        assert_eq!(
+            (a.array_elements, a.face_count(), a.mip_levels),
+            (b.array_elements, b.face_count(), b.mip_levels),
+            "{:?}",
+            path
+        );
+        assert_eq!(original.key_values, written.key_values, "{:?}", path);
+
+        for level in 0..a.level_count() {
+            for layer in 0..a.layer_count() {
+                for face in 0..a.face_count() {
+                    assert!(
+                        texels(&original, level, layer, face)
+                            == texels(&written, level, layer, face),
+                        "{:?} level {} layer {} face {}",
+                        path,
+                        level,
+                        layer,
+                        face
+                    );
+                }
+            }
+        }
+
+        // Files already in the spec layout are written back unchanged
+        assert_eq!(write(&written), file, "{:?}", path);
+    }
+}
+
+#[test]
+fn writes_padded_rows_and_key_values() {
+    let pixels: Vec<u8> = (0..21).collect();
+    let data = KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type: GL_UNSIGNED_BYTE,
+            gl_type_size: 1,
+            gl_format: GL_RGB,
+            gl_internal_format: GL_RGB8,
+            gl_base_internal_format: GL_RGB,
+            pixel_width: 3,
+            pixel_height: 2,
+            pixel_depth: 0,
+            array_elements: 0,
+            faces: 0,
+            mip_levels: 2,
+            key_pair_bytes: 0,
+        },
+        key_values: vec![KeyValue {
+            key: "KTXorient".to_string(),
+            value: b"S=r,T=d\0".to_vec(),
+        }],
+        levels: vec![
+            MipLevel {
+                width: 3,
+                height: 2,
+                depth: 1,
+                images: iter::once(0..18).collect(),
+            },
+            MipLevel {
+                width: 1,
+                height: 1,
+                depth: 1,
+                images: iter::once(18..21).collect(),
+            },
+        ],
+        unpack_alignment: 1,
+        pixels: Cow::Owned(pixels),
+    };
+    let file = write(&data);
+
+    let field = |index: usize| {
+        let offset = 16 + index * 4;
+        u32::from_le_bytes([
+            file[offset],
+            file[offset + 1],
+            file[offset + 2],
+            file[offset + 3],
+        ])
+    };
+    assert_eq!(&file[..12], &KTX_IDENTIFIER[..]);
+    assert_eq!(&file[12..16], &[1, 2, 3, 4][..]);
+    assert_eq!(field(9), 1);
+    assert_eq!(field(11), 24);
+    assert_eq!(&file[64..68], &18u32.to_le_bytes()[..]);
+    assert_eq!(&file[68..86], &b"KTXorient\0S=r,T=d\0"[..]);
+    assert_eq!(&file[86..88], &[0, 0][..]);
+
+    let mut expected = 24u32.to_le_bytes().to_vec();
+    expected.extend_from_slice(&[0, 1, 2, 3, 4, 5, 6, 7, 8, 0, 0, 0]);
+    expected.extend_from_slice(&[9, 10, 11, 12, 13, 14, 15, 16, 17, 0, 0, 0]);
+    expected.extend_from_slice(&4u32.to_le_bytes());
+    expected.extend_from_slice(&[18, 19, 20, 0]);
+    assert_eq!(&file[88..], &expected[..]);
+
+    let written = parse_ktx(&file).unwrap();
+    assert_eq!(written.key_values, data.key_values);
+    assert_eq!(texels(&written, 0, 0, 0), &data.pixels[..18]);
+    assert_eq!(texels(&written, 1, 0, 0), &data.pixels[18..]);
+}
+
+#[test]
+fn writes_cube_map_faces() {
+    let mut fields = rgba8(2, 2);
+    fields.faces = 6;
+    fields.mip_levels = 2;
+    let mut file = ktx_file(&fields, &[], &[]);
+    for (level, face_size) in [16u32, 4].iter().enumerate() {
+        file.extend_from_slice(&face_size.to_le_bytes());
+        for face in 0..6 {
+            file.extend((0..*face_size).map(|_| (level * 6 + face) as u8));
+        }
+    }
+    assert_eq!(write(&parse_ktx(&file).unwrap()), file);
+}
+
+#[test]
+fn refuses_to_write_inconsistent_textures() {
+    let file = valid_file();
+    let mut data = parse_ktx(&file).unwrap();
+    data.levels.pop();
+    let error = write_ktx(&data, &mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let mut data = parse_ktx(&file).unwrap();
+    data.levels[1].images[0].end -= 1;
+    let error = write_ktx(&data, &mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+    let mut data = parse_ktx(&file).unwrap();
+    data.header.faces = 3;
+    let error = write_ktx(&data, &mut Vec::new()).unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+}
+
+#[test]
+fn parses_spec_layout() {
+    let file = valid_file();
+    let data = parse_ktx(&file).unwrap();
+    assert_eq!(data.key_values.len(), 1);
+    assert_eq!(data.key_values[0].key, "KTXorient");
+    assert_eq!(data.key_values[0].value, b"S=r");
+    assert_eq!(data.unpack_alignment, 4);
+    assert_eq!(data.levels.len(), 3);
+    assert_eq!(data.image(0, 0, 0).unwrap(), &[1; 64][..]);
+    assert_eq!(data.image(1, 0, 0).unwrap(), &[2; 16][..]);
+    assert_eq!(data.image(2, 0, 0).unwrap(), &[3; 4][..]);
+    assert!(data.image(3, 0, 0).is_none());
+    assert!(data.image(0, 1, 0).is_none());
+}
+
+#[test]
+fn parses_padded_rows() {
+    let mut fields = rgba8(3, 2);
+    fields.gl_format = GL_RGB;
+    fields.gl_internal_format = GL_RGB8;
+    // Rows of 9 bytes are padded to 12
+    let level: Vec<u8> = (0..2)
+        .flat_map(|row| (0..12).map(move |byte| if byte < 9 { row * 9 + byte } else { 0xFF }))
+        .collect();
+    let file = ktx_file(&fields, &[], &[level]);
+    let data = parse_ktx(&file).unwrap();
+    assert_eq!(data.unpack_alignment, 4);
+    assert_eq!(data.image(0, 0, 0).unwrap().len(), 24);
+    assert_eq!(texels(&data, 0, 0, 0), (0..18).collect::<Vec<u8>>());
+}
+
+#[test]
+fn falls_back_to_packed_layout() {
+    // Levels without imageSize words, with rows aligned to four bytes
+    let mut fields = rgba8(4, 4);
+    fields.mip_levels = 3;
+    let mut file = ktx_file(&fields, &[], &[]);
+    file.extend(vec![1; 64]);
+    file.extend(vec![2; 16]);
+    file.extend(vec![3; 4]);
+    let data = parse_ktx(&file).unwrap();
+    assert_eq!(data.unpack_alignment, 4);
+    assert_eq!(data.image(0, 0, 0).unwrap(), &[1; 64][..]);
+    assert_eq!(data.image(1, 0, 0).unwrap(), &[2; 16][..]);
+    assert_eq!(data.image(2, 0, 0).unwrap(), &[3; 4][..]);
+
+    // Tightly packed rows that are too short for four byte alignment
+    let mut fields = rgba8(3, 2);
+    fields.gl_format = GL_RGB;
+    fields.gl_internal_format = GL_RGB8;
+    let mut file = ktx_file(&fields, &[], &[]);
+    file.extend(0..18);
+    let data = parse_ktx(&file).unwrap();
+    assert_eq!(data.unpack_alignment, 1);
+    assert_eq!(texels(&data, 0, 0, 0), (0..18).collect::<Vec<u8>>());
+
+    // Too short for either layout
+    file.truncate(file.len() - 1);
+    assert!(matches!(
+        parse_ktx(&file),
+        Err(KtxError::Truncated {
+            section: "image data",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parses_cube_map_faces() {
+    let mut fields = rgba8(2, 2);
+    fields.faces = 6;
+    fields.mip_levels = 2;
+    let mut file = ktx_file(&fields, &[], &[]);
+    for (level, face_size) in [16u32, 4].iter().enumerate() {
+        // Non-array cube maps store the size of one face
+        file.extend_from_slice(&face_size.to_le_bytes());
+        for face in 0..6 {
+            file.extend((0..*face_size).map(|_| (level * 6 + face) as u8));
+        }
+    }
+    let data = parse_ktx(&file).unwrap();
+    assert_eq!(determine_target(&data), Ok(GL_TEXTURE_CUBE_MAP));
+    for level in 0..2 {
+        for face in 0..6 {
+            let image = data.image(level, 0, face).unwrap();
+            assert_eq!(image.len(), if level == 0 { 16 } else { 4 });
+            assert!(image.iter().all(|texel| *texel == (level * 6 + face) as u8));
+        }
+    }
+}
+
+#[test]
+fn parses_cube_map_array_layers() {
+    let mut fields = rgba8(1, 1);
+    fields.faces = 6;
+    fields.array_elements = 2;
+    let level = (0..12).flat_map(|image| vec![image as u8; 4]).collect();
+    let file = ktx_file(&fields, &[], &[level]);
+    let data = parse_ktx(&file).unwrap();
+    assert_eq!(determine_target(&data), Ok(GL_TEXTURE_CUBE_MAP_ARRAY));
+    assert_eq!(data.image(0, 1, 2).unwrap(), &[8; 4][..]);
+    assert_eq!(data.level_data(0).unwrap().len(), 48);
+}
+
+#[test]
+fn parses_compressed_levels() {
+    let mut fields = rgba8(8, 6);
+    fields.gl_type = 0;
+    fields.gl_format = 0;
+    fields.gl_internal_format = COMPRESSED_RGB_S3TC_DXT1;
+    fields.mip_levels = 4;
+    let levels = [vec![0; 32], vec![1; 8], vec![2; 8], vec![3; 8]];
+    let file = ktx_file(&fields, &[], &levels);
+    let data = parse_ktx(&file).unwrap();
+    assert!(data.header.is_compressed());
+    for (level, expected) in levels.iter().enumerate() {
+        assert_eq!(data.image(level as u32, 0, 0).unwrap(), &expected[..]);
+    }
+
+    let mut file = file;
+    set_field(&mut file, 3, 0x1234);
+    assert!(matches!(
+        parse_ktx(&file),
+        Err(KtxError::InvalidHeaderField {
+            field: "gl_internal_format",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn swaps_big_endian_files() {
+    let pairs: [(&str, &[u8]); 2] = [("KTXorient", b"S=r,T=d\0"), ("KTXwriter", b"tests\0")];
+    let cases = [
+        (GL_UNSIGNED_BYTE, 1, GL_RGBA8),
+        (GL_UNSIGNED_SHORT, 2, GL_RGBA16),
+        (GL_FLOAT, 4, GL_RGBA32F),
+        (GL_UNSIGNED_INT_8_8_8_8, 4, GL_RGBA8),
+    ];
+    for (gl_type, gl_type_size, gl_internal_format) in cases.iter() {
+        let mut fields = rgba8(2, 2);
+        fields.gl_type = *gl_type;
+        fields.gl_type_size = *gl_type_size;
+        fields.gl_internal_format = *gl_internal_format;
+        fields.mip_levels = 2;
+        let texel_bytes = if *gl_type == GL_UNSIGNED_INT_8_8_8_8 {
+            4
+        } else {
+            4 * *gl_type_size as usize
+        };
+        let levels = [
+            (0..4 * texel_bytes).map(|byte| byte as u8).collect(),
+            (0..texel_bytes).map(|byte| 0x80 | byte as u8).collect(),
+        ];
+
+        let little = encode_file(&fields, &key_value_data(&pairs, false), &levels, false);
+        let big = encode_file(&fields, &key_value_data(&pairs, true), &levels, true);
+        assert_ne!(little, big);
+
+        let expected = parse_ktx(&little).unwrap();
+        let data = parse_ktx(&big).unwrap();
+        assert_eq!(data.header.endianess, [4, 3, 2, 1]);
+        assert_eq!(data.header.gl_type_size, *gl_type_size);
+        assert_eq!(data.key_values, expected.key_values);
+        for level in 0..2 {
+            assert_eq!(
+                data.image(level, 0, 0),
+                expected.image(level, 0, 0),
+                "gl_type 0x{:X} level {}",
+                gl_type,
+                level
+            );
+        }
+
+        // Written back out, the texture is identical to the little endian original
+        assert_eq!(write(&data), little);
+    }
+}
+
+#[test]
+fn rejects_bad_identifier() {
+    let mut file = valid_file();
+    file[1] = b'X';
+    assert_eq!(parse_ktx(&file).err(), Some(KtxError::InvalidIdentifier));
+}
+
+#[test]
+fn rejects_bad_endianness() {
+    let mut file = valid_file();
+    file[12..16].copy_from_slice(&0x1234_5678u32.to_le_bytes());
+    assert_eq!(
+        parse_ktx(&file).err(),
+        Some(KtxError::InvalidEndianness(0x1234_5678))
+    );
+}
+
+#[test]
+fn rejects_invalid_header_fields() {
+    let cases: [(usize, u32, &str); 7] = [
+        (0, 0xDEAD, "gl_type"),
+        (1, 4, "gl_type_size"),
+        (2, 0xBEEF, "gl_format"),
+        (3, 0, "gl_internal_format"),
+        (5, 0, "pixel_width"),
+        (9, 3, "faces"),
+        (10, 4, "mip_levels"),
+    ];
+    for (index, value, name) in cases.iter() {
+        let mut file = valid_file();
+        set_field(&mut file, *index, *value);
+        match parse_ktx(&file) {
+            Err(KtxError::InvalidHeaderField { field, offset, .. }) => {
+                assert_eq!(field, *name);
+                assert_eq!(offset, 16 + index * 4);
+            }
+            other => panic!("{} = {} gave {:?}", name, value, other.err()),
+        }
+    }
+}
+
+#[test]
+fn rejects_mismatched_type_size() {
+    let mut fields = rgba8(2, 2);
+    fields.gl_type = GL_UNSIGNED_SHORT;
+    let file = ktx_file(&fields, &[], &[vec![0; 32]]);
+    assert!(matches!(
+        parse_ktx(&file),
+        Err(KtxError::InvalidHeaderField {
+            field: "gl_type_size",
+            ..
+        })
+    ));
+}
+
+#[test]
+fn reports_truncated_input() {
+    let file = valid_file();
+    for length in 0..file.len() {
+        match parse_ktx(&file[..length]) {
+            Err(KtxError::Truncated { offset, .. }) => assert!(offset <= length),
+            other => panic!("length {} gave {:?}", length, other.err()),
+        }
+    }
+}
+
+#[test]
+fn reports_short_pixel_data() {
+    let file = ktx_file(&rgba8(16, 16), &[], &[vec![0; 16 * 16 * 4]]);
+    match parse_ktx(&file[..file.len() - 1]) {
+        Err(KtxError::Truncated {
+            section, offset, ..
+        }) => {
+            assert_eq!(section, "image data");
+            assert_eq!(offset, 68);
+        }
+        other => panic!("{:?}", other.err()),
+    }
+}
+
+#[test]
+fn rejects_wrong_image_size() {
+    let mut file = valid_file();
+    let level_one = 64 + 20 + 4 + 64;
+    file[level_one..level_one + 4].copy_from_slice(&20u32.to_le_bytes());
+    assert_eq!(
+        parse_ktx(&file).err(),
+        Some(KtxError::ImageSizeMismatch {
+            level: 1,
+            offset: level_one,
+            expected: 16,
+            found: 20,
+        })
+    );
+}
+
+#[test]
+fn rejects_key_value_overrun() {
+    let mut file = valid_file();
+    file[64..68].copy_from_slice(&100u32.to_le_bytes());
+    assert!(matches!(
+        parse_ktx(&file),
+        Err(KtxError::Truncated {
+            section: "key/value data",
+            offset: 68,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn rejects_unterminated_key() {
+    let mut file = valid_file();
+    file[68 + 9] = b'!';
+    assert_eq!(
+        parse_ktx(&file).err(),
+        Some(KtxError::InvalidKeyValue {
+            offset: 64,
+            reason: "key is not NUL terminated"
+        })
+    );
+}
+
+#[test]
+fn survives_arbitrary_bytes() {
+    let mut rng = StdRng::seed_from_u64(0x4B54_5831);
+    let valid = valid_file();
+    for _ in 0..20_000 {
+        let mut file = valid.clone();
+        for _ in 0..rng.gen_range(1..8) {
+            let index = rng.gen_range(0..file.len());
+            file[index] = rng.gen();
+        }
+        let length = rng.gen_range(0..=file.len());
+        let _ = parse_ktx(&file[..length]);
+    }
+    for _ in 0..2_000 {
+        let length = rng.gen_range(0..256);
+        let mut file = KTX_IDENTIFIER.to_vec();
+        file.extend((0..length).map(|_| rng.gen::<u8>()));
+        let _ = parse_ktx(&file);
+    }
+}
@@ -0,0 +1,258 @@
+use miniz_oxide::deflate::compress_to_vec_zlib;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+use support::ktx::{
+    determine_target,
+    ktx2::{
+        parse_ktx2, KTX2_IDENTIFIER, SUPERCOMPRESSION_BASIS_LZ, SUPERCOMPRESSION_NONE,
+        SUPERCOMPRESSION_ZLIB, SUPERCOMPRESSION_ZSTD,
+    },
+    parse_texture, KtxError,
+};
+
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+const VK_FORMAT_BC1_RGB_UNORM_BLOCK: u32 = 131;
+const GL_RGBA: u32 = 0x1908;
+const GL_SRGB8_ALPHA8: u32 = 0x8C43;
+const GL_TEXTURE_2D_ARRAY: u32 = 0x8C1A;
+
+struct Texture {
+    vk_format: u32,
+    type_size: u32,
+    width: u32,
+    height: u32,
+    layers: u32,
+    levels: Vec<Vec<u8>>,
+}
+
+fn rgba8_texture() -> Texture {
+    Texture {
+        vk_format: VK_FORMAT_R8G8B8A8_SRGB,
+        type_size: 1,
+        width: 4,
+        height: 2,
+        layers: 0,
+        levels: vec![
+            (0..32).collect(),
+            (100..108).collect(),
+            (200..204).collect(),
+        ],
+    }
+}
+
+fn data_format_descriptor() -> Vec<u8> {
+    let mut dfd = Vec::new();
+    dfd.extend_from_slice(&28u32.to_le_bytes());
+    dfd.extend_from_slice(&0u32.to_le_bytes());
+    dfd.extend_from_slice(&((24u32 << 16) | 2).to_le_bytes());
+    // RGBSDA color model, BT.709 primaries, sRGB transfer
+    dfd.extend_from_slice(&[1, 1, 2, 0, 0, 0, 0, 0, 4, 0, 0, 0, 0, 0, 0, 0]);
+    dfd
+}
+
+fn ktx2_file(texture: &Texture, scheme: u32) -> Vec<u8> {
+    let payloads: Vec<Vec<u8>> = texture
+        .levels
+        .iter()
+        .map(|level| match scheme {
+            SUPERCOMPRESSION_ZSTD => compress_to_vec(&level[..], CompressionLevel::Fastest),
+            SUPERCOMPRESSION_ZLIB => compress_to_vec_zlib(level, 6),
+            _ => level.clone(),
+        })
+        .collect();
+
+    let dfd = data_format_descriptor();
+    let mut key_values = Vec::new();
+    key_values.extend_from_slice(&16u32.to_le_bytes());
+    key_values.extend_from_slice(b"KTXwriter\0tests\0");
+    key_values.resize(20, 0);
+
+    let index_end = 80 + 24 * texture.levels.len();
+    let dfd_offset = index_end;
+    let kvd_offset = dfd_offset + dfd.len();
+    let mut data_offset = kvd_offset + key_values.len();
+
+    let mut file = KTX2_IDENTIFIER.to_vec();
+    for value in [
+        texture.vk_format,
+        texture.type_size,
+        texture.width,
+        texture.height,
+        0,
+        texture.layers,
+        1,
+        texture.levels.len() as u32,
+        scheme,
+        dfd_offset as u32,
+        dfd.len() as u32,
+        kvd_offset as u32,
+        key_values.len() as u32,
+    ]
+    .iter()
+    {
+        file.extend_from_slice(&value.to_le_bytes());
+    }
+    file.extend_from_slice(&[0; 16]);
+    for (level, payload) in texture.levels.iter().zip(payloads.iter()) {
+        for value in [data_offset, payload.len(), level.len()].iter() {
+            file.extend_from_slice(&(*value as u64).to_le_bytes());
+        }
+        data_offset += payload.len();
+    }
+    file.extend_from_slice(&dfd);
+    file.extend_from_slice(&key_values);
+    for payload in payloads.iter() {
+        file.extend_from_slice(payload);
+    }
+    file
+}
+
+#[test]
+fn parses_uncompressed_levels() {
+    let texture = rgba8_texture();
+    let file = ktx2_file(&texture, SUPERCOMPRESSION_NONE);
+    let data = parse_ktx2(&file).unwrap();
+    assert_eq!(data.vk_format, VK_FORMAT_R8G8B8A8_SRGB);
+    assert_eq!(data.data_format.transfer_function, 2);
+    assert_eq!(data.data_format.texel_block_dimensions, [0; 4]);
+    assert_eq!(data.texture.header.gl_internal_format, GL_SRGB8_ALPHA8);
+    assert_eq!(data.texture.header.gl_format, GL_RGBA);
+    assert_eq!(data.texture.unpack_alignment, 1);
+    assert_eq!(data.texture.key_values[0].key, "KTXwriter");
+    assert_eq!(data.texture.key_values[0].value, b"tests\0");
+    for (level, expected) in texture.levels.iter().enumerate() {
+        assert_eq!(
+            data.texture.image(level as u32, 0, 0).unwrap(),
+            &expected[..]
+        );
+    }
+}
+
+#[test]
+fn decompresses_supercompressed_levels() {
+    let texture = rgba8_texture();
+    for scheme in [SUPERCOMPRESSION_ZSTD, SUPERCOMPRESSION_ZLIB].iter() {
+        let file = ktx2_file(&texture, *scheme);
+        let data = parse_ktx2(&file).unwrap();
+        for (level, expected) in texture.levels.iter().enumerate() {
+            assert_eq!(
+                data.texture.image(level as u32, 0, 0).unwrap(),
+                &expected[..]
+            );
+        }
+    }
+}
+
+#[test]
+fn parse_texture_accepts_ktx2() {
+    let texture = rgba8_texture();
+    let file = ktx2_file(&texture, SUPERCOMPRESSION_ZSTD);
+    let data = parse_texture(&file).unwrap();
+    assert_eq!(data.header.gl_internal_format, GL_SRGB8_ALPHA8);
+    assert_eq!(data.image(0, 0, 0).unwrap(), &texture.levels[0][..]);
+}
+
+#[test]
+fn parses_compressed_arrays() {
+    let texture = Texture {
+        vk_format: VK_FORMAT_BC1_RGB_UNORM_BLOCK,
+        type_size: 1,
+        width: 8,
+        height: 8,
+        layers: 3,
+        levels: vec![(0..96).collect()],
+    };
+    let file = ktx2_file(&texture, SUPERCOMPRESSION_ZSTD);
+    let data = parse_ktx2(&file).unwrap();
+    assert!(data.texture.header.is_compressed());
+    assert_eq!(determine_target(&data.texture), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(
+        data.texture.image(0, 2, 0).unwrap(),
+        &texture.levels[0][64..]
+    );
+}
+
+#[test]
+fn rejects_bad_level_sizes() {
+    let mut texture = rgba8_texture();
+    texture.levels[1].pop();
+    let file = ktx2_file(&texture, SUPERCOMPRESSION_NONE);
+    assert_eq!(
+        parse_ktx2(&file).err(),
+        Some(KtxError::ImageSizeMismatch {
+            level: 1,
+            offset: 104,
+            expected: 8,
+            found: 7,
+        })
+    );
+}
+
+#[test]
+fn rejects_corrupt_supercompression() {
+    let texture = rgba8_texture();
+    let mut file = ktx2_file(&texture, SUPERCOMPRESSION_ZLIB);
+    let last = file.len() - 3;
+    file[last] ^= 0xFF;
+    assert!(matches!(
+        parse_ktx2(&file),
+        Err(KtxError::Decompression { .. })
+    ));
+
+    let mut file = ktx2_file(&texture, SUPERCOMPRESSION_NONE);
+    file[44..48].copy_from_slice(&SUPERCOMPRESSION_BASIS_LZ.to_le_bytes());
+    assert_eq!(
+        parse_ktx2(&file).err(),
+        Some(KtxError::UnsupportedSupercompression(
+            SUPERCOMPRESSION_BASIS_LZ
+        ))
+    );
+}
+
+#[test]
+fn rejects_unknown_vk_format() {
+    let mut file = ktx2_file(&rgba8_texture(), SUPERCOMPRESSION_NONE);
+    file[12..16].copy_from_slice(&0u32.to_le_bytes());
+    assert!(matches!(
+        parse_ktx2(&file),
+        Err(KtxError::InvalidHeaderField {
+            field: "vk_format",
+            offset: 12,
+            ..
+        })
+    ));
+}
+
+#[test]
+fn reports_truncated_input() {
+    let file = ktx2_file(&rgba8_texture(), SUPERCOMPRESSION_NONE);
+    for length in 0..file.len() {
+        assert!(
+            matches!(parse_ktx2(&file[..length]), Err(KtxError::Truncated { .. })),
+            "length {}",
+            length
+        );
+    }
+}
+
+#[test]
+fn survives_arbitrary_bytes() {
+    let mut rng = StdRng::seed_from_u64(0x4B54_5832);
+    for scheme in [
+        SUPERCOMPRESSION_NONE,
+        SUPERCOMPRESSION_ZSTD,
+        SUPERCOMPRESSION_ZLIB,
+    ]
+    .iter()
+    {
+        let valid = ktx2_file(&rgba8_texture(), *scheme);
+        for _ in 0..5_000 {
+            let mut file = valid.clone();
+            for _ in 0..rng.gen_range(1..8) {
+                let index = rng.gen_range(0..file.len());
+                file[index] = rng.gen();
+            }
+            let _ = parse_ktx2(&file);
+        }
+    }
+}
@@ -0,0 +1,527 @@
+use std::{
+    borrow::Cow,
+    env,
+    fs::{self, File},
+    path::{Path, PathBuf},
+    process::{self, Command, Output},
+};
+use support::{
+    image::Image,
+    ktx::{
+        determine_target, half_to_f32, load_ktx_file, writer::write_ktx, Header, KtxData, MipLevel,
+        COMPRESSED_RGBA_S3TC_DXT1,
+    },
+};
+
+const GL_COMPRESSED_RED_RGTC1: u32 = 0x8DBB;
+const GL_COMPRESSED_RG_RGTC2: u32 = 0x8DBD;
+const GL_HALF_FLOAT: u32 = 0x140B;
+const GL_R8: u32 = 0x8229;
+const GL_RGB: u32 = 0x1907;
+const GL_RGB16F: u32 = 0x881B;
+const GL_RGBA: u32 = 0x1908;
+const GL_RGBA8: u32 = 0x8058;
+const GL_SRGB8_ALPHA8: u32 = 0x8C43;
+const GL_TEXTURE_2D_ARRAY: u32 = 0x8C1A;
+const GL_TEXTURE_CUBE_MAP: u32 = 0x8513;
+const GL_TEXTURE_CUBE_MAP_ARRAY: u32 = 0x9009;
+
+fn ktxtool(arguments: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ktxtool"))
+        .args(arguments)
+        .output()
+        .unwrap()
+}
+
+fn run(arguments: &[&str]) -> String {
+    let output = ktxtool(arguments);
+    assert!(
+        output.status.success(),
+        "{:?} failed: {}",
+        arguments,
+        String::from_utf8_lossy(&output.stderr)
+    );
+    String::from_utf8(output.stdout).unwrap()
+}
+
+fn scratch(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("ktxtool-{}-{}", process::id(), name))
+}
+
+fn path(path: &Path) -> &str {
+    path.to_str().unwrap()
+}
+
+#[test]
+fn info_describes_textures() {
+    let output = run(&[
+        "info",
+        "assets/textures/brick.ktx",
+        "assets/textures/cp437_9x16.ktx",
+    ]);
+    assert!(output.contains("assets/textures/brick.ktx"));
+    assert!(output.contains("0x80E0 GL_BGR"));
+    assert!(output.contains("GL_TEXTURE_2D_ARRAY"));
+    assert!(output.contains("9: 1 x 1 x 1, 1 image of 3 bytes"));
+    assert!(output.contains("0: 9 x 16 x 1, 256 images of 192 bytes, 49152 bytes"));
+
+    // A DX10 DDS file holding a single sRGB BC1 block
+    let dds = scratch("srgb.dds");
+    let mut file = vec![0; 148];
+    file[..4].copy_from_slice(b"DDS ");
+    let fields = [
+        (4, 124),
+        (12, 4),
+        (16, 4),
+        (28, 1),
+        (76, 32),
+        (80, 4),
+        (84, u32::from_le_bytes(*b"DX10")),
+        (128, 72),
+        (132, 3),
+        (140, 1),
+    ];
+    for (offset, value) in fields.iter() {
+        file[*offset..*offset + 4].copy_from_slice(&u32::to_le_bytes(*value));
+    }
+    file.extend_from_slice(&[0; 8]);
+    fs::write(&dds, &file).unwrap();
+    let output = run(&["info", path(&dds)]);
+    assert!(
+        output.contains("GL_COMPRESSED_SRGB_ALPHA_S3TC_DXT1_EXT"),
+        "{}",
+        output
+    );
+    fs::remove_file(dds).unwrap();
+
+    assert!(!ktxtool(&["info", "assets/textures/smiley.raw"])
+        .status
+        .success());
+    assert_eq!(ktxtool(&["frobnicate"]).status.code(), Some(2));
+}
+
+#[test]
+fn converts_raw_images() {
+    let ktx = scratch("smiley.ktx");
+    run(&[
+        "convert",
+        "assets/textures/smiley.raw",
+        path(&ktx),
+        "--size",
+        "128x128",
+        "--srgb",
+        "--mipmaps",
+    ]);
+    let texture = load_ktx_file(&ktx).unwrap();
+    assert_eq!(texture.header.gl_format, GL_RGBA);
+    assert_eq!(texture.header.gl_internal_format, GL_SRGB8_ALPHA8);
+    assert_eq!(texture.levels.len(), 8);
+    let raw = fs::read("assets/textures/smiley.raw").unwrap();
+    assert_eq!(texture.image(0, 0, 0).unwrap(), &raw[..128 * 128 * 4]);
+    assert!(run(&["info", path(&ktx)]).contains("KTXorient = \"S=r,T=d\""));
+
+    // The trailing TGA footer is tolerated but a short file is not
+    let short = ktxtool(&[
+        "convert",
+        "assets/textures/smiley.raw",
+        path(&ktx),
+        "--size",
+        "256x128",
+    ]);
+    assert!(!short.status.success());
+    fs::remove_file(ktx).unwrap();
+}
+
+#[test]
+fn extracts_png_images() {
+    let png = scratch("brick.png");
+    run(&[
+        "extract",
+        "assets/textures/brick.ktx",
+        path(&png),
+        "--level",
+        "8",
+    ]);
+    let image = Image::decode_png(&fs::read(&png).unwrap()).unwrap();
+    let texture = load_ktx_file("assets/textures/brick.ktx").unwrap();
+    let bgr = texture.image(8, 0, 0).unwrap();
+    assert_eq!((image.width, image.height, image.components), (2, 2, 3));
+    // BGR texels come out as RGB
+    assert_eq!(&image.data[..3], &[bgr[2], bgr[1], bgr[0]]);
+
+    let layer = scratch("glyph.png");
+    run(&[
+        "extract",
+        "assets/textures/cp437_9x16.ktx",
+        path(&layer),
+        "--layer",
+        "65",
+    ]);
+    let glyph = Image::decode_png(&fs::read(&layer).unwrap()).unwrap();
+    let fonts = load_ktx_file("assets/textures/cp437_9x16.ktx").unwrap();
+    let stored = fonts.image(0, 65, 0).unwrap();
+    for row in 0..16 {
+        assert_eq!(
+            &glyph.data[row * 9..row * 9 + 9],
+            &stored[row * 12..row * 12 + 9]
+        );
+    }
+
+    let missing = ktxtool(&[
+        "extract",
+        "assets/textures/cp437_9x16.ktx",
+        path(&layer),
+        "--layer",
+        "256",
+    ]);
+    assert!(!missing.status.success());
+    fs::remove_file(png).unwrap();
+    fs::remove_file(layer).unwrap();
+}
+
+#[test]
+fn converts_netpbm_images() {
+    let ppm = scratch("gradient.ppm");
+    let mut file = b"P6\n# a comment\n4 2\n255\n".to_vec();
+    file.extend((0..24).map(|value| value as u8 * 10));
+    fs::write(&ppm, &file).unwrap();
+    let ktx = scratch("gradient.ktx");
+    run(&["convert", path(&ppm), path(&ktx), "--flip"]);
+    let texture = load_ktx_file(&ktx).unwrap();
+    assert_eq!(texture.header.gl_format, GL_RGB);
+    let image = Image::from_texture(&texture, 0, 0, 0, 0).unwrap();
+    let expected: Vec<u8> = (12..24).chain(0..12).map(|value| value * 10).collect();
+    assert_eq!(image.data, expected);
+
+    let pgm = Image::decode_pnm(b"P2 2 2 15 0 5 10 15").unwrap();
+    assert_eq!(pgm.data, [0, 85, 170, 255]);
+    let wide = Image::decode_pnm(b"P5 2 1 65535 \xff\xff\x80\x00").unwrap();
+    assert_eq!(wide.data, [255, 128]);
+    assert!(Image::decode_pnm(b"P6 4 2 255 \x00\x00").is_err());
+    // Huge dimensions are refused before anything is allocated for them
+    assert!(Image::decode_pnm(b"P6\n100000 100000\n255\n").is_err());
+    assert!(Image::decode_pnm(b"P3\n100000 100000\n255\n1 2 3").is_err());
+    assert!(Image::decode_pnm(b"P5 4294967295 4294967295 65535 \x00\x00").is_err());
+
+    fs::remove_file(ppm).unwrap();
+    fs::remove_file(ktx).unwrap();
+}
+
+#[test]
+fn converts_hdr_images() {
+    let pfm = scratch("sky.pfm");
+    let mut file = b"PF\n4 2\n-1.0\n".to_vec();
+    file.extend((0..24).flat_map(|value| (value as f32 * 0.5).to_le_bytes()));
+    fs::write(&pfm, &file).unwrap();
+    let ktx = scratch("sky.ktx");
+    run(&["convert", path(&pfm), path(&ktx), "--half", "--mipmaps"]);
+    let texture = load_ktx_file(&ktx).unwrap();
+    assert_eq!(texture.header.gl_type, GL_HALF_FLOAT);
+    assert_eq!(texture.header.gl_internal_format, GL_RGB16F);
+    assert_eq!(texture.levels.len(), 3);
+    // The bottom row comes first in the file but last in the texture
+    let first = texture.image(0, 0, 0).unwrap();
+    assert_eq!(half_to_f32(u16::from_ne_bytes([first[0], first[1]])), 6.0);
+
+    assert!(!ktxtool(&[
+        "convert",
+        "assets/textures/smiley.raw",
+        path(&ktx),
+        "--half"
+    ])
+    .status
+    .success());
+    fs::remove_file(pfm).unwrap();
+    fs::remove_file(ktx).unwrap();
+}
+
+#[test]
+fn assembles_arrays_and_cubes() {
+    let faces: Vec<PathBuf> = (0..12)
+        .map(|face| {
+            let face_path = scratch(&format!("face{}.png", face));
+            Image::new(2, 2, 4, vec![face as u8 * 20; 16])
+                .unwrap()
+                .save_png(&face_path)
+                .unwrap();
+            face_path
+        })
+        .collect();
+    let face_paths: Vec<&str> = faces.iter().map(|face| path(face)).collect();
+
+    let array = scratch("array.ktx");
+    let mut arguments = vec!["assemble", path(&array)];
+    arguments.extend(&face_paths[..3]);
+    run(&arguments);
+    let texture = load_ktx_file(&array).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(texture.image(0, 2, 0).unwrap(), &[40; 16][..]);
+
+    let cube = scratch("cube.ktx");
+    let mut arguments = vec!["assemble", path(&cube), "--cube", "--mipmaps"];
+    arguments.extend(&face_paths[..6]);
+    run(&arguments);
+    let texture = load_ktx_file(&cube).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_CUBE_MAP));
+    assert_eq!(texture.levels.len(), 2);
+    assert_eq!(texture.image(1, 0, 5).unwrap(), &[100; 4][..]);
+
+    let cube_array = scratch("cube-array.ktx");
+    let mut arguments = vec!["assemble", path(&cube_array), "--cube", "--array"];
+    arguments.extend(&face_paths);
+    run(&arguments);
+    let texture = load_ktx_file(&cube_array).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_CUBE_MAP_ARRAY));
+    assert_eq!(texture.image(0, 1, 3).unwrap(), &[180; 16][..]);
+
+    let mut arguments = vec!["assemble", path(&cube), "--cube"];
+    arguments.extend(&face_paths[..5]);
+    assert!(!ktxtool(&arguments).status.success());
+
+    for file in faces.iter().chain([array, cube, cube_array].iter()) {
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn assembles_ktx_layers() {
+    // Layers converted to KTX first can be mixed with images of the same format
+    let png = scratch("layer.png");
+    Image::new(3, 2, 3, vec![90; 18])
+        .unwrap()
+        .save_png(&png)
+        .unwrap();
+    let ktx = scratch("layer.ktx");
+    run(&["convert", path(&png), path(&ktx)]);
+
+    let array = scratch("layers.ktx");
+    run(&[
+        "assemble",
+        path(&array),
+        path(&ktx),
+        path(&png),
+        "--mipmaps",
+    ]);
+    let texture = load_ktx_file(&array).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(texture.header.array_elements, 2);
+    assert_eq!(texture.levels.len(), 2);
+    assert_eq!(&texture.image(0, 1, 0).unwrap()[..9], &[90; 9]);
+
+    let failed = ktxtool(&[
+        "assemble",
+        path(&array),
+        path(&ktx),
+        "assets/textures/brick.ktx",
+    ]);
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("array layer 1"));
+
+    for file in [png, ktx, array].iter() {
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn decodes_compressed_textures() {
+    // One BC1 block, red in the top two rows and transparent below
+    let block = vec![0x00, 0x00, 0x00, 0xF8, 0x55, 0x55, 0xFF, 0xFF];
+    let image = 0..block.len();
+    let texture = KtxData {
+        header: Header {
+            endianess: 0x0403_0201u32.to_ne_bytes(),
+            gl_type: 0,
+            gl_type_size: 1,
+            gl_format: 0,
+            gl_internal_format: COMPRESSED_RGBA_S3TC_DXT1,
+            gl_base_internal_format: GL_RGBA,
+            pixel_width: 4,
+            pixel_height: 4,
+            pixel_depth: 0,
+            array_elements: 0,
+            faces: 1,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: vec![MipLevel {
+            width: 4,
+            height: 4,
+            depth: 1,
+            images: vec![image],
+        }],
+        unpack_alignment: 4,
+        pixels: Cow::Owned(block),
+    };
+    let compressed = scratch("bc1.ktx");
+    write_ktx(&texture, &mut File::create(&compressed).unwrap()).unwrap();
+
+    let decompressed = scratch("bc1-rgba.ktx");
+    run(&["decompress", path(&compressed), path(&decompressed)]);
+    let rgba = load_ktx_file(&decompressed).unwrap();
+    assert_eq!(rgba.header.gl_internal_format, GL_RGBA8);
+    let texels = rgba.image(0, 0, 0).unwrap();
+    assert_eq!(
+        (&texels[..4], &texels[60..]),
+        (&[255, 0, 0, 255][..], &[0; 4][..])
+    );
+
+    let png = scratch("bc1.png");
+    run(&["extract", path(&compressed), path(&png)]);
+    let image = Image::decode_png(&fs::read(&png).unwrap()).unwrap();
+    assert_eq!(image.data, texels);
+
+    for file in [compressed, decompressed, png].iter() {
+        fs::remove_file(file).unwrap();
+    }
+}
+
+#[test]
+fn compresses_to_rgtc() {
+    // A normal map keeps its first two channels
+    let normals = scratch("normals.png");
+    let data = (0..8 * 8)
+        .flat_map(|texel| [(texel % 8 * 32) as u8, (texel / 8 * 32) as u8, 255])
+        .collect();
+    Image::new(8, 8, 3, data)
+        .unwrap()
+        .save_png(&normals)
+        .unwrap();
+    let compressed = scratch("normals.ktx");
+    assert!(!ktxtool(&["compress", path(&normals), path(&compressed)])
+        .status
+        .success());
+    let output = run(&[
+        "compress",
+        path(&normals),
+        path(&compressed),
+        "--channels",
+        "2",
+    ]);
+    assert!(output.contains("level  3:"));
+    assert!(output.contains("PSNR: "));
+    let texture = load_ktx_file(&compressed).unwrap();
+    assert_eq!(texture.header.gl_internal_format, GL_COMPRESSED_RG_RGTC2);
+    assert_eq!(texture.levels.len(), 4);
+
+    // Distance fields stored as KTX are compressed directly
+    run(&[
+        "compress",
+        "assets/textures/chars-df-array.ktx",
+        path(&compressed),
+    ]);
+    let texture = load_ktx_file(&compressed).unwrap();
+    assert_eq!(texture.header.gl_internal_format, GL_COMPRESSED_RED_RGTC1);
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_2D_ARRAY));
+    assert!(
+        !ktxtool(&["compress", "assets/textures/brick.ktx", path(&compressed)])
+            .status
+            .success()
+    );
+
+    fs::remove_file(normals).unwrap();
+    fs::remove_file(compressed).unwrap();
+}
+
+#[test]
+fn reprojects_environment_maps() {
+    let cube = scratch("envmap-cube.ktx");
+    let output = ktxtool(&[
+        "reproject",
+        "assets/textures/envmaps/spheremap2.ktx",
+        path(&cube),
+        "--to",
+        "cube",
+    ]);
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--from"));
+    run(&[
+        "reproject",
+        "assets/textures/envmaps/spheremap2.ktx",
+        path(&cube),
+        "--from",
+        "sphere",
+        "--to",
+        "cube",
+        "--resolution",
+        "32",
+        "--mipmaps",
+    ]);
+    let texture = load_ktx_file(&cube).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_CUBE_MAP));
+    assert_eq!(texture.header.pixel_width, 32);
+    assert_eq!(texture.levels.len(), 6);
+
+    // Six faces are taken to be a cube map
+    let equirect = scratch("envmap-equirect.ktx");
+    run(&[
+        "reproject",
+        path(&cube),
+        path(&equirect),
+        "--to",
+        "equirect",
+    ]);
+    let texture = load_ktx_file(&equirect).unwrap();
+    assert_eq!(
+        (texture.header.pixel_width, texture.header.pixel_height),
+        (64, 32)
+    );
+    assert_eq!(texture.header.faces, 1);
+
+    fs::remove_file(cube).unwrap();
+    fs::remove_file(equirect).unwrap();
+}
+
+#[test]
+fn makes_distance_fields() {
+    // Glyphs drawn in alpha, a bar and a square
+    let glyphs: Vec<_> = (0..2)
+        .map(|index| {
+            let png = scratch(&format!("glyph{}.png", index));
+            let data = (0..32 * 32)
+                .flat_map(|texel| {
+                    let (x, y) = (texel % 32, texel / 32);
+                    let inside = (8..24).contains(&x) && (index == 0 || (8..24).contains(&y));
+                    [0, 0, 0, if inside { 255 } else { 0 }]
+                })
+                .collect();
+            Image::new(32, 32, 4, data).unwrap().save_png(&png).unwrap();
+            png
+        })
+        .collect();
+
+    let fields = scratch("glyphs.ktx");
+    run(&[
+        "distance-field",
+        path(&fields),
+        path(&glyphs[0]),
+        path(&glyphs[1]),
+        "--field-size",
+        "16x16",
+        "--spread",
+        "2.5",
+        "--mipmaps",
+    ]);
+    let texture = load_ktx_file(&fields).unwrap();
+    assert_eq!(determine_target(&texture), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(texture.header.gl_internal_format, GL_R8);
+    assert_eq!(texture.header.array_elements, 2);
+    assert_eq!(texture.levels.len(), 5);
+    // The middle of each glyph is inside, the corners are outside
+    for layer in 0..2 {
+        let image = texture.image(0, layer, 0).unwrap();
+        assert_eq!((image[8 * 16 + 8], image[0]), (0, 255));
+    }
+
+    let failed = ktxtool(&[
+        "distance-field",
+        path(&fields),
+        path(&glyphs[0]),
+        "--threshold",
+        "300",
+    ]);
+    assert!(String::from_utf8_lossy(&failed.stderr).contains("--threshold"));
+
+    fs::remove_file(fields).unwrap();
+    for glyph in glyphs {
+        fs::remove_file(glyph).unwrap();
+    }
+}
@@ -0,0 +1,341 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{borrow::Cow, fs};
+use support::ktx::{
+    f32_to_half, half_to_f32,
+    mipmap::{can_filter, generate_mipmaps, MipmapFilter, MipmapOptions},
+    parse_ktx,
+    writer::write_ktx,
+    Header, KtxData, KtxError, MipLevel, COMPRESSED_RGB_S3TC_DXT1,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_FLOAT: u32 = 0x1406;
+const GL_RED: u32 = 0x1903;
+const GL_RGB: u32 = 0x1907;
+const GL_RGBA: u32 = 0x1908;
+const GL_R8: u32 = 0x8229;
+const GL_RGBA8: u32 = 0x8058;
+const GL_RGBA32F: u32 = 0x8814;
+const GL_SRGB8: u32 = 0x8C41;
+
+const FILTERS: [MipmapFilter; 3] = [
+    MipmapFilter::Box,
+    MipmapFilter::Kaiser,
+    MipmapFilter::Lanczos,
+];
+
+// A single level texture of tightly packed images
+fn texture(
+    gl_type: u32,
+    gl_format: u32,
+    gl_internal_format: u32,
+    dimensions: [u32; 3],
+    layers: u32,
+    images: Vec<Vec<u8>>,
+) -> KtxData<'static> {
+    let [width, height, depth] = dimensions;
+    let mut pixels = Vec::new();
+    let mut ranges = Vec::new();
+    for image in images {
+        ranges.push(pixels.len()..pixels.len() + image.len());
+        pixels.extend(image);
+    }
+    KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type,
+            gl_type_size: if gl_type == GL_FLOAT { 4 } else { 1 },
+            gl_format,
+            gl_internal_format,
+            gl_base_internal_format: gl_format,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: if depth > 1 { depth } else { 0 },
+            array_elements: layers,
+            faces: 1,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: vec![MipLevel {
+            width,
+            height,
+            depth,
+            images: ranges,
+        }],
+        unpack_alignment: 1,
+        pixels: Cow::Owned(pixels),
+    }
+}
+
+fn red(width: u32, height: u32, texels: Vec<u8>) -> KtxData<'static> {
+    texture(
+        GL_UNSIGNED_BYTE,
+        GL_RED,
+        GL_R8,
+        [width, height, 1],
+        0,
+        vec![texels],
+    )
+}
+
+fn options(filter: MipmapFilter) -> MipmapOptions {
+    MipmapOptions {
+        filter,
+        ..Default::default()
+    }
+}
+
+fn alpha_coverage(data: &KtxData, level: u32, reference: f32) -> f32 {
+    let image = data.image(level, 0, 0).unwrap();
+    let covered = image
+        .chunks(4)
+        .filter(|texel| texel[3] as f32 / 255.0 > reference)
+        .count();
+    covered as f32 / (image.len() / 4) as f32
+}
+
+#[test]
+fn box_filter_averages_texels() {
+    #[rustfmt::skip]
+    let texels = vec![
+        0, 40, 100, 100,
+        40, 80, 100, 100,
+        200, 200, 10, 30,
+        200, 200, 50, 70,
+    ];
+    let data = generate_mipmaps(&red(4, 4, texels.clone()), &Default::default()).unwrap();
+    assert_eq!(data.header.mip_levels, 3);
+    assert_eq!(data.levels.len(), 3);
+    assert_eq!(data.unpack_alignment, 4);
+    assert_eq!(data.image(0, 0, 0).unwrap(), &texels[..]);
+    // Rows of the generated levels are padded to four bytes
+    assert_eq!(
+        data.image(1, 0, 0).unwrap(),
+        &[40, 100, 0, 0, 200, 40, 0, 0][..]
+    );
+    assert_eq!(data.image(2, 0, 0).unwrap(), &[95, 0, 0, 0][..]);
+}
+
+#[test]
+fn reduces_odd_sizes_to_one_texel() {
+    let data = generate_mipmaps(&red(5, 3, vec![60; 15]), &Default::default()).unwrap();
+    let sizes: Vec<_> = data
+        .levels
+        .iter()
+        .map(|level| (level.width, level.height))
+        .collect();
+    assert_eq!(sizes, [(5, 3), (2, 1), (1, 1)]);
+    for level in 1..3 {
+        assert!(data
+            .image(level, 0, 0)
+            .unwrap()
+            .iter()
+            .step_by(4)
+            .all(|texel| *texel == 60));
+    }
+}
+
+#[test]
+fn every_filter_keeps_flat_images_flat() {
+    let data = texture(
+        GL_UNSIGNED_BYTE,
+        GL_RGBA,
+        GL_RGBA8,
+        [16, 8, 1],
+        0,
+        vec![[10, 120, 250, 255].repeat(128)],
+    );
+    for filter in FILTERS.iter() {
+        let mipmapped = generate_mipmaps(&data, &options(*filter)).unwrap();
+        assert_eq!(mipmapped.levels.len(), 5);
+        for level in 1..5 {
+            for texel in mipmapped.image(level, 0, 0).unwrap().chunks(4) {
+                assert_eq!(texel, &[10, 120, 250, 255][..], "{:?}", filter);
+            }
+        }
+    }
+}
+
+#[test]
+fn sharpening_filters_stay_in_range() {
+    // A hard edge makes the negative lobes of Kaiser and Lanczos over and undershoot
+    let texels = (0..64).map(|x| if x % 8 < 4 { 0 } else { 255 }).collect();
+    let data = red(64, 1, texels);
+    for filter in [MipmapFilter::Kaiser, MipmapFilter::Lanczos].iter() {
+        let mipmapped = generate_mipmaps(&data, &options(*filter)).unwrap();
+        let level = mipmapped.image(1, 0, 0).unwrap();
+        assert!(level.contains(&0) || level.contains(&255), "{:?}", filter);
+    }
+}
+
+#[test]
+fn filters_srgb_in_linear_space() {
+    let data = texture(
+        GL_UNSIGNED_BYTE,
+        GL_RGB,
+        GL_SRGB8,
+        [2, 1, 1],
+        0,
+        vec![vec![0, 0, 0, 255, 255, 255]],
+    );
+    let mipmapped = generate_mipmaps(&data, &Default::default()).unwrap();
+    assert_eq!(mipmapped.image(1, 0, 0).unwrap(), &[188, 188, 188, 0][..]);
+
+    let linear = MipmapOptions {
+        srgb: Some(false),
+        ..Default::default()
+    };
+    let mipmapped = generate_mipmaps(&data, &linear).unwrap();
+    assert_eq!(mipmapped.image(1, 0, 0).unwrap(), &[128, 128, 128, 0][..]);
+}
+
+#[test]
+fn preserves_alpha_coverage() {
+    let mut rng = StdRng::seed_from_u64(0x4D49_5053);
+    let texels = (0..32 * 32)
+        .flat_map(|_| vec![255, 255, 255, rng.gen::<u8>()])
+        .collect();
+    let data = texture(
+        GL_UNSIGNED_BYTE,
+        GL_RGBA,
+        GL_RGBA8,
+        [32, 32, 1],
+        0,
+        vec![texels],
+    );
+    let reference = 0.8;
+    let base = alpha_coverage(&data, 0, reference);
+    assert!(base > 0.15 && base < 0.25);
+
+    let plain = generate_mipmaps(&data, &Default::default()).unwrap();
+    assert!(alpha_coverage(&plain, 2, reference) < 0.1);
+
+    for filter in FILTERS.iter() {
+        let preserved = generate_mipmaps(
+            &data,
+            &MipmapOptions {
+                filter: *filter,
+                srgb: None,
+                alpha_coverage: Some(reference),
+            },
+        )
+        .unwrap();
+        for level in 1..=3 {
+            let coverage = alpha_coverage(&preserved, level, reference);
+            assert!(
+                (coverage - base).abs() < 0.05,
+                "{:?} level {} coverage {} base {}",
+                filter,
+                level,
+                coverage,
+                base
+            );
+        }
+    }
+}
+
+#[test]
+fn filters_layers_and_depth_separately() {
+    let layered = texture(
+        GL_UNSIGNED_BYTE,
+        GL_RED,
+        GL_R8,
+        [2, 2, 1],
+        2,
+        vec![vec![10; 4], vec![200; 4]],
+    );
+    let mipmapped = generate_mipmaps(&layered, &Default::default()).unwrap();
+    assert_eq!(mipmapped.image(1, 0, 0).unwrap()[0], 10);
+    assert_eq!(mipmapped.image(1, 1, 0).unwrap()[0], 200);
+
+    // Two slices of 4x4 average down in depth as well
+    let slices = [vec![0; 16], vec![100; 16]].concat();
+    let volume = texture(GL_UNSIGNED_BYTE, GL_RED, GL_R8, [4, 4, 2], 0, vec![slices]);
+    let mipmapped = generate_mipmaps(&volume, &Default::default()).unwrap();
+    assert_eq!(
+        (
+            mipmapped.levels[1].width,
+            mipmapped.levels[1].height,
+            mipmapped.levels[1].depth
+        ),
+        (2, 2, 1)
+    );
+    assert_eq!(
+        mipmapped.image(1, 0, 0).unwrap(),
+        &[50, 50, 0, 0, 50, 50, 0, 0][..]
+    );
+    assert_eq!(mipmapped.image(2, 0, 0).unwrap(), &[50, 0, 0, 0][..]);
+}
+
+#[test]
+fn filters_float_data_without_clamping() {
+    let texels: Vec<u8> = [4.0f32, 0.0, 0.0, 1.0, 2.0, 0.0, 0.0, 1.0]
+        .iter()
+        .flat_map(|value| value.to_ne_bytes().to_vec())
+        .collect();
+    let data = texture(GL_FLOAT, GL_RGBA, GL_RGBA32F, [2, 1, 1], 0, vec![texels]);
+    let mipmapped = generate_mipmaps(&data, &Default::default()).unwrap();
+    let level = mipmapped.image(1, 0, 0).unwrap();
+    assert_eq!(
+        f32::from_ne_bytes([level[0], level[1], level[2], level[3]]),
+        3.0
+    );
+}
+
+#[test]
+fn converts_half_floats() {
+    for value in [0.0f32, 1.0, -2.5, 0.333, 65504.0, 6.0e-8, 1.0e-5].iter() {
+        let converted = half_to_f32(f32_to_half(*value));
+        assert!(
+            (converted - value).abs() <= value.abs() / 1024.0 + 6.0e-8,
+            "{} became {}",
+            value,
+            converted
+        );
+    }
+    assert_eq!(f32_to_half(1.0), 0x3C00);
+    assert_eq!(f32_to_half(1.0e6), 0x7C00);
+    assert!(half_to_f32(f32_to_half(f32::NAN)).is_nan());
+}
+
+#[test]
+fn rejects_compressed_textures() {
+    let mut data = red(4, 4, vec![0; 8]);
+    assert!(can_filter(&data));
+    data.header.gl_type = 0;
+    data.header.gl_format = 0;
+    data.header.gl_internal_format = COMPRESSED_RGB_S3TC_DXT1;
+    assert!(!can_filter(&data));
+    assert!(matches!(
+        generate_mipmaps(&data, &Default::default()),
+        Err(KtxError::UnsupportedFormat(_))
+    ));
+}
+
+#[test]
+fn rejects_textures_without_levels() {
+    let mut data = red(4, 4, vec![0; 16]);
+    data.levels.clear();
+    assert_eq!(
+        generate_mipmaps(&data, &Default::default()).err(),
+        Some(KtxError::InvalidArgument("texture has no mip levels"))
+    );
+}
+
+#[test]
+fn mipmapped_assets_write_as_ktx() {
+    let bytes = fs::read("assets/textures/baboon.ktx").unwrap();
+    let data = parse_ktx(&bytes).unwrap();
+    let mipmapped = generate_mipmaps(&data, &options(MipmapFilter::Kaiser)).unwrap();
+    assert_eq!(mipmapped.levels.len(), 8);
+
+    let mut file = Vec::new();
+    write_ktx(&mipmapped, &mut file).unwrap();
+    let written = parse_ktx(&file).unwrap();
+    assert_eq!(written.header.mip_levels, 8);
+    for level in 0..8 {
+        assert_eq!(written.image(level, 0, 0), mipmapped.image(level, 0, 0));
+    }
+    assert_eq!(written.image(0, 0, 0), data.image(0, 0, 0));
+}
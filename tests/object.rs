@@ -0,0 +1,701 @@
+use std::{env, fs, io};
+use support::{
+    assets::asset_path,
+    object::{
+        load_mesh_asset, load_mesh_file, parse_mesh, writer::write_sbm, Indices, Mesh,
+        MeshAttribute, SbmError, SubObject, VertexStream,
+    },
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_SHORT: u32 = 0x1402;
+const GL_UNSIGNED_SHORT: u32 = 0x1403;
+const GL_UNSIGNED_INT: u32 = 0x1405;
+const GL_FLOAT: u32 = 0x1406;
+const GL_HALF_FLOAT: u32 = 0x140B;
+const GL_UNSIGNED_INT_2_10_10_10_REV: u32 = 0x8368;
+
+const CUBE: &[u8] = include_bytes!("../assets/objects/cube.sbm");
+
+// Offsets into cube.sbm, which holds an ATRB chunk for its position and normal then VRTX
+const ATRB: usize = 16;
+const POSITION: usize = ATRB + 12;
+const NORMAL: usize = POSITION + 84;
+const VRTX: usize = 196;
+
+// Attribute fields follow the 64 byte name
+const SIZE: usize = 64;
+const TYPE: usize = 68;
+const STRIDE: usize = 72;
+const DATA_OFFSET: usize = 80;
+
+fn get(file: &[u8], offset: usize) -> u32 {
+    let bytes = &file[offset..offset + 4];
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn set(file: &mut [u8], offset: usize, value: u32) {
+    file[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+}
+
+fn chunk(tag: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut chunk = tag.to_vec();
+    chunk.extend(((body.len() + 8) as u32).to_le_bytes());
+    chunk.extend(body);
+    chunk
+}
+
+fn sbm(chunks: &[Vec<u8>]) -> Vec<u8> {
+    let mut file = b"SB6M".to_vec();
+    for value in [16, chunks.len() as u32, 0] {
+        file.extend(value.to_le_bytes());
+    }
+    for chunk in chunks {
+        file.extend(chunk);
+    }
+    file
+}
+
+fn parse(file: Vec<u8>) -> SbmError {
+    match parse_mesh(&file) {
+        Err(error) => error,
+        Ok(_) => panic!("a malformed file was parsed"),
+    }
+}
+
+fn cube_with<F: FnOnce(&mut Vec<u8>)>(change: F) -> SbmError {
+    let mut file = CUBE.to_vec();
+    change(&mut file);
+    parse(file)
+}
+
+// Puts a chunk in front of the cube's, moving the vertex data that follows them along
+fn insert_chunk(file: &mut Vec<u8>, chunk: Vec<u8>) {
+    let length = chunk.len();
+    let (chunk_count, vertices) = (get(file, 8), get(file, VRTX + 12));
+    set(file, 8, chunk_count + 1);
+    set(file, VRTX + 12, vertices + length as u32);
+    file.splice(ATRB..ATRB, chunk);
+}
+
+// The cube with an INDX chunk for indices appended to the end of the file
+fn indexed_cube(index_type: u32, index_count: u32, indices: &[u8]) -> Vec<u8> {
+    let mut file = CUBE.to_vec();
+    let mut fields = Vec::new();
+    for value in [index_type, index_count, (CUBE.len() + 20) as u32] {
+        fields.extend(value.to_le_bytes());
+    }
+    insert_chunk(&mut file, chunk(b"INDX", &fields));
+    file.extend(indices);
+    file
+}
+
+fn invalid_attribute(error: SbmError) -> (String, &'static str) {
+    match error {
+        SbmError::InvalidAttribute { name, reason, .. } => (name, reason),
+        error => panic!("{:?} is not an attribute error", error),
+    }
+}
+
+#[test]
+fn rejects_malformed_headers() {
+    let truncated = |offset| SbmError::Truncated {
+        chunk: "SB6M".to_string(),
+        offset,
+    };
+    assert_eq!(parse(Vec::new()), truncated(0));
+    assert_eq!(parse(b"SB6".to_vec()), truncated(0));
+    assert_eq!(parse(CUBE[..10].to_vec()), truncated(8));
+    assert_eq!(parse(b"OBJ0".to_vec()), SbmError::InvalidIdentifier);
+    assert_eq!(
+        cube_with(|file| file[0] = b'X'),
+        SbmError::InvalidIdentifier
+    );
+
+    for size in [0, 15, CUBE.len() as u32 + 1, u32::MAX] {
+        let error = cube_with(|file| set(file, 4, size));
+        assert!(
+            matches!(error, SbmError::InvalidChunk { offset: 4, .. }),
+            "{:?} for a header of {} bytes",
+            error,
+            size
+        );
+    }
+
+    // More chunks than the file holds, so the vertex data is read as one
+    let error = cube_with(|file| set(file, 8, 3));
+    assert!(matches!(error, SbmError::Truncated { offset: 216, .. }));
+}
+
+#[test]
+fn rejects_malformed_chunks() {
+    // Comments used to be sized by subtracting the chunk header without checking it fit
+    let mut comment = b"CMNT".to_vec();
+    comment.extend(4u32.to_le_bytes());
+    let error = parse(sbm(&[comment]));
+    assert_eq!(
+        error,
+        SbmError::InvalidChunk {
+            chunk: "CMNT".to_string(),
+            offset: 20,
+            reason: "chunk is smaller than its header",
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "invalid CMNT chunk at offset 20: chunk is smaller than its header"
+    );
+
+    let error = parse(sbm(&[chunk(b"CMNT", &[0xff, 0xfe, 0])]));
+    assert!(matches!(error, SbmError::InvalidChunk { offset: 24, .. }));
+
+    // Chunks running past the end of the file
+    assert_eq!(
+        cube_with(|file| set(file, VRTX + 4, 1197)),
+        SbmError::Truncated {
+            chunk: "VRTX".to_string(),
+            offset: VRTX,
+        }
+    );
+    let error = cube_with(|file| set(file, ATRB + 4, u32::MAX));
+    assert!(matches!(error, SbmError::Truncated { offset: ATRB, .. }));
+
+    let error = cube_with(|file| file[VRTX..VRTX + 4].copy_from_slice(b"NOPE"));
+    assert_eq!(
+        error,
+        SbmError::InvalidChunk {
+            chunk: "NOPE".to_string(),
+            offset: VRTX,
+            reason: "unknown chunk type",
+        }
+    );
+}
+
+#[test]
+fn rejects_counts_past_the_end_of_their_chunk() {
+    let error = cube_with(|file| set(file, ATRB + 8, 3));
+    assert_eq!(
+        error,
+        SbmError::Truncated {
+            chunk: "ATRB".to_string(),
+            offset: VRTX,
+        }
+    );
+
+    let mut sub_objects = 2u32.to_le_bytes().to_vec();
+    sub_objects.extend([0; 8]);
+    let error = parse(sbm(&[chunk(b"OLST", &sub_objects)]));
+    assert!(matches!(error, SbmError::Truncated { ref chunk, .. } if chunk == "OLST"));
+
+    let error = cube_with(|file| file[POSITION] = 0xff);
+    assert!(matches!(
+        error,
+        SbmError::InvalidChunk {
+            offset: POSITION,
+            ..
+        }
+    ));
+
+    // Vertex data past the end of the file
+    let error = cube_with(|file| set(file, VRTX + 8, 1177));
+    assert_eq!(
+        error,
+        SbmError::Truncated {
+            chunk: "VRTX".to_string(),
+            offset: VRTX + 20,
+        }
+    );
+}
+
+#[test]
+fn rejects_attributes_outside_the_vertex_data() {
+    // The normals end exactly at the end of the vertex data
+    let error = cube_with(|file| set(file, NORMAL + DATA_OFFSET, 676));
+    assert_eq!(
+        error,
+        SbmError::InvalidAttribute {
+            name: "normal".to_string(),
+            offset: 676,
+            reason: "reads past the end of the vertex data",
+        }
+    );
+    assert_eq!(
+        error.to_string(),
+        "invalid vertex attribute normal at offset 676: reads past the end of the vertex data"
+    );
+
+    let error = cube_with(|file| set(file, POSITION + STRIDE, 32));
+    assert_eq!(invalid_attribute(error).0, "position");
+    let error = cube_with(|file| set(file, POSITION + DATA_OFFSET, u32::MAX));
+    assert_eq!(invalid_attribute(error).0, "position");
+    let error = cube_with(|file| {
+        set(file, NORMAL + STRIDE, u32::MAX);
+        set(file, NORMAL + DATA_OFFSET, 0);
+    });
+    assert_eq!(invalid_attribute(error).0, "normal");
+
+    // Larger components need more room than the file's floats
+    let error = cube_with(|file| set(file, POSITION + TYPE, 0x140A));
+    assert_eq!(invalid_attribute(error).0, "position");
+
+    for (size, attribute_type) in [
+        (0, GL_FLOAT),
+        (5, GL_FLOAT),
+        (3, 0x1234),
+        (3, GL_UNSIGNED_INT_2_10_10_10_REV),
+    ] {
+        let error = cube_with(|file| {
+            set(file, NORMAL + SIZE, size);
+            set(file, NORMAL + TYPE, attribute_type);
+        });
+        assert_eq!(invalid_attribute(error).0, "normal");
+    }
+
+    // Half floats take half the room, though not little enough to start this late
+    let error = cube_with(|file| {
+        set(file, NORMAL + TYPE, GL_HALF_FLOAT);
+        set(file, NORMAL + DATA_OFFSET, 676 + 252);
+    });
+    assert_eq!(invalid_attribute(error).0, "normal");
+}
+
+#[test]
+fn rejects_missing_chunks_and_sub_objects_out_of_range() {
+    let cube = |chunk_count| {
+        let mut file = CUBE.to_vec();
+        set(&mut file, 8, chunk_count);
+        file
+    };
+    assert_eq!(parse(cube(1)), SbmError::MissingChunk("VRTX"));
+
+    let mut file = CUBE[..ATRB].to_vec();
+    file.extend(&CUBE[VRTX..]);
+    set(&mut file, 8, 1);
+    assert_eq!(parse(file), SbmError::MissingChunk("ATRB"));
+
+    let mut sub_objects = 2u32.to_le_bytes().to_vec();
+    for value in [0u32, 36, 36, 7] {
+        sub_objects.extend(value.to_le_bytes());
+    }
+    let mut file = CUBE.to_vec();
+    insert_chunk(&mut file, chunk(b"OLST", &sub_objects));
+    assert_eq!(
+        parse(file),
+        SbmError::InvalidChunk {
+            chunk: "OLST".to_string(),
+            offset: ATRB,
+            reason: "sub-object draws past the end of the vertex or index data",
+        }
+    );
+}
+
+#[test]
+fn rejects_malformed_index_data() {
+    let error = parse(indexed_cube(GL_FLOAT, 3, &[0; 12]));
+    assert_eq!(
+        error,
+        SbmError::InvalidChunk {
+            chunk: "INDX".to_string(),
+            offset: ATRB + 8,
+            reason: "unknown index type",
+        }
+    );
+
+    // Indices past the end of the file, at each index size
+    let end = CUBE.len() + 20;
+    for (index_type, bytes) in [
+        (GL_UNSIGNED_BYTE, 1),
+        (GL_UNSIGNED_SHORT, 2),
+        (GL_UNSIGNED_INT, 4),
+    ] {
+        let error = parse(indexed_cube(index_type, 4, &vec![0; 3 * bytes]));
+        assert_eq!(
+            error,
+            SbmError::Truncated {
+                chunk: "INDX".to_string(),
+                offset: end,
+            }
+        );
+    }
+    let error = parse(indexed_cube(GL_UNSIGNED_INT, u32::MAX, &[0; 4]));
+    assert!(matches!(error, SbmError::Truncated { offset, .. } if offset == end));
+    let mut file = indexed_cube(GL_UNSIGNED_INT, 1, &[0; 4]);
+    set(&mut file, ATRB + 16, u32::MAX);
+    let length = file.len();
+    assert!(matches!(parse(file), SbmError::Truncated { offset, .. } if offset == length));
+
+    // The cube has 42 vertices, so 41 is the last index that can be drawn
+    let out_of_range = SbmError::InvalidChunk {
+        chunk: "INDX".to_string(),
+        offset: ATRB,
+        reason: "index refers past the last vertex",
+    };
+    assert_eq!(
+        parse(indexed_cube(GL_UNSIGNED_BYTE, 3, &[0, 41, 42])),
+        out_of_range
+    );
+    let indices: Vec<u8> = [0u16, 300, 1]
+        .iter()
+        .flat_map(|index| index.to_le_bytes())
+        .collect();
+    assert_eq!(
+        parse(indexed_cube(GL_UNSIGNED_SHORT, 3, &indices)),
+        out_of_range
+    );
+    let indices: Vec<u8> = [0u32, 1, u32::MAX]
+        .iter()
+        .flat_map(|index| index.to_le_bytes())
+        .collect();
+    assert_eq!(
+        parse(indexed_cube(GL_UNSIGNED_INT, 3, &indices)),
+        out_of_range
+    );
+
+    // Indexed sub-objects are ranges of indices rather than vertices
+    let mut file = indexed_cube(GL_UNSIGNED_BYTE, 6, &[0, 1, 2, 2, 1, 3]);
+    let mut sub_objects = 2u32.to_le_bytes().to_vec();
+    for value in [0u32, 3, 3, 6] {
+        sub_objects.extend(value.to_le_bytes());
+    }
+    insert_chunk(&mut file, chunk(b"OLST", &sub_objects));
+    let error = parse(file);
+    assert!(matches!(error, SbmError::InvalidChunk { ref chunk, .. } if chunk == "OLST"));
+}
+
+#[test]
+fn rejects_payloads_outside_the_file_or_their_data_chunk() {
+    // Vertex data is found by its offset rather than following its chunk
+    let error = cube_with(|file| set(file, VRTX + 12, CUBE.len() as u32 - 1175));
+    assert_eq!(
+        error,
+        SbmError::Truncated {
+            chunk: "VRTX".to_string(),
+            offset: CUBE.len() - 1175,
+        }
+    );
+    let error = cube_with(|file| set(file, VRTX + 12, u32::MAX));
+    assert!(matches!(error, SbmError::Truncated { offset, .. } if offset == CUBE.len()));
+
+    let data_chunk = |encoding: u32, offset: u32, length: u32| {
+        let mut fields = Vec::new();
+        for value in [encoding, offset, length] {
+            fields.extend(value.to_le_bytes());
+        }
+        let mut file = CUBE.to_vec();
+        insert_chunk(&mut file, chunk(b"DATA", &fields));
+        file
+    };
+    // The vertex data starts at 236 once the chunk is in front of it
+    let invalid = |reason| SbmError::InvalidChunk {
+        chunk: "DATA".to_string(),
+        offset: ATRB,
+        reason,
+    };
+    assert_eq!(
+        parse(data_chunk(7, 236, 1176)),
+        invalid("unknown data encoding")
+    );
+    assert_eq!(
+        parse(data_chunk(0, 236, 1175)),
+        invalid("payload runs past the end of its data chunk")
+    );
+    assert_eq!(
+        parse(data_chunk(0, 200, 1000)),
+        invalid("payload runs past the end of its data chunk")
+    );
+    assert_eq!(
+        parse(data_chunk(0, 236, 1177)),
+        SbmError::Truncated {
+            chunk: "DATA".to_string(),
+            offset: 236,
+        }
+    );
+
+    // Indices are read through data chunks too, and move along with the vertex data
+    let mut file = indexed_cube(GL_UNSIGNED_SHORT, 3, &[0; 6]);
+    let indices = CUBE.len() as u32 + 40;
+    let mut fields = Vec::new();
+    for value in [0, indices, 4] {
+        fields.extend(value.to_le_bytes());
+    }
+    insert_chunk(&mut file, chunk(b"DATA", &fields));
+    set(&mut file, ATRB + 20 + 16, indices);
+    assert_eq!(
+        parse(file),
+        invalid("payload runs past the end of its data chunk")
+    );
+}
+
+#[test]
+fn rejects_every_truncation() {
+    for length in 0..CUBE.len() {
+        parse(CUBE[..length].to_vec());
+    }
+}
+
+#[test]
+fn parses_meshes_without_gl() {
+    let cube = parse_mesh(CUBE).unwrap();
+    assert_eq!(cube.vertex_count, 42);
+    assert_eq!(cube.indices, None);
+    assert_eq!(
+        cube.sub_objects,
+        vec![SubObject {
+            first: 0,
+            count: 42
+        }]
+    );
+    assert_eq!(cube.comment, None);
+    let names: Vec<_> = cube
+        .attributes
+        .iter()
+        .map(|attribute| &attribute.name)
+        .collect();
+    assert_eq!(names, ["position", "normal"]);
+
+    let position = cube.attribute("position").unwrap();
+    assert_eq!(position.components, 4);
+    assert!(!position.normalized && !position.integer);
+    match &position.stream {
+        VertexStream::Float(values) => {
+            assert_eq!(values.len(), 42 * 4);
+            assert!(values.chunks(4).all(|position| position[3] == 1.0));
+        }
+        stream => panic!("positions are {:?}", stream),
+    }
+    match &cube.attribute("normal").unwrap().stream {
+        VertexStream::Float(values) => {
+            assert_eq!(values.len(), 42 * 3);
+            for normal in values.chunks(3) {
+                let length = normal.iter().map(|value| value * value).sum::<f32>();
+                assert!((length - 1.0).abs() < 1e-4);
+            }
+        }
+        stream => panic!("normals are {:?}", stream),
+    }
+
+    let asteroids = load_mesh_asset("objects/asteroids.sbm").unwrap();
+    assert_eq!(asteroids.comment.as_deref(), Some("Created by sb6mtool"));
+    assert_eq!(asteroids.sub_objects.len(), 100);
+    let normal = asteroids.attribute("normal").unwrap();
+    assert_eq!(normal.stream.gl_type(), GL_HALF_FLOAT);
+    assert_eq!(
+        normal.stream.to_le_bytes().len(),
+        asteroids.vertex_count as usize * 8
+    );
+}
+
+#[test]
+fn reads_interleaved_and_indexed_vertices() {
+    // Two byte positions and an integer short id interleaved in six byte vertices, with
+    // the vertex data ahead of the chunks
+    let mut vertices = Vec::new();
+    for vertex in 0..3u8 {
+        vertices.extend([vertex, vertex + 10]);
+        vertices.extend((1000 + vertex as u16).to_le_bytes());
+        vertices.extend([0xaa, 0xbb]);
+    }
+    let attribute = |name: &[u8], fields: [u32; 5]| {
+        let mut record = name.to_vec();
+        record.resize(64, 0);
+        for value in fields.iter() {
+            record.extend(value.to_le_bytes());
+        }
+        record
+    };
+    let mut attributes = 2u32.to_le_bytes().to_vec();
+    attributes.extend(attribute(b"position", [2, GL_UNSIGNED_BYTE, 6, 1, 0]));
+    attributes.extend(attribute(b"id", [1, GL_SHORT, 6, 2, 2]));
+    let mut fields = Vec::new();
+    for value in [18, 16, 3, GL_UNSIGNED_SHORT, 3, 34] {
+        fields.extend(value.to_le_bytes());
+    }
+    let mut file = b"SB6M".to_vec();
+    for value in [40u32, 3, 0] {
+        file.extend(value.to_le_bytes());
+    }
+    file.extend(&vertices);
+    file.extend([2, 0, 1, 0, 0, 0]);
+    file.extend(chunk(b"ATRB", &attributes));
+    file.extend(chunk(b"VRTX", &fields[..12]));
+    file.extend(chunk(b"INDX", &fields[12..]));
+
+    let mesh = parse_mesh(&file).unwrap();
+    let position = mesh.attribute("position").unwrap();
+    assert!(position.normalized && !position.integer);
+    assert_eq!(
+        position.stream,
+        VertexStream::UnsignedByte(vec![0, 10, 1, 11, 2, 12])
+    );
+    let id = mesh.attribute("id").unwrap();
+    assert!(id.integer && !id.normalized);
+    assert_eq!(id.stream, VertexStream::Short(vec![1000, 1001, 1002]));
+    assert_eq!(mesh.indices, Some(Indices::UnsignedShort(vec![2, 1, 0])));
+    assert_eq!(mesh.sub_objects, vec![SubObject { first: 0, count: 3 }]);
+
+    // Only integer types can be read as integers
+    set(&mut file, 40 + 12 + 84 + 68, GL_HALF_FLOAT);
+    assert_eq!(
+        parse(file),
+        SbmError::InvalidAttribute {
+            name: "id".to_string(),
+            offset: 2,
+            reason: "only integer types can be read as integers",
+        }
+    );
+}
+
+#[test]
+fn loads_meshes_from_files() {
+    let path = env::temp_dir().join(format!("superbible-{}-cube.sbm", std::process::id()));
+    fs::write(&path, CUBE).unwrap();
+    let mesh = load_mesh_file(&path);
+    fs::remove_file(&path).unwrap();
+    assert_eq!(mesh.unwrap(), parse_mesh(CUBE).unwrap());
+
+    match load_mesh_file(&path) {
+        Err(SbmError::Io { kind, reason }) => {
+            assert_eq!(kind, io::ErrorKind::NotFound);
+            assert!(reason.contains("cube.sbm"));
+        }
+        result => panic!("{:?} for a missing file", result),
+    }
+}
+
+fn round_trip(mesh: &Mesh) -> Vec<u8> {
+    let mut file = Vec::new();
+    write_sbm(mesh, &mut file).unwrap();
+    assert_eq!(&parse_mesh(&file).unwrap(), mesh);
+    file
+}
+
+#[test]
+fn round_trips_every_object() {
+    let mut round_tripped = 0;
+    for entry in fs::read_dir(asset_path("objects")).unwrap() {
+        let path = entry.unwrap().path();
+        let file = fs::read(&path).unwrap();
+        // Models too large for the repository are left as empty placeholders
+        if file.is_empty() {
+            continue;
+        }
+        let mesh = parse_mesh(&file).unwrap();
+        let written = round_trip(&mesh);
+        assert_eq!(round_trip(&parse_mesh(&written).unwrap()), written);
+        round_tripped += 1;
+    }
+    assert!(round_tripped >= 5);
+}
+
+fn attribute(name: &str, components: u32, stream: VertexStream) -> MeshAttribute {
+    MeshAttribute {
+        name: name.to_string(),
+        components,
+        normalized: false,
+        integer: false,
+        stream,
+    }
+}
+
+// Three vertices of every stream type, with byte streams ahead of the doubles so the
+// doubles need padding
+fn every_stream_type() -> Mesh {
+    let mut attributes = vec![
+        attribute("bytes", 1, VertexStream::Byte(vec![-1, 0, 1])),
+        attribute(
+            "doubles",
+            2,
+            VertexStream::Double(vec![0.5, 1.5, 2.5, 3.5, 4.5, 5.5]),
+        ),
+        attribute("colors", 4, VertexStream::UnsignedByte(vec![255; 12])),
+        attribute("shorts", 1, VertexStream::Short(vec![-300, 0, 300])),
+        attribute("ushorts", 1, VertexStream::UnsignedShort(vec![1, 2, 60000])),
+        attribute("halves", 2, VertexStream::HalfFloat(vec![0x3c00; 6])),
+        attribute("ints", 1, VertexStream::Int(vec![-70000, 0, 70000])),
+        attribute("uints", 1, VertexStream::UnsignedInt(vec![0, 1, u32::MAX])),
+        attribute("fixed", 1, VertexStream::Fixed(vec![0x10000; 3])),
+        attribute("floats", 3, VertexStream::Float(vec![0.25; 9])),
+        attribute("normals", 4, VertexStream::Int2_10_10_10Rev(vec![1, 2, 3])),
+        attribute(
+            "tangents",
+            4,
+            VertexStream::UnsignedInt2_10_10_10Rev(vec![4, 5, 6]),
+        ),
+        attribute(
+            "colors_hdr",
+            3,
+            VertexStream::UnsignedInt10F11F11FRev(vec![7, 8, 9]),
+        ),
+    ];
+    attributes[2].normalized = true;
+    attributes[6].integer = true;
+    Mesh {
+        attributes,
+        vertex_count: 3,
+        indices: None,
+        sub_objects: vec![SubObject { first: 0, count: 3 }],
+        comment: Some("every stream type".to_string()),
+    }
+}
+
+#[test]
+fn round_trips_generated_meshes() {
+    let mut mesh = every_stream_type();
+    round_trip(&mesh);
+
+    // Each index type, with sub-objects as ranges of indices
+    mesh.sub_objects = vec![
+        SubObject { first: 0, count: 3 },
+        SubObject { first: 3, count: 3 },
+    ];
+    for indices in [
+        Indices::UnsignedByte(vec![0, 1, 2, 2, 1, 0]),
+        Indices::UnsignedShort(vec![0, 1, 2, 2, 1, 0]),
+        Indices::UnsignedInt(vec![0, 1, 2, 2, 1, 0]),
+    ] {
+        mesh.indices = Some(indices);
+        let file = round_trip(&mesh);
+        // The indices are written last
+        assert!(file.ends_with(&mesh.indices.as_ref().unwrap().to_le_bytes()));
+    }
+
+    // Comments of any length, empty sub-object lists and empty meshes all survive
+    for comment in ["", "a", "abc", "abcd"] {
+        mesh.comment = Some(comment.to_string());
+        round_trip(&mesh);
+    }
+    mesh.comment = None;
+    mesh.sub_objects.clear();
+    round_trip(&mesh);
+    round_trip(&Mesh {
+        attributes: Vec::new(),
+        vertex_count: 0,
+        indices: None,
+        sub_objects: Vec::new(),
+        comment: None,
+    });
+}
+
+#[test]
+fn rejects_meshes_the_loader_would_not_read() {
+    let invalid = |change: &dyn Fn(&mut Mesh)| {
+        let mut mesh = every_stream_type();
+        change(&mut mesh);
+        let error = write_sbm(&mesh, &mut Vec::new()).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        error.to_string()
+    };
+    assert!(invalid(&|mesh| mesh.vertex_count = 4).contains("bytes has 3 values"));
+    assert!(invalid(&|mesh| mesh.attributes[9].components = 5).contains("floats can't have 5"));
+    assert!(invalid(&|mesh| mesh.attributes[10].components = 3).contains("normals"));
+    assert!(invalid(&|mesh| mesh.attributes[1].integer = true).contains("doubles"));
+    assert!(invalid(&|mesh| mesh.attributes[0].name = "x".repeat(65)).contains("longer"));
+    assert!(
+        invalid(&|mesh| mesh.indices = Some(Indices::UnsignedByte(vec![0, 3]))).contains("index 3")
+    );
+    assert!(invalid(&|mesh| mesh.sub_objects[0].count = 4).contains("sub-object 0"));
+    assert!(invalid(&|mesh| {
+        mesh.indices = Some(Indices::UnsignedShort(vec![0, 1]));
+    })
+    .contains("sub-object 0"));
+}
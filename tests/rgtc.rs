@@ -0,0 +1,193 @@
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::borrow::Cow;
+use support::ktx::{
+    decode::{decode_blocks, decode_image, Texels},
+    load_ktx_file,
+    mipmap::MipmapOptions,
+    parse_ktx,
+    rgtc::{compress_rgtc, encode_bc4_block},
+    writer::write_ktx,
+    Header, KeyValue, KtxData, KtxError, MipLevel,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_RED: u32 = 0x1903;
+const GL_RGBA: u32 = 0x1908;
+const GL_RG: u32 = 0x8227;
+const GL_R8: u32 = 0x8229;
+const GL_RG8: u32 = 0x822B;
+const GL_RGBA8: u32 = 0x8058;
+const GL_COMPRESSED_RED_RGTC1: u32 = 0x8DBB;
+const GL_COMPRESSED_RG_RGTC2: u32 = 0x8DBD;
+
+// A single level 2D texture of tightly packed bytes
+fn texture(
+    gl_format: u32,
+    gl_internal_format: u32,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+) -> KtxData<'static> {
+    let image = 0..data.len();
+    KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type: GL_UNSIGNED_BYTE,
+            gl_type_size: 1,
+            gl_format,
+            gl_internal_format,
+            gl_base_internal_format: gl_format,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: 0,
+            array_elements: 0,
+            faces: 1,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: vec![KeyValue {
+            key: "KTXorient".to_string(),
+            value: b"S=r,T=d\0".to_vec(),
+        }],
+        levels: vec![MipLevel {
+            width,
+            height,
+            depth: 1,
+            images: vec![image],
+        }],
+        unpack_alignment: 1,
+        pixels: Cow::Owned(data),
+    }
+}
+
+fn decode_bc4(block: &[u8; 8]) -> Vec<u8> {
+    match decode_blocks(GL_COMPRESSED_RED_RGTC1, 4, 4, 1, block)
+        .unwrap()
+        .texels
+    {
+        Texels::Rgba8(texels) => texels.chunks_exact(4).map(|texel| texel[0]).collect(),
+        Texels::Rgba32F(_) => unreachable!(),
+    }
+}
+
+#[test]
+fn encodes_bc4_blocks() {
+    // Flat blocks, eight evenly spaced values and six values with both extremes are exact
+    let flat = [77; 16];
+    assert_eq!(decode_bc4(&encode_bc4_block(&flat)), flat);
+    let ramp: Vec<u8> = (0..16).map(|texel| (texel % 8) * 35 + 10).collect();
+    let mut block = [0; 16];
+    block.copy_from_slice(&ramp);
+    assert_eq!(decode_bc4(&encode_bc4_block(&block)), ramp);
+    let extremes: Vec<u8> = (0..16)
+        .map(|texel| [0, 255, 100, 125, 150, 175, 200][texel % 7])
+        .collect();
+    block.copy_from_slice(&extremes);
+    let encoded = encode_bc4_block(&block);
+    assert!(encoded[0] <= encoded[1]);
+    assert_eq!(decode_bc4(&encoded), extremes);
+
+    // Random blocks land within half a palette step of every texel
+    let mut rng = StdRng::seed_from_u64(12);
+    for _ in 0..2000 {
+        let (low, high) = (rng.gen_range(0..=255u8), rng.gen_range(0..=255u8));
+        let (low, high) = (low.min(high), low.max(high));
+        for texel in block.iter_mut() {
+            *texel = rng.gen_range(low..=high);
+        }
+        let decoded = decode_bc4(&encode_bc4_block(&block));
+        let step = (high - low) as i32 / 7 + 1;
+        for (texel, value) in block.iter().zip(decoded.iter()) {
+            assert!((*texel as i32 - *value as i32).abs() <= step, "{:?}", block);
+        }
+    }
+}
+
+#[test]
+fn compresses_distance_fields() {
+    let source = load_ktx_file("assets/textures/chars-df-array.ktx").unwrap();
+    let compressed = compress_rgtc(&source, &MipmapOptions::default()).unwrap();
+    let texture = &compressed.texture;
+    assert_eq!(texture.header.gl_internal_format, GL_COMPRESSED_RED_RGTC1);
+    assert_eq!(texture.header.gl_base_internal_format, GL_RED);
+    assert_eq!((texture.header.gl_type, texture.header.gl_format), (0, 0));
+    assert_eq!(texture.header.mip_levels, 7);
+    assert_eq!(texture.levels.len(), 7);
+    assert_eq!(compressed.level_psnr.len(), 7);
+    assert!(texture.levels.iter().all(|level| level.images.len() == 24));
+    assert_eq!(texture.image(0, 23, 0).unwrap().len(), 16 * 16 * 8);
+    assert_eq!(texture.image(6, 0, 0).unwrap().len(), 8);
+    assert!(compressed.psnr > 40.0, "{}", compressed.psnr);
+    assert!(compressed.level_psnr[0] > 40.0);
+
+    // The reported PSNR is that of the decoded base level against the source
+    let decoded = match decode_image(texture, 0, 5, 0).unwrap().texels {
+        Texels::Rgba8(texels) => texels,
+        Texels::Rgba32F(_) => unreachable!(),
+    };
+    let original = source.image(0, 5, 0).unwrap();
+    let largest = decoded
+        .chunks_exact(4)
+        .zip(original.iter())
+        .map(|(texel, value)| (texel[0] as i32 - *value as i32).abs())
+        .max()
+        .unwrap();
+    assert!(largest < 16, "{}", largest);
+
+    let mut file = Vec::new();
+    write_ktx(texture, &mut file).unwrap();
+    let parsed = parse_ktx(&file).unwrap();
+    assert_eq!(parsed.header.gl_internal_format, GL_COMPRESSED_RED_RGTC1);
+    assert_eq!(parsed.image(3, 17, 0), texture.image(3, 17, 0));
+}
+
+#[test]
+fn compresses_rg_textures() {
+    // A smooth gradient whose size doesn't divide into blocks
+    let (width, height) = (6, 5);
+    let data = (0..width * height)
+        .flat_map(|texel| {
+            let (x, y) = (texel % width, texel / width);
+            [(x * 40 + 20) as u8, (y * 50 + 10) as u8]
+        })
+        .collect();
+    let compressed = compress_rgtc(
+        &texture(GL_RG, GL_RG8, width, height, data),
+        &MipmapOptions::default(),
+    )
+    .unwrap();
+    let rg = &compressed.texture;
+    assert_eq!(rg.header.gl_internal_format, GL_COMPRESSED_RG_RGTC2);
+    assert_eq!(rg.header.gl_base_internal_format, GL_RG);
+    assert_eq!(rg.key_values[0].key, "KTXorient");
+    let sizes: Vec<_> = (0..3)
+        .map(|level| rg.image(level, 0, 0).unwrap().len())
+        .collect();
+    assert_eq!(sizes, [4 * 16, 16, 16]);
+    assert!(compressed.psnr > 40.0, "{:?}", compressed.level_psnr);
+    // A lone texel is matched exactly
+    assert_eq!(compressed.level_psnr[2], f64::INFINITY);
+
+    // A texture that already has its full chain is compressed as it is
+    let single = compress_rgtc(
+        &texture(GL_RED, GL_R8, 1, 1, vec![42]),
+        &MipmapOptions::default(),
+    )
+    .unwrap();
+    assert_eq!(single.texture.levels.len(), 1);
+    assert_eq!(single.psnr, f64::INFINITY);
+}
+
+#[test]
+fn rejects_other_formats() {
+    let rgba = texture(GL_RGBA, GL_RGBA8, 4, 4, vec![0; 64]);
+    assert!(matches!(
+        compress_rgtc(&rgba, &MipmapOptions::default()),
+        Err(KtxError::UnsupportedFormat(_))
+    ));
+    let short = texture(GL_RED, GL_R8, 4, 4, vec![0; 15]);
+    assert!(matches!(
+        compress_rgtc(&short, &MipmapOptions::default()),
+        Err(KtxError::InvalidDataFormat { .. })
+    ));
+}
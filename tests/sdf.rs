@@ -0,0 +1,191 @@
+use support::{
+    image::Image,
+    ktx::{determine_target, parse_ktx, writer::write_ktx},
+    sdf::{distance_field_texture, generate_distance_field, DistanceFieldOptions},
+};
+
+const GL_RED: u32 = 0x1903;
+const GL_R8: u32 = 0x8229;
+const GL_TEXTURE_2D: u32 = 0x0DE1;
+const GL_TEXTURE_2D_ARRAY: u32 = 0x8C1A;
+
+// A one channel image that is 255 wherever inside is true of a texel center
+fn shape<F: Fn(f32, f32) -> bool>(width: u32, height: u32, inside: F) -> Image {
+    let mut data = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            let center = (x as f32 + 0.5, y as f32 + 0.5);
+            data.push(if inside(center.0, center.1) { 255 } else { 0 });
+        }
+    }
+    Image::new(width, height, 1, data).unwrap()
+}
+
+fn disc(size: u32, radius: f32) -> Image {
+    let center = size as f32 / 2.0;
+    shape(size, size, |x, y| (x - center).hypot(y - center) <= radius)
+}
+
+// The distance in field texels each value stands for, positive outside
+fn distances(field: &Image, spread: f32) -> Vec<f32> {
+    field
+        .data
+        .iter()
+        .map(|value| (*value as f32 / 255.0 - 0.5) * 2.0 * spread)
+        .collect()
+}
+
+#[test]
+fn measures_euclidean_distances() {
+    // Straight edges are exact, halfway between the last texel inside and the first outside
+    let half = shape(16, 4, |x, _| x < 8.0);
+    let options = DistanceFieldOptions {
+        spread: 8.0,
+        ..Default::default()
+    };
+    let field = generate_distance_field(&half, &options).unwrap();
+    assert_eq!((field.width, field.height, field.components), (16, 4, 1));
+    for (x, distance) in distances(&field, 8.0).iter().take(16).enumerate() {
+        let expected = x as f32 + 0.5 - 8.0;
+        assert!((distance - expected).abs() < 0.05, "{} at {}", distance, x);
+    }
+
+    // Diagonal distances aren't the city block or chessboard distance
+    let corner = shape(32, 32, |x, y| x < 4.0 && y < 4.0);
+    let options = DistanceFieldOptions {
+        spread: 64.0,
+        ..Default::default()
+    };
+    let field = distances(&generate_distance_field(&corner, &options).unwrap(), 64.0);
+    let far = field[31 * 32 + 31];
+    let expected = (27.5f32 * 27.5 * 2.0).sqrt();
+    assert!(
+        (far - expected).abs() < 0.6,
+        "{} instead of {}",
+        far,
+        expected
+    );
+
+    // Around a circle every texel is within a texel of the true distance
+    let options = DistanceFieldOptions {
+        spread: 32.0,
+        ..Default::default()
+    };
+    let field = distances(
+        &generate_distance_field(&disc(64, 20.0), &options).unwrap(),
+        32.0,
+    );
+    for (index, distance) in field.iter().enumerate() {
+        let (x, y) = ((index % 64) as f32 + 0.5, (index / 64) as f32 + 0.5);
+        let expected = (x - 32.0).hypot(y - 32.0) - 20.0;
+        assert!(
+            (distance - expected).abs() < 1.0,
+            "{} instead of {} at {}",
+            distance,
+            expected,
+            index
+        );
+    }
+}
+
+#[test]
+fn downsamples_to_the_target_size() {
+    let options = DistanceFieldOptions {
+        size: Some((32, 32)),
+        spread: 8.0,
+        ..Default::default()
+    };
+    let field = generate_distance_field(&disc(256, 80.0), &options).unwrap();
+    assert_eq!((field.width, field.height), (32, 32));
+    // Distances are measured in field texels, eight source texels each
+    for (index, distance) in distances(&field, 8.0).iter().enumerate() {
+        let (x, y) = ((index % 32) as f32 + 0.5, (index / 32) as f32 + 0.5);
+        let expected = ((x - 16.0).hypot(y - 16.0) - 10.0).clamp(-8.0, 8.0);
+        assert!(
+            (distance - expected).abs() < 0.25,
+            "{} instead of {} at {}",
+            distance,
+            expected,
+            index
+        );
+    }
+
+    // Far from the edge values saturate, dark inside and bright outside
+    assert_eq!(field.data[16 * 32 + 16], 0);
+    assert_eq!(field.data[0], 255);
+}
+
+#[test]
+fn thresholds_alpha() {
+    // White everywhere, with the shape only in alpha
+    let mut data = Vec::new();
+    for x in 0..8 {
+        data.extend([255, 255, 255, if x < 4 { 200 } else { 100 }]);
+    }
+    let image = Image::new(8, 1, 4, data).unwrap();
+    let field = generate_distance_field(&image, &Default::default()).unwrap();
+    assert!(field.data[..4].iter().all(|value| *value < 128));
+    assert!(field.data[4..].iter().all(|value| *value > 128));
+
+    let options = DistanceFieldOptions {
+        threshold: 50,
+        ..Default::default()
+    };
+    let field = generate_distance_field(&image, &options).unwrap();
+    assert!(field.data.iter().all(|value| *value == 0));
+}
+
+#[test]
+fn writes_glyph_arrays() {
+    let glyphs: Vec<_> = [8.0, 12.0, 16.0]
+        .iter()
+        .map(|radius| disc(64, *radius))
+        .collect();
+    let options = DistanceFieldOptions {
+        size: Some((16, 16)),
+        ..Default::default()
+    };
+    let array = distance_field_texture(&glyphs, &options).unwrap();
+    assert_eq!(determine_target(&array), Ok(GL_TEXTURE_2D_ARRAY));
+    assert_eq!(
+        (array.header.gl_format, array.header.gl_internal_format),
+        (GL_RED, GL_R8)
+    );
+    assert_eq!(array.header.array_elements, 3);
+    assert_eq!(array.header.pixel_width, 16);
+
+    let mut file = Vec::new();
+    write_ktx(&array, &mut file).unwrap();
+    let parsed = parse_ktx(&file).unwrap();
+    for (layer, glyph) in glyphs.iter().enumerate() {
+        let field = generate_distance_field(glyph, &options).unwrap();
+        assert_eq!(parsed.image(0, layer as u32, 0), Some(&field.data[..]));
+    }
+    // Bigger glyphs reach further from the center
+    let at_edge = |layer| parsed.image(0, layer, 0).unwrap()[8 * 16 + 13];
+    assert!(at_edge(0) > at_edge(1) && at_edge(1) > at_edge(2));
+
+    let single = distance_field_texture(&glyphs[..1], &options).unwrap();
+    assert_eq!(determine_target(&single), Ok(GL_TEXTURE_2D));
+}
+
+#[test]
+fn rejects_invalid_options() {
+    let image = disc(16, 4.0);
+    let larger = DistanceFieldOptions {
+        size: Some((32, 8)),
+        ..Default::default()
+    };
+    assert!(generate_distance_field(&image, &larger).is_err());
+    let empty = DistanceFieldOptions {
+        size: Some((0, 8)),
+        ..Default::default()
+    };
+    assert!(generate_distance_field(&image, &empty).is_err());
+    let flat = DistanceFieldOptions {
+        spread: 0.0,
+        ..Default::default()
+    };
+    assert!(generate_distance_field(&image, &flat).is_err());
+    assert!(distance_field_texture(&[image, disc(8, 2.0)], &Default::default()).is_err());
+}
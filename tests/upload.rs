@@ -0,0 +1,593 @@
+// Headless contexts are made from a test thread, which winit only allows on these platforms
+#![cfg(all(unix, not(target_os = "macos")))]
+
+use glutin::{
+    dpi::PhysicalSize, event_loop::EventLoop, platform::unix::EventLoopExtUnix, Context,
+    ContextBuilder, PossiblyCurrent,
+};
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use std::{borrow::Cow, env, fs, iter, path::PathBuf};
+use support::ktx::{
+    dds::parse_dds,
+    determine_target,
+    envmap::{reproject, Projection},
+    load_ktx_file,
+    mipmap::{generate_mipmaps, MipmapOptions},
+    parse_ktx, prepare_texture, read_texture,
+    rgtc::compress_rgtc,
+    stream::{StreamHandle, StreamStatus, TextureStreamer},
+    writer::write_ktx,
+    Header, KtxData, KtxError, MipLevel,
+};
+
+const GL_UNSIGNED_BYTE: u32 = 0x1401;
+const GL_FLOAT: u32 = 0x1406;
+const GL_DEPTH_COMPONENT: u32 = 0x1902;
+const GL_RGB: u32 = 0x1907;
+const GL_RGBA: u32 = 0x1908;
+const GL_BGR: u32 = 0x80E0;
+const GL_RGB8: u32 = 0x8051;
+const GL_RGBA32F: u32 = 0x8814;
+const GL_TEXTURE_BUFFER: u32 = 0x8C2A;
+
+// Makes a context current on the test's thread and loads GL from it. Tests that need one
+// are ignored by default, as there is no display to make it on in headless runs.
+fn headless_context() -> (EventLoop<()>, Context<PossiblyCurrent>) {
+    let event_loop: EventLoop<()> = EventLoop::new_any_thread();
+    let context = ContextBuilder::new()
+        .build_headless(&event_loop, PhysicalSize::new(1, 1))
+        .unwrap();
+    let context = unsafe { context.make_current().unwrap() };
+    gl::load_with(|symbol| context.get_proc_address(symbol) as *const _);
+    (event_loop, context)
+}
+
+// A single level RGB8 texture of random texels with tightly packed rows, mipmapped
+// and written out so it is read back at the file's alignment
+fn mipmapped_file(dimensions: [u32; 3], layers: u32) -> KtxData<'static> {
+    let [width, height, depth] = dimensions;
+    let mut random = StdRng::seed_from_u64(16);
+    let image_bytes = (width * height.max(1) * depth.max(1) * 3) as usize;
+    let images: Vec<_> = (0..layers.max(1))
+        .map(|layer| {
+            let start = layer as usize * image_bytes;
+            start..start + image_bytes
+        })
+        .collect();
+    let pixels = (0..image_bytes * images.len())
+        .map(|_| random.gen())
+        .collect();
+    let texture = KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type: GL_UNSIGNED_BYTE,
+            gl_type_size: 1,
+            gl_format: GL_RGB,
+            gl_internal_format: GL_RGB8,
+            gl_base_internal_format: GL_RGB,
+            pixel_width: width,
+            pixel_height: height,
+            pixel_depth: depth,
+            array_elements: layers,
+            faces: 1,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: vec![MipLevel {
+            width,
+            height: height.max(1),
+            depth: depth.max(1),
+            images,
+        }],
+        unpack_alignment: 1,
+        pixels: Cow::Owned(pixels),
+    };
+
+    let mipmapped = generate_mipmaps(&texture, &MipmapOptions::default()).unwrap();
+    let mut file = Vec::new();
+    write_ktx(&mipmapped, &mut file).unwrap();
+    parse_ktx(&file).unwrap().into_owned()
+}
+
+// A DX10 DDS file of two BC1 cube maps, each face with 8x8 to 1x1 levels of random blocks
+fn dds_cube_array() -> KtxData<'static> {
+    let mut random = StdRng::seed_from_u64(20);
+    let mut file = vec![0; 148];
+    file[..4].copy_from_slice(b"DDS ");
+    let dx10 = u32::from_le_bytes(*b"DX10");
+    let fields = [
+        (4, 124),
+        (12, 8),
+        (16, 8),
+        (28, 4),
+        (76, 32),
+        (80, 4),
+        (84, dx10),
+        (128, 71),
+        (132, 3),
+        (136, 4),
+        (140, 2),
+    ];
+    for (offset, value) in fields.iter() {
+        file[*offset..*offset + 4].copy_from_slice(&u32::to_le_bytes(*value));
+    }
+    // Four blocks in the first level and one in each of the other three
+    file.extend((0..12 * 7 * 8).map(|_| random.gen::<u8>()));
+    parse_dds(&file).unwrap()
+}
+
+// Every target with more than one level, including the book's files, some tightly packed,
+// and a compressed array with a full chain. Odd widths pad every row of the written files.
+fn level_textures() -> Vec<(&'static str, KtxData<'static>)> {
+    let distance_fields = load_ktx_file("assets/textures/chars-df-array.ktx").unwrap();
+    let compressed = compress_rgtc(&distance_fields, &MipmapOptions::default()).unwrap();
+    assert!(compressed.texture.levels.len() > 1);
+    vec![
+        ("1D", mipmapped_file([13, 0, 0], 0)),
+        ("1D array", mipmapped_file([13, 0, 0], 3)),
+        ("2D", mipmapped_file([7, 5, 0], 0)),
+        ("2D array", mipmapped_file([7, 5, 0], 3)),
+        ("3D", mipmapped_file([7, 5, 3], 0)),
+        (
+            "mountains3d.ktx",
+            load_ktx_file("assets/textures/envmaps/mountains3d.ktx").unwrap(),
+        ),
+        (
+            "gllogodistsmarray.ktx",
+            load_ktx_file("assets/textures/gllogodistsmarray.ktx").unwrap(),
+        ),
+        ("compressed array", compressed.texture),
+    ]
+}
+
+// Compares the rows of two images stored at different alignments, ignoring row padding.
+// Stored BGR texels are swapped to the RGB order GL reads them back in.
+fn assert_rows_match(
+    read: (&[u8], usize),
+    stored: (&[u8], usize),
+    row: usize,
+    bgr: bool,
+    name: &str,
+) {
+    let stride = |alignment: usize| row.div_ceil(alignment) * alignment;
+    let read_rows = read.0.chunks(stride(read.1));
+    let stored_rows = stored.0.chunks(stride(stored.1));
+    assert_eq!(read_rows.len(), stored_rows.len(), "{}", name);
+    for (index, (read, stored)) in read_rows.zip(stored_rows).enumerate() {
+        let mut stored = stored[..row].to_vec();
+        if bgr {
+            stored
+                .chunks_exact_mut(3)
+                .for_each(|texel| texel.swap(0, 2));
+        }
+        assert_eq!(read[..row], stored[..], "{} row {}", name, index);
+    }
+}
+
+// Uploads a texture and reads every level back from GL to compare with the texture it was
+// uploaded from. The texture is left for the caller to delete.
+fn assert_uploads(name: &str, ktx_texture: &KtxData) -> u32 {
+    let ktx = &ktx_texture.header;
+    let target = determine_target(ktx_texture).unwrap();
+    let texture = prepare_texture(ktx_texture).unwrap();
+    let mut alignment = 0;
+    unsafe {
+        gl::GetIntegerv(gl::UNPACK_ALIGNMENT, &mut alignment);
+        gl::PixelStorei(gl::PACK_ALIGNMENT, ktx_texture.unpack_alignment);
+    }
+    assert_eq!(alignment, 4, "{} left UNPACK_ALIGNMENT changed", name);
+
+    for (level, mip) in ktx_texture.levels.iter().enumerate() {
+        let expected = ktx_texture.level_data(level as u32).unwrap();
+        let mut pixels = vec![0u8; expected.len()];
+        unsafe {
+            if ktx.is_compressed() {
+                gl::GetCompressedTexImage(target, level as i32, pixels.as_mut_ptr() as *mut _);
+            } else {
+                gl::GetTexImage(
+                    target,
+                    level as i32,
+                    ktx.gl_format,
+                    ktx.gl_type,
+                    pixels.as_mut_ptr() as *mut _,
+                );
+            }
+            assert_eq!(gl::GetError(), gl::NO_ERROR, "{} level {}", name, level);
+        }
+
+        if ktx.is_compressed() {
+            assert_eq!(pixels, expected, "{} level {}", name, level);
+        } else {
+            let alignment = ktx_texture.unpack_alignment as usize;
+            assert_rows_match(
+                (&pixels, alignment),
+                (expected, alignment),
+                (mip.width * ktx.pixel_size().unwrap()) as usize,
+                false,
+                &format!("{} level {}", name, level),
+            );
+        }
+    }
+
+    texture
+}
+
+// Compares what read_texture makes of a texture with the texture it was uploaded from
+fn assert_reads_back(name: &str, texture: u32, ktx_texture: &KtxData) {
+    let ktx = &ktx_texture.header;
+    let read = read_texture(texture, determine_target(ktx_texture).unwrap()).unwrap();
+    assert_eq!(read.unpack_alignment, 4);
+    assert_eq!(
+        [
+            read.header.gl_internal_format,
+            read.header.pixel_width,
+            read.header.pixel_height,
+            read.header.pixel_depth,
+            read.header.array_elements,
+            read.header.face_count(),
+            read.header.mip_levels,
+        ],
+        [
+            ktx.gl_internal_format,
+            ktx.pixel_width,
+            ktx.pixel_height,
+            ktx.pixel_depth,
+            ktx.array_elements,
+            ktx.face_count(),
+            ktx.level_count(),
+        ],
+        "{} read back",
+        name
+    );
+    for (level, mip) in ktx_texture.levels.iter().enumerate() {
+        for (index, range) in mip.images.iter().enumerate() {
+            let name = format!("{} read back level {} image {}", name, level, index);
+            let image = &read.pixels[read.levels[level].images[index].clone()];
+            let stored = &ktx_texture.pixels[range.clone()];
+            if ktx.is_compressed() {
+                assert_eq!(image, stored, "{}", name);
+            } else {
+                assert_rows_match(
+                    (image, 4),
+                    (stored, ktx_texture.unpack_alignment as usize),
+                    (mip.width * ktx.pixel_size().unwrap()) as usize,
+                    ktx.gl_format == GL_BGR,
+                    &name,
+                );
+            }
+        }
+    }
+}
+
+fn temporary_file(name: &str, ktx_texture: &KtxData) -> PathBuf {
+    let path = env::temp_dir().join(format!("superbible-{}-{}", std::process::id(), name));
+    let mut file = Vec::new();
+    write_ktx(ktx_texture, &mut file).unwrap();
+    fs::write(&path, file).unwrap();
+    path
+}
+
+#[test]
+#[ignore = "needs a display to make a GL context on"]
+fn uploads_every_level() {
+    let _context = headless_context();
+    for (name, ktx_texture) in level_textures() {
+        let texture = assert_uploads(name, &ktx_texture);
+        unsafe {
+            gl::DeleteTextures(1, &texture);
+        }
+    }
+}
+
+#[test]
+#[ignore = "needs a display to make a GL context on"]
+fn reads_back_every_level() {
+    let _context = headless_context();
+    for (name, ktx_texture) in level_textures() {
+        let texture = prepare_texture(&ktx_texture).unwrap();
+        assert_reads_back(name, texture, &ktx_texture);
+        unsafe {
+            gl::DeleteTextures(1, &texture);
+        }
+    }
+}
+
+// A depth render target cleared to a quarter, and a mutable texture with one level
+#[test]
+#[ignore = "needs a display to make a GL context on"]
+fn reads_back_render_targets() {
+    let _context = headless_context();
+    let (mut framebuffer, mut depth, mut color, mut empty) = (0, 0, 0, 0);
+    let texels = [[1u8, 2, 3, 4], [5, 6, 7, 8], [9, 10, 11, 12]].concat();
+    unsafe {
+        gl::GenTextures(1, &mut depth);
+        gl::BindTexture(gl::TEXTURE_2D, depth);
+        gl::TexStorage2D(gl::TEXTURE_2D, 2, gl::DEPTH_COMPONENT32F, 6, 6);
+        gl::GenFramebuffers(1, &mut framebuffer);
+        gl::BindFramebuffer(gl::FRAMEBUFFER, framebuffer);
+        gl::FramebufferTexture(gl::FRAMEBUFFER, gl::DEPTH_ATTACHMENT, depth, 0);
+        gl::ClearBufferfv(gl::DEPTH, 0, [0.25f32].as_ptr());
+        gl::BindFramebuffer(gl::FRAMEBUFFER, 0);
+
+        gl::GenTextures(1, &mut color);
+        gl::BindTexture(gl::TEXTURE_2D, color);
+        gl::TexImage2D(
+            gl::TEXTURE_2D,
+            0,
+            gl::RGBA8 as i32,
+            3,
+            1,
+            0,
+            gl::RGBA,
+            gl::UNSIGNED_BYTE,
+            texels.as_ptr() as *const _,
+        );
+    }
+
+    let read = read_texture(depth, gl::TEXTURE_2D).unwrap();
+    assert_eq!(
+        (read.header.gl_format, read.header.gl_type),
+        (GL_DEPTH_COMPONENT, GL_FLOAT)
+    );
+    assert_eq!(read.levels.len(), 2);
+    let values: Vec<f32> = read
+        .image(0, 0, 0)
+        .unwrap()
+        .chunks_exact(4)
+        .map(|value| f32::from_ne_bytes([value[0], value[1], value[2], value[3]]))
+        .collect();
+    assert_eq!(values, [0.25; 36]);
+
+    // The caller's pack state and pixel pack buffer don't change where levels are read to,
+    // and are left as they were
+    let pack_state = [
+        (gl::PACK_ROW_LENGTH, 64),
+        (gl::PACK_SKIP_ROWS, 3),
+        (gl::PACK_SKIP_PIXELS, 5),
+        (gl::PACK_ALIGNMENT, 8),
+    ];
+    let mut pack_buffer = 0;
+    unsafe {
+        for (parameter, value) in pack_state.iter() {
+            gl::PixelStorei(*parameter, *value);
+        }
+        gl::GenBuffers(1, &mut pack_buffer);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, pack_buffer);
+        gl::BufferData(gl::PIXEL_PACK_BUFFER, 4, std::ptr::null(), gl::STREAM_READ);
+    }
+    let read = read_texture(color, gl::TEXTURE_2D).unwrap();
+    assert_eq!(read.header.mip_levels, 1);
+    assert_eq!(read.header.gl_format, GL_RGBA);
+    assert_eq!(read.image(0, 0, 0).unwrap(), &texels[..]);
+    unsafe {
+        for (parameter, value) in pack_state.iter() {
+            let mut current = 0;
+            gl::GetIntegerv(*parameter, &mut current);
+            assert_eq!(current, *value);
+            gl::PixelStorei(
+                *parameter,
+                if *parameter == gl::PACK_ALIGNMENT {
+                    4
+                } else {
+                    0
+                },
+            );
+        }
+        let mut bound = 0;
+        gl::GetIntegerv(gl::PIXEL_PACK_BUFFER_BINDING, &mut bound);
+        assert_eq!(bound as u32, pack_buffer);
+        gl::BindBuffer(gl::PIXEL_PACK_BUFFER, 0);
+        gl::DeleteBuffers(1, &pack_buffer);
+    }
+
+    let mut file = Vec::new();
+    write_ktx(&read, &mut file).unwrap();
+    assert_eq!(
+        parse_ktx(&file).unwrap().image(0, 0, 0),
+        read.image(0, 0, 0)
+    );
+
+    assert_eq!(
+        read_texture(color, GL_TEXTURE_BUFFER).err(),
+        Some(KtxError::Readback("target has no mip levels"))
+    );
+    unsafe {
+        gl::GenTextures(1, &mut empty);
+    }
+    assert_eq!(
+        read_texture(empty, gl::TEXTURE_2D).err(),
+        Some(KtxError::Readback("texture has no storage"))
+    );
+
+    unsafe {
+        gl::DeleteFramebuffers(1, &framebuffer);
+        gl::DeleteTextures(1, &depth);
+        gl::DeleteTextures(1, &color);
+        gl::DeleteTextures(1, &empty);
+    }
+}
+
+// Streams files of every kind of layout a few hundred bytes a frame, then reads them back
+#[test]
+#[ignore = "needs a display to make a GL context on"]
+fn streams_textures_through_pixel_buffers() {
+    let _context = headless_context();
+    assert_eq!(
+        TextureStreamer::new(0).err(),
+        Some(KtxError::InvalidArgument("frame budget of zero bytes"))
+    );
+    let sphere = load_ktx_file("assets/textures/envmaps/spheremap1.ktx").unwrap();
+    let cube = reproject(&sphere, Projection::SphereMap, Projection::CubeMap, 16).unwrap();
+    let distance_fields = load_ktx_file("assets/textures/chars-df-array.ktx").unwrap();
+    let compressed = compress_rgtc(&distance_fields, &MipmapOptions::default()).unwrap();
+    let paths = [
+        PathBuf::from("assets/textures/brick.ktx"),
+        temporary_file("cube.ktx", &cube),
+        temporary_file("compressed.ktx", &compressed.texture),
+        temporary_file("volume.ktx", &mipmapped_file([7, 5, 3], 0)),
+        temporary_file("layers.ktx", &mipmapped_file([13, 0, 0], 3)),
+    ];
+
+    let textures: Vec<_> = paths
+        .iter()
+        .map(|path| load_ktx_file(path).unwrap())
+        .collect();
+    let totals: Vec<usize> = textures
+        .iter()
+        .map(|texture| {
+            texture
+                .levels
+                .iter()
+                .flat_map(|mip| mip.images.iter())
+                .map(|range| range.len())
+                .sum()
+        })
+        .collect();
+
+    // Rows of brick.ktx's first level are wider than the budget, and go one per frame
+    let budget = 1024;
+    let widest_row = 512 * 3;
+    let mut streamer = TextureStreamer::new(budget).unwrap();
+    let handles: Vec<_> = paths.iter().map(|path| streamer.load_file(path)).collect();
+    let missing = streamer.load_file("assets/textures/missing.ktx");
+    let uploaded = |handles: &[StreamHandle]| -> usize {
+        handles
+            .iter()
+            .zip(totals.iter())
+            .map(|(handle, total)| match handle.status() {
+                StreamStatus::Loading => 0,
+                StreamStatus::Uploading {
+                    uploaded,
+                    total: streamed,
+                } => {
+                    assert_eq!(streamed, *total);
+                    uploaded
+                }
+                StreamStatus::Ready(_) => *total,
+                StreamStatus::Failed(error) => panic!("{}", error),
+            })
+            .sum()
+    };
+
+    let mut frames = 0;
+    let mut partial = false;
+    while streamer.is_busy() {
+        let before = uploaded(&handles);
+        streamer.update();
+        let sent = uploaded(&handles) - before;
+        assert!(sent <= widest_row, "{} bytes in a frame", sent);
+        partial |= handles.iter().any(|handle| match handle.status() {
+            StreamStatus::Uploading { uploaded, total } => uploaded > 0 && uploaded < total,
+            _ => false,
+        });
+        frames += 1;
+        unsafe {
+            gl::Flush();
+        }
+        assert!(frames < 1_000_000, "streaming never finished");
+    }
+    assert!(partial);
+    assert!(frames > totals.iter().sum::<usize>() / widest_row);
+    match missing.status() {
+        StreamStatus::Failed(KtxError::Io { .. }) => {}
+        status => panic!("{:?}", status),
+    }
+    unsafe {
+        assert_eq!(gl::GetError(), gl::NO_ERROR);
+    }
+
+    for ((path, handle), ktx_texture) in paths.iter().zip(handles.iter()).zip(textures.iter()) {
+        let texture = handle.texture().unwrap();
+        let name = format!("streamed {}", path.display());
+        assert_reads_back(&name, texture, ktx_texture);
+        unsafe {
+            gl::DeleteTextures(1, &texture);
+        }
+    }
+
+    // Textures still loading when the streamer goes away are never uploaded
+    let mut streamer = TextureStreamer::new(budget).unwrap();
+    let handle = streamer.load_file(&paths[0]);
+    drop(streamer);
+    match handle.status() {
+        StreamStatus::Failed(KtxError::StreamCancelled) => {}
+        status => panic!("{:?}", status),
+    }
+
+    for path in paths.iter().skip(1) {
+        fs::remove_file(path).unwrap();
+    }
+}
+
+#[test]
+#[ignore = "needs a display to make a GL context on"]
+fn streams_float_textures_with_an_odd_budget() {
+    let _context = headless_context();
+    let mut random = StdRng::seed_from_u64(1001);
+    let size = 24;
+    let pixels: Vec<u8> = (0..size * size * 4)
+        .flat_map(|_| random.gen::<f32>().to_le_bytes())
+        .collect();
+    let ktx_texture = KtxData {
+        header: Header {
+            endianess: [1, 2, 3, 4],
+            gl_type: GL_FLOAT,
+            gl_type_size: 4,
+            gl_format: GL_RGBA,
+            gl_internal_format: GL_RGBA32F,
+            gl_base_internal_format: GL_RGBA,
+            pixel_width: size,
+            pixel_height: size,
+            pixel_depth: 0,
+            array_elements: 0,
+            faces: 1,
+            mip_levels: 1,
+            key_pair_bytes: 0,
+        },
+        key_values: Vec::new(),
+        levels: vec![MipLevel {
+            width: size,
+            height: size,
+            depth: 1,
+            images: iter::once(0..pixels.len()).collect(),
+        }],
+        unpack_alignment: 4,
+        pixels: Cow::Owned(pixels),
+    };
+    let path = temporary_file("float.ktx", &ktx_texture);
+
+    // Slots after the first would start on an offset no float can be read from
+    let mut streamer = TextureStreamer::new(1001).unwrap();
+    let handle = streamer.load_file(&path);
+    let mut frames = 0;
+    while streamer.is_busy() {
+        streamer.update();
+        frames += 1;
+        unsafe {
+            gl::Flush();
+            assert_eq!(gl::GetError(), gl::NO_ERROR, "frame {}", frames);
+        }
+        assert!(frames < 1_000_000, "streaming never finished");
+    }
+    assert!(frames > 3);
+
+    let texture = handle.texture().unwrap();
+    assert_reads_back("streamed float texture", texture, &ktx_texture);
+    unsafe {
+        gl::DeleteTextures(1, &texture);
+    }
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+#[ignore = "needs a display to make a GL context on"]
+fn uploads_and_reads_back_dds_cube_map_arrays() {
+    let _context = headless_context();
+    let ktx_texture = dds_cube_array();
+    let texture = assert_uploads("DDS cube map array", &ktx_texture);
+    assert_reads_back("DDS cube map array", texture, &ktx_texture);
+    unsafe {
+        gl::DeleteTextures(1, &texture);
+    }
+}